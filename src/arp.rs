@@ -0,0 +1,164 @@
+#![allow(dead_code)]
+#![allow(unused)]
+
+pub mod arp {
+    //! Address Resolution Protocol (RFC 826) for IPv4 over Ethernet, and a
+    //! cache of resolved neighbors.
+
+    use crate::FrameError;
+    use mac_address::MacAddress;
+    use std::collections::HashMap;
+    use std::net::Ipv4Addr;
+    use std::time::{Duration, Instant};
+
+    /// Length of an ARP packet for IPv4 over Ethernet.
+    pub const ARP_LEN: usize = 28;
+    /// How long a resolved entry stays valid by default.
+    pub const DEFAULT_TTL: Duration = Duration::from_secs(60);
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub enum ArpOperation {
+        Request,
+        Reply,
+        Other(u16),
+    }
+
+    impl From<u16> for ArpOperation {
+        fn from(value: u16) -> Self {
+            match value {
+                1 => ArpOperation::Request,
+                2 => ArpOperation::Reply,
+                _ => ArpOperation::Other(value),
+            }
+        }
+    }
+
+    impl From<ArpOperation> for u16 {
+        fn from(operation: ArpOperation) -> Self {
+            match operation {
+                ArpOperation::Request => 1,
+                ArpOperation::Reply => 2,
+                ArpOperation::Other(value) => value,
+            }
+        }
+    }
+
+    /// An ARP packet for IPv4 over Ethernet.
+    #[derive(Clone, Debug, PartialEq, Eq)]
+    pub struct ArpPacket {
+        pub operation: ArpOperation,
+        pub sender_mac: MacAddress,
+        pub sender_ip: Ipv4Addr,
+        pub target_mac: MacAddress,
+        pub target_ip: Ipv4Addr,
+    }
+
+    impl ArpPacket {
+        /// A request asking who has `target_ip`.
+        pub fn request(sender_mac: MacAddress, sender_ip: Ipv4Addr, target_ip: Ipv4Addr) -> Self {
+            ArpPacket {
+                operation: ArpOperation::Request,
+                sender_mac,
+                sender_ip,
+                target_mac: MacAddress::new([0; 6]),
+                target_ip,
+            }
+        }
+
+        /// The reply to this request, announcing that `mac` has the target IP.
+        pub fn reply(&self, mac: MacAddress) -> Self {
+            ArpPacket {
+                operation: ArpOperation::Reply,
+                sender_mac: mac,
+                sender_ip: self.target_ip,
+                target_mac: self.sender_mac,
+                target_ip: self.sender_ip,
+            }
+        }
+
+        pub fn encode(&self) -> Vec<u8> {
+            let mut data = Vec::with_capacity(ARP_LEN);
+            data.extend_from_slice(&1u16.to_be_bytes());
+            data.extend_from_slice(&0x0800u16.to_be_bytes());
+            data.push(6);
+            data.push(4);
+            data.extend_from_slice(&u16::from(self.operation).to_be_bytes());
+            data.extend_from_slice(&self.sender_mac.bytes());
+            data.extend_from_slice(&self.sender_ip.octets());
+            data.extend_from_slice(&self.target_mac.bytes());
+            data.extend_from_slice(&self.target_ip.octets());
+            data
+        }
+
+        /// Decodes an ARP packet from the payload of an Ethernet frame.
+        /// Only Ethernet/IPv4 address types are accepted.
+        pub fn decode(data: &[u8]) -> Result<Self, FrameError> {
+            if data.len() < ARP_LEN {
+                return Err(FrameError::Truncated { offset: 0, needed: ARP_LEN, available: data.len() });
+            }
+            if data[0..2] != [0, 1] {
+                return Err(FrameError::InvalidField { offset: 0, field: "hardware type" });
+            }
+            if data[2..4] != [0x08, 0x00] || data[4] != 6 || data[5] != 4 {
+                return Err(FrameError::InvalidField { offset: 2, field: "protocol type" });
+            }
+            let mac = |offset: usize| MacAddress::new(data[offset..offset+6].try_into().unwrap());
+            let ip = |offset: usize| Ipv4Addr::from(<[u8; 4]>::try_from(&data[offset..offset+4]).unwrap());
+            Ok(ArpPacket {
+                operation: ArpOperation::from(u16::from_be_bytes([data[6], data[7]])),
+                sender_mac: mac(8),
+                sender_ip: ip(14),
+                target_mac: mac(18),
+                target_ip: ip(24),
+            })
+        }
+    }
+
+    /// Resolved IPv4 neighbors, with entries expiring after a fixed time.
+    #[derive(Clone, Debug)]
+    pub struct ArpCache {
+        entries: HashMap<Ipv4Addr, (MacAddress, Instant)>,
+        ttl: Duration,
+    }
+
+    impl Default for ArpCache {
+        fn default() -> Self {
+            ArpCache::new(DEFAULT_TTL)
+        }
+    }
+
+    impl ArpCache {
+        pub fn new(ttl: Duration) -> Self {
+            ArpCache { entries: HashMap::new(), ttl }
+        }
+
+        /// The MAC address of `ip`, unless unknown or expired.
+        pub fn lookup(&self, ip: Ipv4Addr) -> Option<MacAddress> {
+            self.entries.get(&ip)
+                .filter(|(_, updated)| updated.elapsed() < self.ttl)
+                .map(|(mac, _)| *mac)
+        }
+
+        pub fn insert(&mut self, ip: Ipv4Addr, mac: MacAddress) {
+            self.entries.insert(ip, (mac, Instant::now()));
+        }
+
+        pub fn remove(&mut self, ip: Ipv4Addr) -> Option<MacAddress> {
+            self.entries.remove(&ip).map(|(mac, _)| mac)
+        }
+
+        /// Drop expired entries.
+        pub fn expire(&mut self) {
+            let ttl = self.ttl;
+            self.entries.retain(|_, (_, updated)| updated.elapsed() < ttl);
+        }
+
+        /// All valid entries.
+        pub fn entries(&self) -> Vec<(Ipv4Addr, MacAddress)> {
+            self.entries.iter()
+                .filter(|(_, (_, updated))| updated.elapsed() < self.ttl)
+                .map(|(ip, (mac, _))| (*ip, *mac))
+                .collect()
+        }
+    }
+}
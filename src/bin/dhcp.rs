@@ -0,0 +1,147 @@
+#![allow(dead_code)]
+#![allow(unused)]
+
+//! Run a DHCP client or server on given device. The client reports the
+//! leases it obtains; built with the `netlink` feature, it also assigns
+//! them to the device, so that namespaces auto-configure. The server hands
+//! out addresses from a pool in the subnet of the device. With `-c`, the
+//! device is the one of role `dhcp` in the configuration, its `mode`
+//! setting gives the side to run, and its `first` and `last` settings the
+//! pool of a server.
+
+use rlink::cli::cli::{Command, Matches};
+use rlink::dhcp::dhcp::{DhcpClient, DhcpServer, Lease, ServerConfig};
+use rlink::netns::netns;
+use rlink::{RlinkError, Stack};
+use std::net::Ipv4Addr;
+use std::process;
+use std::time::Duration;
+use std::thread;
+
+fn main() {
+    let command = Command::new("dhcp", "client [dev name] | server [dev name] [first ip] [last ip]",
+        "Run a DHCP client reporting its leases, or a server handing out addresses from first to last.")
+        .option(Some('r'), "router", "ip", "router given to clients, may be repeated")
+        .option(Some('d'), "dns", "ip", "DNS server given to clients, may be repeated")
+        .option(Some('l'), "lease", "secs", "lease time given to clients (default 3600)");
+    let matches = command.parse_env();
+    if let Err(e) = run(&command, &matches) {
+        println!("dhcp: {}", e);
+        process::exit(1);
+    }
+}
+
+fn run(command: &Command, matches: &Matches) -> Result<(), RlinkError> {
+    let options = matches.capture()?;
+    let usage = || RlinkError::InvalidArgument(command.usage());
+    // Mode, device, its namespace, and the pool of a server
+    let (mode, name, netns, pool) = match (matches.positionals.as_slice(), &matches.config) {
+        ([mode, name], _) => (mode.clone(), name.clone(), None, None),
+        ([mode, name, first, last], _) => (mode.clone(), name.clone(), None, Some((first.clone(), last.clone()))),
+        ([], Some(config)) => match config.devices("dhcp").next() {
+            Some(device) => (device.get("mode").unwrap_or_default(), device.name.clone(), device.netns.clone(),
+                device.get("first").zip(device.get("last"))),
+            None => return Err(RlinkError::InvalidConfig("no device of role dhcp".to_string())),
+        },
+        _ => return Err(usage()),
+    };
+    let stack = options.open_stack(vec![(netns.clone(), name.clone())])?;
+    match (mode.as_str(), pool) {
+        ("client", None) => client(&stack, &name, netns.as_deref()),
+        ("server", Some((first, last))) => server(&stack, &name, matches, &first, &last),
+        _ => Err(usage()),
+    }
+}
+
+/// Run `f` in network namespace `netns`, if given.
+fn in_netns<T>(netns: Option<&str>, f: impl FnOnce() -> Result<T, RlinkError>) -> Result<T, RlinkError> {
+    match netns {
+        Some(netns) => netns::with_netns(netns, f),
+        None => f(),
+    }
+}
+
+fn client(stack: &Stack, name: &str, netns: Option<&str>) -> Result<(), RlinkError> {
+    let client = DhcpClient::new(stack)?;
+    client.start(0)?;
+
+    let mut current: Option<Lease> = None;
+    loop {
+        let lease = client.lease(0);
+        let changed = match (&current, &lease) {
+            (Some(old), Some(new)) => old.address != new.address || old.prefix_len != new.prefix_len,
+            (None, None) => false,
+            _ => true,
+        };
+        if changed {
+            if let Some(old) = current.as_ref() {
+                println!("lost {}/{}", old.address, old.prefix_len);
+                #[cfg(feature = "netlink")]
+                if let Err(e) = in_netns(netns, || rlink::netlink::netlink::del_address(name, old.address.into(), old.prefix_len)) {
+                    println!("dhcp: {}", e);
+                }
+            }
+            if let Some(new) = lease.as_ref() {
+                println!("bound {}/{} from {}, lease {}s", new.address, new.prefix_len, new.server,
+                    new.lease_time.as_secs());
+                for router in new.routers.iter() {
+                    println!("  router {}", router);
+                }
+                for server in new.dns_servers.iter() {
+                    println!("  dns {}", server);
+                }
+                #[cfg(feature = "netlink")]
+                if let Err(e) = in_netns(netns, || rlink::netlink::netlink::add_address(name, new.address.into(), new.prefix_len)) {
+                    println!("dhcp: {}", e);
+                }
+            }
+        }
+        current = lease;
+        thread::sleep(Duration::from_secs(1));
+    }
+}
+
+/// The addresses given with a repeatable option.
+fn addresses(matches: &Matches, long: &str) -> Result<Vec<Ipv4Addr>, RlinkError> {
+    matches.values(long).iter()
+        .map(|addr| addr.parse().map_err(|_| RlinkError::InvalidArgument(format!("invalid --{} {:?}", long, addr))))
+        .collect()
+}
+
+fn server(stack: &Stack, name: &str, matches: &Matches, first: &str, last: &str) -> Result<(), RlinkError> {
+    let (first, last) = match (first.parse::<Ipv4Addr>(), last.parse::<Ipv4Addr>()) {
+        (Ok(first), Ok(last)) if first <= last => (first, last),
+        _ => return Err(RlinkError::InvalidArgument(format!("invalid pool {} to {}", first, last))),
+    };
+    let prefix_len = match stack.link(0).and_then(|link| link.addresses.first().copied()) {
+        Some((_, prefix_len)) => prefix_len,
+        None => return Err(RlinkError::NoAddress(name.to_string())),
+    };
+    let mut config = ServerConfig::new(first, last, prefix_len);
+    config.routers = addresses(matches, "router")?;
+    config.dns_servers = addresses(matches, "dns")?;
+    if let Some(secs) = matches.parsed("lease")? {
+        config.lease_time = Duration::from_secs(secs);
+    }
+
+    let server = DhcpServer::start(stack, 0, config)?;
+    println!("serving {}-{} on {} as {}", first, last, name, server.server_id());
+    let mut current = Vec::new();
+    loop {
+        let leases: Vec<_> = server.leases().into_iter()
+            .map(|lease| (lease.address, lease.mac_address))
+            .collect();
+        for (address, mac_address) in leases.iter() {
+            if !current.contains(&(*address, *mac_address)) {
+                println!("leased {} to {}", address, mac_address);
+            }
+        }
+        for (address, mac_address) in current.iter() {
+            if !leases.contains(&(*address, *mac_address)) {
+                println!("freed {} from {}", address, mac_address);
+            }
+        }
+        current = leases;
+        thread::sleep(Duration::from_secs(1));
+    }
+}
@@ -1,27 +1,38 @@
 #![allow(dead_code)]
 #![allow(unused)]
 
-//! Multi-thread to gather packets from multiple devices.
+//! Multi-thread to gather packets from multiple devices, optionally only
+//! those matching a filter expression given with `-f`. With `-c`, the
+//! devices are those of role `gatherer` in the configuration.
 
-use rlink::{DeviceHandle, EtherType, DevicePool, Packet};
-use std::env;
+use rlink::cli::cli::{Command, Matches};
+use rlink::RlinkError;
+use std::process;
 
 fn main() {
-    let args: Vec<String> = env::args().collect();
-    if args.len() < 2 {
-        println!("Usage: gatherer [dev name..]\n");
-        return;
+    let command = Command::new("gatherer", "[dev name..]",
+        "Print the packets received on all the devices.");
+    let matches = command.parse_env();
+    if let Err(e) = run(&command, &matches) {
+        println!("gatherer: {}", e);
+        process::exit(1);
     }
+}
 
-    let pool = DevicePool::new(
-        args[1..].iter().map(|s| s.clone()).collect(),
-        50
-    ).unwrap();
-
+fn run(command: &Command, matches: &Matches) -> Result<(), RlinkError> {
+    let options = matches.capture()?;
+    let devices = matches.devices();
+    if devices.is_empty() {
+        return Err(RlinkError::InvalidArgument(command.usage()));
+    }
+    let pool = options.open_pool(devices.into_iter().map(|device| (device.netns, device.name)).collect())?;
 
     loop {
-        let packet = pool.select().unwrap();
-        let parsed_packet = packet.parse_eth(false).unwrap();
-        println!("Received Packet:\n{}", parsed_packet);
+        let packet = pool.select()?;
+        match packet.parse_eth(options.checksum.unwrap_or(false)) {
+            Ok(parsed_packet) => println!("Received Packet:\n{}", parsed_packet),
+            Err(e) if matches.verbosity() > 1 => println!("Received invalid packet: {}", e),
+            Err(_) => {}
+        }
     }
 }
@@ -2,42 +2,114 @@
 #![allow(unused)]
 
 //! Relay packets on the network. Do make sure that the network contains
-//! no loop!
+//! no loop! Per-device counters are printed every 10 seconds. With the
+//! `prometheus` feature, `-m addr` also serves them, together with the
+//! health of the capturing pool, e.g. `-m 127.0.0.1:9100`. With `-c`, the
+//! devices are those of role `hub` in the configuration.
 
-use rlink::{DeviceHandle, EtherType, DevicePool};
+use rlink::cli::cli::{Command, Matches};
+use rlink::{DeviceHandle, DeviceStats, DropPolicy, RlinkError};
+use rlink::device_pool::device_pool::DEFAULT_CAPACITY;
+use rlink::stats::stats;
 use pcap::Direction;
 use std::{thread, time};
-use std::env;
+use std::net::SocketAddr;
+use std::process;
 
 fn main() {
-    let args: Vec<String> = env::args().collect();
-    if args.len() < 2 {
-        println!("Usage: hub [dev name..]");
-        return;
+    let command = Command::new("hub", "[dev name..]",
+        "Relay the packets received on each device to all the others.")
+        .option(Some('m'), "metrics", "addr", "serve metrics on addr (prometheus feature)");
+    let matches = command.parse_env();
+    if let Err(e) = run(&command, &matches) {
+        println!("hub: {}", e);
+        process::exit(1);
     }
+}
+
+fn run(command: &Command, matches: &Matches) -> Result<(), RlinkError> {
+    let options = matches.capture()?;
+    let metrics_addr: Option<SocketAddr> = matches.parsed("metrics")?;
+    let verbosity = matches.verbosity();
+    let devices: Vec<(Option<String>, String)> = matches.devices().into_iter()
+        .map(|device| (device.netns, device.name))
+        .collect();
+    if devices.is_empty() {
+        return Err(RlinkError::InvalidArgument(command.usage()));
+    }
+    let pool = options.open_pool(devices.clone())?;
+    // Keep relaying the other ports while one of them floods
+    pool.set_queue(DEFAULT_CAPACITY, DropPolicy::FairShare)?;
 
-    let names: Vec<String> = args[1..].iter().map(|s| s.clone()).collect();
-    let pool = DevicePool::new(names.clone(),50).unwrap();
-    let mut devices: Vec<DeviceHandle> = names.iter().map(|name| {
-        let handle = DeviceHandle::new(name, 50, false).unwrap();
-        handle.direction(Direction::Out);
-        handle
-    }).collect();
+    #[cfg(feature = "prometheus")]
+    let _exporter = match metrics_addr {
+        Some(addr) => {
+            let exporter = rlink::metrics::metrics::MetricsExporter::start(addr)?;
+            exporter.watch_pool("hub", pool.monitor());
+            if verbosity > 0 {
+                println!("Serving metrics on http://{}/metrics", exporter.local_addr());
+            }
+            Some(exporter)
+        }
+        None => None,
+    };
+    #[cfg(not(feature = "prometheus"))]
+    if metrics_addr.is_some() {
+        println!("hub: built without the prometheus feature, -m ignored");
+    }
+    let mut handles: Vec<DeviceHandle> = devices.iter().map(|(netns, name)| {
+        let handle = options.open(name, netns.as_deref())?;
+        handle.direction(Direction::Out)?;
+        Ok(handle)
+    }).collect::<Result<_, RlinkError>>()?;
+
+    // Print the counters of the last period
+    if verbosity > 0 {
+        thread::spawn(|| {
+            let mut last: Vec<DeviceStats> = Vec::new();
+            loop {
+                thread::sleep(time::Duration::from_secs(10));
+                let now = stats::snapshot();
+                for device in now.iter() {
+                    match last.iter().find(|earlier| (&earlier.netns, &earlier.device) == (&device.netns, &device.device)) {
+                        Some(earlier) => println!("{}", device.since(earlier)),
+                        None => println!("{}", device),
+                    }
+                }
+                last = now;
+            }
+        });
+    }
 
     // Relay packets to other devices
     loop {
-        let parsed_packet = pool.select().unwrap().parse_eth(false).unwrap();
+        let parsed_packet = match pool.select()?.parse_eth(options.checksum.unwrap_or(false)) {
+            Ok(parsed_packet) => parsed_packet,
+            Err(e) => {
+                if verbosity > 1 {
+                    println!("Dropped invalid packet: {}", e);
+                }
+                continue;
+            }
+        };
         
-        println!("Relayed packet...");
+        if verbosity > 0 {
+            println!("Relayed packet...");
+        }
 
-        for device in devices.iter_mut() {
+        for device in handles.iter_mut() {
             // Avoid sending packets back to sender
             if !device.mac_address().eq(&parsed_packet.mac_address) {
-                device.send_packet(
+                let sent = device.send_packet(
                     parsed_packet.data(),
                     parsed_packet.ethtype(), 
                     parsed_packet.dst_addr(),
-                    false);
+                    options.checksum.unwrap_or(false));
+                if let Err(e) = sent {
+                    if verbosity > 1 {
+                        println!("Failed to relay on {}: {}", device.device().name, e);
+                    }
+                }
             }
         }
     }
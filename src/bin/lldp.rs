@@ -0,0 +1,71 @@
+#![allow(dead_code)]
+#![allow(unused)]
+
+//! Advertise LLDP on given devices, and print the neighbors discovered on
+//! each of them whenever the adjacencies change. Devices may be given as
+//! glob patterns, e.g. `veth*`. With `-c`, the devices are those of role
+//! `lldp` in the configuration.
+
+use rlink::cli::cli::{Command, Matches};
+use rlink::lldp::lldp::{LldpAgent, LldpConfig};
+use rlink::RlinkError;
+use std::process;
+use std::time::Duration;
+use std::thread;
+
+fn main() {
+    let command = Command::new("lldp", "[dev name or pattern..]",
+        "Advertise LLDP on the devices, and print their neighbors whenever they change.")
+        .option(Some('n'), "system-name", "name", "system name to advertise (default the host name)")
+        .option(Some('i'), "interval", "secs", "interval between advertisements (default 30)");
+    let matches = command.parse_env();
+    if let Err(e) = run(&command, &matches) {
+        println!("lldp: {}", e);
+        process::exit(1);
+    }
+}
+
+fn run(command: &Command, matches: &Matches) -> Result<(), RlinkError> {
+    let options = matches.capture()?;
+    let mut config = LldpConfig::default();
+    if let Some(name) = matches.value("system-name") {
+        config.system_name = Some(name);
+    }
+    match matches.parsed::<u64>("interval")? {
+        Some(0) => return Err(RlinkError::InvalidArgument("invalid --interval 0".to_string())),
+        Some(secs) => config.tx_interval = Duration::from_secs(secs),
+        None => {}
+    }
+    let ifaces = matches.interfaces()?;
+    if ifaces.is_empty() {
+        return Err(RlinkError::InvalidArgument(command.usage()));
+    }
+
+    let agent = LldpAgent::new(config);
+    for (netns, iface) in ifaces.iter() {
+        let mut builder = options.builder(&iface.name).immediate(true);
+        if let Some(netns) = netns {
+            builder = builder.netns(netns);
+        }
+        let handle = builder.open()?;
+        agent.add_port(&iface.name, *handle.mac_address(), Box::new(handle));
+    }
+    let names: Vec<_> = ifaces.iter().map(|(_, iface)| iface.name.clone()).collect();
+    agent.attach(options.open_pool(ifaces.into_iter().map(|(netns, iface)| (netns, iface.name)).collect())?);
+    if matches.verbosity() > 0 {
+        println!("advertising on {}", names.join(", "));
+    }
+
+    let mut current = Vec::new();
+    loop {
+        thread::sleep(Duration::from_secs(1));
+        let neighbors: Vec<String> = agent.neighbors().iter().map(|neighbor| neighbor.to_string()).collect();
+        if neighbors != current {
+            println!("{} neighbor(s):", neighbors.len());
+            for neighbor in neighbors.iter() {
+                println!("  {}", neighbor);
+            }
+            current = neighbors;
+        }
+    }
+}
@@ -0,0 +1,132 @@
+#![allow(dead_code)]
+#![allow(unused)]
+
+//! Send ICMP echo requests from given device and report round-trip times.
+//! With a small TTL, reports the router where the request expired, 
+//! traceroute-style. With `-c`, the device is the one of role `ping` in the
+//! configuration, and its `dst` setting the address to ping.
+
+use rlink::cli::cli::{Command, Matches};
+use rlink::icmp::icmp::IcmpMessage;
+use rlink::ipv4::ipv4::{IpProtocol, Ipv4Header, Route};
+use rlink::RlinkError;
+use std::net::Ipv4Addr;
+use std::process;
+use std::sync::{mpsc, Mutex};
+use std::time::{Duration, Instant};
+
+fn main() {
+    let command = Command::new("ping", "[dev name] [dst ip]",
+        "Send ICMP echo requests from the device to dst, and report round-trip times.")
+        .option(Some('g'), "gateway", "ip", "router to send through")
+        .option(Some('n'), "count", "number", "number of requests to send (default 4)")
+        .option(Some('t'), "ttl", "hops", "time to live of the requests (default 64)");
+    let matches = command.parse_env();
+    if let Err(e) = run(&command, &matches) {
+        println!("ping: {}", e);
+        process::exit(1);
+    }
+}
+
+fn run(command: &Command, matches: &Matches) -> Result<(), RlinkError> {
+    let options = matches.capture()?;
+    let (netns, name, dst) = match (matches.positionals.as_slice(), &matches.config) {
+        ([name, dst], _) => (None, name.clone(), dst.clone()),
+        ([], Some(config)) => match config.devices("ping").next() {
+            Some(device) => match device.get("dst") {
+                Some(dst) => (device.netns.clone(), device.name.clone(), dst),
+                None => return Err(RlinkError::InvalidConfig(format!("no dst for {}", device.name))),
+            },
+            None => return Err(RlinkError::InvalidConfig("no device of role ping".to_string())),
+        },
+        _ => return Err(RlinkError::InvalidArgument(command.usage())),
+    };
+    let dst: Ipv4Addr = dst.parse()
+        .map_err(|_| RlinkError::InvalidArgument(format!("invalid destination {:?}", dst)))?;
+    let gateway: Option<Ipv4Addr> = matches.parsed("gateway")?;
+    let count: u16 = matches.parsed("count")?.unwrap_or(4);
+    let ttl: u8 = matches.parsed("ttl")?.unwrap_or(64);
+
+    let stack = options.open_stack(vec![(netns, name.clone())])?;
+    if let Some(gateway) = gateway {
+        stack.add_route(Route {
+            prefix: Ipv4Addr::UNSPECIFIED,
+            prefix_len: 0,
+            gateway: Some(gateway),
+            device: name,
+        });
+    }
+
+    let (tx, rx) = mpsc::channel();
+    let tx = Mutex::new(tx);
+    stack.register_handler(IpProtocol::ICMP, Box::new(move |_, _, packet| {
+        if let Ok(message) = IcmpMessage::decode(packet.payload()) {
+            tx.lock().unwrap().send((packet.src(), packet.ttl(), message));
+        }
+    }));
+
+    let id = std::process::id() as u16;
+    let data: Vec<u8> = (0..56u8).collect();
+    let (mut received, mut rtts) = (0u16, Vec::new());
+    println!("PING {} {} data bytes", dst, data.len());
+
+    for seq in 0..count {
+        let mut header = Ipv4Header::new(Ipv4Addr::UNSPECIFIED, dst, IpProtocol::ICMP);
+        header.ttl = ttl;
+        let request = IcmpMessage::EchoRequest { id, seq, data: data.clone() };
+        let sent = Instant::now();
+        stack.send_ipv4_with(header, &request.encode())?;
+
+        let deadline = sent + Duration::from_secs(1);
+        while let Some(timeout) = deadline.checked_duration_since(Instant::now()) {
+            let (src, reply_ttl, message) = match rx.recv_timeout(timeout) {
+                Ok(reply) => reply,
+                Err(_) => break,
+            };
+            match message {
+                IcmpMessage::EchoReply { id: reply_id, seq: reply_seq, data } 
+                    if reply_id == id && reply_seq == seq => {
+                    let rtt = sent.elapsed();
+                    received += 1;
+                    rtts.push(rtt);
+                    println!("{} bytes from {}: icmp_seq={} ttl={} time={:.3} ms",
+                        data.len() + 8, src, seq, reply_ttl, rtt.as_secs_f64() * 1000.0);
+                    break;
+                }
+                ref error if error.is_error() => {
+                    // Match the quoted echo request against ours.
+                    let quoted = error.quoted().unwrap();
+                    let header_len = quoted.first().map_or(0, |b| (b & 0x0f) as usize * 4);
+                    if quoted.len() < header_len + 8 
+                        || quoted[header_len + 4..header_len + 8] != [id.to_be_bytes(), seq.to_be_bytes()].concat()[..] {
+                        continue;
+                    }
+                    let why = match error {
+                        IcmpMessage::TimeExceeded { .. } => "Time to live exceeded".to_string(),
+                        IcmpMessage::DestinationUnreachable { code, .. } => 
+                            format!("Destination unreachable (code {})", code),
+                        _ => "Parameter problem".to_string(),
+                    };
+                    println!("From {} icmp_seq={} {}", src, seq, why);
+                    break;
+                }
+                _ => continue,
+            }
+        }
+        if seq + 1 < count {
+            std::thread::sleep(deadline.saturating_duration_since(Instant::now()));
+        }
+    }
+
+    println!("--- {} ping statistics ---", dst);
+    println!("{} packets transmitted, {} received, {:.0}% packet loss",
+        count, received, 100.0 * (count - received) as f64 / count.max(1) as f64);
+    if !rtts.is_empty() {
+        let ms = |d: &Duration| d.as_secs_f64() * 1000.0;
+        let min = rtts.iter().map(ms).fold(f64::MAX, f64::min);
+        let max = rtts.iter().map(ms).fold(0.0, f64::max);
+        let avg = rtts.iter().map(ms).sum::<f64>() / rtts.len() as f64;
+        println!("rtt min/avg/max = {:.3}/{:.3}/{:.3} ms", min, avg, max);
+    }
+    Ok(())
+}
@@ -0,0 +1,109 @@
+#![allow(dead_code)]
+#![allow(unused)]
+
+//! Measure the offset and path delay between two clocks with PTP, e.g.
+//! `ptp master veth1-2` in one network namespace and `ptp slave veth2-1`
+//! in another. The slave prints every measurement, and their summary once
+//! `-n` measurements are made. As namespaces share the system clock, `-o`
+//! runs the clock of a port ahead of it, for the slave to measure. With
+//! `-c`, the device is the one of role `ptp` in the configuration, and its
+//! `mode` setting gives the role of the port.
+
+use rlink::cli::cli::{Command, Matches};
+use rlink::ptp::ptp::{Measurement, PtpConfig, PtpPort, Role};
+use rlink::{Precision, RlinkError};
+use std::process;
+use std::thread;
+use std::time::Duration;
+
+fn main() {
+    let command = Command::new("ptp", "[master|slave] [dev name]",
+        "Run a PTP master, or a slave measuring its offset from the master.")
+        .option(Some('d'), "domain", "number", "PTP domain (default 0)")
+        .option(Some('i'), "interval", "secs", "interval between Sync messages (default 1)")
+        .option(Some('o'), "clock-offset", "ns", "run the clock of the port ahead of the system clock")
+        .option(Some('n'), "count", "number", "stop after this many measurements");
+    let matches = command.parse_env();
+    if let Err(e) = run(&command, &matches) {
+        println!("ptp: {}", e);
+        process::exit(1);
+    }
+}
+
+fn run(command: &Command, matches: &Matches) -> Result<(), RlinkError> {
+    let options = matches.capture()?;
+    let (mode, name, netns) = match (matches.positionals.as_slice(), &matches.config) {
+        ([mode, name], _) => (mode.clone(), name.clone(), None),
+        ([], Some(config)) => match config.devices("ptp").next() {
+            Some(device) => (device.get("mode").unwrap_or_default(), device.name.clone(), device.netns.clone()),
+            None => return Err(RlinkError::InvalidConfig("no device of role ptp".to_string())),
+        },
+        _ => return Err(RlinkError::InvalidArgument(command.usage())),
+    };
+    let role = match mode.as_str() {
+        "master" => Role::Master,
+        "slave" => Role::Slave,
+        _ => return Err(RlinkError::InvalidArgument(format!("unknown mode {:?}", mode))),
+    };
+    let mut config = PtpConfig::default();
+    if let Some(domain) = matches.parsed("domain")? {
+        config.domain = domain;
+    }
+    if let Some(secs) = matches.parsed::<f64>("interval")? {
+        config.sync_interval = Duration::try_from_secs_f64(secs)
+            .map_err(|_| RlinkError::InvalidArgument(format!("invalid --interval {}", secs)))?;
+    }
+    if let Some(ns) = matches.parsed("clock-offset")? {
+        config.clock_offset = ns;
+    }
+    let count: Option<usize> = matches.parsed("count")?;
+
+    // Timestamp frames to the nanosecond, and handle them right away
+    let mut builder = options.builder(&name).immediate(true).precision(Precision::Nano);
+    if let Some(netns) = netns.as_deref() {
+        builder = builder.netns(netns);
+    }
+    let port = PtpPort::start(builder.open()?, role, config)?;
+    if matches.verbosity() > 0 {
+        println!("{:?} port {} on {}", role, port.identity(), name);
+    }
+
+    if role == Role::Master {
+        loop {
+            thread::sleep(Duration::from_secs(10));
+            if matches.verbosity() > 1 {
+                println!("{:?}", port.stats());
+            }
+        }
+    }
+    let mut measurements = Vec::new();
+    while count.is_none_or(|count| measurements.len() < count) {
+        if let Some(measurement) = port.next_measurement(Duration::from_secs(1))? {
+            println!("{}", measurement);
+            measurements.push(measurement);
+        }
+    }
+    summarize(&measurements);
+    Ok(())
+}
+
+fn summarize(measurements: &[Measurement]) {
+    if measurements.is_empty() {
+        return;
+    }
+    let n = measurements.len() as f64;
+    let offsets: Vec<f64> = measurements.iter().map(|m| m.offset() as f64).collect();
+    let delays: Vec<f64> = measurements.iter().map(|m| m.path_delay() as f64).collect();
+    let mean = |values: &[f64]| values.iter().sum::<f64>() / n;
+    let deviation = |values: &[f64]| {
+        let mean = mean(values);
+        (values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / n).sqrt()
+    };
+    let min = |values: &[f64]| values.iter().cloned().fold(f64::INFINITY, f64::min);
+    let max = |values: &[f64]| values.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+    println!("{} measurements", measurements.len());
+    println!("offset ns: mean {:.0}, stddev {:.0}, min {:.0}, max {:.0}",
+        mean(&offsets), deviation(&offsets), min(&offsets), max(&offsets));
+    println!("path delay ns: mean {:.0}, stddev {:.0}, min {:.0}, max {:.0}",
+        mean(&delays), deviation(&delays), min(&delays), max(&delays));
+}
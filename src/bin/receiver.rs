@@ -1,20 +1,51 @@
 #![allow(dead_code)]
 #![allow(unused)]
 
-//! Expect a packet from given device. 
+//! Expect a packet from given device, optionally one matching a filter
+//! expression, e.g. `receiver veth1-2 udp port 53`. With `-c`, the device
+//! is the one of role `receiver` in the configuration.
 
-use rlink::{DeviceHandle, EtherType};
-use std::env;
+use rlink::cli::cli::{Command, Matches};
+use rlink::{Filter, PError, RlinkError};
+use std::process;
 
 fn main() {
-    let args: Vec<String> = env::args().collect();
-    if args.len() != 2 {
-        println!("Usage: receiver [dev name]\n");
-        return;
+    let command = Command::new("receiver", "[dev name] [filter..]",
+        "Print the first packet received on the device, or the first one matching filter.");
+    let matches = command.parse_env();
+    if let Err(e) = run(&command, &matches) {
+        println!("receiver: {}", e);
+        process::exit(1);
     }
-    let mut device = DeviceHandle::new(args[1].as_ref(), 50, false).unwrap();
+}
+
+fn run(command: &Command, matches: &Matches) -> Result<(), RlinkError> {
+    let mut options = matches.capture()?;
+    let (name, netns) = match (matches.positionals.split_first(), &matches.config) {
+        (Some((name, filter)), _) => {
+            if !filter.is_empty() {
+                options.filter = Some(filter.join(" ").parse::<Filter>()?);
+            }
+            (name.clone(), None)
+        }
+        (None, Some(config)) => match config.devices("receiver").next() {
+            Some(device) => (device.name.clone(), device.netns.clone()),
+            None => return Err(RlinkError::InvalidConfig("no device of role receiver".to_string())),
+        },
+        (None, None) => return Err(RlinkError::InvalidArgument(command.usage())),
+    };
+    let mut device = options.open(&name, netns.as_deref())?;
 
-    let packet = device.next_packet().unwrap().unwrap();
-    let parsed_packet = packet.parse_eth(false).unwrap();
-    println!("Received packet:\n{}", parsed_packet);
+    let packet = loop {
+        match device.next_packet() {
+            Ok(Some(packet)) => break packet,
+            Ok(None) | Err(PError::TimeoutExpired) => {}
+            Err(e) => return Err(e.into()),
+        }
+    };
+    match packet.parse_eth(options.checksum.unwrap_or(false)) {
+        Ok(parsed_packet) => println!("Received packet:\n{}", parsed_packet),
+        Err(e) => println!("Received invalid packet: {}", e),
+    }
+    Ok(())
 }
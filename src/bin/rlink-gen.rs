@@ -0,0 +1,379 @@
+#![allow(dead_code)]
+#![allow(unused)]
+
+//! Generate streams of frames, or receive them and measure the stream.
+//!
+//! In `send` mode, frames of the given EtherType (by default 0x88B5, for
+//! local experiments) are sent round-robin to the destinations given with
+//! `-d` (by default broadcast), at `-R` frames or `-b` bits per second, until
+//! `-n` frames were sent or `-t` seconds elapsed. Payload sizes are fixed
+//! (`-s 100`), uniform in a range (`-s 64-1400`) or follow the simple IMIX
+//! (`-s imix`); their content is a repeated hex pattern (`-p 00ff`), random
+//! bytes (`-r`) or a file (`-F path`).
+//!
+//! Every payload starts with a header holding a stream ID, a sequence
+//! number and the time of sending, from which `recv` mode reports loss,
+//! reordering, duplicates and one-way latency percentiles, every `-i`
+//! seconds. Duplicates are detected among the last 65536 sequence numbers;
+//! older frames are counted as late. Latencies are only meaningful
+//! between devices of one host. With `-c`, the device is the one of role
+//! `rlink-gen` in the configuration, and its `mode` setting gives the mode.
+
+use rlink::cli::cli::{CaptureOptions, Command, Matches};
+use rlink::shaper::shaper::parse_rate;
+use rlink::qos::qos::{self, TokenBucket};
+use rlink::{DeviceHandle, EtherType, Packet, PError, Precision, Raw, RlinkError, parse_mac};
+use std::collections::HashMap;
+use std::fs;
+use std::process;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+const DEFAULT_ETHTYPE: u16 = 0x88B5;
+const MAGIC: &[u8; 4] = b"RLGN";
+/// Magic, stream ID, sequence number and sending time in nanoseconds
+const HEADER_LEN: usize = 4 + 4 + 8 + 8;
+const MAX_PAYLOAD: usize = 1499;
+
+/// xorshift64*, for sizes and random content.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545F4914F6CDD1D)
+    }
+}
+
+enum Sizes {
+    Fixed(usize),
+    Uniform(usize, usize),
+    /// 7:4:1 of small, medium and large frames
+    Imix,
+}
+
+impl Sizes {
+    fn parse(s: &str) -> Option<Sizes> {
+        let valid = |size: usize| (HEADER_LEN..=MAX_PAYLOAD).contains(&size);
+        match s.split_once('-') {
+            _ if s == "imix" => Some(Sizes::Imix),
+            Some((min, max)) => match (min.parse(), max.parse()) {
+                (Ok(min), Ok(max)) if valid(min) && valid(max) && min <= max => Some(Sizes::Uniform(min, max)),
+                _ => None,
+            },
+            None => s.parse().ok().filter(|size| valid(*size)).map(Sizes::Fixed),
+        }
+    }
+
+    fn next(&self, rng: &mut Rng) -> usize {
+        match *self {
+            Sizes::Fixed(size) => size,
+            Sizes::Uniform(min, max) => min + (rng.next() % (max - min + 1) as u64) as usize,
+            Sizes::Imix => match rng.next() % 12 {
+                0..=6 => 46,
+                7..=10 => 576,
+                _ => MAX_PAYLOAD,
+            },
+        }
+    }
+}
+
+enum Content {
+    Pattern(Vec<u8>),
+    Random,
+}
+
+fn now_nanos() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos() as u64
+}
+
+fn main() {
+    let command = Command::new("rlink-gen", "send|recv [dev name]",
+        "Send streams of frames, or receive them and report loss, reordering and latency.")
+        .option(Some('e'), "ethertype", "type", "EtherType of the frames (default 0x88B5)")
+        .option(Some('d'), "dst", "mac", "destination, may be repeated (default broadcast)")
+        .option(Some('s'), "size", "size|min-max|imix", "payload sizes (default 64)")
+        .option(Some('p'), "pattern", "hex", "payload content, repeated")
+        .flag(Some('r'), "random", "random payload content")
+        .option(Some('F'), "file", "path", "payload content, read from path")
+        .option(Some('R'), "frame-rate", "frames", "frames sent per second")
+        .option(Some('b'), "bit-rate", "rate", "bits sent per second, e.g. 10mbit")
+        .option(Some('n'), "count", "number", "stop after this many frames")
+        .option(Some('t'), "duration", "secs", "stop after this many seconds")
+        .option(Some('i'), "interval", "secs", "interval between reports when receiving (default 1)");
+    let matches = command.parse_env();
+    if let Err(e) = run(&command, &matches) {
+        println!("rlink-gen: {}", e);
+        process::exit(1);
+    }
+}
+
+fn run(command: &Command, matches: &Matches) -> Result<(), RlinkError> {
+    let options = matches.capture()?;
+    let (mode, name, netns) = match (matches.positionals.as_slice(), &matches.config) {
+        ([mode, name], _) => (mode.clone(), name.clone(), None),
+        ([], Some(config)) => match config.devices("rlink-gen").next() {
+            Some(device) => (device.get("mode").unwrap_or_default(), device.name.clone(), device.netns.clone()),
+            None => return Err(RlinkError::InvalidConfig("no device of role rlink-gen".to_string())),
+        },
+        _ => return Err(RlinkError::InvalidArgument(command.usage())),
+    };
+    let ethtype = match matches.value("ethertype") {
+        Some(ethtype) => ethtype.parse::<EtherType>()?,
+        None => EtherType::from(DEFAULT_ETHTYPE),
+    };
+
+    // Time arrivals to the nanosecond, by the clock stamping the payloads
+    let mut builder = options.builder(&name).immediate(true).precision(Precision::Nano);
+    if let Some(netns) = netns.as_deref() {
+        builder = builder.netns(netns);
+    }
+    let mut device = builder.open()?;
+    device.set_filter(options.filter.clone());
+    match mode.as_str() {
+        "send" => send(&mut device, ethtype, &options, matches),
+        "recv" => receive(&mut device, ethtype, matches),
+        _ => Err(RlinkError::InvalidArgument(format!("unknown mode {:?}", mode))),
+    }
+}
+
+/// A positive and finite number, e.g. a rate.
+fn positive(matches: &Matches, long: &str) -> Result<Option<f64>, RlinkError> {
+    match matches.parsed::<f64>(long)? {
+        Some(value) if !(value.is_finite() && value > 0.0) =>
+            Err(RlinkError::InvalidArgument(format!("invalid --{} {}, must be positive", long, value))),
+        value => Ok(value),
+    }
+}
+
+/// A positive duration given in seconds.
+fn seconds(matches: &Matches, long: &str) -> Result<Option<Duration>, RlinkError> {
+    match positive(matches, long)? {
+        Some(secs) => match Duration::try_from_secs_f64(secs) {
+            Ok(duration) if !duration.is_zero() => Ok(Some(duration)),
+            _ => Err(RlinkError::InvalidArgument(format!("invalid --{} {}, must be positive", long, secs))),
+        },
+        None => Ok(None),
+    }
+}
+
+fn send(device: &mut DeviceHandle, ethtype: EtherType, options: &CaptureOptions, matches: &Matches)
+    -> Result<(), RlinkError> {
+    let destinations = match matches.values("dst") {
+        macs if macs.is_empty() => vec![[0xff; 6]],
+        macs => macs.iter()
+            .map(|mac| parse_mac(mac).map(|mac| mac.bytes()))
+            .collect::<Result<Vec<_>, _>>()?,
+    };
+    let sizes = match matches.value("size") {
+        Some(size) => Sizes::parse(&size).ok_or(RlinkError::InvalidArgument(
+            format!("invalid size {:?}, sizes range from {} to {}", size, HEADER_LEN, MAX_PAYLOAD)))?,
+        None => Sizes::Fixed(64),
+    };
+    let content = match (matches.value("pattern"), matches.flag("random"), matches.value("file")) {
+        (Some(pattern), false, None) => {
+            let hex = pattern.trim_start_matches("0x");
+            let bytes = (0..hex.len()).step_by(2)
+                .map(|i| hex.get(i..i + 2).and_then(|byte| u8::from_str_radix(byte, 16).ok()))
+                .collect::<Option<Vec<u8>>>()
+                .filter(|bytes| !bytes.is_empty())
+                .ok_or(RlinkError::InvalidArgument(format!("invalid pattern {:?}", pattern)))?;
+            Content::Pattern(bytes)
+        }
+        (None, true, None) => Content::Random,
+        (None, false, Some(path)) => match fs::read(&path) {
+            Ok(bytes) if !bytes.is_empty() => Content::Pattern(bytes),
+            Ok(_) => return Err(RlinkError::InvalidArgument(format!("{} is empty", path))),
+            Err(e) => return Err(RlinkError::InvalidArgument(format!("{}: {}", path, e))),
+        },
+        (None, false, None) => Content::Pattern(vec![0]),
+        _ => return Err(RlinkError::InvalidArgument("-p, -r and -F are exclusive".to_string())),
+    };
+    let pps = positive(matches, "frame-rate")?;
+    let mut bucket = match matches.value("bit-rate") {
+        Some(rate) => Some(TokenBucket::new(parse_rate(&rate)?, qos::wire_len(MAX_PAYLOAD))),
+        None => None,
+    };
+    let count: Option<u64> = matches.parsed("count")?;
+    let duration = seconds(matches, "duration")?;
+    if pps.is_some() && bucket.is_some() {
+        return Err(RlinkError::InvalidArgument("-R and -b are exclusive".to_string()));
+    }
+    let checksum = options.checksum.unwrap_or(true);
+
+    let stream = (now_nanos() as u32) ^ std::process::id().rotate_left(16);
+    let mut rng = Rng(now_nanos() | 1);
+    let start = Instant::now();
+    let deadline = duration.and_then(|duration| start.checked_add(duration));
+    let (mut sent, mut bytes, mut errors) = (0u64, 0u64, 0u64);
+    let mut payload = Vec::with_capacity(MAX_PAYLOAD);
+    println!("sending stream {:08x} as {} on {}", stream, ethtype, device.device().name);
+    while count.is_none_or(|count| sent + errors < count) && deadline.is_none_or(|deadline| Instant::now() < deadline) {
+        let size = sizes.next(&mut rng);
+        payload.clear();
+        payload.extend_from_slice(MAGIC);
+        payload.extend_from_slice(&stream.to_be_bytes());
+        payload.extend_from_slice(&(sent + errors).to_be_bytes());
+        payload.extend_from_slice(&[0; 8]);
+        match &content {
+            Content::Pattern(pattern) => payload.extend(pattern.iter().cycle().take(size - HEADER_LEN)),
+            Content::Random => payload.extend((HEADER_LEN..size).map(|_| rng.next() as u8)),
+        }
+
+        // Pace before stamping the time of sending
+        if let Some(pps) = pps {
+            // At very low rates, the next frame may never be due
+            let due = Duration::try_from_secs_f64((sent + errors) as f64 / pps).ok()
+                .and_then(|delay| start.checked_add(delay));
+            match due {
+                Some(due) => thread::sleep(due.saturating_duration_since(Instant::now())),
+                None => break,
+            }
+        }
+        if let Some(bucket) = bucket.as_mut() {
+            let now = Instant::now();
+            let delay = bucket.delay(qos::wire_len(size), now);
+            thread::sleep(delay);
+            bucket.consume(qos::wire_len(size), now + delay);
+        }
+        payload[16..24].copy_from_slice(&now_nanos().to_be_bytes());
+        let dst = &destinations[((sent + errors) % destinations.len() as u64) as usize];
+        match device.send_packet(payload.as_slice(), ethtype, dst, checksum) {
+            Ok(()) => {
+                sent += 1;
+                bytes += qos::wire_len(size) as u64;
+            }
+            Err(_) => errors += 1,
+        }
+    }
+    let secs = start.elapsed().as_secs_f64();
+    println!("sent {} frames, {} bytes in {:.3}s ({:.0} frames/s, {:.0} bit/s), {} errors",
+        sent, bytes, secs, sent as f64 / secs, bytes as f64 * 8.0 / secs, errors);
+    Ok(())
+}
+
+/// Sequence numbers behind the highest one checked for duplicates.
+const WINDOW: u64 = 1 << 16;
+
+/// Reception of one stream.
+#[derive(Default)]
+struct Stream {
+    received: u64,
+    duplicates: u64,
+    /// Frames arriving after a frame with a higher sequence number
+    reordered: u64,
+    /// Reordered frames older than the window, not checked for duplicates
+    late: u64,
+    highest: Option<u64>,
+    /// Sequence numbers of the window received, a bit each, as a ring
+    seen: Vec<u64>,
+    /// One-way latencies in microseconds
+    latencies: Vec<f64>,
+}
+
+impl Stream {
+    fn record(&mut self, seq: u64, latency: f64) {
+        if self.seen.is_empty() {
+            self.seen = vec![0; (WINDOW / 64) as usize];
+        }
+        match self.highest {
+            // The sequence numbers leaving the window free their bits
+            Some(highest) if seq > highest => {
+                for old in highest + 1..=seq.min(highest.saturating_add(WINDOW)) {
+                    self.set(old, false);
+                }
+            }
+            Some(highest) if highest - seq >= WINDOW => {
+                self.late += 1;
+                self.reordered += 1;
+                self.received += 1;
+                self.latencies.push(latency);
+                return;
+            }
+            _ => (),
+        }
+        if self.is_set(seq) {
+            self.duplicates += 1;
+            return;
+        }
+        self.set(seq, true);
+        self.received += 1;
+        if self.highest.is_some_and(|highest| seq < highest) {
+            self.reordered += 1;
+        }
+        self.highest = Some(self.highest.map_or(seq, |highest| highest.max(seq)));
+        self.latencies.push(latency);
+    }
+
+    fn is_set(&self, seq: u64) -> bool {
+        let bit = seq % WINDOW;
+        self.seen[(bit / 64) as usize] & (1 << (bit % 64)) != 0
+    }
+
+    fn set(&mut self, seq: u64, to: bool) {
+        let bit = seq % WINDOW;
+        let word = &mut self.seen[(bit / 64) as usize];
+        match to {
+            true => *word |= 1 << (bit % 64),
+            false => *word &= !(1 << (bit % 64)),
+        }
+    }
+
+    fn report(&self, id: u32) -> String {
+        let expected = self.highest.map_or(0, |highest| highest.saturating_add(1));
+        // Late duplicates may outnumber the losses
+        let lost = expected.saturating_sub(self.received);
+        let mut sorted = self.latencies.clone();
+        sorted.sort_by(|a, b| a.total_cmp(b));
+        let percentile = |p: f64| sorted.get(((sorted.len() as f64 - 1.0) * p).round() as usize).copied().unwrap_or(0.0);
+        format!("stream {:08x}: {} received, {} lost ({:.2}%), {} reordered ({} late), \
+            {} duplicates, latency us p50 {:.0} p90 {:.0} p99 {:.0} max {:.0}",
+            id, self.received, lost, lost as f64 * 100.0 / expected.max(1) as f64, self.reordered, self.late,
+            self.duplicates, percentile(0.5), percentile(0.9), percentile(0.99), percentile(1.0))
+    }
+}
+
+fn receive(device: &mut DeviceHandle, ethtype: EtherType, matches: &Matches) -> Result<(), RlinkError> {
+    let count: Option<u64> = matches.parsed("count")?;
+    let duration = seconds(matches, "duration")?;
+    let interval = seconds(matches, "interval")?.unwrap_or(Duration::from_secs(1));
+    let start = Instant::now();
+    let deadline = duration.and_then(|duration| start.checked_add(duration));
+    let mut next_report = start.checked_add(interval);
+    let mut streams: HashMap<u32, Stream> = HashMap::new();
+    let mut received = 0u64;
+    println!("receiving {} on {}", ethtype, device.device().name);
+    while count.is_none_or(|count| received < count) && deadline.is_none_or(|deadline| Instant::now() < deadline) {
+        if next_report.is_some_and(|next_report| Instant::now() >= next_report) {
+            for (id, stream) in streams.iter() {
+                println!("{}", stream.report(*id));
+            }
+            next_report = next_report.and_then(|next_report| next_report.checked_add(interval));
+        }
+        let packet = match device.next_packet() {
+            Ok(Some(packet)) => packet,
+            Ok(None) | Err(PError::TimeoutExpired) => continue,
+            Err(e) => return Err(e.into()),
+        };
+        let data = &packet.data;
+        if data.len() < 14 + HEADER_LEN || u16::from_be_bytes([data[12], data[13]]) != u16::from(ethtype)
+            || &data[14..18] != MAGIC {
+            continue;
+        }
+        let field = |at: usize| u64::from_be_bytes(data[14 + at..14 + at + 8].try_into().unwrap());
+        let id = u32::from_be_bytes(data[18..22].try_into().unwrap());
+        let captured = packet.timestamp().duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos() as u64;
+        let latency = captured.saturating_sub(field(16)) as f64 / 1000.0;
+        streams.entry(id).or_default().record(field(8), latency);
+        received += 1;
+    }
+    for (id, stream) in streams.iter() {
+        println!("{}", stream.report(*id));
+    }
+    if streams.is_empty() {
+        println!("no stream received");
+    }
+    Ok(())
+}
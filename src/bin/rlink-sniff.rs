@@ -0,0 +1,255 @@
+#![allow(dead_code)]
+#![allow(unused)]
+
+//! Capture on given devices and print a one-line summary of each frame,
+//! decoding Ethernet, ARP, IPv4, IPv6, ICMP, NDP, UDP and TCP. Devices may
+//! be given as glob patterns, e.g. `veth*`. Frames sent and received are
+//! both captured. Frames may be filtered with `-f` (see `rlink::filter`),
+//! dumped in hex with `-x` and saved to a pcap file with `-w`. Capture
+//! stops after `-n` frames or `-t` seconds. With `-c`, the devices are
+//! those of role `rlink-sniff` in the configuration.
+
+use rlink::arp::arp::{ArpOperation, ArpPacket};
+use rlink::cli::cli::{Command, Matches};
+use rlink::icmp::icmp::IcmpMessage;
+use rlink::ipv4::ipv4::IpProtocol;
+use rlink::ndp::ndp::NdpMessage;
+use rlink::tcp::tcp::{self, TcpSegment};
+use rlink::udp::udp::UdpDatagram;
+use rlink::{Direction, EtherType, MacAddress, Packet, Precision, Raw, RlinkError};
+use pcap::{Capture, Linktype};
+use std::collections::HashMap;
+use std::process;
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+fn main() {
+    let command = Command::new("rlink-sniff", "[dev name or pattern..]",
+        "Print a one-line summary of each frame sent or received on the devices.")
+        .flag(Some('x'), "hex", "dump frames in hex")
+        .option(Some('w'), "write", "file", "save frames to a pcap file")
+        .option(Some('n'), "count", "number", "stop after this many frames")
+        .option(Some('t'), "duration", "secs", "stop after this many seconds");
+    let matches = command.parse_env();
+    if let Err(e) = run(&command, &matches) {
+        println!("rlink-sniff: {}", e);
+        process::exit(1);
+    }
+}
+
+fn run(command: &Command, matches: &Matches) -> Result<(), RlinkError> {
+    let mut options = matches.capture()?;
+    // Frames sent by the devices are captured too
+    options.direction = Some(Direction::InOut);
+    let ifaces = matches.interfaces()?;
+    if ifaces.is_empty() {
+        return Err(RlinkError::InvalidArgument(command.usage()));
+    }
+    let hex = matches.flag("hex");
+    let count = match matches.parsed::<u64>("count")? {
+        Some(0) => return Err(RlinkError::InvalidArgument("invalid --count 0".to_string())),
+        count => count,
+    };
+    let duration = match matches.parsed::<f64>("duration")? {
+        Some(secs) => match Duration::try_from_secs_f64(secs) {
+            Ok(duration) if !duration.is_zero() => Some(duration),
+            _ => return Err(RlinkError::InvalidArgument(format!("invalid --duration {}", secs))),
+        },
+        None => None,
+    };
+
+    let names: HashMap<MacAddress, String> = ifaces.iter()
+        .filter_map(|(_, iface)| iface.mac_address.map(|mac| (mac, iface.name.clone())))
+        .collect();
+    let listed: Vec<_> = ifaces.iter().map(|(_, iface)| iface.name.clone()).collect();
+    let mut savefile = match matches.value("write") {
+        Some(path) => {
+            let precision = if options.nanosecond { Precision::Nano } else { Precision::Micro };
+            let mut savefile = Capture::dead_with_precision(Linktype::ETHERNET, precision)
+                .and_then(|dead| dead.savefile(path))?;
+            // The header is written even if nothing gets captured
+            let _ = savefile.flush();
+            Some(savefile)
+        }
+        None => None,
+    };
+    let pool = options.open_pool(ifaces.into_iter().map(|(netns, iface)| (netns, iface.name)).collect())?;
+    if matches.verbosity() > 0 {
+        println!("capturing on {}", listed.join(", "));
+    }
+
+    // Select on another thread, so that the duration is honoured even
+    // when nothing arrives.
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        while let Ok(packet) = pool.select() {
+            if tx.send(packet).is_err() {
+                break;
+            }
+        }
+    });
+
+    let deadline = duration.and_then(|duration| Instant::now().checked_add(duration));
+    let mut captured = 0;
+    while count.is_none_or(|count| captured < count) {
+        let packet = match deadline {
+            Some(deadline) => match rx.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+                Ok(packet) => packet,
+                Err(_) => break,
+            },
+            None => match rx.recv() {
+                Ok(packet) => packet,
+                Err(_) => break,
+            },
+        };
+        captured += 1;
+        let device = names.get(&packet.mac_address).map_or("?", |name| name.as_str());
+        println!("{} {} {}", timestamp(&packet), device, summary(&packet));
+        if hex {
+            print!("{}", hex_dump(&packet.data));
+        }
+        // Flushed at once, as Ctrl-C ends the capture without unwinding
+        if let Some(savefile) = savefile.as_mut() {
+            savefile.write(&pcap::Packet::new(&packet.header, &packet.data));
+            let _ = savefile.flush();
+        }
+    }
+    if matches.verbosity() > 0 {
+        println!("{} frame(s) captured", captured);
+    }
+    Ok(())
+}
+
+/// Local time of capture, as `HH:MM:SS.micros`, or with nanoseconds.
+fn timestamp(packet: &Packet<Raw>) -> String {
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    let secs = packet.header.ts.tv_sec as libc::time_t;
+    unsafe { libc::localtime_r(&secs, &mut tm) };
+    let digits = match packet.precision() {
+        Precision::Micro => 6,
+        Precision::Nano => 9,
+    };
+    format!("{:02}:{:02}:{:02}.{:0digits$}", tm.tm_hour, tm.tm_min, tm.tm_sec, packet.header.ts.tv_usec, digits = digits)
+}
+
+fn tcp_flags(flags: u8) -> String {
+    [(tcp::SYN, 'S'), (tcp::FIN, 'F'), (tcp::RST, 'R'), (tcp::PSH, 'P'), (tcp::URG, 'U'), (tcp::ACK, '.')]
+        .iter()
+        .filter(|(flag, _)| flags & flag != 0)
+        .map(|(_, c)| *c)
+        .collect()
+}
+
+/// One line describing a frame, in the manner of tcpdump.
+fn summary(packet: &Packet<Raw>) -> String {
+    // Frames captured from the kernel come without FCS, and possibly
+    // unpadded: add a dummy trailer, as parsers only check lengths
+    let mut data = packet.data.clone();
+    data.extend_from_slice(&[0; 4]);
+    data.resize(data.len().max(64), 0);
+    let frame = Packet::<Raw>::from_bytes(data, packet.mac_address);
+    let eth = match frame.parse_eth(false) {
+        Ok(eth) => eth,
+        Err(e) => return format!("{} ({} bytes)", e, packet.data.len()),
+    };
+    let link = format!("{} > {}", eth.src_mac(), eth.dst_mac());
+    let length = packet.data.len().saturating_sub(14);
+    let ethtype = eth.ethtype();
+    match ethtype {
+        EtherType::ARP => match ArpPacket::decode(eth.data()) {
+            Ok(arp) => format!("{}, ARP, {}", link, match arp.operation {
+                ArpOperation::Request => format!("Request who-has {} tell {}", arp.target_ip, arp.sender_ip),
+                ArpOperation::Reply => format!("Reply {} is-at {}", arp.sender_ip, arp.sender_mac),
+                _ => format!("{:?} {} > {}", arp.operation, arp.sender_ip, arp.target_ip),
+            }),
+            Err(e) => format!("{}, ARP, {}", link, e),
+        },
+        EtherType::IPv4 => match eth.parse_ipv4() {
+            Ok(ip) => {
+                let (src, dst) = (ip.src(), ip.dst());
+                let detail = match ip.protocol() {
+                    _ if ip.fragment_offset() != 0 => format!("{} > {}: fragment at {}, length {}",
+                        src, dst, ip.fragment_offset() as usize * 8, ip.payload().len()),
+                    IpProtocol::UDP => match UdpDatagram::decode(ip.payload(), src, dst) {
+                        Ok(udp) => format!("{}.{} > {}.{}: UDP, length {}",
+                            src, udp.src_port, dst, udp.dst_port, udp.payload.len()),
+                        Err(e) => format!("{} > {}: UDP, {}", src, dst, e),
+                    },
+                    IpProtocol::TCP => match TcpSegment::decode(ip.payload(), src, dst) {
+                        Ok(tcp) => format!("{}.{} > {}.{}: Flags [{}], seq {}, ack {}, win {}, length {}",
+                            src, tcp.src_port, dst, tcp.dst_port, tcp_flags(tcp.flags),
+                            tcp.seq, tcp.ack, tcp.window, tcp.payload.len()),
+                        Err(e) => format!("{} > {}: TCP, {}", src, dst, e),
+                    },
+                    IpProtocol::ICMP => match IcmpMessage::decode(ip.payload()) {
+                        Ok(IcmpMessage::EchoRequest { id, seq, .. }) =>
+                            format!("{} > {}: ICMP echo request, id {}, seq {}", src, dst, id, seq),
+                        Ok(IcmpMessage::EchoReply { id, seq, .. }) =>
+                            format!("{} > {}: ICMP echo reply, id {}, seq {}", src, dst, id, seq),
+                        Ok(IcmpMessage::DestinationUnreachable { code, .. }) =>
+                            format!("{} > {}: ICMP unreachable, code {}", src, dst, code),
+                        Ok(IcmpMessage::TimeExceeded { .. }) =>
+                            format!("{} > {}: ICMP time exceeded in-transit", src, dst),
+                        Ok(message) => format!("{} > {}: ICMP {:?}", src, dst, message),
+                        Err(e) => format!("{} > {}: ICMP, {}", src, dst, e),
+                    },
+                    protocol => format!("{} > {}: {}, length {}", src, dst, protocol, ip.payload().len()),
+                };
+                format!("{}, IPv4, ttl {}, {}", link, ip.ttl(), detail)
+            }
+            Err(e) => format!("{}, IPv4, {}", link, e),
+        },
+        EtherType::IPv6 => match eth.parse_ipv6() {
+            Ok(ip) => {
+                let (src, dst) = (ip.src(), ip.dst());
+                let payload = ip.payload();
+                let detail = match ip.protocol() {
+                    IpProtocol::ICMPv6 => match NdpMessage::decode(payload, src, dst) {
+                        Ok(NdpMessage::RouterSolicitation { .. }) => "ICMP6, router solicitation".to_string(),
+                        Ok(NdpMessage::RouterAdvertisement { router_lifetime, .. }) =>
+                            format!("ICMP6, router advertisement, lifetime {}s", router_lifetime),
+                        Ok(NdpMessage::NeighborSolicitation { target, .. }) =>
+                            format!("ICMP6, neighbor solicitation, who has {}", target),
+                        Ok(NdpMessage::NeighborAdvertisement { target, .. }) =>
+                            format!("ICMP6, neighbor advertisement, tgt is {}", target),
+                        Ok(NdpMessage::Redirect { destination, target, .. }) =>
+                            format!("ICMP6, redirect, {} to {}", destination, target),
+                        Err(_) if !payload.is_empty() => format!("ICMP6, type {}", payload[0]),
+                        Err(e) => format!("ICMP6, {}", e),
+                    },
+                    // Checksums are not verified here
+                    IpProtocol::UDP | IpProtocol::TCP if payload.len() >= 4 => {
+                        let port = |at: usize| u16::from_be_bytes([payload[at], payload[at + 1]]);
+                        return format!("{}, IPv6, {}.{} > {}.{}: {}, length {}", link, src, port(0), dst, port(2),
+                            ip.protocol(), payload.len());
+                    }
+                    protocol => format!("{}, length {}", protocol, payload.len()),
+                };
+                format!("{}, IPv6, {} > {}: {}", link, src, dst, detail)
+            }
+            Err(e) => format!("{}, IPv6, {}", link, e),
+        },
+        EtherType::VLAN if eth.data().len() >= 4 => {
+            let tci = u16::from_be_bytes([eth.data()[0], eth.data()[1]]);
+            let inner = EtherType::from(u16::from_be_bytes([eth.data()[2], eth.data()[3]]));
+            format!("{}, 802.1Q vlan {}, p {}, {} (0x{:04X}), length {}", link, tci & 0x0FFF, tci >> 13,
+                inner.abbreviation().unwrap_or("unknown"), u16::from(inner), length.saturating_sub(4))
+        }
+        ethtype => format!("{}, {} (0x{:04X}), length {}", link, ethtype.abbreviation().unwrap_or("unknown"),
+            u16::from(ethtype), length),
+    }
+}
+
+/// Offset, bytes in hex and printable characters, 16 bytes per line.
+fn hex_dump(data: &[u8]) -> String {
+    let mut out = String::new();
+    for (line, chunk) in data.chunks(16).enumerate() {
+        let bytes: Vec<String> = chunk.iter().map(|b| format!("{:02x}", b)).collect();
+        let text: String = chunk.iter()
+            .map(|&b| if b.is_ascii_graphic() || b == b' ' { b as char } else { '.' })
+            .collect();
+        out += &format!("\t0x{:04x}:  {:<47}  {}\n", line * 16, bytes.join(" "), text);
+    }
+    out
+}
@@ -0,0 +1,93 @@
+#![allow(dead_code)]
+#![allow(unused)]
+
+//! Forward IPv4 datagrams between given devices. Routes to the subnets of
+//! the devices are added automatically; others are given with `-r`, in the
+//! notation of `ip route`. Datagrams whose TTL runs out are answered with
+//! ICMP time exceeded. With `--qos`, control traffic (ARP, NDP, network
+//! control DSCPs) is sent ahead of forwarded data on every device. With
+//! `-s rate[:burst]`, e.g. `-s 1mbit` or `-s 10mbit:3000`, every device
+//! sends no faster than the given rate, emulating a slow link. With `-c`,
+//! the devices are those of role `router` in the configuration.
+
+use rlink::cli::cli::{Command, Matches};
+use rlink::{QosConfig, RlinkError, ShaperConfig};
+use rlink::shaper::shaper::parse_rate;
+use rlink::ipv4::ipv4::Route;
+use std::{process, thread, time};
+
+fn main() {
+    let command = Command::new("router", "[dev name..]",
+        "Forward IPv4 datagrams between the devices.")
+        .option(Some('r'), "route", "route", "route, e.g. \"10.0.0.0/8 via 10.1.0.1 dev eth1\", may be repeated")
+        .flag(None, "qos", "send control traffic ahead of forwarded data")
+        .option(Some('s'), "shape", "rate[:burst]", "send no faster than rate on every device");
+    let matches = command.parse_env();
+    if let Err(e) = run(&command, &matches) {
+        println!("router: {}", e);
+        process::exit(1);
+    }
+}
+
+fn run(command: &Command, matches: &Matches) -> Result<(), RlinkError> {
+    let options = matches.capture()?;
+    let devices = matches.devices();
+    if devices.is_empty() {
+        return Err(RlinkError::InvalidArgument(command.usage()));
+    }
+    let routes = matches.values("route").iter()
+        .map(|route| route.parse::<Route>())
+        .collect::<Result<Vec<_>, _>>()?;
+    let qos = matches.flag("qos");
+    let shaping = match matches.value("shape") {
+        Some(spec) => Some(parse_shaping(&spec)?),
+        None => None,
+    };
+
+    let stack = options.open_stack(devices.iter()
+        .map(|device| (device.netns.clone(), device.name.clone()))
+        .collect())?;
+    for route in routes {
+        stack.add_route(route);
+    }
+    stack.set_forwarding(true);
+    if qos || shaping.is_some() {
+        let config = match (qos, shaping) {
+            (false, Some(shaping)) => shaping.into(),
+            (_, shaping) => {
+                let mut config = QosConfig::control_first();
+                config.rate = shaping.map(|shaping| (shaping.bits_per_sec, shaping.burst));
+                config
+            }
+        };
+        for (link, device) in devices.iter().enumerate() {
+            if let Err(e) = stack.set_egress(link, config.clone()) {
+                println!("router: {}: {}", device.name, e);
+                process::exit(1);
+            }
+        }
+    }
+
+    println!("Routing table:");
+    for route in stack.routes().iter() {
+        println!("  {}", route);
+    }
+
+    loop {
+        thread::sleep(time::Duration::from_secs(60));
+    }
+}
+
+/// Parse `rate[:burst]`.
+fn parse_shaping(spec: &str) -> Result<ShaperConfig, RlinkError> {
+    let (rate, burst) = match spec.split_once(':') {
+        Some((rate, burst)) => (rate, Some(burst)),
+        None => (spec, None),
+    };
+    let mut config = ShaperConfig::new(parse_rate(rate)?);
+    if let Some(burst) = burst {
+        config.burst = burst.parse()
+            .map_err(|_| RlinkError::InvalidConfig(format!("invalid burst: {:?}", burst)))?;
+    }
+    Ok(config)
+}
@@ -1,47 +1,49 @@
 #![allow(dead_code)]
 #![allow(unused)]
 
-//! Inject a packet to given device. 
+//! Inject a packet to given device. With `-c`, inject one on each device
+//! of role `sender` in the configuration instead, to its `dst` and
+//! carrying its `message`.
 
-use rlink::{DeviceHandle, EtherType};
-use std::env;
-
-fn cvt(char: char) -> u8 {
-    match char {
-        '0' => 0u8,
-        '1' => 1u8,
-        '2' => 2u8,
-        '3' => 3u8,
-        '4' => 4u8,
-        '5' => 5u8,
-        '6' => 6u8,
-        '7' => 7u8,
-        '8' => 8u8,
-        '9' => 9u8,
-        'a' => 0xau8,
-        'b' => 0xbu8,
-        'c' => 0xcu8,
-        'd' => 0xdu8,
-        'e' => 0xeu8,
-        'f' => 0xfu8,
-        _ => 0x0u8,
-    }
-}
+use rlink::cli::cli::Command;
+use rlink::{EtherType, RlinkError, parse_mac};
+use std::process;
 
 fn main() {
-    let args: Vec<String> = env::args().collect();
-    if args.len() != 4 {
-        println!("Usage: sender [dst mac addr] [src dev name] [msg]\n");
-        return;
+    let command = Command::new("sender", "[dst mac addr] [src dev name] [msg]",
+        "Inject an IPv4-typed frame carrying msg on the device.");
+    let matches = command.parse_env();
+    if let Err(e) = run(&command, &matches) {
+        println!("sender: {}", e);
+        process::exit(1);
     }
+}
 
-    let mut device = DeviceHandle::new(&args[2], 50, false).unwrap();
+fn run(command: &Command, matches: &rlink::cli::cli::Matches) -> Result<(), RlinkError> {
+    let options = matches.capture()?;
+    // (dst, device, netns, message) of each packet
+    let packets = match (matches.positionals.as_slice(), &matches.config) {
+        ([dst, name, message], _) => vec![(dst.clone(), name.clone(), None, message.clone())],
+        ([], Some(config)) => config.devices("sender").map(|device| {
+            match (device.get("dst"), device.get("message")) {
+                (Some(dst), Some(message)) => Ok((dst, device.name.clone(), device.netns.clone(), message)),
+                _ => Err(RlinkError::InvalidConfig(format!("sender {} needs dst and message", device.name))),
+            }
+        }).collect::<Result<Vec<_>, _>>()?,
+        _ => return Err(RlinkError::InvalidArgument(command.usage())),
+    };
 
-    device.send_packet(args[3].as_ref(), 
-        EtherType::IPv4,
-        args[1].split(":").into_iter().map(|b| {
-            cvt(b.chars().nth(0).unwrap()) * 16 + cvt(b.chars().nth(1).unwrap())
-        }).collect::<Vec<u8>>()[0..6].try_into().unwrap(),
-        true
-    );
+    for (dst, name, netns, message) in packets {
+        let dst_mac = parse_mac(&dst)?;
+        let mut device = options.open(&name, netns.as_deref())?;
+        device.send_packet(message.as_bytes(), 
+            EtherType::IPv4,
+            &dst_mac.bytes(),
+            options.checksum.unwrap_or(true)
+        )?;
+        if matches.verbosity() > 1 {
+            println!("Sent {} bytes to {} on {}", message.len(), dst, name);
+        }
+    }
+    Ok(())
 }
@@ -0,0 +1,556 @@
+#![allow(dead_code)]
+#![allow(unused)]
+
+pub mod cli {
+    //! Command line parsing shared by the tools. Every tool accepts the
+    //! capture options below, `--help`, and `-c file` to read a node
+    //! configuration (see the `config` module), in which its devices are
+    //! those of its role, and its settings those of `[defaults]` and of the
+    //! table named after it. Options given on the command line win over
+    //! the file.
+    //!
+    //! Options are written `-x value`, `--long value` or `--long=value`;
+    //! flags without value may be grouped, as in `-vv`, and `--` ends the
+    //! options.
+
+    use crate::config::config::{Config, DeviceConfig, Value};
+    use crate::interface::interface::{interface_by_name, select};
+    use crate::netns::netns::with_netns;
+    use crate::{DeviceHandle, DeviceHandleBuilder, DevicePool, Direction, Filter, Interface, Precision, RlinkError, Stack};
+    use std::collections::HashMap;
+    use std::env;
+    use std::fmt::Write;
+    use std::process;
+    use std::str::FromStr;
+
+    /// Read timeout of the devices, in milliseconds, by default.
+    pub const DEFAULT_TIMEOUT: i32 = 50;
+    /// Bytes captured of each packet, by default.
+    pub const DEFAULT_SNAPLEN: i32 = 65535;
+
+    /// How the devices of a tool are opened.
+    #[derive(Clone, Debug)]
+    pub struct CaptureOptions {
+        /// Read timeout, in milliseconds
+        pub timeout: i32,
+        /// Deliver packets as soon as they arrive, without buffering
+        pub immediate: bool,
+        pub promisc: bool,
+        pub snaplen: i32,
+        /// Bytes of the kernel capture buffer, or `None` for the default
+        pub buffer_size: Option<i32>,
+        /// Timestamp packets to the nanosecond
+        pub nanosecond: bool,
+        /// Whether to compute the FCS of sent frames and check it on
+        /// received ones, or `None` to leave the choice to the tool
+        pub checksum: Option<bool>,
+        /// Filter evaluated in user space on received packets
+        pub filter: Option<Filter>,
+        /// Direction of capture, or `None` for both on single devices and
+        /// `Direction::In` on pools
+        pub direction: Option<Direction>,
+    }
+
+    impl Default for CaptureOptions {
+        fn default() -> Self {
+            CaptureOptions {
+                timeout: DEFAULT_TIMEOUT,
+                immediate: false,
+                promisc: false,
+                snaplen: DEFAULT_SNAPLEN,
+                buffer_size: None,
+                nanosecond: false,
+                checksum: None,
+                filter: None,
+                direction: None,
+            }
+        }
+    }
+
+    impl CaptureOptions {
+        /// A builder of a device with these options. The user space filter
+        /// is left to the caller.
+        pub fn builder(&self, name: &str) -> DeviceHandleBuilder {
+            let mut builder = DeviceHandle::builder(name)
+                .timeout(self.timeout)
+                .immediate(self.immediate)
+                .promisc(self.promisc)
+                .snaplen(self.snaplen);
+            if let Some(bytes) = self.buffer_size {
+                builder = builder.buffer_size(bytes);
+            }
+            if self.nanosecond {
+                builder = builder.precision(Precision::Nano);
+            }
+            builder
+        }
+
+        /// Open a device with these options, in network namespace `netns`
+        /// if given.
+        pub fn open(&self, name: &str, netns: Option<&str>) -> Result<DeviceHandle, RlinkError> {
+            let mut handle = self.open_unfiltered(name, netns)?;
+            handle.set_filter(self.filter.clone());
+            Ok(handle)
+        }
+
+        fn open_unfiltered(&self, name: &str, netns: Option<&str>) -> Result<DeviceHandle, RlinkError> {
+            let mut builder = self.builder(name);
+            if let Some(netns) = netns {
+                builder = builder.netns(netns);
+            }
+            let handle = builder.open()?;
+            if let Some(direction) = self.direction {
+                handle.direction(direction)?;
+            }
+            Ok(handle)
+        }
+
+        /// Open a pool on `(netns, name)` pairs with these options. The
+        /// filter is set on the pool, discarding packets before they are
+        /// queued.
+        pub fn open_pool(&self, devices: Vec<(Option<String>, String)>) -> Result<DevicePool, RlinkError> {
+            let mut options = self.clone();
+            options.direction.get_or_insert(Direction::In);
+            let pool = DevicePool::with_netns_opener(devices,
+                move |netns, name| options.open_unfiltered(name, netns))?;
+            pool.set_filter(self.filter.clone());
+            Ok(pool)
+        }
+
+        /// Open a stack on `(netns, name)` pairs with these options, as
+        /// `Stack::open()` does.
+        pub fn open_stack(&self, devices: Vec<(Option<String>, String)>) -> Result<Stack, RlinkError> {
+            let stack = Stack::new();
+            for (netns, name) in devices.iter() {
+                let iface = interface(name, netns.as_deref())?;
+                let mut builder = self.builder(name).immediate(true);
+                if let Some(netns) = netns {
+                    builder = builder.netns(netns);
+                }
+                stack.add_interface(&iface, builder.open()?)?;
+            }
+            stack.attach(self.open_pool(devices)?);
+            Ok(stack)
+        }
+    }
+
+    /// An interface of the inventory of network namespace `netns`, or of
+    /// the current one.
+    fn interface(name: &str, netns: Option<&str>) -> Result<Interface, RlinkError> {
+        match netns {
+            Some(netns) => with_netns(netns, || interface_by_name(name)),
+            None => interface_by_name(name),
+        }
+    }
+
+    struct Opt {
+        short: Option<char>,
+        long: &'static str,
+        /// Name of the value, for options taking one
+        value: Option<&'static str>,
+        help: &'static str,
+    }
+
+    /// A tool and the options it accepts.
+    pub struct Command {
+        name: &'static str,
+        /// Positional arguments, as shown in the usage line
+        synopsis: &'static str,
+        about: &'static str,
+        options: Vec<Opt>,
+        /// Number of common options, which come first
+        common: usize,
+    }
+
+    impl Command {
+        /// A tool accepting the common options.
+        pub fn new(name: &'static str, synopsis: &'static str, about: &'static str) -> Command {
+            let mut command = Command { name, synopsis, about, options: Vec::new(), common: 0 }
+                .option(None, "timeout", "ms", "read timeout of the devices (default 50)")
+                .flag(None, "immediate", "deliver packets as soon as they arrive")
+                .flag(None, "promisc", "capture in promiscuous mode")
+                .option(None, "snaplen", "bytes", "bytes captured of each packet (default 65535)")
+                .option(None, "buffer-size", "bytes", "bytes of the kernel capture buffer")
+                .flag(None, "nano", "timestamp packets to the nanosecond")
+                .flag(None, "checksum", "compute the FCS of sent frames, and check it on received ones")
+                .flag(None, "no-checksum", "send frames with a zero FCS, and accept any")
+                .option(Some('f'), "filter", "expr", "only handle packets matching expr, e.g. \"udp port 53\"")
+                .flag(Some('v'), "verbose", "print more, may be repeated")
+                .flag(Some('q'), "quiet", "print only what the tool is for")
+                .option(Some('c'), "config", "file", "read devices and settings from file")
+                .flag(Some('h'), "help", "print this help");
+            command.common = command.options.len();
+            command
+        }
+
+        /// Accept an option taking a value.
+        pub fn option(mut self, short: Option<char>, long: &'static str, value: &'static str, help: &'static str)
+            -> Command {
+            self.options.push(Opt { short, long, value: Some(value), help });
+            self
+        }
+
+        /// Accept an option without value.
+        pub fn flag(mut self, short: Option<char>, long: &'static str, help: &'static str) -> Command {
+            self.options.push(Opt { short, long, value: None, help });
+            self
+        }
+
+        pub fn usage(&self) -> String {
+            format!("Usage: {} [options] {}", self.name, self.synopsis)
+        }
+
+        pub fn help(&self) -> String {
+            let mut help = format!("{}\n\n{}\n\nOptions:\n", self.usage(), self.about);
+            let width = self.options.iter().map(|opt| opt.long.len() + opt.value.map_or(0, |v| v.len() + 3)).max()
+                .unwrap_or(0);
+            // Tool options first, then the common ones
+            for opt in self.options[self.common..].iter().chain(&self.options[..self.common]) {
+                let short = opt.short.map_or("    ".to_string(), |c| format!("-{}, ", c));
+                let long = match opt.value {
+                    Some(value) => format!("{} <{}>", opt.long, value),
+                    None => opt.long.to_string(),
+                };
+                writeln!(help, "  {}--{:<width$}  {}", short, long, opt.help, width = width).unwrap();
+            }
+            help
+        }
+
+        fn find_long(&self, long: &str) -> Result<&Opt, RlinkError> {
+            self.options.iter().find(|opt| opt.long == long)
+                .ok_or(RlinkError::InvalidArgument(format!("unknown option --{}", long)))
+        }
+
+        fn find_short(&self, short: char) -> Result<&Opt, RlinkError> {
+            self.options.iter().find(|opt| opt.short == Some(short))
+                .ok_or(RlinkError::InvalidArgument(format!("unknown option -{}", short)))
+        }
+
+        /// Parse `args`, the program name excluded, and read the
+        /// configuration file given with `-c`.
+        pub fn parse<I: IntoIterator<Item = String>>(&self, args: I) -> Result<Matches, RlinkError> {
+            let mut values: HashMap<&'static str, Vec<String>> = HashMap::new();
+            let mut positionals = Vec::new();
+            let mut args = args.into_iter();
+            let missing = |opt: &Opt| RlinkError::InvalidArgument(format!("missing {} after --{}", opt.value.unwrap(), opt.long));
+            while let Some(arg) = args.next() {
+                if arg == "--" {
+                    positionals.extend(args.by_ref());
+                }
+                else if let Some(long) = arg.strip_prefix("--") {
+                    let (long, value) = match long.split_once('=') {
+                        Some((long, value)) => (long, Some(value.to_string())),
+                        None => (long, None),
+                    };
+                    let opt = self.find_long(long)?;
+                    let value = match (opt.value, value) {
+                        (Some(_), Some(value)) => value,
+                        (Some(_), None) => args.next().ok_or_else(|| missing(opt))?,
+                        (None, Some(_)) => return Err(RlinkError::InvalidArgument(format!("--{} takes no value", long))),
+                        (None, None) => "true".to_string(),
+                    };
+                    values.entry(opt.long).or_default().push(value);
+                }
+                else if arg.len() > 1 && arg.starts_with('-') {
+                    let shorts: Vec<char> = arg[1..].chars().collect();
+                    for (i, short) in shorts.iter().enumerate() {
+                        let opt = self.find_short(*short)?;
+                        if opt.value.is_none() {
+                            values.entry(opt.long).or_default().push("true".to_string());
+                        }
+                        else if i + 1 == shorts.len() {
+                            let value = args.next().ok_or_else(|| missing(opt))?;
+                            values.entry(opt.long).or_default().push(value);
+                        }
+                        else {
+                            return Err(RlinkError::InvalidArgument(format!("-{} takes a value", short)));
+                        }
+                    }
+                }
+                else {
+                    positionals.push(arg);
+                }
+            }
+            let config = match values.get("config") {
+                Some(paths) => Some(Config::load(paths.last().unwrap())?),
+                None => None,
+            };
+            Ok(Matches { tool: self.name, positionals, values, config })
+        }
+
+        /// Parse the arguments of the process. Prints the help and exits
+        /// on `--help`, and prints the usage and exits on errors.
+        pub fn parse_env(&self) -> Matches {
+            match self.parse(env::args().skip(1)) {
+                Ok(matches) if matches.flag("help") => {
+                    print!("{}", self.help());
+                    process::exit(0);
+                }
+                Ok(matches) => matches,
+                Err(e) => {
+                    println!("{}: {}\n{}\nTry '{} --help' for more information.", self.name, e, self.usage(), self.name);
+                    process::exit(2);
+                }
+            }
+        }
+    }
+
+    /// The options and arguments given to a tool.
+    pub struct Matches {
+        tool: &'static str,
+        pub positionals: Vec<String>,
+        values: HashMap<&'static str, Vec<String>>,
+        pub config: Option<Config>,
+    }
+
+    impl Matches {
+        fn setting(&self, long: &str) -> Option<&Value> {
+            self.config.as_ref().and_then(|config| config.setting(self.tool, long))
+        }
+
+        /// The last value of an option, or its setting in the configuration.
+        pub fn value(&self, long: &str) -> Option<String> {
+            match self.values.get(long) {
+                Some(values) => values.last().cloned(),
+                None => self.setting(long).map(|value| value.to_string()),
+            }
+        }
+
+        /// All values of a repeatable option. An array in the
+        /// configuration gives one value per item.
+        pub fn values(&self, long: &str) -> Vec<String> {
+            match (self.values.get(long), self.setting(long)) {
+                (Some(values), _) => values.clone(),
+                (None, Some(Value::Array(items))) => items.iter().map(|item| item.to_string()).collect(),
+                (None, Some(value)) => vec![value.to_string()],
+                (None, None) => Vec::new(),
+            }
+        }
+
+        /// Whether a flag is given, or set to `true` in the configuration.
+        pub fn flag(&self, long: &str) -> bool {
+            self.values.contains_key(long) || self.setting(long) == Some(&Value::Boolean(true))
+        }
+
+        /// The value of an option, parsed.
+        pub fn parsed<T: FromStr>(&self, long: &str) -> Result<Option<T>, RlinkError> {
+            match self.value(long) {
+                Some(value) => value.parse().map(Some)
+                    .map_err(|_| RlinkError::InvalidArgument(format!("invalid --{} {:?}", long, value))),
+                None => Ok(None),
+            }
+        }
+
+        /// 0 with `-q`, 1 by default, and 1 more for each `-v`. In the
+        /// configuration, `verbose` holds the level itself.
+        pub fn verbosity(&self) -> u8 {
+            if self.flag("quiet") {
+                return 0;
+            }
+            match (self.values.get("verbose"), self.setting("verbose")) {
+                (Some(flags), _) => 1 + flags.len() as u8,
+                (None, Some(Value::Integer(level))) => (*level).clamp(0, 255) as u8,
+                (None, Some(Value::Boolean(true))) => 2,
+                _ => 1,
+            }
+        }
+
+        /// The devices given as arguments, or else those of the tool's
+        /// role in the configuration.
+        pub fn devices(&self) -> Vec<DeviceConfig> {
+            if !self.positionals.is_empty() {
+                return self.positionals.iter().map(|name| DeviceConfig {
+                    name: name.clone(),
+                    role: self.tool.to_string(),
+                    netns: None,
+                    settings: Default::default(),
+                }).collect();
+            }
+            self.config.as_ref()
+                .map(|config| config.devices(self.tool).cloned().collect())
+                .unwrap_or_default()
+        }
+
+        /// Like `devices()`, with their interfaces looked up in their
+        /// network namespace. Arguments are glob patterns, e.g. `veth*`.
+        pub fn interfaces(&self) -> Result<Vec<(Option<String>, Interface)>, RlinkError> {
+            let mut ifaces = Vec::new();
+            for pattern in self.positionals.iter() {
+                let selected = select(pattern)?;
+                if selected.is_empty() {
+                    return Err(RlinkError::InvalidArgument(format!("no device matches {}", pattern)));
+                }
+                ifaces.extend(selected.into_iter().map(|iface| (None, iface)));
+            }
+            if self.positionals.is_empty() {
+                for device in self.devices() {
+                    let iface = interface(&device.name, device.netns.as_deref())?;
+                    ifaces.push((device.netns, iface));
+                }
+            }
+            Ok(ifaces)
+        }
+
+        pub fn capture(&self) -> Result<CaptureOptions, RlinkError> {
+            let defaults = CaptureOptions::default();
+            let checksum = match (self.values.contains_key("checksum"), self.values.contains_key("no-checksum")) {
+                (true, true) => return Err(RlinkError::InvalidArgument(
+                    "--checksum and --no-checksum are exclusive".to_string())),
+                (true, false) => Some(true),
+                (false, true) => Some(false),
+                (false, false) => match self.setting("checksum") {
+                    Some(Value::Boolean(checksum)) => Some(*checksum),
+                    Some(value) => return Err(RlinkError::InvalidArgument(format!("invalid checksum {}", value))),
+                    None => None,
+                },
+            };
+            let filter = match self.value("filter") {
+                Some(filter) => Some(filter.parse::<Filter>()?),
+                None => None,
+            };
+            Ok(CaptureOptions {
+                timeout: self.parsed("timeout")?.unwrap_or(defaults.timeout),
+                immediate: self.flag("immediate"),
+                promisc: self.flag("promisc"),
+                snaplen: self.parsed("snaplen")?.unwrap_or(defaults.snaplen),
+                buffer_size: self.parsed("buffer-size")?,
+                nanosecond: self.flag("nano"),
+                checksum,
+                filter,
+                direction: None,
+            })
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use std::fs;
+
+        fn command() -> Command {
+            Command::new("hub", "[dev name..]", "Forward frames between devices.")
+                .option(Some('m'), "metrics", "addr", "serve metrics on addr")
+                .option(Some('d'), "dst", "mac", "destination, may be repeated")
+                .flag(Some('x'), "exclusive", "an extra flag")
+        }
+
+        fn parse(args: &[&str]) -> Result<Matches, RlinkError> {
+            command().parse(args.iter().map(|arg| arg.to_string()))
+        }
+
+        fn parse_err(args: &[&str]) -> String {
+            match parse(args) {
+                Err(RlinkError::InvalidArgument(why)) => why,
+                Err(e) => panic!("{:?}: {}", args, e),
+                Ok(_) => panic!("{:?} parsed", args),
+            }
+        }
+
+        #[test]
+        fn long_options() {
+            let matches = parse(&["--timeout=10", "--snaplen", "128", "--immediate", "--metrics=a=b", "eth0"]).unwrap();
+            let capture = matches.capture().unwrap();
+            assert_eq!((capture.timeout, capture.snaplen, capture.immediate), (10, 128, true));
+            assert!(!capture.promisc);
+            assert_eq!(matches.value("metrics").as_deref(), Some("a=b"));
+            assert_eq!(matches.positionals, ["eth0"]);
+            assert_eq!(matches.devices()[0].role, "hub");
+
+            let capture = parse(&[]).unwrap().capture().unwrap();
+            assert_eq!((capture.timeout, capture.snaplen, capture.checksum), (DEFAULT_TIMEOUT, DEFAULT_SNAPLEN, None));
+            assert_eq!(parse(&["--timeout", "1", "--timeout", "2"]).unwrap().value("timeout").as_deref(), Some("2"));
+        }
+
+        #[test]
+        fn short_options() {
+            let matches = parse(&["-vv", "-xvf", "udp port 53", "-d", "a", "-d", "b"]).unwrap();
+            assert_eq!(matches.verbosity(), 4);
+            assert!(matches.flag("exclusive"));
+            assert_eq!(matches.values("dst"), ["a", "b"]);
+            assert_eq!(matches.capture().unwrap().filter, Some("udp port 53".parse().unwrap()));
+            assert_eq!(parse(&["-vq"]).unwrap().verbosity(), 0);
+            assert_eq!(parse(&[]).unwrap().verbosity(), 1);
+            // A lone dash is an argument
+            assert_eq!(parse(&["-"]).unwrap().positionals, ["-"]);
+        }
+
+        #[test]
+        fn end_of_options() {
+            let matches = parse(&["-x", "--", "-v", "--timeout=5", "eth0"]).unwrap();
+            assert!(matches.flag("exclusive"));
+            assert_eq!(matches.positionals, ["-v", "--timeout=5", "eth0"]);
+            assert_eq!(matches.verbosity(), 1);
+            assert_eq!(matches.value("timeout"), None);
+        }
+
+        #[test]
+        fn errors() {
+            assert_eq!(parse_err(&["--bogus"]), "unknown option --bogus");
+            assert_eq!(parse_err(&["-b"]), "unknown option -b");
+            assert_eq!(parse_err(&["-vb"]), "unknown option -b");
+            assert_eq!(parse_err(&["--immediate=yes"]), "--immediate takes no value");
+            assert_eq!(parse_err(&["--snaplen"]), "missing bytes after --snaplen");
+            assert_eq!(parse_err(&["-m"]), "missing addr after --metrics");
+            assert_eq!(parse_err(&["-fv", "arp"]), "-f takes a value");
+            let capture_err = |args: &[&str]| match parse(args).unwrap().capture() {
+                Err(e) => e.to_string(),
+                Ok(_) => panic!("{:?} accepted", args),
+            };
+            assert!(capture_err(&["--timeout", "soon"]).contains("invalid --timeout \"soon\""));
+            assert!(capture_err(&["--checksum", "--no-checksum"]).contains("exclusive"));
+            assert!(capture_err(&["-f", "udp port"]).contains("missing port"));
+        }
+
+        #[test]
+        fn config_file() {
+            let path = env::temp_dir().join(format!("rlink-cli-test-{}.toml", process::id()));
+            fs::write(&path, r#"
+                [defaults]
+                timeout = 20
+                promisc = true
+                checksum = true
+                verbose = 3
+
+                [hub]
+                metrics = "127.0.0.1:9100"
+                filter = "arp"
+                dst = ["a", "b"]
+
+                [[device]]
+                name = "veth1-2"
+                role = "hub"
+                netns = "ns1"
+
+                [[device]]
+                name = "veth0"
+                role = "sender"
+            "#).unwrap();
+            let path = path.to_str().unwrap();
+
+            let matches = parse(&["-c", path]).unwrap();
+            let capture = matches.capture().unwrap();
+            assert_eq!((capture.timeout, capture.promisc, capture.checksum), (20, true, Some(true)));
+            assert_eq!(capture.filter, Some("arp".parse().unwrap()));
+            assert_eq!(matches.value("metrics").as_deref(), Some("127.0.0.1:9100"));
+            assert_eq!(matches.values("dst"), ["a", "b"]);
+            assert_eq!(matches.verbosity(), 3);
+            let devices = matches.devices();
+            assert_eq!(devices.len(), 1);
+            assert_eq!((devices[0].name.as_str(), devices[0].netns.as_deref()), ("veth1-2", Some("ns1")));
+
+            // The command line wins over the file
+            let matches = parse(&["--timeout=30", "-m", "0.0.0.0:1", "--no-checksum", "-f", "ip", "-d", "c", "-v",
+                "-c", path, "eth1"]).unwrap();
+            let capture = matches.capture().unwrap();
+            assert_eq!((capture.timeout, capture.promisc, capture.checksum), (30, true, Some(false)));
+            assert_eq!(capture.filter, Some("ip".parse().unwrap()));
+            assert_eq!(matches.value("metrics").as_deref(), Some("0.0.0.0:1"));
+            assert_eq!(matches.values("dst"), ["c"]);
+            assert_eq!(matches.verbosity(), 2);
+            assert_eq!(matches.devices().iter().map(|device| device.name.as_str()).collect::<Vec<_>>(), ["eth1"]);
+            fs::remove_file(path).unwrap();
+
+            assert!(matches!(parse(&["-c", "/nonexistent/rlink.toml"]), Err(RlinkError::Io(_))));
+        }
+    }
+}
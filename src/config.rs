@@ -0,0 +1,425 @@
+#![allow(dead_code)]
+#![allow(unused)]
+
+pub mod config {
+    //! Node configuration files, in a subset of TOML: tables, arrays of
+    //! tables, and keys holding strings, integers, floats, booleans or
+    //! single-line arrays of these. A file describes the devices of a node
+    //! and their roles, and the settings of the tools run on it:
+    //!
+    //! ```toml
+    //! # Settings shared by all tools
+    //! [defaults]
+    //! timeout = 50
+    //! promisc = true
+    //!
+    //! # Settings of one tool, named after its long options
+    //! [hub]
+    //! metrics = "127.0.0.1:9100"
+    //!
+    //! [[device]]
+    //! name = "veth1-2"
+    //! role = "hub"
+    //!
+    //! [[device]]
+    //! name = "veth1-3"
+    //! role = "hub"
+    //! netns = "ns1"
+    //! ```
+
+    use crate::RlinkError;
+    use std::collections::BTreeMap;
+    use std::fmt;
+    use std::fs;
+    use std::path::Path;
+
+    /// A value of a key.
+    #[derive(Clone, Debug, PartialEq)]
+    pub enum Value {
+        String(String),
+        Integer(i64),
+        Float(f64),
+        Boolean(bool),
+        Array(Vec<Value>),
+    }
+
+    impl fmt::Display for Value {
+        /// Strings unquoted, and arrays as their comma-separated items.
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            match self {
+                Value::String(s) => write!(f, "{}", s),
+                Value::Integer(i) => write!(f, "{}", i),
+                Value::Float(x) => write!(f, "{}", x),
+                Value::Boolean(b) => write!(f, "{}", b),
+                Value::Array(items) => {
+                    let items: Vec<String> = items.iter().map(|item| item.to_string()).collect();
+                    write!(f, "{}", items.join(","))
+                }
+            }
+        }
+    }
+
+    /// The keys of a table.
+    pub type Table = BTreeMap<String, Value>;
+
+    /// A device of the node, from a `[[device]]` table.
+    #[derive(Clone, Debug, PartialEq)]
+    pub struct DeviceConfig {
+        pub name: String,
+        /// The tool the device is given to, e.g. `"hub"`
+        pub role: String,
+        /// Network namespace of the device, if not the current one
+        pub netns: Option<String>,
+        /// Other keys, for the tool to interpret
+        pub settings: Table,
+    }
+
+    impl DeviceConfig {
+        /// A string setting of the device.
+        pub fn get(&self, key: &str) -> Option<String> {
+            self.settings.get(key).map(|value| value.to_string())
+        }
+    }
+
+    /// A parsed configuration file.
+    #[derive(Clone, Debug, Default, PartialEq)]
+    pub struct Config {
+        /// Tables by name, `[defaults]` included
+        pub tables: BTreeMap<String, Table>,
+        pub devices: Vec<DeviceConfig>,
+    }
+
+    impl Config {
+        pub fn load<P: AsRef<Path>>(path: P) -> Result<Config, RlinkError> {
+            let text = fs::read_to_string(path.as_ref())?;
+            Config::parse(&text).map_err(|e| match e {
+                RlinkError::InvalidConfig(why) =>
+                    RlinkError::InvalidConfig(format!("{}: {}", path.as_ref().display(), why)),
+                e => e,
+            })
+        }
+
+        pub fn parse(text: &str) -> Result<Config, RlinkError> {
+            let mut tables: BTreeMap<String, Table> = BTreeMap::new();
+            let mut devices: Vec<Table> = Vec::new();
+            // The table keys go to: a named table, or the last device
+            let mut current: Option<Option<String>> = None;
+            for (index, line) in text.lines().enumerate() {
+                let error = |why: &str| RlinkError::InvalidConfig(format!("line {}: {}", index + 1, why));
+                let mut parser = Parser { rest: line.trim() };
+                // Table names hold no '#', anything after one is a comment
+                let header = parser.rest.split('#').next().unwrap().trim_end();
+                if header.starts_with("[[") {
+                    match header.strip_prefix("[[").and_then(|rest| rest.strip_suffix("]]")) {
+                        Some(name) if name.trim() == "device" => {
+                            devices.push(Table::new());
+                            current = Some(None);
+                        }
+                        Some(name) => return Err(error(&format!("unknown array of tables {:?}", name.trim()))),
+                        None => return Err(error("unterminated table header")),
+                    }
+                    continue;
+                }
+                if header.starts_with('[') {
+                    match header.strip_prefix('[').and_then(|rest| rest.strip_suffix(']')) {
+                        Some(name) if is_bare_key(name.trim()) => {
+                            let name = name.trim().to_string();
+                            if tables.contains_key(&name) {
+                                return Err(error(&format!("table {:?} defined twice", name)));
+                            }
+                            tables.insert(name.clone(), Table::new());
+                            current = Some(Some(name));
+                        }
+                        _ => return Err(error("invalid table header")),
+                    }
+                    continue;
+                }
+                parser.skip_comment();
+                if parser.rest.is_empty() {
+                    continue;
+                }
+                let (key, value) = parser.key_value().map_err(|why| error(&why))?;
+                let table = match &current {
+                    Some(Some(name)) => tables.get_mut(name).unwrap(),
+                    Some(None) => devices.last_mut().unwrap(),
+                    None => return Err(error("key outside of a table")),
+                };
+                if table.insert(key.clone(), value).is_some() {
+                    return Err(error(&format!("key {:?} defined twice", key)));
+                }
+            }
+
+            let devices = devices.into_iter().enumerate().map(|(index, mut table)| {
+                let mut take = |key: &str| match table.remove(key) {
+                    Some(Value::String(s)) => Ok(Some(s)),
+                    Some(_) => Err(RlinkError::InvalidConfig(format!("device {}: {} is not a string", index + 1, key))),
+                    None => Ok(None),
+                };
+                let name = take("name")?;
+                let role = take("role")?;
+                let netns = take("netns")?;
+                match (name, role) {
+                    (Some(name), Some(role)) => Ok(DeviceConfig { name, role, netns, settings: table }),
+                    _ => Err(RlinkError::InvalidConfig(format!("device {}: name and role are required", index + 1))),
+                }
+            }).collect::<Result<Vec<_>, _>>()?;
+            Ok(Config { tables, devices })
+        }
+
+        /// The devices given to a tool.
+        pub fn devices<'a>(&'a self, role: &'a str) -> impl Iterator<Item = &'a DeviceConfig> + 'a {
+            self.devices.iter().filter(move |device| device.role == role)
+        }
+
+        /// A key of a tool's table, or of `[defaults]`.
+        pub fn setting(&self, tool: &str, key: &str) -> Option<&Value> {
+            self.tables.get(tool).and_then(|table| table.get(key))
+                .or_else(|| self.tables.get("defaults").and_then(|table| table.get(key)))
+        }
+    }
+
+    fn is_bare_key(s: &str) -> bool {
+        !s.is_empty() && s.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+    }
+
+    struct Parser<'a> {
+        rest: &'a str,
+    }
+
+    impl<'a> Parser<'a> {
+        fn skip_whitespace(&mut self) {
+            self.rest = self.rest.trim_start();
+        }
+
+        fn skip_comment(&mut self) {
+            self.skip_whitespace();
+            if self.rest.starts_with('#') {
+                self.rest = "";
+            }
+        }
+
+        fn key_value(&mut self) -> Result<(String, Value), String> {
+            let end = self.rest.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '-'))
+                .unwrap_or(self.rest.len());
+            let key = &self.rest[..end];
+            if key.is_empty() {
+                return Err("expected a key".to_string());
+            }
+            self.rest = &self.rest[end..];
+            self.skip_whitespace();
+            self.rest = self.rest.strip_prefix('=').ok_or(format!("expected = after {:?}", key))?;
+            let value = self.value()?;
+            self.skip_comment();
+            if !self.rest.is_empty() {
+                return Err(format!("unexpected {:?}", self.rest));
+            }
+            Ok((key.to_string(), value))
+        }
+
+        fn value(&mut self) -> Result<Value, String> {
+            self.skip_whitespace();
+            if let Some(rest) = self.rest.strip_prefix('"') {
+                let mut value = String::new();
+                let mut chars = rest.char_indices();
+                while let Some((i, c)) = chars.next() {
+                    match c {
+                        '"' => {
+                            self.rest = &rest[i + 1..];
+                            return Ok(Value::String(value));
+                        }
+                        '\\' => match chars.next().map(|(_, c)| c) {
+                            Some('"') => value.push('"'),
+                            Some('\\') => value.push('\\'),
+                            Some('n') => value.push('\n'),
+                            Some('t') => value.push('\t'),
+                            _ => return Err("invalid escape in string".to_string()),
+                        },
+                        c => value.push(c),
+                    }
+                }
+                return Err("unterminated string".to_string());
+            }
+            if let Some(rest) = self.rest.strip_prefix('\'') {
+                let end = rest.find('\'').ok_or("unterminated string")?;
+                self.rest = &rest[end + 1..];
+                return Ok(Value::String(rest[..end].to_string()));
+            }
+            if let Some(rest) = self.rest.strip_prefix('[') {
+                self.rest = rest;
+                let mut items = Vec::new();
+                loop {
+                    self.skip_whitespace();
+                    if let Some(rest) = self.rest.strip_prefix(']') {
+                        self.rest = rest;
+                        return Ok(Value::Array(items));
+                    }
+                    items.push(self.value()?);
+                    self.skip_whitespace();
+                    match self.rest.strip_prefix(',') {
+                        Some(rest) => self.rest = rest,
+                        None if self.rest.starts_with(']') => {}
+                        None => return Err("expected , or ] in array".to_string()),
+                    }
+                }
+            }
+            let end = self.rest.find(|c: char| c.is_whitespace() || c == ',' || c == ']' || c == '#')
+                .unwrap_or(self.rest.len());
+            let token = &self.rest[..end];
+            self.rest = &self.rest[end..];
+            let digits = token.replace('_', "");
+            match token {
+                "true" => Ok(Value::Boolean(true)),
+                "false" => Ok(Value::Boolean(false)),
+                _ if digits.starts_with("0x") => i64::from_str_radix(&digits[2..], 16).map(Value::Integer)
+                    .map_err(|_| format!("invalid value {:?}", token)),
+                _ => digits.parse::<i64>().map(Value::Integer)
+                    .or_else(|_| digits.parse::<f64>().map(Value::Float))
+                    .map_err(|_| format!("invalid value {:?}", token)),
+            }
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        fn parse_err(text: &str) -> String {
+            match Config::parse(text) {
+                Err(RlinkError::InvalidConfig(why)) => why,
+                other => panic!("{:?} parsed as {:?}", text, other),
+            }
+        }
+
+        #[test]
+        fn node() {
+            let config = Config::parse(r#"
+                # Settings shared by all tools
+                [defaults]
+                timeout = 50   # milliseconds
+                promisc = true
+
+                [hub]
+                metrics = "127.0.0.1:9100"
+                timeout = 10
+
+                [[device]]
+                name = "veth1-2"
+                role = "hub"
+
+                [[ device ]]  # spaces are allowed
+                name = "veth1-3"
+                role = "hub"
+                netns = "ns1"
+                vlan = 7
+
+                [[device]]
+                name = "veth0"
+                role = "sender"
+            "#).unwrap();
+            assert_eq!(config.tables.len(), 2);
+            assert_eq!(config.setting("hub", "timeout"), Some(&Value::Integer(10)));
+            assert_eq!(config.setting("sender", "timeout"), Some(&Value::Integer(50)));
+            assert_eq!(config.setting("hub", "promisc"), Some(&Value::Boolean(true)));
+            assert_eq!(config.setting("sender", "metrics"), None);
+
+            let hubs: Vec<_> = config.devices("hub").collect();
+            assert_eq!(hubs.len(), 2);
+            assert_eq!((hubs[0].name.as_str(), hubs[0].netns.as_deref()), ("veth1-2", None));
+            assert_eq!((hubs[1].name.as_str(), hubs[1].netns.as_deref()), ("veth1-3", Some("ns1")));
+            assert_eq!(hubs[1].get("vlan").as_deref(), Some("7"));
+            assert!(!hubs[1].settings.contains_key("name"));
+            assert_eq!(config.devices("sender").count(), 1);
+            assert_eq!(config.devices("gatherer").count(), 0);
+        }
+
+        #[test]
+        fn comments() {
+            let config = Config::parse("# a comment\n\n  # indented\n[t] # after a header\n\
+                a = \"x # y\" # after a value\nb = 'z#' #\n").unwrap();
+            assert_eq!(config.tables["t"]["a"], Value::String("x # y".to_string()));
+            assert_eq!(config.tables["t"]["b"], Value::String("z#".to_string()));
+            assert_eq!(Config::parse("").unwrap(), Config::default());
+        }
+
+        #[test]
+        fn values() {
+            let config = Config::parse(r#"
+                [t]
+                int = -42
+                hex = 0x88B5
+                big = 1_000_000
+                float = 2.5
+                yes = true
+                no = false
+                basic = "a\"b\\c\n\td"
+                literal = 'C:\path\n'
+                empty = ""
+                ints = [1, 2, 3]
+                mixed = [ "a", 'b' , 3, ]
+                nested = [[1, 2], []]
+                none = []
+            "#).unwrap();
+            let t = &config.tables["t"];
+            assert_eq!(t["int"], Value::Integer(-42));
+            assert_eq!(t["hex"], Value::Integer(0x88B5));
+            assert_eq!(t["big"], Value::Integer(1_000_000));
+            assert_eq!(t["float"], Value::Float(2.5));
+            assert_eq!(t["yes"], Value::Boolean(true));
+            assert_eq!(t["no"], Value::Boolean(false));
+            assert_eq!(t["basic"], Value::String("a\"b\\c\n\td".to_string()));
+            assert_eq!(t["literal"], Value::String("C:\\path\\n".to_string()));
+            assert_eq!(t["empty"], Value::String(String::new()));
+            assert_eq!(t["ints"], Value::Array(vec![Value::Integer(1), Value::Integer(2), Value::Integer(3)]));
+            assert_eq!(t["mixed"], Value::Array(vec![
+                Value::String("a".to_string()), Value::String("b".to_string()), Value::Integer(3)]));
+            assert_eq!(t["nested"], Value::Array(vec![
+                Value::Array(vec![Value::Integer(1), Value::Integer(2)]), Value::Array(Vec::new())]));
+            assert_eq!(t["none"], Value::Array(Vec::new()));
+            assert_eq!(t["mixed"].to_string(), "a,b,3");
+            assert_eq!(t["basic"].to_string(), "a\"b\\c\n\td");
+        }
+
+        #[test]
+        fn errors() {
+            for (text, why) in [
+                ("a = 1", "line 1: key outside of a table"),
+                ("[t]\na = 1\na = 2", "line 3: key \"a\" defined twice"),
+                ("[t]\n[u]\n[t]", "line 3: table \"t\" defined twice"),
+                ("[t.u]", "line 1: invalid table header"),
+                ("[t", "line 1: invalid table header"),
+                ("[]", "line 1: invalid table header"),
+                ("[[device]", "line 1: unterminated table header"),
+                ("[[tool]]", "line 1: unknown array of tables \"tool\""),
+                ("[t]\n= 1", "line 2: expected a key"),
+                ("[t]\na 1", "line 2: expected = after \"a\""),
+                ("[t]\na = \"x", "line 2: unterminated string"),
+                ("[t]\na = 'x", "line 2: unterminated string"),
+                ("[t]\na = \"\\q\"", "line 2: invalid escape in string"),
+                ("[t]\na = [1, 2", "line 2: expected , or ] in array"),
+                ("[t]\na = [1 2]", "line 2: expected , or ] in array"),
+                ("[t]\na = yes", "line 2: invalid value \"yes\""),
+                ("[t]\na = 0xZZ", "line 2: invalid value \"0xZZ\""),
+                ("[t]\na = 1 2", "line 2: unexpected \"2\""),
+                ("[t]\na =", "line 2: invalid value \"\""),
+            ] {
+                assert_eq!(parse_err(text), why, "{:?}", text);
+            }
+        }
+
+        #[test]
+        fn devices() {
+            assert_eq!(parse_err("[[device]]\nrole = \"hub\""), "device 1: name and role are required");
+            assert_eq!(parse_err("[[device]]\nname = \"a\"\nrole = \"hub\"\n[[device]]\nname = \"b\""),
+                "device 2: name and role are required");
+            assert_eq!(parse_err("[[device]]\nname = 1\nrole = \"hub\""), "device 1: name is not a string");
+            assert_eq!(parse_err("[[device]]\nname = \"a\"\nrole = \"hub\"\nnetns = true"),
+                "device 1: netns is not a string");
+            assert_eq!(parse_err("[[device]]\nname = \"a\"\nname = \"b\"\nrole = \"hub\""),
+                "line 3: key \"name\" defined twice");
+            // Keys after a table go to the table, not to the last device
+            let config = Config::parse("[[device]]\nname = \"a\"\nrole = \"hub\"\n[hub]\nvlan = 7").unwrap();
+            assert!(config.devices[0].settings.is_empty());
+            assert_eq!(config.setting("hub", "vlan"), Some(&Value::Integer(7)));
+        }
+    }
+}
@@ -4,39 +4,299 @@
 pub mod device_pool {
     
     use pcap::{Device, Capture, Direction};
-    use std::sync::mpsc;
-    use std::sync::Arc;
+    use std::collections::VecDeque;
+    use std::sync::{Arc, Condvar, Mutex, RwLock};
     use std::thread;
-    use crate::{DeviceHandle, RlinkError, Packet, Raw};
+    use crate::{DeviceHandle, RlinkError, Packet, Raw, Interface, NetNs, Filter};
+    use crate::stats::stats::{self, DeviceCounters, DeviceStats};
     use std::error::Error;
 
+    /// Default capacity of the queue of captured packets.
+    pub const DEFAULT_CAPACITY: usize = 4096;
+
+    /// What a worker does with a captured packet when the queue is full.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub enum DropPolicy {
+        /// Wait for room, leaving packets to pile up (and eventually be
+        /// dropped) in the capture buffer of the device.
+        Block,
+        /// Drop the captured packet.
+        DropNewest,
+        /// Drop the oldest queued packet, whatever its device.
+        DropOldest,
+        /// Give each device an equal share of the queue, dropping packets
+        /// of devices exceeding theirs, so that a flood on one device does
+        /// not starve the others.
+        FairShare,
+    }
+
     /// A pool of DeviceHandles for group capturing. Internally contains 
     /// a thread pool for capturing packets from all DeviceHandles.
     pub struct DevicePool {
         /// Worker threads, one for each device.
         workers: Vec<thread::JoinHandle<()>>,
-        /// Receiver of packets from all devices.
-        rx: Option<mpsc::Receiver<Packet<Raw>>>,
+        /// Queue of packets from all devices, with the names of the
+        /// devices, for health reports.
+        monitor: PoolMonitor,
+    }
+
+    /// Health of a pool.
+    #[derive(Clone, Debug, PartialEq, Eq)]
+    pub struct PoolHealth {
+        pub workers: usize,
+        /// Workers still capturing; a worker stops when its device fails
+        /// to open or the pool is dropped.
+        pub live_workers: usize,
+        /// Packets captured but not yet returned by `select()`
+        pub queue_depth: usize,
+        pub capacity: usize,
+        /// Packets dropped by the queue, by device
+        pub drops: Vec<u64>,
+    }
+
+    struct QueueState {
+        /// Packets with the index of their device
+        packets: VecDeque<(usize, Packet<Raw>)>,
+        /// Packets queued per device
+        queued: Vec<usize>,
+        drops: Vec<u64>,
+        capacity: usize,
+        policy: DropPolicy,
+        live: usize,
+        /// Set when the pool is dropped
+        closed: bool,
+    }
+
+    /// Bounded queue between the workers and `select()`.
+    struct Queue {
+        state: Mutex<QueueState>,
+        not_empty: Condvar,
+        not_full: Condvar,
+        counters: Vec<Arc<DeviceCounters>>,
+        /// Packets not matching are discarded before being queued
+        filter: RwLock<Option<Filter>>,
+    }
+
+    impl Queue {
+        /// An empty queue for the devices of the given counters, each
+        /// counted live, with the default capacity and policy.
+        fn new(counters: Vec<Arc<DeviceCounters>>) -> Self {
+            Queue {
+                state: Mutex::new(QueueState {
+                    packets: VecDeque::new(),
+                    queued: vec![0; counters.len()],
+                    drops: vec![0; counters.len()],
+                    capacity: DEFAULT_CAPACITY,
+                    policy: DropPolicy::Block,
+                    live: counters.len(),
+                    closed: false,
+                }),
+                not_empty: Condvar::new(),
+                not_full: Condvar::new(),
+                counters,
+                filter: RwLock::new(None),
+            }
+        }
+
+        /// Queue a packet of device `device` according to the policy.
+        /// Returns false once the pool is gone.
+        fn push(&self, device: usize, packet: Packet<Raw>) -> bool {
+            if self.filter.read().unwrap().as_ref().is_some_and(|filter| !filter.matches(&packet)) {
+                return true;
+            }
+            let mut state = self.state.lock().unwrap();
+            loop {
+                if state.closed {
+                    return false;
+                }
+                let full = state.packets.len() >= state.capacity;
+                match state.policy {
+                    DropPolicy::Block if full => {
+                        state = self.not_full.wait(state).unwrap();
+                        continue;
+                    }
+                    DropPolicy::DropNewest if full => {
+                        self.drop_packet(&mut state, device);
+                        return true;
+                    }
+                    DropPolicy::DropOldest => {
+                        while state.packets.len() >= state.capacity.max(1) {
+                            let (oldest, _) = state.packets.pop_front().unwrap();
+                            state.queued[oldest] -= 1;
+                            self.drop_packet(&mut state, oldest);
+                        }
+                    }
+                    DropPolicy::FairShare => {
+                        let share = (state.capacity / state.queued.len()).max(1);
+                        if full || state.queued[device] >= share {
+                            self.drop_packet(&mut state, device);
+                            return true;
+                        }
+                    }
+                    _ => {}
+                }
+                break;
+            }
+            state.packets.push_back((device, packet));
+            state.queued[device] += 1;
+            self.not_empty.notify_one();
+            true
+        }
+
+        fn drop_packet(&self, state: &mut QueueState, device: usize) {
+            state.drops[device] += 1;
+            self.counters[device].record_queue_drop();
+        }
+
+        /// Take the oldest packet, waiting for one. Fails once the queue is
+        /// empty and no worker is left.
+        fn pop(&self) -> Result<Packet<Raw>, RlinkError> {
+            let mut state = self.state.lock().unwrap();
+            loop {
+                if let Some((device, packet)) = state.packets.pop_front() {
+                    state.queued[device] -= 1;
+                    self.not_full.notify_one();
+                    return Ok(packet);
+                }
+                if state.live == 0 {
+                    return Err(RlinkError::BrokenDevicePool);
+                }
+                state = self.not_empty.wait(state).unwrap();
+            }
+        }
+    }
+
+    /// A handle reporting on a pool from other threads.
+    #[derive(Clone)]
+    pub struct PoolMonitor {
+        names: Vec<String>,
+        queue: Arc<Queue>,
+    }
+
+    impl PoolMonitor {
+        pub fn names(&self) -> &[String] {
+            &self.names
+        }
+
+        pub fn health(&self) -> PoolHealth {
+            let state = self.queue.state.lock().unwrap();
+            PoolHealth {
+                workers: self.names.len(),
+                live_workers: state.live,
+                queue_depth: state.packets.len(),
+                capacity: state.capacity,
+                drops: state.drops.clone(),
+            }
+        }
+
+        /// See `DevicePool::stats()`.
+        pub fn stats(&self) -> Vec<DeviceStats> {
+            self.queue.counters.iter().map(|counters| counters.snapshot()).collect()
+        }
+    }
+
+    /// Capture only the packets received by a device, not those sent,
+    /// where the device supports it.
+    fn incoming(device: DeviceHandle) -> DeviceHandle {
+        let _ = device.direction(Direction::In);
+        device
+    }
+
+    /// Marks a worker as gone however it ends.
+    struct LiveGuard(Arc<Queue>);
+
+    impl Drop for LiveGuard {
+        fn drop(&mut self) {
+            self.0.state.lock().unwrap().live -= 1;
+            self.0.not_empty.notify_all();
+        }
     }
 
     impl DevicePool {
         /// Initiate a pool of handlers based on given names.
         /// Packets captured on these handlers are collected for centralized
         /// handling.
-        pub fn new(names: Vec<String>, timeout: i32) -> Result<DevicePool, Box<dyn Error>> {
-            let (tx, rx) = mpsc::channel();
-            let workers = names
+        pub fn new(names: Vec<String>, timeout: i32) -> Result<DevicePool, RlinkError> {
+            DevicePool::spawn(names
+                .into_iter()
+                .map(|name| (None, name.clone(), move || DeviceHandle::new(&name, timeout, false).map(incoming)))
+                .collect())
+        }
+
+        /// Initiate a pool of handlers on interfaces obtained from the 
+        /// interface inventory, e.g. all the results of `interface::select("veth*")`.
+        pub fn from_interfaces(ifaces: Vec<Interface>, timeout: i32) -> Result<DevicePool, RlinkError> {
+            DevicePool::spawn(ifaces
+                .into_iter()
+                .map(|iface| (None, iface.name.clone(), move || DeviceHandle::open(&iface, timeout, false).map(incoming)))
+                .collect())
+        }
+
+        /// Initiate a pool of handlers on `(netns name, device name)` pairs,
+        /// possibly spanning several network namespaces. Each worker thread
+        /// enters the namespace of its device before opening it.
+        pub fn new_in_netns(devices: Vec<(String, String)>, timeout: i32) -> Result<DevicePool, RlinkError> {
+            DevicePool::spawn(devices
+                .into_iter()
+                .map(|(netns, name)| (Some(netns.clone()), name.clone(),
+                    move || DeviceHandle::new_in_netns(&netns, &name, timeout, false).map(incoming)))
+                .collect())
+        }
+
+        /// Initiate a pool of handlers on given names, each opened by `open`
+        /// on its worker thread, e.g. to choose capture options. Unlike the
+        /// other constructors, which capture received packets only, packets
+        /// are captured in the direction `open` leaves the handle in, e.g.
+        /// `Direction::InOut` to see the packets sent by the devices too.
+        pub fn with_opener<F>(names: Vec<String>, open: F) -> Result<DevicePool, RlinkError>
+        where F: Fn(&str) -> Result<DeviceHandle, RlinkError> + Send + Sync + 'static {
+            DevicePool::with_netns_opener(names.into_iter().map(|name| (None, name)).collect(),
+                move |_, name| open(name))
+        }
+
+        /// Like `with_opener()`, on `(netns name, device name)` pairs. `open`
+        /// is given the pair of its device, so that devices of the same name
+        /// in different network namespaces are told apart.
+        pub fn with_netns_opener<F>(devices: Vec<(Option<String>, String)>, open: F) -> Result<DevicePool, RlinkError>
+        where F: Fn(Option<&str>, &str) -> Result<DeviceHandle, RlinkError> + Send + Sync + 'static {
+            let open = Arc::new(open);
+            DevicePool::spawn(devices
+                .into_iter()
+                .map(|(netns, name)| {
+                    let open = open.clone();
+                    (netns.clone(), name.clone(), move || open(netns.as_deref(), &name))
+                })
+                .collect())
+        }
+
+        /// Spawn one worker for each opener, given with the network namespace
+        /// and name of its device. The DeviceHandle is opened on the worker
+        /// thread itself.
+        fn spawn<F>(openers: Vec<(Option<String>, String, F)>) -> Result<DevicePool, RlinkError>
+        where F: FnOnce() -> Result<DeviceHandle, RlinkError> + Send + 'static {
+            let mut counters = Vec::new();
+            let mut names = Vec::new();
+            let openers: Vec<_> = openers.into_iter()
+                .map(|(netns, name, open)| {
+                    counters.push(stats::device_counters(netns.as_deref(), &name));
+                    names.push(name);
+                    open
+                })
+                .collect();
+            let queue = Arc::new(Queue::new(counters));
+            let workers = openers
                 .into_iter()
-                .map(|name| {
-                    let tx = tx.clone();
-                    let thread = thread::spawn(move || {
+                .enumerate()
+                .map(|(index, open)| {
+                    let guard = LiveGuard(queue.clone());
+                    thread::spawn(move || {
+                        let queue = guard.0.clone();
 
-                        let result = DeviceHandle::new(&name, timeout, false);
+                        let result = open();
                         if let Err(e) = result {
                             return;
                         }
                         let mut device = result.unwrap();
-                        device.direction(Direction::In);
                         loop {
                             // This is not so clean. next_packet() can be blocking when
                             // the device pool is already dropped, causing the worker
@@ -45,42 +305,214 @@ pub mod device_pool {
                             let packet = device.next_packet();
                             match packet {
                                 Ok(Some(packet)) => {
-                                    match tx.send(packet) {
-                                        Ok(_) => continue,
-                                        Err(e) => break,
+                                    if queue.push(index, packet) {
+                                        continue;
                                     }
+                                    break;
                                 }
                                 Ok(None) => continue,
                                 Err(e) => continue,
                             }
                         }
                         // println!("closing handle of {}\n", device.device.name);
-                    });
-
-                    thread
+                    })
                     })
                 .collect();
-            Ok(DevicePool{ workers, rx: Some(rx) })
+            Ok(DevicePool{ workers, monitor: PoolMonitor { names, queue } })
         }
 
         /// Block until packets arrive at any device in the pool.
         /// Returns error when all device handles in the pool are no longer 
         /// reading packets.
         pub fn select(&self) -> Result<Packet<Raw>, RlinkError> {
-            match self.rx.as_ref().unwrap().recv() {
-                Ok(packet) => Ok(packet),
-                Err(e) => Err(RlinkError::BrokenDevicePool),
+            self.monitor.queue.pop()
+        }
+
+        /// Bound the queue of captured packets to `capacity` packets,
+        /// handling overflows according to `policy`. Defaults to
+        /// `DEFAULT_CAPACITY` and `DropPolicy::Block`. A queue without room
+        /// is rejected.
+        pub fn set_queue(&self, capacity: usize, policy: DropPolicy) -> Result<(), RlinkError> {
+            if capacity == 0 {
+                return Err(RlinkError::InvalidConfig("queue has no room".to_string()));
             }
+            let mut state = self.monitor.queue.state.lock().unwrap();
+            state.capacity = capacity;
+            state.policy = policy;
+            self.monitor.queue.not_full.notify_all();
+            Ok(())
+        }
+
+        /// Set a filter evaluated in user space on the packets of all
+        /// devices, or remove it. Packets not matching are discarded by the
+        /// workers, before taking room in the queue.
+        pub fn set_filter(&self, filter: Option<Filter>) {
+            *self.monitor.queue.filter.write().unwrap() = filter;
+        }
+
+        /// Names of the devices in the pool.
+        pub fn names(&self) -> &[String] {
+            self.monitor.names()
+        }
+
+        /// Snapshots of the rlink-level counters of the devices in the pool.
+        /// Frames sent through other handles on these devices are included.
+        pub fn stats(&self) -> Vec<DeviceStats> {
+            self.monitor.stats()
+        }
+
+        pub fn health(&self) -> PoolHealth {
+            self.monitor.health()
+        }
+
+        /// A handle reporting the health and counters of the pool, which
+        /// unlike the pool itself can be shared between threads.
+        pub fn monitor(&self) -> PoolMonitor {
+            self.monitor.clone()
+        }
+
+        /// The counters of all devices in the pool added up.
+        pub fn total_stats(&self) -> DeviceStats {
+            DeviceStats::total("total", self.stats().iter())
         }
     }
 
     impl Drop for DevicePool {
-        /// Explicitly drop `rx` to properly terminate worker threads.
+        /// Close the queue to properly terminate worker threads.
         fn drop(&mut self) {
-            drop(self.rx.take());
+            self.monitor.queue.state.lock().unwrap().closed = true;
+            self.monitor.queue.not_full.notify_all();
 
             // Drop the thread handles. Workers terminate once they realize 
-            // `rx` is dropped.
+            // the queue is closed.
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use mac_address::MacAddress;
+        use std::time::Duration;
+
+        /// A queue of `devices` devices, with counters of their own.
+        fn queue(test: &str, devices: usize, capacity: usize, policy: DropPolicy) -> Queue {
+            let queue = Queue::new((0..devices)
+                .map(|device| stats::device_counters(Some("device-pool-test"), &format!("{}{}", test, device)))
+                .collect());
+            {
+                let mut state = queue.state.lock().unwrap();
+                state.capacity = capacity;
+                state.policy = policy;
+            }
+            queue
+        }
+
+        /// A packet of `device`, numbered `seq`.
+        fn packet(device: usize, seq: u8) -> Packet<Raw> {
+            Packet::<Raw>::from_bytes(vec![device as u8, seq], MacAddress::new([2, 0, 0, 0, 0, 1]))
+        }
+
+        /// The queued packets, as `(device, seq)`, and the drops by device.
+        fn contents(queue: &Queue) -> (Vec<(u8, u8)>, Vec<u64>) {
+            let state = queue.state.lock().unwrap();
+            let packets = state.packets.iter().map(|(_, packet)| (packet.data[0], packet.data[1])).collect();
+            (packets, state.drops.clone())
+        }
+
+        #[test]
+        fn drop_newest() {
+            let queue = queue("drop-newest", 1, 2, DropPolicy::DropNewest);
+            for seq in 0..4 {
+                assert!(queue.push(0, packet(0, seq)));
+            }
+            assert_eq!(contents(&queue), (vec![(0, 0), (0, 1)], vec![2]));
+            assert_eq!(queue.counters[0].snapshot().rx_queue_drops, 2);
+        }
+
+        #[test]
+        fn drop_oldest() {
+            let queue = queue("drop-oldest", 2, 2, DropPolicy::DropOldest);
+            queue.push(0, packet(0, 0));
+            queue.push(1, packet(1, 0));
+            queue.push(1, packet(1, 1));
+            queue.push(1, packet(1, 2));
+            // Drops are counted against the device of the dropped packet
+            assert_eq!(contents(&queue), (vec![(1, 1), (1, 2)], vec![1, 1]));
+            assert_eq!(queue.counters[0].snapshot().rx_queue_drops, 1);
+            assert_eq!(queue.counters[1].snapshot().rx_queue_drops, 1);
+            assert_eq!(queue.state.lock().unwrap().queued, vec![0, 2]);
+        }
+
+        #[test]
+        fn fair_share() {
+            let queue = queue("fair-share", 2, 4, DropPolicy::FairShare);
+            // A flood on device 0 is held to its share of the queue
+            for seq in 0..5 {
+                queue.push(0, packet(0, seq));
+            }
+            queue.push(1, packet(1, 0));
+            queue.push(1, packet(1, 1));
+            assert_eq!(contents(&queue), (vec![(0, 0), (0, 1), (1, 0), (1, 1)], vec![3, 0]));
+            // Room is given back as packets are taken
+            assert_eq!(queue.pop().unwrap().data, vec![0, 0]);
+            queue.push(0, packet(0, 5));
+            assert_eq!(contents(&queue), (vec![(0, 1), (1, 0), (1, 1), (0, 5)], vec![3, 0]));
+            assert_eq!(queue.counters[0].snapshot().rx_queue_drops, 3);
+        }
+
+        #[test]
+        fn block() {
+            let queue = Arc::new(queue("block", 1, 1, DropPolicy::Block));
+            queue.push(0, packet(0, 0));
+            let pusher = {
+                let queue = queue.clone();
+                thread::spawn(move || queue.push(0, packet(0, 1)))
+            };
+            thread::sleep(Duration::from_millis(100));
+            assert!(!pusher.is_finished());
+            assert_eq!(queue.pop().unwrap().data, vec![0, 0]);
+            assert!(pusher.join().unwrap());
+            assert_eq!(contents(&queue), (vec![(0, 1)], vec![0]));
+
+            // A worker waiting for room gives up once the pool is gone
+            let pusher = {
+                let queue = queue.clone();
+                thread::spawn(move || queue.push(0, packet(0, 2)))
+            };
+            thread::sleep(Duration::from_millis(100));
+            queue.state.lock().unwrap().closed = true;
+            queue.not_full.notify_all();
+            assert!(!pusher.join().unwrap());
+        }
+
+        #[test]
+        fn set_queue() {
+            let pool = DevicePool::with_opener(vec!["set-queue0".to_string()],
+                |name| Err(RlinkError::InvalidArgument(name.to_string()))).unwrap();
+            assert!(matches!(pool.set_queue(0, DropPolicy::DropNewest), Err(RlinkError::InvalidConfig(_))));
+            assert_eq!(pool.health().capacity, DEFAULT_CAPACITY);
+            pool.set_queue(8, DropPolicy::FairShare).unwrap();
+            assert_eq!(pool.health().capacity, 8);
+            assert_eq!(pool.monitor.queue.state.lock().unwrap().policy, DropPolicy::FairShare);
+            // The worker failed to open its device
+            assert!(matches!(pool.select(), Err(RlinkError::BrokenDevicePool)));
+        }
+
+        #[test]
+        fn netns_opener() {
+            let (tx, rx) = std::sync::mpsc::channel();
+            let tx = Mutex::new(tx);
+            let devices = vec![(Some("ns-a".to_string()), "eth0".to_string()), (Some("ns-b".to_string()), "eth0".to_string())];
+            let pool = DevicePool::with_netns_opener(devices.clone(), move |netns, name| {
+                tx.lock().unwrap().send((netns.map(str::to_string), name.to_string())).unwrap();
+                Err(RlinkError::InvalidArgument(name.to_string()))
+            }).unwrap();
+            // Each device is opened in its own namespace
+            let mut opened: Vec<_> = (0..2).map(|_| rx.recv_timeout(Duration::from_secs(5)).unwrap()).collect();
+            opened.sort();
+            assert_eq!(opened, devices);
+            let counted: Vec<_> = pool.stats().into_iter().map(|stats| (stats.netns, stats.device)).collect();
+            assert_eq!(counted, devices);
         }
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,995 @@
+#![allow(dead_code)]
+#![allow(unused)]
+
+pub mod dhcp {
+    //! DHCPv4 (RFC 2131) on the rlink stack: message encoding, a client
+    //! that configures links from the leases it obtains and renews them,
+    //! and a small server handing out addresses from a pool.
+    //!
+    //! The client asks for broadcast replies, as it cannot receive unicast
+    //! datagrams before its address is assigned to the stack.
+
+    use crate::ipv4::ipv4::{prefix_mask, IpProtocol, Ipv4Header, Route};
+    use crate::udp::udp::{self, UdpDatagram};
+    use crate::{FrameError, RlinkError, Stack};
+    use crate::stack::stack::WeakStack;
+    use mac_address::MacAddress;
+    use std::collections::HashMap;
+    use std::net::{Ipv4Addr, SocketAddrV4};
+    use std::sync::{Arc, Condvar, Mutex, Weak};
+    use std::thread;
+    use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+    pub const SERVER_PORT: u16 = 67;
+    pub const CLIENT_PORT: u16 = 68;
+
+    /// Values of the `op` field.
+    pub const BOOTREQUEST: u8 = 1;
+    pub const BOOTREPLY: u8 = 2;
+
+    /// Option codes (RFC 2132).
+    pub const PAD: u8 = 0;
+    pub const SUBNET_MASK: u8 = 1;
+    pub const ROUTER: u8 = 3;
+    pub const DNS_SERVERS: u8 = 6;
+    pub const HOST_NAME: u8 = 12;
+    pub const REQUESTED_ADDRESS: u8 = 50;
+    pub const LEASE_TIME: u8 = 51;
+    pub const MESSAGE_TYPE: u8 = 53;
+    pub const SERVER_IDENTIFIER: u8 = 54;
+    pub const PARAMETER_REQUEST_LIST: u8 = 55;
+    pub const MESSAGE: u8 = 56;
+    pub const RENEWAL_TIME: u8 = 58;
+    pub const REBINDING_TIME: u8 = 59;
+    pub const END: u8 = 255;
+
+    const HTYPE_ETHERNET: u8 = 1;
+    const MAGIC_COOKIE: [u8; 4] = [99, 130, 83, 99];
+    const BROADCAST_FLAG: u16 = 0x8000;
+    /// Fixed part of a message, up to and including the magic cookie.
+    const FIXED_LEN: usize = 240;
+    /// Messages are padded to the minimum BOOTP message length (RFC 1542).
+    const MIN_LEN: usize = 300;
+
+    /// Retransmission timeouts of the client, doubled on each attempt.
+    const INITIAL_TIMEOUT: Duration = Duration::from_secs(4);
+    const MAX_TIMEOUT: Duration = Duration::from_secs(64);
+    /// Requests sent for an offer before starting over.
+    const MAX_REQUESTS: u32 = 4;
+    /// Minimum interval between requests while renewing or rebinding.
+    const MIN_RENEW_RETRY: Duration = Duration::from_secs(60);
+    /// How long the server reserves an offered address.
+    const OFFER_TIMEOUT: Duration = Duration::from_secs(30);
+    /// How long the server keeps a declined address out of the pool.
+    const DECLINE_TIMEOUT: Duration = Duration::from_secs(600);
+    /// Resolution of the client timer thread.
+    const TICK: Duration = Duration::from_millis(100);
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+    pub enum DhcpMessageType {
+        Discover = 1,
+        Offer = 2,
+        Request = 3,
+        Decline = 4,
+        Ack = 5,
+        Nak = 6,
+        Release = 7,
+        Inform = 8,
+    }
+
+    impl TryFrom<u8> for DhcpMessageType {
+        type Error = u8;
+
+        fn try_from(value: u8) -> Result<Self, Self::Error> {
+            use DhcpMessageType::*;
+            Ok(match value {
+                1 => Discover,
+                2 => Offer,
+                3 => Request,
+                4 => Decline,
+                5 => Ack,
+                6 => Nak,
+                7 => Release,
+                8 => Inform,
+                other => return Err(other),
+            })
+        }
+    }
+
+    #[derive(Clone, Debug, PartialEq, Eq)]
+    pub enum DhcpOption {
+        SubnetMask(Ipv4Addr),
+        Router(Vec<Ipv4Addr>),
+        DnsServers(Vec<Ipv4Addr>),
+        HostName(String),
+        RequestedAddress(Ipv4Addr),
+        /// Seconds, `u32::MAX` meaning infinity
+        LeaseTime(u32),
+        MessageType(DhcpMessageType),
+        ServerIdentifier(Ipv4Addr),
+        ParameterRequestList(Vec<u8>),
+        /// Error message, e.g. the reason of a NAK
+        Message(String),
+        /// T1, in seconds
+        RenewalTime(u32),
+        /// T2, in seconds
+        RebindingTime(u32),
+        /// Any other option, or a known one with an unexpected length
+        Other { code: u8, data: Vec<u8> },
+    }
+
+    impl DhcpOption {
+        fn encode(&self, out: &mut Vec<u8>) {
+            let addrs = |addrs: &[Ipv4Addr]| addrs.iter().flat_map(|addr| addr.octets()).collect::<Vec<u8>>();
+            let (code, data) = match self {
+                DhcpOption::SubnetMask(mask) => (SUBNET_MASK, mask.octets().to_vec()),
+                DhcpOption::Router(routers) => (ROUTER, addrs(routers)),
+                DhcpOption::DnsServers(servers) => (DNS_SERVERS, addrs(servers)),
+                DhcpOption::HostName(name) => (HOST_NAME, name.as_bytes().to_vec()),
+                DhcpOption::RequestedAddress(addr) => (REQUESTED_ADDRESS, addr.octets().to_vec()),
+                DhcpOption::LeaseTime(secs) => (LEASE_TIME, secs.to_be_bytes().to_vec()),
+                DhcpOption::MessageType(message_type) => (MESSAGE_TYPE, vec![*message_type as u8]),
+                DhcpOption::ServerIdentifier(addr) => (SERVER_IDENTIFIER, addr.octets().to_vec()),
+                DhcpOption::ParameterRequestList(codes) => (PARAMETER_REQUEST_LIST, codes.clone()),
+                DhcpOption::Message(message) => (MESSAGE, message.as_bytes().to_vec()),
+                DhcpOption::RenewalTime(secs) => (RENEWAL_TIME, secs.to_be_bytes().to_vec()),
+                DhcpOption::RebindingTime(secs) => (REBINDING_TIME, secs.to_be_bytes().to_vec()),
+                DhcpOption::Other { code, data } => (*code, data.clone()),
+            };
+            // Longer values are split into several options (RFC 3396).
+            for chunk in data.chunks(255) {
+                out.extend_from_slice(&[code, chunk.len() as u8]);
+                out.extend_from_slice(chunk);
+            }
+            if data.is_empty() {
+                out.extend_from_slice(&[code, 0]);
+            }
+        }
+
+        fn decode(code: u8, data: &[u8]) -> Self {
+            let addr = |data: &[u8]| Ipv4Addr::new(data[0], data[1], data[2], data[3]);
+            let secs = |data: &[u8]| u32::from_be_bytes(data.try_into().unwrap());
+            let text = |data: &[u8]| String::from_utf8_lossy(data).into_owned();
+            match (code, data.len()) {
+                (SUBNET_MASK, 4) => DhcpOption::SubnetMask(addr(data)),
+                (ROUTER, len) if len > 0 && len % 4 == 0 => DhcpOption::Router(data.chunks(4).map(addr).collect()),
+                (DNS_SERVERS, len) if len > 0 && len % 4 == 0 => DhcpOption::DnsServers(data.chunks(4).map(addr).collect()),
+                (HOST_NAME, _) => DhcpOption::HostName(text(data)),
+                (REQUESTED_ADDRESS, 4) => DhcpOption::RequestedAddress(addr(data)),
+                (LEASE_TIME, 4) => DhcpOption::LeaseTime(secs(data)),
+                (MESSAGE_TYPE, 1) if DhcpMessageType::try_from(data[0]).is_ok() =>
+                    DhcpOption::MessageType(DhcpMessageType::try_from(data[0]).unwrap()),
+                (SERVER_IDENTIFIER, 4) => DhcpOption::ServerIdentifier(addr(data)),
+                (PARAMETER_REQUEST_LIST, _) => DhcpOption::ParameterRequestList(data.to_vec()),
+                (MESSAGE, _) => DhcpOption::Message(text(data)),
+                (RENEWAL_TIME, 4) => DhcpOption::RenewalTime(secs(data)),
+                (REBINDING_TIME, 4) => DhcpOption::RebindingTime(secs(data)),
+                (code, _) => DhcpOption::Other { code, data: data.to_vec() },
+            }
+        }
+    }
+
+    /// A DHCP (BOOTP) message. The `sname` and `file` fields are not used.
+    #[derive(Clone, Debug, PartialEq, Eq)]
+    pub struct DhcpMessage {
+        /// `BOOTREQUEST` or `BOOTREPLY`
+        pub op: u8,
+        pub hops: u8,
+        pub xid: u32,
+        pub secs: u16,
+        /// Whether the client asks for broadcast replies
+        pub broadcast: bool,
+        /// Client address, when it already has one
+        pub ciaddr: Ipv4Addr,
+        /// Address assigned to the client
+        pub yiaddr: Ipv4Addr,
+        pub siaddr: Ipv4Addr,
+        /// Relay agent address
+        pub giaddr: Ipv4Addr,
+        /// Client hardware address
+        pub chaddr: MacAddress,
+        pub options: Vec<DhcpOption>,
+    }
+
+    impl DhcpMessage {
+        /// A request of the given type from `chaddr`, without addresses.
+        pub fn request(message_type: DhcpMessageType, xid: u32, chaddr: MacAddress) -> Self {
+            DhcpMessage {
+                op: BOOTREQUEST,
+                hops: 0,
+                xid,
+                secs: 0,
+                broadcast: false,
+                ciaddr: Ipv4Addr::UNSPECIFIED,
+                yiaddr: Ipv4Addr::UNSPECIFIED,
+                siaddr: Ipv4Addr::UNSPECIFIED,
+                giaddr: Ipv4Addr::UNSPECIFIED,
+                chaddr,
+                options: vec![DhcpOption::MessageType(message_type)],
+            }
+        }
+
+        /// A reply of the given type to `request`.
+        pub fn reply(message_type: DhcpMessageType, request: &DhcpMessage) -> Self {
+            DhcpMessage {
+                op: BOOTREPLY,
+                hops: 0,
+                xid: request.xid,
+                secs: 0,
+                broadcast: request.broadcast,
+                ciaddr: Ipv4Addr::UNSPECIFIED,
+                yiaddr: Ipv4Addr::UNSPECIFIED,
+                siaddr: Ipv4Addr::UNSPECIFIED,
+                giaddr: request.giaddr,
+                chaddr: request.chaddr,
+                options: vec![DhcpOption::MessageType(message_type)],
+            }
+        }
+
+        pub fn encode(&self) -> Vec<u8> {
+            let mut data = Vec::with_capacity(MIN_LEN);
+            data.extend_from_slice(&[self.op, HTYPE_ETHERNET, 6, self.hops]);
+            data.extend_from_slice(&self.xid.to_be_bytes());
+            data.extend_from_slice(&self.secs.to_be_bytes());
+            data.extend_from_slice(&(if self.broadcast { BROADCAST_FLAG } else { 0 }).to_be_bytes());
+            for addr in [self.ciaddr, self.yiaddr, self.siaddr, self.giaddr] {
+                data.extend_from_slice(&addr.octets());
+            }
+            data.extend_from_slice(&self.chaddr.bytes());
+            // Rest of chaddr, sname and file
+            data.resize(FIXED_LEN - MAGIC_COOKIE.len(), 0);
+            data.extend_from_slice(&MAGIC_COOKIE);
+            for option in self.options.iter() {
+                option.encode(&mut data);
+            }
+            data.push(END);
+            data.resize(data.len().max(MIN_LEN), PAD);
+            data
+        }
+
+        /// Decodes a message of an Ethernet client. Options split into
+        /// several parts are concatenated.
+        pub fn decode(data: &[u8]) -> Result<Self, FrameError> {
+            if data.len() < FIXED_LEN {
+                return Err(FrameError::Truncated { offset: 0, needed: FIXED_LEN, available: data.len() });
+            }
+            if data[0] != BOOTREQUEST && data[0] != BOOTREPLY {
+                return Err(FrameError::InvalidField { offset: 0, field: "op" });
+            }
+            if data[1] != HTYPE_ETHERNET || data[2] != 6 {
+                return Err(FrameError::InvalidField { offset: 1, field: "hardware type" });
+            }
+            if data[236..240] != MAGIC_COOKIE {
+                return Err(FrameError::InvalidField { offset: 236, field: "magic cookie" });
+            }
+            let addr = |offset: usize| Ipv4Addr::new(data[offset], data[offset + 1], data[offset + 2], data[offset + 3]);
+
+            let mut values: Vec<(u8, Vec<u8>)> = Vec::new();
+            let mut offset = FIXED_LEN;
+            while offset < data.len() {
+                let code = data[offset];
+                match code {
+                    PAD => {
+                        offset += 1;
+                        continue;
+                    }
+                    END => break,
+                    _ => {}
+                }
+                if offset + 2 > data.len() {
+                    return Err(FrameError::Truncated { offset, needed: 2, available: data.len() - offset });
+                }
+                let len = data[offset + 1] as usize;
+                if offset + 2 + len > data.len() {
+                    return Err(FrameError::Truncated { offset, needed: len + 2, available: data.len() - offset });
+                }
+                let value = &data[offset + 2..offset + 2 + len];
+                match values.iter_mut().find(|(seen, _)| *seen == code) {
+                    Some((_, data)) => data.extend_from_slice(value),
+                    None => values.push((code, value.to_vec())),
+                }
+                offset += 2 + len;
+            }
+
+            Ok(DhcpMessage {
+                op: data[0],
+                hops: data[3],
+                xid: u32::from_be_bytes(data[4..8].try_into().unwrap()),
+                secs: u16::from_be_bytes([data[8], data[9]]),
+                broadcast: u16::from_be_bytes([data[10], data[11]]) & BROADCAST_FLAG != 0,
+                ciaddr: addr(12),
+                yiaddr: addr(16),
+                siaddr: addr(20),
+                giaddr: addr(24),
+                chaddr: MacAddress::new(data[28..34].try_into().unwrap()),
+                options: values.iter().map(|(code, data)| DhcpOption::decode(*code, data)).collect(),
+            })
+        }
+
+        pub fn message_type(&self) -> Option<DhcpMessageType> {
+            self.options.iter().find_map(|option| match option {
+                DhcpOption::MessageType(message_type) => Some(*message_type),
+                _ => None,
+            })
+        }
+
+        pub fn server_identifier(&self) -> Option<Ipv4Addr> {
+            self.options.iter().find_map(|option| match option {
+                DhcpOption::ServerIdentifier(addr) => Some(*addr),
+                _ => None,
+            })
+        }
+
+        pub fn requested_address(&self) -> Option<Ipv4Addr> {
+            self.options.iter().find_map(|option| match option {
+                DhcpOption::RequestedAddress(addr) => Some(*addr),
+                _ => None,
+            })
+        }
+    }
+
+    /// An address leased by a server, with the configuration sent along.
+    #[derive(Clone, Debug, PartialEq, Eq)]
+    pub struct Lease {
+        pub address: Ipv4Addr,
+        pub prefix_len: u8,
+        pub server: Ipv4Addr,
+        pub routers: Vec<Ipv4Addr>,
+        pub dns_servers: Vec<Ipv4Addr>,
+        pub lease_time: Duration,
+        /// T1, when renewing starts
+        pub renewal_time: Duration,
+        /// T2, when rebinding starts
+        pub rebinding_time: Duration,
+        /// When the request leading to the lease was sent
+        pub obtained: Instant,
+    }
+
+    impl Lease {
+        /// The lease granted by an ACK. T1 and T2 default to 1/2 and 7/8 of
+        /// the lease time, and a missing subnet mask to the address alone.
+        fn from_ack(ack: &DhcpMessage, obtained: Instant) -> Option<Lease> {
+            let (mut lease_time, mut renewal_time, mut rebinding_time) = (None, None, None);
+            let mut lease = Lease {
+                address: ack.yiaddr,
+                prefix_len: 32,
+                server: ack.server_identifier()?,
+                routers: Vec::new(),
+                dns_servers: Vec::new(),
+                lease_time: Duration::ZERO,
+                renewal_time: Duration::ZERO,
+                rebinding_time: Duration::ZERO,
+                obtained,
+            };
+            for option in ack.options.iter() {
+                match option {
+                    DhcpOption::SubnetMask(mask) => lease.prefix_len = u32::from(*mask).leading_ones() as u8,
+                    DhcpOption::Router(routers) => lease.routers = routers.clone(),
+                    DhcpOption::DnsServers(servers) => lease.dns_servers = servers.clone(),
+                    DhcpOption::LeaseTime(secs) => lease_time = Some(*secs),
+                    DhcpOption::RenewalTime(secs) => renewal_time = Some(*secs),
+                    DhcpOption::RebindingTime(secs) => rebinding_time = Some(*secs),
+                    _ => {}
+                }
+            }
+            let lease_time = Duration::from_secs(lease_time? as u64);
+            lease.lease_time = lease_time;
+            lease.renewal_time = renewal_time.map_or(lease_time / 2, |secs| Duration::from_secs(secs as u64));
+            lease.rebinding_time = rebinding_time.map_or(lease_time.mul_f64(0.875), |secs| Duration::from_secs(secs as u64));
+            Some(lease)
+        }
+
+        pub fn expires(&self) -> Instant {
+            self.obtained + self.lease_time
+        }
+    }
+
+    /// Send a DHCP message in a UDP datagram on `link` to `dst_mac`,
+    /// bypassing routing and ARP.
+    fn send_on(stack: &Stack, link: usize, src: SocketAddrV4, dst: SocketAddrV4, dst_mac: &MacAddress,
+        message: &DhcpMessage) -> Result<(), RlinkError> {
+        let datagram = UdpDatagram { src_port: src.port(), dst_port: dst.port(), payload: message.encode() };
+        let header = Ipv4Header::new(*src.ip(), *dst.ip(), IpProtocol::UDP);
+        stack.send_ipv4_on(link, header, &datagram.encode(*src.ip(), *dst.ip()), dst_mac)
+    }
+
+    fn broadcast(port: u16) -> SocketAddrV4 {
+        SocketAddrV4::new(Ipv4Addr::BROADCAST, port)
+    }
+
+    /// States of the client on a link (RFC 2131, figure 5).
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub enum ClientState {
+        Init,
+        Selecting,
+        Requesting,
+        Bound,
+        Renewing,
+        Rebinding,
+    }
+
+    struct ClientLink {
+        state: ClientState,
+        xid: u32,
+        /// Start of the current exchange, for the `secs` field
+        started: Instant,
+        /// Offer being requested
+        offer: Option<DhcpMessage>,
+        lease: Option<Lease>,
+        /// Transmissions of the current message
+        attempts: u32,
+        next_send: Instant,
+    }
+
+    #[derive(Default)]
+    struct ClientInner {
+        links: Mutex<HashMap<usize, ClientLink>>,
+        changed: Condvar,
+    }
+
+    /// A DHCP client, configuring the links it is started on with the
+    /// address, subnet route and default route of their leases. Leases are
+    /// renewed in the background; addresses are removed when they expire.
+    /// The client port is released when the client is dropped.
+    pub struct DhcpClient {
+        stack: Stack,
+        inner: Arc<ClientInner>,
+    }
+
+    impl DhcpClient {
+        /// Bind the client port of `stack`. Links are only configured once
+        /// `start()` is called on them.
+        pub fn new(stack: &Stack) -> Result<DhcpClient, RlinkError> {
+            let inner = Arc::new(ClientInner::default());
+            let weak = Arc::downgrade(&inner);
+            stack.udp().bind_handler(CLIENT_PORT, Box::new(move |stack, link, src, _, payload| {
+                if let (Some(inner), Ok(message)) = (weak.upgrade(), DhcpMessage::decode(payload)) {
+                    inner.input(stack, link, message);
+                }
+            }))?;
+            ClientInner::start_timer(Arc::downgrade(&inner), stack.downgrade());
+            Ok(DhcpClient { stack: stack.clone(), inner })
+        }
+
+        /// Start acquiring a lease for `link`.
+        pub fn start(&self, link: usize) -> Result<(), RlinkError> {
+            self.stack.link(link).ok_or(RlinkError::NoAddress(format!("link {}", link)))?;
+            let now = Instant::now();
+            self.inner.links.lock().unwrap().entry(link).or_insert(ClientLink {
+                state: ClientState::Init,
+                xid: 0,
+                started: now,
+                offer: None,
+                lease: None,
+                attempts: 0,
+                next_send: now,
+            });
+            Ok(())
+        }
+
+        pub fn state(&self, link: usize) -> Option<ClientState> {
+            self.inner.links.lock().unwrap().get(&link).map(|client| client.state)
+        }
+
+        /// The current lease of `link`, if any.
+        pub fn lease(&self, link: usize) -> Option<Lease> {
+            self.inner.links.lock().unwrap().get(&link)?.lease.clone()
+        }
+
+        /// Wait up to `timeout` for `link` to be bound, returning its lease.
+        pub fn wait_bound(&self, link: usize, timeout: Duration) -> Result<Lease, RlinkError> {
+            let deadline = Instant::now() + timeout;
+            let mut links = self.inner.links.lock().unwrap();
+            loop {
+                if let Some(client) = links.get(&link) {
+                    if client.state == ClientState::Bound {
+                        return Ok(client.lease.clone().unwrap());
+                    }
+                }
+                let now = Instant::now();
+                if now >= deadline {
+                    return Err(RlinkError::Timeout);
+                }
+                links = self.inner.changed.wait_timeout(links, deadline - now).unwrap().0;
+            }
+        }
+
+        /// Release the lease of `link` to its server, remove the address
+        /// and stop managing the link.
+        pub fn release(&self, link: usize) -> Result<(), RlinkError> {
+            let client = match self.inner.links.lock().unwrap().remove(&link) {
+                Some(client) => client,
+                None => return Ok(()),
+            };
+            if let Some(lease) = client.lease {
+                let chaddr = self.stack.link(link).unwrap().mac_address;
+                let mut release = DhcpMessage::request(DhcpMessageType::Release, client.xid, chaddr);
+                release.ciaddr = lease.address;
+                release.options.push(DhcpOption::ServerIdentifier(lease.server));
+                let result = udp::send_to(&self.stack, SocketAddrV4::new(lease.address, CLIENT_PORT),
+                    SocketAddrV4::new(lease.server, SERVER_PORT), &release.encode());
+                deconfigure(&self.stack, link, &lease);
+                result?;
+            }
+            self.inner.changed.notify_all();
+            Ok(())
+        }
+    }
+
+    impl Drop for DhcpClient {
+        fn drop(&mut self) {
+            self.stack.udp().unbind(CLIENT_PORT);
+        }
+    }
+
+    /// A transaction ID unlikely to be used by another client, different
+    /// from `previous`.
+    fn new_xid(previous: u32) -> u32 {
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.subsec_nanos());
+        (nanos ^ std::process::id().rotate_left(16) ^ previous.rotate_left(7)).wrapping_add(1)
+    }
+
+    /// Assign the leased address and routes to `link`.
+    fn configure(stack: &Stack, link: usize, lease: &Lease) -> Result<(), RlinkError> {
+        stack.add_address(link, lease.address, lease.prefix_len)?;
+        if let Some(router) = lease.routers.first() {
+            stack.add_route(Route {
+                prefix: Ipv4Addr::UNSPECIFIED,
+                prefix_len: 0,
+                gateway: Some(*router),
+                device: stack.link(link).unwrap().name.clone(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Undo `configure()`.
+    fn deconfigure(stack: &Stack, link: usize, lease: &Lease) {
+        let name = stack.link(link).map(|link| link.name.clone());
+        if let Some(router) = lease.routers.first() {
+            let default = stack.routes().iter()
+                .any(|route| route.prefix_len == 0 && route.gateway == Some(*router) && Some(&route.device) == name.as_ref());
+            if default {
+                stack.remove_route(Ipv4Addr::UNSPECIFIED, 0);
+            }
+        }
+        stack.remove_address(link, lease.address);
+    }
+
+    impl ClientInner {
+        /// Process a message received on the client port.
+        fn input(&self, stack: &Stack, link: usize, message: DhcpMessage) {
+            let chaddr = match stack.link(link) {
+                Some(link) => link.mac_address,
+                None => return,
+            };
+            let mut links = self.links.lock().unwrap();
+            let client = match links.get_mut(&link) {
+                Some(client) if message.op == BOOTREPLY && message.xid == client.xid
+                    && message.chaddr == chaddr => client,
+                _ => return,
+            };
+            match (message.message_type(), client.state) {
+                (Some(DhcpMessageType::Offer), ClientState::Selecting) => {
+                    if message.server_identifier().is_none() {
+                        return;
+                    }
+                    client.offer = Some(message);
+                    client.state = ClientState::Requesting;
+                    client.attempts = 0;
+                    client.send(stack, link, chaddr);
+                }
+                (Some(DhcpMessageType::Ack), ClientState::Requesting | ClientState::Renewing | ClientState::Rebinding) => {
+                    let lease = match Lease::from_ack(&message, client.started) {
+                        Some(lease) => lease,
+                        None => return,
+                    };
+                    if let Some(old) = client.lease.take() {
+                        if old.address != lease.address || old.prefix_len != lease.prefix_len
+                            || old.routers != lease.routers {
+                            deconfigure(stack, link, &old);
+                        }
+                    }
+                    if configure(stack, link, &lease).is_err() {
+                        return;
+                    }
+                    client.next_send = lease.obtained + lease.renewal_time;
+                    client.lease = Some(lease);
+                    client.offer = None;
+                    client.state = ClientState::Bound;
+                    self.changed.notify_all();
+                }
+                (Some(DhcpMessageType::Nak), ClientState::Requesting | ClientState::Renewing | ClientState::Rebinding) => {
+                    if let Some(old) = client.lease.take() {
+                        deconfigure(stack, link, &old);
+                    }
+                    client.restart();
+                    self.changed.notify_all();
+                }
+                _ => {}
+            }
+        }
+
+        /// Drive the retransmissions and lease timers, until either the
+        /// client or the stack goes away.
+        fn start_timer(inner: Weak<ClientInner>, stack: WeakStack) {
+            thread::spawn(move || loop {
+                thread::sleep(TICK);
+                let (inner, stack) = match (inner.upgrade(), stack.upgrade()) {
+                    (Some(inner), Some(stack)) => (inner, stack),
+                    _ => break,
+                };
+                inner.tick(&stack);
+            });
+        }
+
+        fn tick(&self, stack: &Stack) {
+            let now = Instant::now();
+            let mut links = self.links.lock().unwrap();
+            let mut changed = false;
+            for (&link, client) in links.iter_mut() {
+                let chaddr = match stack.link(link) {
+                    Some(link) => link.mac_address,
+                    None => continue,
+                };
+                if let Some(lease) = client.lease.as_ref() {
+                    if now >= lease.expires() {
+                        deconfigure(stack, link, lease);
+                        client.lease = None;
+                        client.restart();
+                        changed = true;
+                    }
+                    else if client.state == ClientState::Bound && now >= lease.obtained + lease.renewal_time {
+                        client.state = ClientState::Renewing;
+                        client.begin(now);
+                        changed = true;
+                    }
+                    else if client.state == ClientState::Renewing && now >= lease.obtained + lease.rebinding_time {
+                        client.state = ClientState::Rebinding;
+                        client.next_send = now;
+                        changed = true;
+                    }
+                }
+                if client.state == ClientState::Init {
+                    client.state = ClientState::Selecting;
+                    client.begin(now);
+                    changed = true;
+                }
+                if client.state == ClientState::Requesting && client.attempts >= MAX_REQUESTS
+                    && now >= client.next_send {
+                    client.restart();
+                    client.state = ClientState::Selecting;
+                    client.begin(now);
+                    changed = true;
+                }
+                if client.state != ClientState::Bound && now >= client.next_send {
+                    client.send(stack, link, chaddr);
+                }
+            }
+            if changed {
+                self.changed.notify_all();
+            }
+        }
+    }
+
+    impl ClientLink {
+        /// Start a new exchange.
+        fn begin(&mut self, now: Instant) {
+            self.xid = new_xid(self.xid);
+            self.started = now;
+            self.attempts = 0;
+            self.next_send = now;
+        }
+
+        /// Go back to the INIT state, without a lease.
+        fn restart(&mut self) {
+            self.state = ClientState::Init;
+            self.offer = None;
+            self.next_send = Instant::now();
+        }
+
+        /// Send the message of the current state, and schedule its
+        /// retransmission.
+        fn send(&mut self, stack: &Stack, link: usize, chaddr: MacAddress) {
+            let now = Instant::now();
+            let message_type = match self.state {
+                ClientState::Selecting => DhcpMessageType::Discover,
+                _ => DhcpMessageType::Request,
+            };
+            let mut message = DhcpMessage::request(message_type, self.xid, chaddr);
+            message.secs = now.duration_since(self.started).as_secs().min(u16::MAX as u64) as u16;
+            message.broadcast = true;
+            message.options.push(DhcpOption::ParameterRequestList(vec![SUBNET_MASK, ROUTER, DNS_SERVERS,
+                LEASE_TIME, RENEWAL_TIME, REBINDING_TIME]));
+
+            let backoff = (INITIAL_TIMEOUT * 2u32.pow(self.attempts.min(4))).min(MAX_TIMEOUT);
+            let result = match (self.state, self.offer.as_ref(), self.lease.as_ref()) {
+                (ClientState::Selecting, _, _) => {
+                    self.next_send = now + backoff;
+                    send_on(stack, link, SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, CLIENT_PORT),
+                        broadcast(SERVER_PORT), &crate::mac::mac::broadcast(), &message)
+                }
+                (ClientState::Requesting, Some(offer), _) => {
+                    message.options.push(DhcpOption::RequestedAddress(offer.yiaddr));
+                    message.options.push(DhcpOption::ServerIdentifier(offer.server_identifier().unwrap()));
+                    self.next_send = now + backoff;
+                    send_on(stack, link, SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, CLIENT_PORT),
+                        broadcast(SERVER_PORT), &crate::mac::mac::broadcast(), &message)
+                }
+                (ClientState::Renewing | ClientState::Rebinding, _, Some(lease)) => {
+                    message.ciaddr = lease.address;
+                    // Retry after half the time left to T2 (renewing) or to
+                    // the expiry (rebinding), but not too often.
+                    let until = if self.state == ClientState::Renewing {
+                        lease.obtained + lease.rebinding_time
+                    }
+                    else {
+                        lease.expires()
+                    };
+                    let left = until.saturating_duration_since(now);
+                    self.next_send = now + (left / 2).max(MIN_RENEW_RETRY).min(left);
+                    // The lease is renewed from the time of the request.
+                    self.started = now;
+                    let src = SocketAddrV4::new(lease.address, CLIENT_PORT);
+                    if self.state == ClientState::Renewing {
+                        udp::send_to(stack, src, SocketAddrV4::new(lease.server, SERVER_PORT), &message.encode())
+                    }
+                    else {
+                        send_on(stack, link, src, broadcast(SERVER_PORT), &crate::mac::mac::broadcast(), &message)
+                    }
+                }
+                _ => return,
+            };
+            self.attempts += 1;
+        }
+    }
+
+    /// Configuration of a DHCP server.
+    #[derive(Clone, Debug)]
+    pub struct ServerConfig {
+        /// First address of the pool
+        pub first: Ipv4Addr,
+        /// Last address of the pool, included
+        pub last: Ipv4Addr,
+        pub prefix_len: u8,
+        pub routers: Vec<Ipv4Addr>,
+        pub dns_servers: Vec<Ipv4Addr>,
+        pub lease_time: Duration,
+    }
+
+    impl ServerConfig {
+        /// A pool from `first` to `last` in a subnet of the given prefix
+        /// length, with one-hour leases.
+        pub fn new(first: Ipv4Addr, last: Ipv4Addr, prefix_len: u8) -> Self {
+            ServerConfig {
+                first,
+                last,
+                prefix_len,
+                routers: Vec::new(),
+                dns_servers: Vec::new(),
+                lease_time: Duration::from_secs(3600),
+            }
+        }
+
+        fn contains(&self, addr: Ipv4Addr) -> bool {
+            (u32::from(self.first)..=u32::from(self.last)).contains(&u32::from(addr))
+        }
+    }
+
+    /// An address the server offered or leased.
+    #[derive(Clone, Debug, PartialEq, Eq)]
+    pub struct ServerLease {
+        pub mac_address: MacAddress,
+        pub address: Ipv4Addr,
+        /// Whether the client took the address, rather than being offered it
+        pub bound: bool,
+        pub expires: Instant,
+    }
+
+    struct ServerInner {
+        link: usize,
+        server_id: Ipv4Addr,
+        config: ServerConfig,
+        leases: Mutex<HashMap<MacAddress, ServerLease>>,
+        declined: Mutex<HashMap<Ipv4Addr, Instant>>,
+    }
+
+    /// A DHCP server handing out addresses from a pool to the clients on
+    /// one link. The server port is released when the server is dropped.
+    pub struct DhcpServer {
+        stack: Stack,
+        inner: Arc<ServerInner>,
+    }
+
+    impl DhcpServer {
+        /// Serve the clients on `link`, whose primary address identifies
+        /// the server.
+        pub fn start(stack: &Stack, link: usize, config: ServerConfig) -> Result<DhcpServer, RlinkError> {
+            let link_ref = stack.link(link).ok_or(RlinkError::NoAddress(format!("link {}", link)))?;
+            let server_id = link_ref.ipv4().ok_or_else(|| RlinkError::NoAddress(link_ref.name.clone()))?;
+            let inner = Arc::new(ServerInner {
+                link,
+                server_id,
+                config,
+                leases: Mutex::new(HashMap::new()),
+                declined: Mutex::new(HashMap::new()),
+            });
+            let weak = Arc::downgrade(&inner);
+            stack.udp().bind_handler(SERVER_PORT, Box::new(move |stack, link, src, _, payload| {
+                if let (Some(inner), Ok(message)) = (weak.upgrade(), DhcpMessage::decode(payload)) {
+                    if link == inner.link && message.op == BOOTREQUEST {
+                        inner.input(stack, message);
+                    }
+                }
+            }))?;
+            Ok(DhcpServer { stack: stack.clone(), inner })
+        }
+
+        pub fn server_id(&self) -> Ipv4Addr {
+            self.inner.server_id
+        }
+
+        /// Addresses currently leased, excluding pending offers.
+        pub fn leases(&self) -> Vec<ServerLease> {
+            let now = Instant::now();
+            let mut leases: Vec<_> = self.inner.leases.lock().unwrap().values()
+                .filter(|lease| lease.bound && lease.expires > now)
+                .cloned()
+                .collect();
+            leases.sort_by_key(|lease| lease.address);
+            leases
+        }
+    }
+
+    impl Drop for DhcpServer {
+        fn drop(&mut self) {
+            self.stack.udp().unbind(SERVER_PORT);
+        }
+    }
+
+    impl ServerInner {
+        fn input(&self, stack: &Stack, request: DhcpMessage) {
+            let now = Instant::now();
+            match request.message_type() {
+                Some(DhcpMessageType::Discover) => {
+                    let address = match self.allocate(stack, &request, now) {
+                        Some(address) => address,
+                        None => return,
+                    };
+                    self.leases.lock().unwrap().insert(request.chaddr, ServerLease {
+                        mac_address: request.chaddr,
+                        address,
+                        bound: false,
+                        expires: now + OFFER_TIMEOUT,
+                    });
+                    self.reply(stack, &request, DhcpMessageType::Offer, address);
+                }
+                Some(DhcpMessageType::Request) => self.input_request(stack, request, now),
+                Some(DhcpMessageType::Decline) => {
+                    let mut leases = self.leases.lock().unwrap();
+                    if let Some(address) = request.requested_address() {
+                        if leases.get(&request.chaddr).is_some_and(|lease| lease.address == address) {
+                            leases.remove(&request.chaddr);
+                            self.declined.lock().unwrap().insert(address, now + DECLINE_TIMEOUT);
+                        }
+                    }
+                }
+                Some(DhcpMessageType::Release) => {
+                    let mut leases = self.leases.lock().unwrap();
+                    if leases.get(&request.chaddr).is_some_and(|lease| lease.address == request.ciaddr) {
+                        leases.remove(&request.chaddr);
+                    }
+                }
+                Some(DhcpMessageType::Inform) if !request.ciaddr.is_unspecified() => {
+                    self.reply(stack, &request, DhcpMessageType::Ack, Ipv4Addr::UNSPECIFIED);
+                }
+                _ => {}
+            }
+        }
+
+        /// Handle a REQUEST while selecting (server identifier given),
+        /// rebooting (requested address given) or renewing/rebinding
+        /// (client address given).
+        fn input_request(&self, stack: &Stack, request: DhcpMessage, now: Instant) {
+            let mut leases = self.leases.lock().unwrap();
+            let current = leases.get(&request.chaddr).filter(|lease| lease.expires > now).cloned();
+            let address = match (request.server_identifier(), request.requested_address()) {
+                (Some(server_id), _) if server_id != self.server_id => {
+                    // The client took another offer.
+                    if current.is_some_and(|lease| !lease.bound) {
+                        leases.remove(&request.chaddr);
+                    }
+                    return;
+                }
+                (Some(_), requested) => requested,
+                (None, Some(requested)) => {
+                    let on_subnet = u32::from(requested) & prefix_mask(self.config.prefix_len)
+                        == u32::from(self.server_id) & prefix_mask(self.config.prefix_len);
+                    if current.is_none() && on_subnet && !self.is_taken(requested, &request.chaddr, &leases, now) {
+                        // Not ours to confirm (RFC 2131, section 4.3.2).
+                        return;
+                    }
+                    Some(requested)
+                }
+                (None, None) => Some(request.ciaddr),
+            };
+            match (address, current) {
+                (Some(address), Some(lease)) if lease.address == address => {
+                    leases.insert(request.chaddr, ServerLease {
+                        mac_address: request.chaddr,
+                        address,
+                        bound: true,
+                        expires: now + self.config.lease_time,
+                    });
+                    drop(leases);
+                    self.reply(stack, &request, DhcpMessageType::Ack, address);
+                }
+                _ => {
+                    drop(leases);
+                    self.reply(stack, &request, DhcpMessageType::Nak, Ipv4Addr::UNSPECIFIED);
+                }
+            }
+        }
+
+        /// Whether `address` is leased or offered to another client, or
+        /// was declined.
+        fn is_taken(&self, address: Ipv4Addr, mac: &MacAddress, leases: &HashMap<MacAddress, ServerLease>,
+            now: Instant) -> bool {
+            leases.values().any(|lease| lease.address == address && lease.mac_address != *mac && lease.expires > now)
+                || self.declined.lock().unwrap().get(&address).is_some_and(|until| *until > now)
+        }
+
+        /// The address to offer a client: its previous one if still free,
+        /// else the one it asks for, else the first free one of the pool.
+        fn allocate(&self, stack: &Stack, request: &DhcpMessage, now: Instant) -> Option<Ipv4Addr> {
+            let leases = self.leases.lock().unwrap();
+            let free = |address: Ipv4Addr| self.config.contains(address) && !stack.is_local(address)
+                && !self.is_taken(address, &request.chaddr, &leases, now);
+            leases.get(&request.chaddr).map(|lease| lease.address)
+                .into_iter()
+                .chain(request.requested_address())
+                .chain((u32::from(self.config.first)..=u32::from(self.config.last)).map(Ipv4Addr::from))
+                .find(|address| free(*address))
+        }
+
+        /// Reply to `request`, addressing the reply as RFC 2131, section 4.1
+        /// asks.
+        fn reply(&self, stack: &Stack, request: &DhcpMessage, message_type: DhcpMessageType, yiaddr: Ipv4Addr) {
+            let mut reply = DhcpMessage::reply(message_type, request);
+            reply.yiaddr = yiaddr;
+            reply.options.push(DhcpOption::ServerIdentifier(self.server_id));
+            if message_type != DhcpMessageType::Nak {
+                if message_type != DhcpMessageType::Ack || !yiaddr.is_unspecified() {
+                    let secs = self.config.lease_time.as_secs().min(u32::MAX as u64) as u32;
+                    reply.options.push(DhcpOption::LeaseTime(secs));
+                    if secs != u32::MAX {
+                        reply.options.push(DhcpOption::RenewalTime(secs / 2));
+                        reply.options.push(DhcpOption::RebindingTime((secs as u64 * 7 / 8) as u32));
+                    }
+                }
+                reply.options.push(DhcpOption::SubnetMask(Ipv4Addr::from(prefix_mask(self.config.prefix_len))));
+                if !self.config.routers.is_empty() {
+                    reply.options.push(DhcpOption::Router(self.config.routers.clone()));
+                }
+                if !self.config.dns_servers.is_empty() {
+                    reply.options.push(DhcpOption::DnsServers(self.config.dns_servers.clone()));
+                }
+            }
+
+            let src = SocketAddrV4::new(self.server_id, SERVER_PORT);
+            if !request.giaddr.is_unspecified() {
+                udp::send_to(stack, src, SocketAddrV4::new(request.giaddr, SERVER_PORT), &reply.encode());
+            }
+            else if message_type == DhcpMessageType::Nak || (request.ciaddr.is_unspecified() && request.broadcast) {
+                send_on(stack, self.link, src, broadcast(CLIENT_PORT), &crate::mac::mac::broadcast(), &reply);
+            }
+            else if !request.ciaddr.is_unspecified() {
+                udp::send_to(stack, src, SocketAddrV4::new(request.ciaddr, CLIENT_PORT), &reply.encode());
+            }
+            else {
+                // The client cannot answer ARP yet.
+                send_on(stack, self.link, src, SocketAddrV4::new(yiaddr, CLIENT_PORT), &request.chaddr, &reply);
+            }
+        }
+    }
+}
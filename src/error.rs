@@ -0,0 +1,227 @@
+#![allow(dead_code)]
+#![allow(unused)]
+
+pub mod error {
+    //! Error types of rlink. All fallible operations of the library return
+    //! `RlinkError`, which wraps the underlying pcap and MAC lookup errors
+    //! and keeps enough context for callers to recover programmatically.
+
+    use crate::{Packet, Raw};
+    use mac_address::MacAddressError;
+    use pcap::Error as PError;
+    use std::error::Error;
+    use std::fmt;
+
+    /// Errors of rlink operations.
+    #[derive(Debug)]
+    pub enum RlinkError {
+        /// Error reported by libpcap
+        Pcap(PError),
+        /// Error reported by the operating system
+        Io(std::io::Error),
+        /// An invalid device name is requested
+        InvalidDeviceName {
+            /// The name that was requested
+            name: String,
+            /// Names of devices that are available
+            available: Vec<String>,
+        },
+        /// Looking up the MAC address of a device failed
+        MacLookup {
+            /// Name of the device
+            device: String,
+            /// Underlying error, or `None` if the device has no MAC address
+            source: Option<MacAddressError>,
+        },
+        /// Payload exceeds maximum frame size
+        PayloadTooLarge {
+            size: usize,
+            max: usize,
+        },
+        /// Payload size mismatch with specified length
+        PayloadLengthMismatch {
+            expected: usize,
+            actual: usize,
+        },
+        /// Invalid Packet Format
+        InvalidPacket(Packet::<Raw>, FrameError),
+        /// Broken Device Pool
+        BrokenDevicePool,
+        /// EtherType value already in use
+        EtherTypeTaken(u16),
+        /// Unrecognized EtherType name or value
+        InvalidEtherType(String),
+        /// Malformed MAC address string
+        InvalidMacAddress(String),
+        /// Malformed interface name pattern
+        InvalidPattern(String),
+        /// Malformed route specification
+        InvalidRoute(String),
+        /// No route towards the destination
+        NoRoute(std::net::IpAddr),
+        /// The named link has no address to send from
+        NoAddress(String),
+        /// An operation did not complete in time
+        Timeout,
+        /// The port is already bound
+        AddrInUse(u16),
+        /// A network namespace could not be opened
+        InvalidNetns {
+            name: String,
+            source: std::io::Error,
+        },
+        /// The thread could not return from a network namespace it entered,
+        /// and is still in it
+        NetnsRestore {
+            name: String,
+            source: std::io::Error,
+        },
+        /// The peer refused the connection
+        ConnectionRefused(std::net::SocketAddr),
+        /// The peer reset the connection
+        ConnectionReset,
+        /// The connection is not established, or was closed for sending
+        NotConnected,
+        /// Inconsistent configuration
+        InvalidConfig(String),
+        /// Malformed filter expression
+        InvalidFilter(String),
+        /// Unknown or malformed command line argument
+        InvalidArgument(String),
+    }
+
+    /// Reasons for a frame to be rejected by a parser.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum FrameError {
+        /// Frame is shorter than the minimum size
+        TooSmall {
+            size: usize,
+            min: usize,
+        },
+        /// Frame Check Sequence does not match the content
+        ChecksumMismatch {
+            expected: u32,
+            actual: u32,
+        },
+        /// A header or field extends beyond the end of the data
+        Truncated {
+            /// Offset at which the field starts
+            offset: usize,
+            /// Bytes required from `offset`
+            needed: usize,
+            /// Bytes actually present from `offset`
+            available: usize,
+        },
+        /// A field holds a value the parser does not accept
+        InvalidField {
+            /// Offset of the field
+            offset: usize,
+            /// Name of the field
+            field: &'static str,
+        },
+    }
+
+    impl fmt::Display for FrameError {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            use FrameError::*;
+            match self {
+                TooSmall { size, min } =>
+                    write!(f, "packet size too small ({} < {} bytes)", size, min),
+                ChecksumMismatch { expected, actual } =>
+                    write!(f, "checksum mismatch (expected 0x{:0>8X}, found 0x{:0>8X})", expected, actual),
+                Truncated { offset, needed, available } =>
+                    write!(f, "truncated at offset {} ({} bytes needed, {} available)", offset, needed, available),
+                InvalidField { offset, field } =>
+                    write!(f, "invalid {} at offset {}", field, offset),
+            }
+        }
+    }
+
+    impl Error for FrameError {}
+
+    impl fmt::Display for RlinkError {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            use RlinkError::*;
+            match self {
+                Pcap(e) => write!(f, "pcap error: {}", e),
+                Io(e) => write!(f, "I/O error: {}", e),
+                InvalidDeviceName { name, available } =>
+                    write!(f, "invalid device name: {:?} (available: {})", name, available.join(", ")),
+                MacLookup { device, source: Some(_) } =>
+                    write!(f, "failed to look up MAC address of {:?}", device),
+                MacLookup { device, source: None } =>
+                    write!(f, "device {:?} has no MAC address", device),
+                PayloadTooLarge { size, max } =>
+                    write!(f, "payload too large ({} > {} bytes)", size, max),
+                PayloadLengthMismatch { expected, actual } =>
+                    write!(f, "payload size mismatch with ether type (expected {}, found {})", expected, actual),
+                InvalidPacket(_, why) => write!(f, "invalid packet format: {}", why),
+                BrokenDevicePool => write!(f, "all devices in the pool are offline"),
+                EtherTypeTaken(value) => write!(f, "ether type 0x{:0>4X} already in use", value),
+                InvalidEtherType(s) => write!(f, "invalid ether type: {:?}", s),
+                InvalidMacAddress(s) => write!(f, "invalid MAC address: {:?}", s),
+                InvalidPattern(s) => write!(f, "invalid interface pattern: {:?}", s),
+                InvalidRoute(s) => write!(f, "invalid route: {:?}", s),
+                NoRoute(addr) => write!(f, "no route to {}", addr),
+                NoAddress(link) => write!(f, "no address assigned to {}", link),
+                Timeout => write!(f, "operation timed out"),
+                AddrInUse(0) => write!(f, "no free port"),
+                AddrInUse(port) => write!(f, "port {} already in use", port),
+                InvalidNetns { name, .. } => write!(f, "invalid network namespace: {:?}", name),
+                NetnsRestore { name, .. } => write!(f, "failed to leave network namespace {:?}", name),
+                ConnectionRefused(addr) => write!(f, "connection to {} refused", addr),
+                ConnectionReset => write!(f, "connection reset by peer"),
+                NotConnected => write!(f, "not connected"),
+                InvalidConfig(why) => write!(f, "invalid configuration: {}", why),
+                InvalidFilter(why) => write!(f, "invalid filter: {}", why),
+                InvalidArgument(why) => write!(f, "invalid argument: {}", why),
+            }
+        }
+    }
+
+    impl Error for RlinkError {
+        fn source(&self) -> Option<&(dyn Error + 'static)> {
+            use RlinkError::*;
+            match self {
+                Pcap(e) => Some(e),
+                Io(e) => Some(e),
+                MacLookup { source: Some(e), .. } => Some(e),
+                InvalidNetns { source, .. } => Some(source),
+                NetnsRestore { source, .. } => Some(source),
+                InvalidPacket(_, why) => Some(why),
+                _ => None,
+            }
+        }
+    }
+
+    impl From<PError> for RlinkError {
+        fn from(e: PError) -> Self {
+            RlinkError::Pcap(e)
+        }
+    }
+
+    impl From<std::io::Error> for RlinkError {
+        fn from(e: std::io::Error) -> Self {
+            RlinkError::Io(e)
+        }
+    }
+
+    impl RlinkError {
+        /// The offending packet, if this error was raised while parsing one.
+        /// Allows the caller to recover the data.
+        pub fn into_packet(self) -> Option<Packet<Raw>> {
+            match self {
+                RlinkError::InvalidPacket(packet, _) => Some(packet),
+                _ => None,
+            }
+        }
+
+        /// The parse failure reason, if this is an `InvalidPacket`.
+        pub fn frame_error(&self) -> Option<FrameError> {
+            match self {
+                RlinkError::InvalidPacket(_, why) => Some(*why),
+                _ => None,
+            }
+        }
+    }
+}
@@ -2,55 +2,221 @@
 #![allow(unused)]
 
 pub mod ethtype {
-    //! Collection of EtherType values for notable protocols.
-    #[derive(Clone, Debug, Copy, PartialEq)]
-    pub enum EtherType {
-        IPv4,
+    //! Registry of EtherType values, following the IEEE public listing.
+    //! Applications may register private EtherTypes at runtime with
+    //! `EtherType::register()`, which are then recognized when parsing
+    //! frames and strings.
+
+    use crate::RlinkError;
+    use std::collections::HashMap;
+    use std::str::FromStr;
+    use std::sync::{OnceLock, RwLock};
+
+    /// Generates the `EtherType` enum and its lookup tables from a list
+    /// of `(variant, value, abbreviation, name)` entries.
+    macro_rules! ethertypes {
+        ($( $(#[$meta:meta])* $variant:ident = $value:literal, $abbr:literal, $name:literal; )*) => {
+            /// EtherTypes compare and hash by value, so that a frame parsed
+            /// as `UNKNOWN(v)` before `v` was registered equals `Custom(v)`.
+            #[derive(Clone, Debug, Copy)]
+            pub enum EtherType {
+                $( $(#[$meta])* $variant, )*
+                /// Length field of an IEEE 802.3 frame (values up to 1500).
+                IEEE802_3(u16),
+                /// A private EtherType registered with `EtherType::register()`.
+                Custom(u16),
+                UNKNOWN(u16),
+            }
+
+            impl EtherType {
+                /// All well-known EtherTypes, in ascending order of value.
+                pub const KNOWN: &'static [EtherType] = &[ $( EtherType::$variant, )* ];
+
+                fn known_from(value: u16) -> Option<Self> {
+                    match value {
+                        $( $value => Some(EtherType::$variant), )*
+                        _ => None,
+                    }
+                }
+
+                fn known_info(&self) -> Option<(u16, &'static str, &'static str)> {
+                    match *self {
+                        $( EtherType::$variant => Some(($value, $abbr, $name)), )*
+                        _ => None,
+                    }
+                }
+            }
+        };
+    }
+
+    ethertypes! {
+        IPv4 = 0x0800, "IPv4", "Internet Protocol version 4";
+        ARP = 0x0806, "ARP", "Address Resolution Protocol";
+        WakeOnLan = 0x0842, "WoL", "Wake-on-LAN";
         /// Neighbor Detection Protocol, part of Rip implementation.
-        NDP,
-        ARP,
-        RARP,
-        IPX,
-        IPv6,
-        EFC,
-        IEEE802_3(u16),
-        UNKNOWN(u16),
+        /// The number is my birthday.
+        NDP = 0x1101, "NDP", "Neighbor Detection Protocol";
+        AVTP = 0x22F0, "AVTP", "Audio Video Transport Protocol";
+        TRILL = 0x22F3, "TRILL", "IETF TRILL Protocol";
+        DECMop = 0x6002, "MOP", "DEC MOP RC";
+        DECnet = 0x6003, "DECnet", "DECnet Phase IV";
+        DECLat = 0x6004, "LAT", "DEC LAT";
+        RARP = 0x8035, "RARP", "Reverse Address Resolution Protocol";
+        AppleTalk = 0x809B, "AppleTalk", "AppleTalk (EtherTalk)";
+        AARP = 0x80F3, "AARP", "AppleTalk Address Resolution Protocol";
+        VLAN = 0x8100, "VLAN", "IEEE 802.1Q VLAN-tagged frame";
+        SLPP = 0x8102, "SLPP", "Simple Loop Prevention Protocol";
+        VLACP = 0x8103, "VLACP", "Virtual Link Aggregation Control Protocol";
+        IPX = 0x8137, "IPX", "Internetwork Packet Exchange";
+        QNXQnet = 0x8204, "Qnet", "QNX Qnet";
+        IPv6 = 0x86DD, "IPv6", "Internet Protocol version 6";
+        EFC = 0x8808, "EFC", "Ethernet Flow Control";
+        LACP = 0x8809, "LACP", "Ethernet Slow Protocols (LACP)";
+        CobraNet = 0x8819, "CobraNet", "CobraNet";
+        MPLS = 0x8847, "MPLS", "MPLS unicast";
+        MPLSMulticast = 0x8848, "MPLS-MC", "MPLS multicast";
+        PPPoEDiscovery = 0x8863, "PPPoE-D", "PPPoE Discovery Stage";
+        PPPoESession = 0x8864, "PPPoE-S", "PPPoE Session Stage";
+        HomePlug = 0x887B, "HomePlug", "HomePlug 1.0 MME";
+        EAPOL = 0x888E, "EAPOL", "EAP over LAN (IEEE 802.1X)";
+        PROFINET = 0x8892, "PROFINET", "PROFINET Protocol";
+        HyperSCSI = 0x889A, "HyperSCSI", "HyperSCSI (SCSI over Ethernet)";
+        ATAoE = 0x88A2, "AoE", "ATA over Ethernet";
+        EtherCAT = 0x88A4, "EtherCAT", "EtherCAT Protocol";
+        QinQ = 0x88A8, "QinQ", "IEEE 802.1ad Service VLAN (Q-in-Q)";
+        Powerlink = 0x88AB, "EPL", "Ethernet Powerlink";
+        GOOSE = 0x88B8, "GOOSE", "Generic Object Oriented Substation event";
+        GSE = 0x88B9, "GSE", "GSE (Generic Substation Events) Management Services";
+        SV = 0x88BA, "SV", "Sampled Value Transmission";
+        MikroTikRoMON = 0x88BF, "RoMON", "MikroTik RoMON";
+        LLDP = 0x88CC, "LLDP", "Link Layer Discovery Protocol";
+        SERCOS = 0x88CD, "SERCOS", "SERCOS III";
+        HomePlugAV = 0x88E1, "HomePlugAV", "HomePlug Green PHY";
+        MRP = 0x88E3, "MRP", "Media Redundancy Protocol (IEC 62439-2)";
+        MACsec = 0x88E5, "MACsec", "IEEE 802.1AE MAC security";
+        PBB = 0x88E7, "PBB", "Provider Backbone Bridges (IEEE 802.1ah)";
+        PTP = 0x88F7, "PTP", "Precision Time Protocol (IEEE 1588)";
+        NCSI = 0x88F8, "NC-SI", "Network Controller Sideband Interface";
+        PRP = 0x88FB, "PRP", "Parallel Redundancy Protocol (IEC 62439-3)";
+        CFM = 0x8902, "CFM", "IEEE 802.1ag Connectivity Fault Management";
+        FCoE = 0x8906, "FCoE", "Fibre Channel over Ethernet";
+        FIP = 0x8914, "FIP", "FCoE Initialization Protocol";
+        RoCE = 0x8915, "RoCE", "RDMA over Converged Ethernet";
+        TTE = 0x891D, "TTE", "TTEthernet Protocol Control Frame";
+        HSR = 0x892F, "HSR", "High-availability Seamless Redundancy";
+        IEEE1905 = 0x893A, "1905.1", "IEEE 1905.1 Protocol";
+        ECTP = 0x9000, "ECTP", "Ethernet Configuration Testing Protocol";
+        VLANDoubleTag = 0x9100, "Q-in-Q(legacy)", "VLAN-tagged frame with double tagging";
+        RedundancyTag = 0xF1C1, "R-Tag", "Redundancy Tag (IEEE 802.1CB)";
+    }
+
+    /// A private EtherType registered at runtime.
+    #[derive(Clone, Debug)]
+    struct CustomEntry {
+        abbreviation: &'static str,
+        name: &'static str,
+    }
+
+    fn custom_registry() -> &'static RwLock<HashMap<u16, CustomEntry>> {
+        static REGISTRY: OnceLock<RwLock<HashMap<u16, CustomEntry>>> = OnceLock::new();
+        REGISTRY.get_or_init(|| RwLock::new(HashMap::new()))
+    }
+
+    impl EtherType {
+        /// Register a private EtherType, so that frames carrying `value` are
+        /// parsed as `EtherType::Custom(value)` and `abbreviation` is accepted
+        /// by `FromStr`.
+        ///
+        /// Fails if `value` is below 0x0600, where EtherTypes start, or is
+        /// already taken by a well-known or previously registered EtherType.
+        pub fn register(value: u16, abbreviation: &'static str, name: &'static str)
+            -> Result<EtherType, RlinkError> {
+            if value < 0x0600 || EtherType::known_from(value).is_some() {
+                return Err(RlinkError::EtherTypeTaken(value));
+            }
+            let mut registry = custom_registry().write().unwrap();
+            if registry.contains_key(&value) {
+                return Err(RlinkError::EtherTypeTaken(value));
+            }
+            registry.insert(value, CustomEntry { abbreviation, name });
+            Ok(EtherType::Custom(value))
+        }
+
+        /// Remove a private EtherType previously added with `register()`.
+        /// Returns whether it was registered.
+        pub fn unregister(value: u16) -> bool {
+            custom_registry().write().unwrap().remove(&value).is_some()
+        }
+
+        /// Short name of this EtherType, e.g. "ARP".
+        pub fn abbreviation(&self) -> Option<&'static str> {
+            match *self {
+                EtherType::Custom(value) => custom_registry().read().unwrap()
+                    .get(&value)
+                    .map(|entry| entry.abbreviation),
+                _ => self.known_info().map(|(_, abbr, _)| abbr),
+            }
+        }
+
+        /// Descriptive name of this EtherType, e.g. "Address Resolution Protocol".
+        pub fn name(&self) -> Option<&'static str> {
+            match *self {
+                EtherType::Custom(value) => custom_registry().read().unwrap()
+                    .get(&value)
+                    .map(|entry| entry.name),
+                _ => self.known_info().map(|(_, _, name)| name),
+            }
+        }
+
+        /// Whether the field holds a frame length rather than a protocol.
+        pub fn is_length(&self) -> bool {
+            matches!(self, EtherType::IEEE802_3(_))
+        }
     }
-    
+
     use super::ethtype::EtherType::*;
     impl std::fmt::Display for EtherType {
         fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
             match *self {
-                IPv4 => write!(f, "Internet Protocol version 4"),
-                NDP => write!(f, "Neighbor Detection Protocol"),
-                ARP => write!(f, "Address Resolution Protocol"),
-                RARP => write!(f, "Reverse Address Resolution Protocol"),
-                IPX => write!(f, "Internetwork Packet Exchange"),
-                IPv6 => write!(f, "Internet Protocol version 6"),
-                EFC => write!(f, "Reverse Address Resolution Protocol"),
                 IEEE802_3(len) => write!(f, "IEEE 802.3 length field ({})", len),
                 UNKNOWN(value) => write!(f, "Unknown EtherType ({})", value),
+                Custom(value) => match self.name() {
+                    Some(name) => write!(f, "{}", name),
+                    None => write!(f, "Unregistered EtherType (0x{:0>4X})", value),
+                },
+                _ => write!(f, "{}", self.name().unwrap()),
             }
         }
     }
-    
+
+    impl PartialEq for EtherType {
+        fn eq(&self, other: &Self) -> bool {
+            u16::from(*self) == u16::from(*other)
+        }
+    }
+
+    impl Eq for EtherType {}
+
+    impl std::hash::Hash for EtherType {
+        fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+            u16::from(*self).hash(state);
+        }
+    }
+
     use std::convert::From;
     impl From<u16> for EtherType {
         fn from(value: u16) -> Self {
             if value <= 1500 {
                 IEEE802_3(value)
             }
+            else if let Some(ethtype) = EtherType::known_from(value) {
+                ethtype
+            }
+            else if custom_registry().read().unwrap().contains_key(&value) {
+                Custom(value)
+            }
             else {
-                match value {
-                    0x0800 => IPv4,
-                    0x1101 => NDP,
-                    0x0806 => ARP,
-                    0x8035 => RARP,
-                    0x8137 => IPX,
-                    0x86DD => IPv6,
-                    0x8808 => EFC,
-                    _ => UNKNOWN(value),
-                }
+                UNKNOWN(value)
             }
         }
     }
@@ -58,17 +224,66 @@ pub mod ethtype {
     impl From<EtherType> for u16 {
         fn from(ethtype: EtherType) -> Self {
             match ethtype {
-                IPv4 => 0x0800,
-                /// Rip specific. The number is my birthday.
-                NDP => 0x1101,
-                ARP => 0x0806,
-                RARP => 0x8035,
-                IPX => 0x8137,
-                IPv6 => 0x86DD,
-                EFC => 0x8808,
                 IEEE802_3(len) => len,
+                Custom(value) => value,
                 UNKNOWN(value) => value,
+                _ => ethtype.known_info().unwrap().0,
             }
         }
     }
-}
\ No newline at end of file
+
+    impl FromStr for EtherType {
+        type Err = RlinkError;
+
+        /// Parses an EtherType from its abbreviation (case-insensitive, e.g.
+        /// `arp`), or from its numeric value in hex (`0x0806`) or decimal.
+        fn from_str(s: &str) -> Result<Self, Self::Err> {
+            let s = s.trim();
+            let value = if let Some(hex) = s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+                u16::from_str_radix(hex, 16).ok()
+            }
+            else {
+                s.parse::<u16>().ok()
+            };
+            if let Some(value) = value {
+                return Ok(EtherType::from(value));
+            }
+
+            if let Some(ethtype) = EtherType::KNOWN.iter()
+                .find(|ethtype| ethtype.abbreviation().unwrap().eq_ignore_ascii_case(s)) {
+                return Ok(*ethtype);
+            }
+            custom_registry().read().unwrap()
+                .iter()
+                .find(|(_, entry)| entry.abbreviation.eq_ignore_ascii_case(s))
+                .map(|(value, _)| Custom(*value))
+                .ok_or_else(|| RlinkError::InvalidEtherType(s.to_string()))
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use std::collections::HashSet;
+
+        #[test]
+        fn register() {
+            assert!(EtherType::register(1501, "L", "Length").is_err());
+            assert!(EtherType::register(0x05FF, "L", "Length").is_err());
+            assert!(EtherType::register(0x0806, "A", "ARP again").is_err());
+
+            let parsed = EtherType::from(0x88B6);
+            assert!(matches!(parsed, UNKNOWN(0x88B6)));
+            assert_eq!(parsed.to_string(), "Unknown EtherType (34998)");
+            let registered = EtherType::register(0x88B6, "EXP2", "Local experimental 2").unwrap();
+            assert!(EtherType::register(0x88B6, "EXP2", "Local experimental 2").is_err());
+            assert!(matches!(EtherType::from(0x88B6), Custom(0x88B6)));
+            assert_eq!("exp2".parse::<EtherType>().unwrap(), registered);
+            // Frames parsed before the registration still match
+            assert_eq!(parsed, registered);
+            assert!(HashSet::from([parsed]).contains(&registered));
+            assert!(EtherType::unregister(0x88B6));
+            assert!(!EtherType::unregister(0x88B6));
+        }
+    }
+}
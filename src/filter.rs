@@ -0,0 +1,690 @@
+#![allow(dead_code)]
+#![allow(unused)]
+
+pub mod filter {
+    //! Filter expressions evaluated in user space, in a subset of the
+    //! pcap-filter syntax. Unlike `DeviceHandle::filter()`, which hands a
+    //! BPF program to libpcap, they apply to frames from any source: pool
+    //! callbacks, replays or in-memory links.
+    //!
+    //! Supported primitives:
+    //!
+    //! - `ether src|dst|host MAC`, `ether proto TYPE` (name or number),
+    //!   `arp`, `ip`, `ip6`
+    //! - `vlan [ID]`
+    //! - `[ip|ip6] [src|dst] host ADDR`, `[src|dst] net ADDR/LEN`
+    //! - `icmp`, `icmp6`, `tcp`, `udp`, `ip proto N`
+    //! - `[tcp|udp] [src|dst] port N`
+    //!
+    //! combined with `not`/`!`, `and`/`&&`, `or`/`||` and parentheses, e.g.
+    //! `arp or (udp port 53 and not src net 10.0.0.0/8)`. As in pcap,
+    //! `host` without `ip` also matches the addresses of ARP packets. Layer
+    //! 3 and 4 primitives look past VLAN tags.
+
+    use crate::mac::mac::parse_mac;
+    use crate::packet::packet::Type;
+    use crate::{EtherType, Packet, RlinkError};
+    use mac_address::MacAddress;
+    use std::fmt;
+    use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+    use std::str::FromStr;
+
+    const IPPROTO_ICMP: u8 = 1;
+    const IPPROTO_TCP: u8 = 6;
+    const IPPROTO_UDP: u8 = 17;
+    const IPPROTO_ICMPV6: u8 = 58;
+
+    /// Which address or port of a packet a primitive looks at.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub enum Dir {
+        Src,
+        Dst,
+        /// Either the source or the destination
+        Any,
+    }
+
+    /// A compiled filter expression.
+    #[derive(Clone, Debug, PartialEq, Eq)]
+    pub enum Filter {
+        Not(Box<Filter>),
+        And(Box<Filter>, Box<Filter>),
+        Or(Box<Filter>, Box<Filter>),
+        EtherAddr(Dir, MacAddress),
+        EtherProto(EtherType),
+        /// A VLAN tagged frame, with the given outer VLAN ID if any
+        Vlan(Option<u16>),
+        /// An IP address; with `arp`, also the addresses of ARP packets
+        Host { dir: Dir, addr: IpAddr, arp: bool },
+        Net { dir: Dir, addr: IpAddr, prefix_len: u8 },
+        /// IPv4 protocol or IPv6 next header
+        Proto(u8),
+        /// A TCP or UDP port, of the given protocol if any
+        Port { proto: Option<u8>, dir: Dir, port: u16 },
+    }
+
+    /// The layers of a frame relevant to filters.
+    struct View<'a> {
+        frame: &'a [u8],
+        /// Outer VLAN ID
+        vlan: Option<u16>,
+        /// EtherType past VLAN tags
+        ethtype: u16,
+        /// Payload past VLAN tags
+        payload: &'a [u8],
+    }
+
+    impl<'a> View<'a> {
+        fn new(frame: &'a [u8]) -> Option<View<'a>> {
+            if frame.len() < 14 {
+                return None;
+            }
+            let mut vlan = None;
+            let mut ethtype = u16::from_be_bytes([frame[12], frame[13]]);
+            let mut payload = &frame[14..];
+            while matches!(ethtype, 0x8100 | 0x88A8 | 0x9100) && payload.len() >= 4 {
+                vlan.get_or_insert(u16::from_be_bytes([payload[0], payload[1]]) & 0x0FFF);
+                ethtype = u16::from_be_bytes([payload[2], payload[3]]);
+                payload = &payload[4..];
+            }
+            Some(View { frame, vlan, ethtype, payload })
+        }
+
+        /// Source and destination addresses of an IP datagram or ARP
+        /// packet, the latter only with `arp`.
+        fn addresses(&self, arp: bool) -> Option<(IpAddr, IpAddr)> {
+            let p = self.payload;
+            match self.ethtype {
+                0x0800 if p.len() >= 20 => Some((
+                    Ipv4Addr::from(<[u8; 4]>::try_from(&p[12..16]).unwrap()).into(),
+                    Ipv4Addr::from(<[u8; 4]>::try_from(&p[16..20]).unwrap()).into(),
+                )),
+                0x86DD if p.len() >= 40 => Some((
+                    Ipv6Addr::from(<[u8; 16]>::try_from(&p[8..24]).unwrap()).into(),
+                    Ipv6Addr::from(<[u8; 16]>::try_from(&p[24..40]).unwrap()).into(),
+                )),
+                // Ethernet/IPv4 ARP: sender and target protocol addresses
+                0x0806 if arp && p.len() >= 28 && p[4] == 6 && p[5] == 4 => Some((
+                    Ipv4Addr::from(<[u8; 4]>::try_from(&p[14..18]).unwrap()).into(),
+                    Ipv4Addr::from(<[u8; 4]>::try_from(&p[24..28]).unwrap()).into(),
+                )),
+                _ => None,
+            }
+        }
+
+        /// Protocol and payload of an IP datagram. The payload is empty
+        /// for non-first fragments.
+        fn transport(&self) -> Option<(u8, &'a [u8])> {
+            let p = self.payload;
+            match self.ethtype {
+                0x0800 if p.len() >= 20 => {
+                    let header_len = ((p[0] & 0x0F) as usize * 4).clamp(20, p.len());
+                    let fragment_offset = u16::from_be_bytes([p[6], p[7]]) & 0x1FFF;
+                    Some((p[9], if fragment_offset == 0 { &p[header_len..] } else { &[] }))
+                }
+                0x86DD if p.len() >= 40 => Some((p[6], &p[40..])),
+                _ => None,
+            }
+        }
+
+        fn ports(&self, proto: Option<u8>) -> Option<(u16, u16)> {
+            match self.transport()? {
+                (found, l4) if (found == IPPROTO_TCP || found == IPPROTO_UDP)
+                    && proto.is_none_or(|proto| proto == found) && l4.len() >= 4 =>
+                    Some((u16::from_be_bytes([l4[0], l4[1]]), u16::from_be_bytes([l4[2], l4[3]]))),
+                _ => None,
+            }
+        }
+    }
+
+    fn either<T: PartialEq>(dir: Dir, (src, dst): (T, T), matches: impl Fn(&T) -> bool) -> bool {
+        match dir {
+            Dir::Src => matches(&src),
+            Dir::Dst => matches(&dst),
+            Dir::Any => matches(&src) || matches(&dst),
+        }
+    }
+
+    fn in_net(addr: &IpAddr, net: &IpAddr, prefix_len: u8) -> bool {
+        match (addr, net) {
+            (IpAddr::V4(addr), IpAddr::V4(net)) => {
+                let mask = u32::MAX.checked_shl(32 - prefix_len as u32).unwrap_or(0);
+                u32::from(*addr) & mask == u32::from(*net) & mask
+            }
+            (IpAddr::V6(addr), IpAddr::V6(net)) => {
+                let mask = u128::MAX.checked_shl(128 - prefix_len as u32).unwrap_or(0);
+                u128::from(*addr) & mask == u128::from(*net) & mask
+            }
+            _ => false,
+        }
+    }
+
+    impl Filter {
+        /// Whether a packet, of any layer, matches.
+        pub fn matches<T: Type>(&self, packet: &Packet<T>) -> bool {
+            self.matches_frame(&packet.data)
+        }
+
+        /// Whether an Ethernet frame, starting with its header, matches.
+        pub fn matches_frame(&self, frame: &[u8]) -> bool {
+            match View::new(frame) {
+                Some(view) => self.eval(&view),
+                None => false,
+            }
+        }
+
+        fn eval(&self, view: &View) -> bool {
+            match self {
+                Filter::Not(inner) => !inner.eval(view),
+                Filter::And(a, b) => a.eval(view) && b.eval(view),
+                Filter::Or(a, b) => a.eval(view) || b.eval(view),
+                Filter::EtherAddr(dir, mac) => either(*dir, (&view.frame[6..12], &view.frame[0..6]),
+                    |addr| *addr == mac.bytes().as_slice()),
+                Filter::EtherProto(ethtype) => view.ethtype == u16::from(*ethtype)
+                    || u16::from_be_bytes([view.frame[12], view.frame[13]]) == u16::from(*ethtype),
+                Filter::Vlan(id) => view.vlan.is_some() && (id.is_none() || view.vlan == *id),
+                Filter::Host { dir, addr, arp } => view.addresses(*arp)
+                    .is_some_and(|addresses| either(*dir, addresses, |found| found == addr)),
+                Filter::Net { dir, addr, prefix_len } => view.addresses(true)
+                    .is_some_and(|addresses| either(*dir, addresses, |found| in_net(found, addr, *prefix_len))),
+                Filter::Proto(proto) => view.transport().is_some_and(|(found, _)| found == *proto),
+                Filter::Port { proto, dir, port } => view.ports(*proto)
+                    .is_some_and(|ports| either(*dir, ports, |found| found == port)),
+            }
+        }
+    }
+
+    /// Splits an expression into words, parentheses and `!`.
+    fn tokenize(s: &str) -> Vec<String> {
+        let mut tokens = Vec::new();
+        let mut word = String::new();
+        for c in s.chars() {
+            if c.is_whitespace() || c == '(' || c == ')' || (c == '!' && word.is_empty()) {
+                if !word.is_empty() {
+                    tokens.push(std::mem::take(&mut word));
+                }
+                if !c.is_whitespace() {
+                    tokens.push(c.to_string());
+                }
+            }
+            else {
+                word.push(c);
+            }
+        }
+        if !word.is_empty() {
+            tokens.push(word);
+        }
+        tokens
+    }
+
+    /// Recursive descent parser; `or` binds looser than `and`, which binds
+    /// looser than `not`.
+    struct Parser<'a> {
+        expr: &'a str,
+        tokens: Vec<String>,
+        pos: usize,
+    }
+
+    impl<'a> Parser<'a> {
+        fn invalid<T>(&self, why: String) -> Result<T, RlinkError> {
+            Err(RlinkError::InvalidFilter(format!("{} in {:?}", why, self.expr)))
+        }
+
+        fn peek(&self) -> Option<&str> {
+            self.tokens.get(self.pos).map(|token| token.as_str())
+        }
+
+        fn next(&mut self) -> Option<String> {
+            let token = self.tokens.get(self.pos).cloned();
+            self.pos += 1;
+            token
+        }
+
+        fn accept(&mut self, words: &[&str]) -> bool {
+            match self.peek() {
+                Some(token) if words.contains(&token) => {
+                    self.pos += 1;
+                    true
+                }
+                _ => false,
+            }
+        }
+
+        /// The next word, parsed.
+        fn value<T: FromStr>(&mut self, what: &str) -> Result<T, RlinkError> {
+            match self.next() {
+                Some(token) => match token.parse() {
+                    Ok(value) => Ok(value),
+                    Err(_) => self.invalid(format!("invalid {} {:?}", what, token)),
+                },
+                None => self.invalid(format!("missing {}", what)),
+            }
+        }
+
+        fn or(&mut self) -> Result<Filter, RlinkError> {
+            let mut filter = self.and()?;
+            while self.accept(&["or", "||"]) {
+                filter = Filter::Or(Box::new(filter), Box::new(self.and()?));
+            }
+            Ok(filter)
+        }
+
+        fn and(&mut self) -> Result<Filter, RlinkError> {
+            let mut filter = self.not()?;
+            while self.accept(&["and", "&&"]) {
+                filter = Filter::And(Box::new(filter), Box::new(self.not()?));
+            }
+            Ok(filter)
+        }
+
+        fn not(&mut self) -> Result<Filter, RlinkError> {
+            if self.accept(&["not", "!"]) {
+                return Ok(Filter::Not(Box::new(self.not()?)));
+            }
+            if self.accept(&["("]) {
+                let filter = self.or()?;
+                if !self.accept(&[")"]) {
+                    return self.invalid("missing \")\"".to_string());
+                }
+                return Ok(filter);
+            }
+            self.primitive()
+        }
+
+        fn dir(&mut self) -> Dir {
+            if self.accept(&["src"]) {
+                Dir::Src
+            }
+            else if self.accept(&["dst"]) {
+                Dir::Dst
+            }
+            else {
+                Dir::Any
+            }
+        }
+
+        /// `[src|dst] host|net|port ...`, or `[src|dst] ADDR`, after an
+        /// optional protocol qualifier.
+        fn qualified(&mut self, family: Option<&str>, proto: Option<u8>) -> Result<Filter, RlinkError> {
+            let dir = self.dir();
+            let kind = match self.peek() {
+                Some("host") | Some("net") | Some("port") => self.next().unwrap(),
+                _ if proto.is_some() => "port".to_string(),
+                _ => "host".to_string(),
+            };
+            match (kind.as_str(), proto) {
+                ("host", None) => {
+                    let addr: IpAddr = self.value("address")?;
+                    match (family, addr) {
+                        (Some("ip"), IpAddr::V6(_)) | (Some("ip6"), IpAddr::V4(_)) =>
+                            self.invalid(format!("{} is not an {} address", addr,
+                                if addr.is_ipv4() { "IPv6" } else { "IPv4" })),
+                        _ => Ok(Filter::Host { dir, addr, arp: family.is_none() }),
+                    }
+                }
+                ("net", None) => {
+                    let net: String = self.value("network")?;
+                    let (addr, len) = net.split_once('/').unwrap_or((net.as_str(), ""));
+                    let addr: IpAddr = match addr.parse() {
+                        Ok(addr) => addr,
+                        Err(_) => return self.invalid(format!("invalid network {:?}", net)),
+                    };
+                    let max = if addr.is_ipv4() { 32 } else { 128 };
+                    match if len.is_empty() { Ok(max) } else { len.parse::<u8>() } {
+                        Ok(prefix_len) if prefix_len <= max => Ok(Filter::Net { dir, addr, prefix_len }),
+                        _ => self.invalid(format!("invalid network {:?}", net)),
+                    }
+                }
+                ("port", _) if family.is_none() => Ok(Filter::Port { proto, dir, port: self.value("port")? }),
+                (kind, _) => self.invalid(format!("unexpected {:?}", kind)),
+            }
+        }
+
+        fn primitive(&mut self) -> Result<Filter, RlinkError> {
+            let word = match self.next() {
+                Some(word) => word,
+                None => return self.invalid("unexpected end".to_string()),
+            };
+            let qualifies = |token: Option<&str>| matches!(token,
+                Some("src") | Some("dst") | Some("host") | Some("net") | Some("port"));
+            match word.as_str() {
+                "ether" => match self.next().as_deref() {
+                    Some("src") => Ok(Filter::EtherAddr(Dir::Src, self.mac()?)),
+                    Some("dst") => Ok(Filter::EtherAddr(Dir::Dst, self.mac()?)),
+                    Some("host") => Ok(Filter::EtherAddr(Dir::Any, self.mac()?)),
+                    Some("proto") => {
+                        let name: String = self.value("EtherType")?;
+                        match name.as_str() {
+                            "ip" => Ok(Filter::EtherProto(EtherType::IPv4)),
+                            "ip6" => Ok(Filter::EtherProto(EtherType::IPv6)),
+                            name => match name.parse::<EtherType>() {
+                                Ok(ethtype) => Ok(Filter::EtherProto(ethtype)),
+                                Err(_) => self.invalid(format!("invalid EtherType {:?}", name)),
+                            },
+                        }
+                    }
+                    other => self.invalid(format!("unexpected {:?} after \"ether\"", other.unwrap_or("end"))),
+                },
+                "vlan" => match self.peek().map(|token| token.parse::<u16>()) {
+                    Some(Ok(id)) if id < 4096 => {
+                        self.pos += 1;
+                        Ok(Filter::Vlan(Some(id)))
+                    }
+                    _ => Ok(Filter::Vlan(None)),
+                },
+                "arp" => Ok(Filter::EtherProto(EtherType::ARP)),
+                "ip" if self.accept(&["proto"]) => Ok(Filter::Proto(self.value("protocol")?)),
+                "ip" | "ip6" if qualifies(self.peek()) => self.qualified(Some(&word), None),
+                "ip" => Ok(Filter::EtherProto(EtherType::IPv4)),
+                "ip6" => Ok(Filter::EtherProto(EtherType::IPv6)),
+                "icmp" => Ok(Filter::Proto(IPPROTO_ICMP)),
+                "icmp6" => Ok(Filter::Proto(IPPROTO_ICMPV6)),
+                "tcp" | "udp" => {
+                    let proto = if word == "tcp" { IPPROTO_TCP } else { IPPROTO_UDP };
+                    if qualifies(self.peek()) {
+                        self.qualified(None, Some(proto))
+                    }
+                    else {
+                        Ok(Filter::Proto(proto))
+                    }
+                }
+                "src" | "dst" | "host" | "net" | "port" => {
+                    self.pos -= 1;
+                    self.qualified(None, None)
+                }
+                word => self.invalid(format!("unexpected {:?}", word)),
+            }
+        }
+
+        fn mac(&mut self) -> Result<MacAddress, RlinkError> {
+            match self.next() {
+                Some(token) => parse_mac(&token)
+                    .or_else(|_| self.invalid(format!("invalid MAC address {:?}", token))),
+                None => self.invalid("missing MAC address".to_string()),
+            }
+        }
+    }
+
+    impl FromStr for Filter {
+        type Err = RlinkError;
+
+        fn from_str(s: &str) -> Result<Self, Self::Err> {
+            let mut parser = Parser { expr: s, tokens: tokenize(s), pos: 0 };
+            let filter = parser.or()?;
+            match parser.peek() {
+                None => Ok(filter),
+                Some(token) => parser.invalid(format!("unexpected {:?}", token)),
+            }
+        }
+    }
+
+    impl fmt::Display for Dir {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            match self {
+                Dir::Src => write!(f, "src "),
+                Dir::Dst => write!(f, "dst "),
+                Dir::Any => Ok(()),
+            }
+        }
+    }
+
+    /// Displays the filter as an expression parsing back to it.
+    impl fmt::Display for Filter {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            // Parenthesize operands binding looser than the operator
+            let operand = |f: &mut fmt::Formatter, filter: &Filter, looser: fn(&Filter) -> bool| {
+                if looser(filter) { write!(f, "({})", filter) } else { write!(f, "{}", filter) }
+            };
+            match self {
+                Filter::Not(inner) => {
+                    write!(f, "not ")?;
+                    operand(f, inner, |inner| matches!(inner, Filter::And(..) | Filter::Or(..)))
+                }
+                Filter::And(a, b) => {
+                    operand(f, a, |a| matches!(a, Filter::Or(..)))?;
+                    write!(f, " and ")?;
+                    operand(f, b, |b| matches!(b, Filter::And(..) | Filter::Or(..)))
+                }
+                Filter::Or(a, b) => {
+                    write!(f, "{} or ", a)?;
+                    operand(f, b, |b| matches!(b, Filter::Or(..)))
+                }
+                Filter::EtherAddr(Dir::Any, mac) => write!(f, "ether host {}", mac),
+                Filter::EtherAddr(dir, mac) => write!(f, "ether {}{}", dir, mac),
+                Filter::EtherProto(EtherType::ARP) => write!(f, "arp"),
+                Filter::EtherProto(EtherType::IPv4) => write!(f, "ip"),
+                Filter::EtherProto(EtherType::IPv6) => write!(f, "ip6"),
+                Filter::EtherProto(ethtype) => write!(f, "ether proto 0x{:04X}", u16::from(*ethtype)),
+                Filter::Vlan(None) => write!(f, "vlan"),
+                Filter::Vlan(Some(id)) => write!(f, "vlan {}", id),
+                Filter::Host { dir, addr, arp: true } => write!(f, "{}host {}", dir, addr),
+                Filter::Host { dir, addr: addr @ IpAddr::V4(_), arp: false } => write!(f, "ip {}host {}", dir, addr),
+                Filter::Host { dir, addr, arp: false } => write!(f, "ip6 {}host {}", dir, addr),
+                Filter::Net { dir, addr, prefix_len } => write!(f, "{}net {}/{}", dir, addr, prefix_len),
+                Filter::Proto(IPPROTO_ICMP) => write!(f, "icmp"),
+                Filter::Proto(IPPROTO_ICMPV6) => write!(f, "icmp6"),
+                Filter::Proto(IPPROTO_TCP) => write!(f, "tcp"),
+                Filter::Proto(IPPROTO_UDP) => write!(f, "udp"),
+                Filter::Proto(proto) => write!(f, "ip proto {}", proto),
+                Filter::Port { proto, dir, port } => {
+                    match proto {
+                        Some(IPPROTO_TCP) => write!(f, "tcp ")?,
+                        Some(_) => write!(f, "udp ")?,
+                        None => {}
+                    }
+                    write!(f, "{}port {}", dir, port)
+                }
+            }
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        fn parse(expr: &str) -> Filter {
+            expr.parse().unwrap_or_else(|e| panic!("{}: {}", expr, e))
+        }
+
+        fn host(dir: Dir, addr: &str, arp: bool) -> Filter {
+            Filter::Host { dir, addr: addr.parse().unwrap(), arp }
+        }
+
+        fn not(a: Filter) -> Filter {
+            Filter::Not(Box::new(a))
+        }
+
+        fn and(a: Filter, b: Filter) -> Filter {
+            Filter::And(Box::new(a), Box::new(b))
+        }
+
+        fn or(a: Filter, b: Filter) -> Filter {
+            Filter::Or(Box::new(a), Box::new(b))
+        }
+
+        /// An Ethernet header from 02:00:00:00:00:01 to `dst`.
+        fn ether(dst: [u8; 6], ethtype: u16) -> Vec<u8> {
+            let mut frame = dst.to_vec();
+            frame.extend_from_slice(&[0x02, 0, 0, 0, 0, 0x01]);
+            frame.extend_from_slice(&ethtype.to_be_bytes());
+            frame
+        }
+
+        /// Broadcast ARP request from 10.0.0.1 for 10.0.0.2.
+        fn arp_frame() -> Vec<u8> {
+            let mut frame = ether([0xff; 6], 0x0806);
+            frame.extend_from_slice(&[0, 1, 0x08, 0x00, 6, 4, 0, 1]);
+            frame.extend_from_slice(&[0x02, 0, 0, 0, 0, 0x01, 10, 0, 0, 1]);
+            frame.extend_from_slice(&[0, 0, 0, 0, 0, 0, 10, 0, 0, 2]);
+            frame.resize(60, 0);
+            frame
+        }
+
+        /// DNS query from 192.168.1.1:5353 to 10.1.2.3:53 on VLAN 100.
+        fn vlan_udp_frame() -> Vec<u8> {
+            let mut frame = ether([0x02, 0, 0, 0, 0, 0x02], 0x8100);
+            frame.extend_from_slice(&[0x00, 100, 0x08, 0x00]);
+            frame.extend_from_slice(&[0x45, 0, 0, 28, 0, 0, 0, 0, 64, IPPROTO_UDP, 0, 0]);
+            frame.extend_from_slice(&[192, 168, 1, 1, 10, 1, 2, 3]);
+            frame.extend_from_slice(&[0x14, 0xe9, 0, 53, 0, 8, 0, 0]);
+            frame
+        }
+
+        /// TCP segment from [2001:db8::1]:40000 to [fe80::2]:80.
+        fn ipv6_tcp_frame() -> Vec<u8> {
+            let mut frame = ether([0x33, 0x33, 0, 0, 0, 0x02], 0x86DD);
+            frame.extend_from_slice(&[0x60, 0, 0, 0, 0, 20, IPPROTO_TCP, 64]);
+            frame.extend_from_slice(&"2001:db8::1".parse::<Ipv6Addr>().unwrap().octets());
+            frame.extend_from_slice(&"fe80::2".parse::<Ipv6Addr>().unwrap().octets());
+            frame.extend_from_slice(&[0x9c, 0x40, 0, 80]);
+            frame.resize(frame.len() + 16, 0);
+            frame
+        }
+
+        #[test]
+        fn primitives() {
+            let mac = MacAddress::new([0x02, 0, 0, 0, 0, 0x01]);
+            let cases = [
+                ("ether src 02:00:00:00:00:01", Filter::EtherAddr(Dir::Src, mac)),
+                ("ether dst 02-00-00-00-00-01", Filter::EtherAddr(Dir::Dst, mac)),
+                ("ether host 0200.0000.0001", Filter::EtherAddr(Dir::Any, mac)),
+                ("ether proto arp", Filter::EtherProto(EtherType::ARP)),
+                ("ether proto ip", Filter::EtherProto(EtherType::IPv4)),
+                ("ether proto 0x88B5", Filter::EtherProto(EtherType::from(0x88B5))),
+                ("arp", Filter::EtherProto(EtherType::ARP)),
+                ("ip", Filter::EtherProto(EtherType::IPv4)),
+                ("ip6", Filter::EtherProto(EtherType::IPv6)),
+                ("vlan", Filter::Vlan(None)),
+                ("vlan 100", Filter::Vlan(Some(100))),
+                ("host 10.0.0.1", host(Dir::Any, "10.0.0.1", true)),
+                ("src host 10.0.0.1", host(Dir::Src, "10.0.0.1", true)),
+                ("dst 10.0.0.1", host(Dir::Dst, "10.0.0.1", true)),
+                ("ip dst host 10.0.0.1", host(Dir::Dst, "10.0.0.1", false)),
+                ("ip6 host fe80::1", host(Dir::Any, "fe80::1", false)),
+                ("net 10.0.0.0/8", Filter::Net { dir: Dir::Any, addr: "10.0.0.0".parse().unwrap(), prefix_len: 8 }),
+                ("src net 2001:db8::/32",
+                    Filter::Net { dir: Dir::Src, addr: "2001:db8::".parse().unwrap(), prefix_len: 32 }),
+                ("net 10.0.0.1", Filter::Net { dir: Dir::Any, addr: "10.0.0.1".parse().unwrap(), prefix_len: 32 }),
+                ("icmp", Filter::Proto(IPPROTO_ICMP)),
+                ("icmp6", Filter::Proto(IPPROTO_ICMPV6)),
+                ("tcp", Filter::Proto(IPPROTO_TCP)),
+                ("udp", Filter::Proto(IPPROTO_UDP)),
+                ("ip proto 47", Filter::Proto(47)),
+                ("port 53", Filter::Port { proto: None, dir: Dir::Any, port: 53 }),
+                ("udp port 53", Filter::Port { proto: Some(IPPROTO_UDP), dir: Dir::Any, port: 53 }),
+                ("tcp dst port 80", Filter::Port { proto: Some(IPPROTO_TCP), dir: Dir::Dst, port: 80 }),
+                ("tcp src 22", Filter::Port { proto: Some(IPPROTO_TCP), dir: Dir::Src, port: 22 }),
+            ];
+            for (expr, filter) in cases {
+                assert_eq!(parse(expr), filter, "{}", expr);
+            }
+        }
+
+        #[test]
+        fn precedence() {
+            let (arp, ip, ip6, udp) = (parse("arp"), parse("ip"), parse("ip6"), parse("udp"));
+            assert_eq!(parse("arp or ip and not udp"), or(arp.clone(), and(ip.clone(), not(udp.clone()))));
+            assert_eq!(parse("not arp and ip"), and(not(arp.clone()), ip.clone()));
+            assert_eq!(parse("! arp && ip || ip6"), or(and(not(arp.clone()), ip.clone()), ip6.clone()));
+            assert_eq!(parse("!arp"), not(arp.clone()));
+            assert_eq!(parse("(arp or ip) and udp"), and(or(arp.clone(), ip.clone()), udp.clone()));
+            assert_eq!(parse("not (arp or ip)"), not(or(arp.clone(), ip.clone())));
+            assert_eq!(parse("arp or ip or ip6"), or(or(arp.clone(), ip.clone()), ip6.clone()));
+            assert_eq!(parse("arp and ip and ip6"), and(and(arp.clone(), ip.clone()), ip6.clone()));
+            assert_eq!(parse("not not arp"), not(not(arp)));
+        }
+
+        #[test]
+        fn errors() {
+            for expr in [
+                "", "(", "()", "(arp", "arp)", "arp or", "and arp", "not", "bogus",
+                "ether", "ether src", "ether src zz:00:00:00:00:01", "ether proto bogus",
+                "host", "host 10.0.0", "ip host fe80::1", "ip6 host 10.0.0.1",
+                "net 10.0.0.0/33", "net fe80::/129", "net 10.0.0.0/x", "net nowhere",
+                "port", "port http", "port 65536", "udp port", "ip port 80", "tcp net 10.0.0.0/8",
+                "ip proto", "ip proto 256", "vlan and",
+            ] {
+                match expr.parse::<Filter>() {
+                    Err(RlinkError::InvalidFilter(why)) => assert!(why.contains(&format!("{:?}", expr)), "{}", why),
+                    other => panic!("{:?} parsed as {:?}", expr, other),
+                }
+            }
+        }
+
+        #[test]
+        fn display_parses_back() {
+            for expr in [
+                "arp or (udp port 53 and not src net 10.0.0.0/8)",
+                "not (arp or ip) and (ip6 or vlan 7)",
+                "arp and (ip or ip6 or vlan)",
+                "(arp or ip) or (ip6 or udp)",
+                "(arp and ip) and (ip6 and udp)",
+                "not not not tcp",
+                "ether src 02:00:00:00:00:01 or ether dst 02:00:00:00:00:02 or ether host 02:00:00:00:00:03",
+                "ether proto 0x88B5 and ether proto 0x0806 and ether proto ip6",
+                "ip dst host 10.0.0.1 or ip6 src host fe80::1 or host 10.0.0.2",
+                "tcp src port 22 or udp dst port 53 or port 123 or ip proto 47 or icmp or icmp6",
+            ] {
+                let filter = parse(expr);
+                let shown = filter.to_string();
+                assert_eq!(parse(&shown), filter, "{} displayed as {}", expr, shown);
+                assert_eq!(parse(&shown).to_string(), shown);
+            }
+            assert_eq!(parse("ether proto 0x0806").to_string(), "arp");
+            assert_eq!(parse("(arp or ip) and udp").to_string(), "(arp or ip) and udp");
+            assert_eq!(parse("arp or (ip or udp)").to_string(), "arp or (ip or udp)");
+            assert_eq!(parse("src 10.0.0.1 && !(tcp dst 80)").to_string(), "src host 10.0.0.1 and not tcp dst port 80");
+        }
+
+        #[test]
+        fn matches_arp() {
+            let frame = arp_frame();
+            for expr in [
+                "arp", "ether proto arp", "ether src 02:00:00:00:00:01", "ether dst ff:ff:ff:ff:ff:ff",
+                "host 10.0.0.1", "src host 10.0.0.1", "dst 10.0.0.2", "net 10.0.0.0/24", "not ip",
+            ] {
+                assert!(parse(expr).matches_frame(&frame), "{}", expr);
+            }
+            for expr in [
+                "ip", "vlan", "ether dst 02:00:00:00:00:01", "ip host 10.0.0.1", "dst host 10.0.0.1",
+                "net 10.1.0.0/16", "udp", "port 53",
+            ] {
+                assert!(!parse(expr).matches_frame(&frame), "{}", expr);
+            }
+        }
+
+        #[test]
+        fn matches_vlan_udp() {
+            let frame = vlan_udp_frame();
+            for expr in [
+                "vlan", "vlan 100", "ether proto 0x8100", "ip", "udp", "udp port 53", "src port 5353",
+                "udp dst port 53", "ip src host 192.168.1.1", "dst net 10.0.0.0/8", "vlan 100 and udp port 53",
+            ] {
+                assert!(parse(expr).matches_frame(&frame), "{}", expr);
+            }
+            for expr in [
+                "vlan 101", "arp", "ip6", "tcp", "tcp port 53", "dst port 5353", "src net 10.0.0.0/8",
+                "not udp port 53",
+            ] {
+                assert!(!parse(expr).matches_frame(&frame), "{}", expr);
+            }
+        }
+
+        #[test]
+        fn matches_ipv6_tcp() {
+            let frame = ipv6_tcp_frame();
+            for expr in [
+                "ip6", "tcp", "tcp dst port 80", "port 40000", "ip6 src host 2001:db8::1", "dst host fe80::2",
+                "src net 2001:db8::/32", "ether dst 33:33:00:00:00:02", "ip6 and not ip",
+            ] {
+                assert!(parse(expr).matches_frame(&frame), "{}", expr);
+            }
+            for expr in [
+                "ip", "udp", "icmp6", "vlan", "tcp src port 80", "host 10.0.0.1", "net fe80::/10 and src net fe80::/10",
+            ] {
+                assert!(!parse(expr).matches_frame(&frame), "{}", expr);
+            }
+            // Too short for an Ethernet header
+            assert!(!parse("not arp").matches_frame(&frame[..13]));
+        }
+    }
+}
@@ -0,0 +1,176 @@
+#![allow(dead_code)]
+#![allow(unused)]
+
+pub mod icmp {
+    //! Internet Control Message Protocol (RFC 792): echo, destination
+    //! unreachable, time exceeded and parameter problem messages.
+
+    use crate::ipv4::ipv4::{checksum, Ipv4};
+    use crate::{FrameError, Packet};
+    use std::net::Ipv4Addr;
+
+    pub const ECHO_REPLY: u8 = 0;
+    pub const DESTINATION_UNREACHABLE: u8 = 3;
+    pub const ECHO_REQUEST: u8 = 8;
+    pub const TIME_EXCEEDED: u8 = 11;
+    pub const PARAMETER_PROBLEM: u8 = 12;
+
+    /// Destination unreachable codes.
+    pub const NET_UNREACHABLE: u8 = 0;
+    pub const HOST_UNREACHABLE: u8 = 1;
+    pub const PROTOCOL_UNREACHABLE: u8 = 2;
+    pub const PORT_UNREACHABLE: u8 = 3;
+    pub const FRAGMENTATION_NEEDED: u8 = 4;
+
+    /// Time exceeded codes.
+    pub const TTL_EXCEEDED: u8 = 0;
+    pub const REASSEMBLY_TIME_EXCEEDED: u8 = 1;
+
+    /// Bytes of the offending datagram's payload quoted in error messages.
+    pub const QUOTED_PAYLOAD_LEN: usize = 8;
+
+    #[derive(Clone, Debug, PartialEq, Eq)]
+    pub enum IcmpMessage {
+        EchoReply { id: u16, seq: u16, data: Vec<u8> },
+        EchoRequest { id: u16, seq: u16, data: Vec<u8> },
+        /// `next_hop_mtu` is only meaningful for `FRAGMENTATION_NEEDED`.
+        DestinationUnreachable { code: u8, next_hop_mtu: u16, quoted: Vec<u8> },
+        TimeExceeded { code: u8, quoted: Vec<u8> },
+        ParameterProblem { pointer: u8, quoted: Vec<u8> },
+        Other { icmp_type: u8, code: u8, rest: [u8; 4], data: Vec<u8> },
+    }
+
+    /// The part of a datagram that error messages quote: the IP header
+    /// and the first 8 bytes of the payload.
+    pub fn quote(original: &Packet<Ipv4>) -> Vec<u8> {
+        let len = original.header_len() + original.payload().len().min(QUOTED_PAYLOAD_LEN);
+        original.datagram()[..len].to_vec()
+    }
+
+    impl IcmpMessage {
+        /// Time exceeded in transit, to be sent back to the source of
+        /// `original` when its TTL reaches zero.
+        pub fn time_exceeded(original: &Packet<Ipv4>) -> Self {
+            IcmpMessage::TimeExceeded { code: TTL_EXCEEDED, quoted: quote(original) }
+        }
+
+        pub fn destination_unreachable(code: u8, original: &Packet<Ipv4>) -> Self {
+            IcmpMessage::DestinationUnreachable { code, next_hop_mtu: 0, quoted: quote(original) }
+        }
+
+        /// Parameter problem, `pointer` being the offset of the offending
+        /// byte in the datagram.
+        pub fn parameter_problem(pointer: u8, original: &Packet<Ipv4>) -> Self {
+            IcmpMessage::ParameterProblem { pointer, quoted: quote(original) }
+        }
+
+        pub fn icmp_type(&self) -> u8 {
+            match self {
+                IcmpMessage::EchoReply { .. } => ECHO_REPLY,
+                IcmpMessage::EchoRequest { .. } => ECHO_REQUEST,
+                IcmpMessage::DestinationUnreachable { .. } => DESTINATION_UNREACHABLE,
+                IcmpMessage::TimeExceeded { .. } => TIME_EXCEEDED,
+                IcmpMessage::ParameterProblem { .. } => PARAMETER_PROBLEM,
+                IcmpMessage::Other { icmp_type, .. } => *icmp_type,
+            }
+        }
+
+        pub fn code(&self) -> u8 {
+            match self {
+                IcmpMessage::DestinationUnreachable { code, .. } => *code,
+                IcmpMessage::TimeExceeded { code, .. } => *code,
+                IcmpMessage::Other { code, .. } => *code,
+                _ => 0,
+            }
+        }
+
+        /// Whether this is an error message (as opposed to a query).
+        pub fn is_error(&self) -> bool {
+            matches!(self, IcmpMessage::DestinationUnreachable { .. }
+                | IcmpMessage::TimeExceeded { .. }
+                | IcmpMessage::ParameterProblem { .. })
+        }
+
+        /// The quoted part of the offending datagram, for error messages.
+        pub fn quoted(&self) -> Option<&[u8]> {
+            match self {
+                IcmpMessage::DestinationUnreachable { quoted, .. }
+                | IcmpMessage::TimeExceeded { quoted, .. }
+                | IcmpMessage::ParameterProblem { quoted, .. } => Some(quoted),
+                _ => None,
+            }
+        }
+
+        /// Source and destination of the quoted datagram, if well-formed.
+        pub fn quoted_addrs(&self) -> Option<(Ipv4Addr, Ipv4Addr)> {
+            let quoted = self.quoted()?;
+            if quoted.len() < 20 {
+                return None;
+            }
+            Some((Ipv4Addr::from(<[u8; 4]>::try_from(&quoted[12..16]).unwrap()),
+                Ipv4Addr::from(<[u8; 4]>::try_from(&quoted[16..20]).unwrap())))
+        }
+
+        /// Encodes the message with its checksum.
+        pub fn encode(&self) -> Vec<u8> {
+            let (rest, data): ([u8; 4], &[u8]) = match self {
+                IcmpMessage::EchoReply { id, seq, data }
+                | IcmpMessage::EchoRequest { id, seq, data } => {
+                    let mut rest = [0u8; 4];
+                    rest[0..2].copy_from_slice(&id.to_be_bytes());
+                    rest[2..4].copy_from_slice(&seq.to_be_bytes());
+                    (rest, data)
+                }
+                IcmpMessage::DestinationUnreachable { next_hop_mtu, quoted, .. } => {
+                    let mtu = next_hop_mtu.to_be_bytes();
+                    ([0, 0, mtu[0], mtu[1]], quoted)
+                }
+                IcmpMessage::TimeExceeded { quoted, .. } => ([0; 4], quoted),
+                IcmpMessage::ParameterProblem { pointer, quoted } => ([*pointer, 0, 0, 0], quoted),
+                IcmpMessage::Other { rest, data, .. } => (*rest, data),
+            };
+            let mut message = Vec::with_capacity(8 + data.len());
+            message.push(self.icmp_type());
+            message.push(self.code());
+            message.extend_from_slice(&[0, 0]);
+            message.extend_from_slice(&rest);
+            message.extend_from_slice(data);
+            let sum = checksum(&message);
+            message[2..4].copy_from_slice(&sum.to_be_bytes());
+            message
+        }
+
+        /// Decodes a message from an IPv4 payload, verifying the checksum.
+        pub fn decode(data: &[u8]) -> Result<Self, FrameError> {
+            if data.len() < 8 {
+                return Err(FrameError::Truncated { offset: 0, needed: 8, available: data.len() });
+            }
+            if checksum(data) != 0 {
+                let mut zeroed = data.to_vec();
+                zeroed[2] = 0;
+                zeroed[3] = 0;
+                return Err(FrameError::ChecksumMismatch {
+                    expected: checksum(&zeroed) as u32,
+                    actual: u16::from_be_bytes([data[2], data[3]]) as u32,
+                });
+            }
+            let (icmp_type, code) = (data[0], data[1]);
+            let rest: [u8; 4] = data[4..8].try_into().unwrap();
+            let body = data[8..].to_vec();
+            let id = u16::from_be_bytes([rest[0], rest[1]]);
+            let seq = u16::from_be_bytes([rest[2], rest[3]]);
+            Ok(match icmp_type {
+                ECHO_REPLY => IcmpMessage::EchoReply { id, seq, data: body },
+                ECHO_REQUEST => IcmpMessage::EchoRequest { id, seq, data: body },
+                DESTINATION_UNREACHABLE => IcmpMessage::DestinationUnreachable {
+                    code,
+                    next_hop_mtu: seq,
+                    quoted: body,
+                },
+                TIME_EXCEEDED => IcmpMessage::TimeExceeded { code, quoted: body },
+                PARAMETER_PROBLEM => IcmpMessage::ParameterProblem { pointer: rest[0], quoted: body },
+                _ => IcmpMessage::Other { icmp_type, code, rest, data: body },
+            })
+        }
+    }
+}
@@ -0,0 +1,378 @@
+#![allow(dead_code)]
+#![allow(unused)]
+
+pub mod interface {
+    //! Inventory of the network interfaces in the current network namespace,
+    //! queried over rtnetlink. Interfaces can be selected by glob or regex
+    //! (e.g. `veth3-*`), opened as `DeviceHandle`s, and watched for changes.
+
+    use crate::netlink::netlink::*;
+    use crate::{DeviceHandle, RlinkError};
+    use mac_address::MacAddress;
+    use pcap::{Capture, Device, Linktype};
+    use regex::Regex;
+    use std::fmt;
+    use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+    /// Operational state of a link, as reported by the kernel (RFC 2863).
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub enum LinkState {
+        Unknown,
+        NotPresent,
+        Down,
+        LowerLayerDown,
+        Testing,
+        Dormant,
+        Up,
+    }
+
+    impl From<u8> for LinkState {
+        fn from(value: u8) -> Self {
+            use LinkState::*;
+            match value {
+                1 => NotPresent,
+                2 => Down,
+                3 => LowerLayerDown,
+                4 => Testing,
+                5 => Dormant,
+                6 => Up,
+                _ => Unknown,
+            }
+        }
+    }
+
+    impl fmt::Display for LinkState {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            use LinkState::*;
+            write!(f, "{}", match self {
+                Unknown => "UNKNOWN",
+                NotPresent => "NOTPRESENT",
+                Down => "DOWN",
+                LowerLayerDown => "LOWERLAYERDOWN",
+                Testing => "TESTING",
+                Dormant => "DORMANT",
+                Up => "UP",
+            })
+        }
+    }
+
+    /// An address assigned to an interface.
+    #[derive(Clone, Debug, PartialEq, Eq)]
+    pub struct InterfaceAddress {
+        pub addr: IpAddr,
+        pub prefix_len: u8,
+        pub broadcast: Option<IpAddr>,
+    }
+
+    impl fmt::Display for InterfaceAddress {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "{}/{}", self.addr, self.prefix_len)
+        }
+    }
+
+    /// A network interface and its configuration.
+    #[derive(Clone, Debug)]
+    pub struct Interface {
+        /// Interface name, e.g. `veth1-2`
+        pub name: String,
+        /// Kernel interface index
+        pub index: u32,
+        /// Hardware address, if the link has one
+        pub mac_address: Option<MacAddress>,
+        /// Maximum transmission unit
+        pub mtu: u32,
+        /// Operational state
+        pub link_state: LinkState,
+        /// Kernel `IFF_*` flags
+        pub flags: u32,
+        /// Assigned IPv4 and IPv6 addresses
+        pub addresses: Vec<InterfaceAddress>,
+    }
+
+    impl Interface {
+        /// Whether the interface is administratively up (`IFF_UP`).
+        pub fn is_up(&self) -> bool {
+            self.flags & libc::IFF_UP as u32 != 0
+        }
+
+        /// Whether the interface has carrier (`IFF_LOWER_UP`).
+        pub fn is_lower_up(&self) -> bool {
+            self.flags & libc::IFF_LOWER_UP as u32 != 0
+        }
+
+        pub fn is_loopback(&self) -> bool {
+            self.flags & libc::IFF_LOOPBACK as u32 != 0
+        }
+
+        pub fn is_promiscuous(&self) -> bool {
+            self.flags & libc::IFF_PROMISC as u32 != 0
+        }
+
+        /// Names of the set `IFF_*` flags, as `ip link` prints them.
+        pub fn flag_names(&self) -> Vec<&'static str> {
+            const NAMES: &[(i32, &str)] = &[
+                (libc::IFF_UP, "UP"),
+                (libc::IFF_BROADCAST, "BROADCAST"),
+                (libc::IFF_LOOPBACK, "LOOPBACK"),
+                (libc::IFF_POINTOPOINT, "POINTOPOINT"),
+                (libc::IFF_RUNNING, "RUNNING"),
+                (libc::IFF_NOARP, "NOARP"),
+                (libc::IFF_PROMISC, "PROMISC"),
+                (libc::IFF_ALLMULTI, "ALLMULTI"),
+                (libc::IFF_MULTICAST, "MULTICAST"),
+                (libc::IFF_LOWER_UP, "LOWER_UP"),
+                (libc::IFF_DORMANT, "DORMANT"),
+            ];
+            NAMES.iter()
+                .filter(|(flag, _)| self.flags & *flag as u32 != 0)
+                .map(|(_, name)| *name)
+                .collect()
+        }
+
+        /// The pcap `Device` describing this interface.
+        pub fn device(&self) -> Device {
+            let mut device = Device::from(self.name.as_str());
+            device.addresses = self.addresses.iter()
+                .map(|addr| pcap::Address {
+                    addr: addr.addr,
+                    netmask: Some(prefix_to_mask(addr.addr, addr.prefix_len)),
+                    broadcast_addr: addr.broadcast,
+                    dst_addr: None,
+                })
+                .collect();
+            device
+        }
+
+        /// List the datalink types the interface supports. This activates
+        /// a capture on it, and thus requires capture privileges.
+        pub fn datalinks(&self) -> Result<Vec<Linktype>, RlinkError> {
+            let cap = Capture::from_device(self.name.as_str())?.open()?;
+            Ok(cap.list_datalinks()?)
+        }
+
+        /// Open a `DeviceHandle` on this interface, without listing the
+        /// devices again.
+        pub fn open(&self, timeout: i32, immediate: bool) -> Result<DeviceHandle, RlinkError> {
+            DeviceHandle::open(self, timeout, immediate)
+        }
+    }
+
+    impl fmt::Display for Interface {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "{}: {} <{}> mtu {} state {}",
+                self.index, self.name, self.flag_names().join(","), self.mtu, self.link_state)?;
+            if let Some(mac) = self.mac_address {
+                write!(f, " link/ether {}", mac)?;
+            }
+            for addr in self.addresses.iter() {
+                write!(f, " {}", addr)?;
+            }
+            Ok(())
+        }
+    }
+
+    fn prefix_to_mask(addr: IpAddr, prefix_len: u8) -> IpAddr {
+        match addr {
+            IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::from(
+                u32::MAX.checked_shl(32 - prefix_len.min(32) as u32).unwrap_or(0))),
+            IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::from(
+                u128::MAX.checked_shl(128 - prefix_len.min(128) as u32).unwrap_or(0))),
+        }
+    }
+
+    /// Decode an `RTM_NEWLINK` payload. Addresses are left empty.
+    fn parse_link(payload: &[u8]) -> Option<Interface> {
+        if payload.len() < IFINFOMSG_LEN {
+            return None;
+        }
+        let index = i32::from_ne_bytes(payload[4..8].try_into().unwrap()) as u32;
+        let flags = u32::from_ne_bytes(payload[8..12].try_into().unwrap());
+        let mut iface = Interface {
+            name: String::new(),
+            index,
+            mac_address: None,
+            mtu: 0,
+            link_state: LinkState::Unknown,
+            flags,
+            addresses: Vec::new(),
+        };
+        for (kind, value) in parse_attrs(&payload[IFINFOMSG_LEN..]) {
+            match kind {
+                IFLA_IFNAME => iface.name = attr_str(value),
+                IFLA_MTU => iface.mtu = attr_u32(value).unwrap_or(0),
+                IFLA_OPERSTATE => iface.link_state = value.first()
+                    .map(|&state| LinkState::from(state))
+                    .unwrap_or(LinkState::Unknown),
+                IFLA_ADDRESS if value.len() == 6 => 
+                    iface.mac_address = Some(MacAddress::new(value.try_into().unwrap())),
+                _ => {}
+            }
+        }
+        Some(iface)
+    }
+
+    /// Decode an `RTM_NEWADDR`/`RTM_DELADDR` payload into the interface
+    /// index and the address.
+    fn parse_addr(payload: &[u8]) -> Option<(u32, InterfaceAddress)> {
+        if payload.len() < IFADDRMSG_LEN {
+            return None;
+        }
+        let family = payload[0] as i32;
+        let prefix_len = payload[1];
+        let index = u32::from_ne_bytes(payload[4..8].try_into().unwrap());
+
+        let to_ip = |value: &[u8]| -> Option<IpAddr> {
+            match (family, value.len()) {
+                (libc::AF_INET, 4) => Some(IpAddr::V4(Ipv4Addr::from(<[u8; 4]>::try_from(value).unwrap()))),
+                (libc::AF_INET6, 16) => Some(IpAddr::V6(Ipv6Addr::from(<[u8; 16]>::try_from(value).unwrap()))),
+                _ => None,
+            }
+        };
+        let (mut address, mut local, mut broadcast) = (None, None, None);
+        for (kind, value) in parse_attrs(&payload[IFADDRMSG_LEN..]) {
+            match kind {
+                IFA_ADDRESS => address = to_ip(value),
+                IFA_LOCAL => local = to_ip(value),
+                IFA_BROADCAST => broadcast = to_ip(value),
+                _ => {}
+            }
+        }
+        // On point-to-point links IFA_ADDRESS is the peer; IFA_LOCAL is ours.
+        let addr = local.or(address)?;
+        Some((index, InterfaceAddress { addr, prefix_len, broadcast }))
+    }
+
+    /// List all interfaces in the current network namespace.
+    pub fn interfaces() -> Result<Vec<Interface>, RlinkError> {
+        let mut socket = NetlinkSocket::open(0)?;
+        let mut ifaces: Vec<Interface> = socket
+            .request(RTM_GETLINK, NLM_F_DUMP, &ifinfomsg(libc::AF_UNSPEC as u8, 0, 0, 0))?
+            .iter()
+            .filter(|msg| msg.msg_type == RTM_NEWLINK)
+            .filter_map(|msg| parse_link(&msg.payload))
+            .collect();
+        for msg in socket.request(RTM_GETADDR, NLM_F_DUMP, &ifaddrmsg(libc::AF_UNSPEC as u8, 0, 0))? {
+            if msg.msg_type != RTM_NEWADDR {
+                continue;
+            }
+            if let Some((index, addr)) = parse_addr(&msg.payload) {
+                if let Some(iface) = ifaces.iter_mut().find(|iface| iface.index == index) {
+                    iface.addresses.push(addr);
+                }
+            }
+        }
+        ifaces.sort_by_key(|iface| iface.index);
+        Ok(ifaces)
+    }
+
+    /// Look up a single interface by name.
+    pub fn interface_by_name(name: &str) -> Result<Interface, RlinkError> {
+        let ifaces = interfaces()?;
+        let available = ifaces.iter().map(|iface| iface.name.clone()).collect();
+        ifaces.into_iter()
+            .find(|iface| iface.name == name)
+            .ok_or(RlinkError::InvalidDeviceName { name: name.to_string(), available })
+    }
+
+    /// Translate a shell-style glob (`*`, `?`, `[...]`) into an anchored regex.
+    pub fn glob_to_regex(glob: &str) -> Result<Regex, RlinkError> {
+        let mut pattern = String::from("^");
+        let mut chars = glob.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '*' => pattern.push_str(".*"),
+                '?' => pattern.push('.'),
+                '[' => {
+                    pattern.push('[');
+                    if chars.peek() == Some(&'!') {
+                        chars.next();
+                        pattern.push('^');
+                    }
+                    loop {
+                        match chars.next() {
+                            Some(']') => break,
+                            Some('\\') => pattern.push_str("\\\\"),
+                            Some(c) => pattern.push(c),
+                            None => return Err(RlinkError::InvalidPattern(glob.to_string())),
+                        }
+                    }
+                    pattern.push(']');
+                }
+                c => pattern.push_str(&regex::escape(&c.to_string())),
+            }
+        }
+        pattern.push('$');
+        Regex::new(&pattern).map_err(|_| RlinkError::InvalidPattern(glob.to_string()))
+    }
+
+    /// Interfaces whose names match the glob `pattern`, e.g. `veth3-*`.
+    pub fn select(pattern: &str) -> Result<Vec<Interface>, RlinkError> {
+        select_regex(&glob_to_regex(pattern)?)
+    }
+
+    /// Interfaces whose names match `regex`.
+    pub fn select_regex(regex: &Regex) -> Result<Vec<Interface>, RlinkError> {
+        Ok(interfaces()?
+            .into_iter()
+            .filter(|iface| regex.is_match(&iface.name))
+            .collect())
+    }
+
+    /// A change to the interfaces of the namespace.
+    #[derive(Clone, Debug)]
+    pub enum InterfaceEvent {
+        /// A link was added or its configuration changed. `addresses` is
+        /// not populated.
+        NewLink(Interface),
+        /// A link was removed.
+        DelLink { index: u32, name: String },
+        /// An address was assigned to the interface with `index`.
+        NewAddress { index: u32, address: InterfaceAddress },
+        /// An address was removed from the interface with `index`.
+        DelAddress { index: u32, address: InterfaceAddress },
+    }
+
+    /// Subscribes to link and address notifications over rtnetlink.
+    pub struct InterfaceWatcher {
+        socket: NetlinkSocket,
+        pending: std::collections::VecDeque<InterfaceEvent>,
+    }
+
+    impl InterfaceWatcher {
+        /// Start watching the current network namespace.
+        pub fn new() -> Result<Self, RlinkError> {
+            let socket = NetlinkSocket::open(RTMGRP_LINK | RTMGRP_IPV4_IFADDR | RTMGRP_IPV6_IFADDR)?;
+            Ok(InterfaceWatcher { socket, pending: Default::default() })
+        }
+
+        /// Block until the next change.
+        pub fn next_event(&mut self) -> Result<InterfaceEvent, RlinkError> {
+            loop {
+                if let Some(event) = self.pending.pop_front() {
+                    return Ok(event);
+                }
+                for msg in self.socket.recv()? {
+                    let event = match msg.msg_type {
+                        RTM_NEWLINK => parse_link(&msg.payload).map(InterfaceEvent::NewLink),
+                        RTM_DELLINK => parse_link(&msg.payload)
+                            .map(|iface| InterfaceEvent::DelLink { index: iface.index, name: iface.name }),
+                        RTM_NEWADDR => parse_addr(&msg.payload)
+                            .map(|(index, address)| InterfaceEvent::NewAddress { index, address }),
+                        RTM_DELADDR => parse_addr(&msg.payload)
+                            .map(|(index, address)| InterfaceEvent::DelAddress { index, address }),
+                        _ => None,
+                    };
+                    self.pending.extend(event);
+                }
+            }
+        }
+    }
+
+    impl Iterator for InterfaceWatcher {
+        type Item = Result<InterfaceEvent, RlinkError>;
+
+        fn next(&mut self) -> Option<Self::Item> {
+            Some(self.next_event())
+        }
+    }
+}
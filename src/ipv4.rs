@@ -0,0 +1,451 @@
+#![allow(dead_code)]
+#![allow(unused)]
+
+pub mod ipv4 {
+    //! IPv4 datagrams carried in Ethernet II frames, the Internet checksum,
+    //! and a longest-prefix-match routing table.
+
+    use crate::packet::packet::Type;
+    use crate::{EtherType, Eth, FrameError, Packet, Raw, RlinkError};
+    use mac_address::MacAddress;
+    use std::fmt;
+    use std::net::Ipv4Addr;
+    use std::str::FromStr;
+
+    /// Length of the Ethernet II header preceding the datagram.
+    pub const ETH_HEADER_LEN: usize = 14;
+    /// Length of an IPv4 header without options.
+    pub const MIN_HEADER_LEN: usize = 20;
+    /// Default Time To Live of outgoing datagrams.
+    pub const DEFAULT_TTL: u8 = 64;
+
+    /// Parsed as IPv4 datagram
+    #[derive(Debug)]
+    pub enum Ipv4 {}
+    impl Type for Ipv4 {}
+
+    /// IP protocol numbers of the protocols rlink knows about.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+    pub enum IpProtocol {
+        ICMP,
+        TCP,
+        UDP,
+        ICMPv6,
+        UNKNOWN(u8),
+    }
+
+    impl From<u8> for IpProtocol {
+        fn from(value: u8) -> Self {
+            match value {
+                1 => IpProtocol::ICMP,
+                6 => IpProtocol::TCP,
+                17 => IpProtocol::UDP,
+                58 => IpProtocol::ICMPv6,
+                _ => IpProtocol::UNKNOWN(value),
+            }
+        }
+    }
+
+    impl From<IpProtocol> for u8 {
+        fn from(protocol: IpProtocol) -> Self {
+            match protocol {
+                IpProtocol::ICMP => 1,
+                IpProtocol::TCP => 6,
+                IpProtocol::UDP => 17,
+                IpProtocol::ICMPv6 => 58,
+                IpProtocol::UNKNOWN(value) => value,
+            }
+        }
+    }
+
+    impl fmt::Display for IpProtocol {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            match *self {
+                IpProtocol::ICMP => write!(f, "ICMP"),
+                IpProtocol::TCP => write!(f, "TCP"),
+                IpProtocol::UDP => write!(f, "UDP"),
+                IpProtocol::ICMPv6 => write!(f, "ICMPv6"),
+                IpProtocol::UNKNOWN(value) => write!(f, "Unknown protocol ({})", value),
+            }
+        }
+    }
+
+    /// Adds `data` to a running one's complement sum of 16-bit words.
+    pub fn checksum_add(mut sum: u32, data: &[u8]) -> u32 {
+        let mut chunks = data.chunks_exact(2);
+        for chunk in chunks.by_ref() {
+            sum += u16::from_be_bytes([chunk[0], chunk[1]]) as u32;
+        }
+        if let [last] = chunks.remainder() {
+            sum += (*last as u32) << 8;
+        }
+        sum
+    }
+
+    /// Folds a running sum into the final Internet checksum.
+    pub fn checksum_fold(mut sum: u32) -> u16 {
+        while sum >> 16 != 0 {
+            sum = (sum & 0xffff) + (sum >> 16);
+        }
+        !(sum as u16)
+    }
+
+    /// The Internet checksum (RFC 1071) of `data`. Computing it over data
+    /// that includes a valid checksum yields 0.
+    pub fn checksum(data: &[u8]) -> u16 {
+        checksum_fold(checksum_add(0, data))
+    }
+
+    /// Checksum over the IPv4 pseudo-header followed by `data`, as used
+    /// by UDP and TCP.
+    pub fn pseudo_header_checksum(src: Ipv4Addr, dst: Ipv4Addr, protocol: IpProtocol, data: &[u8]) -> u16 {
+        let mut sum = checksum_add(0, &src.octets());
+        sum = checksum_add(sum, &dst.octets());
+        sum += u8::from(protocol) as u32;
+        sum += data.len() as u32;
+        checksum_fold(checksum_add(sum, data))
+    }
+
+    /// Netmask of a prefix length, as an integer.
+    pub fn prefix_mask(prefix_len: u8) -> u32 {
+        u32::MAX.checked_shl(32 - prefix_len.min(32) as u32).unwrap_or(0)
+    }
+
+    impl Packet<Eth> {
+        /// Parses the frame content as an IPv4 datagram. The header length,
+        /// total length and header checksum are checked.
+        pub fn parse_ipv4(self) -> Result<Packet<Ipv4>, RlinkError> {
+            match validate(&self) {
+                Ok(()) => Ok(self.cast()),
+                Err(why) => Err(RlinkError::InvalidPacket(self.cast::<Raw>(), why)),
+            }
+        }
+    }
+
+    fn validate(packet: &Packet<Eth>) -> Result<(), FrameError> {
+        if packet.ethtype() != EtherType::IPv4 {
+            return Err(FrameError::InvalidField { offset: 12, field: "ether type" });
+        }
+        let ip = packet.data();
+        let at = |offset| ETH_HEADER_LEN + offset;
+        if ip.len() < MIN_HEADER_LEN {
+            return Err(FrameError::Truncated { offset: at(0), needed: MIN_HEADER_LEN, available: ip.len() });
+        }
+        if ip[0] >> 4 != 4 {
+            return Err(FrameError::InvalidField { offset: at(0), field: "IP version" });
+        }
+        let header_len = (ip[0] & 0x0f) as usize * 4;
+        if header_len < MIN_HEADER_LEN {
+            return Err(FrameError::InvalidField { offset: at(0), field: "IHL" });
+        }
+        let total_len = u16::from_be_bytes([ip[2], ip[3]]) as usize;
+        if total_len < header_len {
+            return Err(FrameError::InvalidField { offset: at(2), field: "total length" });
+        }
+        if ip.len() < total_len {
+            return Err(FrameError::Truncated { offset: at(0), needed: total_len, available: ip.len() });
+        }
+        let sum = checksum(&ip[..header_len]);
+        if sum != 0 {
+            let actual = u16::from_be_bytes([ip[10], ip[11]]);
+            let mut header = ip[..header_len].to_vec();
+            header[10] = 0;
+            header[11] = 0;
+            return Err(FrameError::ChecksumMismatch { expected: checksum(&header) as u32, actual: actual as u32 });
+        }
+        Ok(())
+    }
+
+    impl Packet<Ipv4> {
+        fn ip(&self) -> &[u8] {
+            &self.data[ETH_HEADER_LEN..]
+        }
+
+        /// Destination MAC address of the carrying frame.
+        pub fn dst_mac(&self) -> MacAddress {
+            MacAddress::new(self.data[0..6].try_into().unwrap())
+        }
+
+        /// Source MAC address of the carrying frame.
+        pub fn src_mac(&self) -> MacAddress {
+            MacAddress::new(self.data[6..12].try_into().unwrap())
+        }
+
+        pub fn header_len(&self) -> usize {
+            (self.ip()[0] & 0x0f) as usize * 4
+        }
+
+        pub fn dscp(&self) -> u8 {
+            self.ip()[1] >> 2
+        }
+
+        pub fn ecn(&self) -> u8 {
+            self.ip()[1] & 0x03
+        }
+
+        pub fn total_len(&self) -> usize {
+            u16::from_be_bytes([self.ip()[2], self.ip()[3]]) as usize
+        }
+
+        pub fn identification(&self) -> u16 {
+            u16::from_be_bytes([self.ip()[4], self.ip()[5]])
+        }
+
+        pub fn dont_fragment(&self) -> bool {
+            self.ip()[6] & 0x40 != 0
+        }
+
+        pub fn more_fragments(&self) -> bool {
+            self.ip()[6] & 0x20 != 0
+        }
+
+        /// Fragment offset, in units of 8 bytes.
+        pub fn fragment_offset(&self) -> u16 {
+            u16::from_be_bytes([self.ip()[6] & 0x1f, self.ip()[7]])
+        }
+
+        pub fn ttl(&self) -> u8 {
+            self.ip()[8]
+        }
+
+        pub fn protocol(&self) -> IpProtocol {
+            IpProtocol::from(self.ip()[9])
+        }
+
+        pub fn src(&self) -> Ipv4Addr {
+            Ipv4Addr::from(<[u8; 4]>::try_from(&self.ip()[12..16]).unwrap())
+        }
+
+        pub fn dst(&self) -> Ipv4Addr {
+            Ipv4Addr::from(<[u8; 4]>::try_from(&self.ip()[16..20]).unwrap())
+        }
+
+        pub fn options(&self) -> &[u8] {
+            &self.ip()[MIN_HEADER_LEN..self.header_len()]
+        }
+
+        /// The IPv4 header, including options.
+        pub fn header(&self) -> &[u8] {
+            &self.ip()[..self.header_len()]
+        }
+
+        /// The whole datagram, header and payload.
+        pub fn datagram(&self) -> &[u8] {
+            &self.ip()[..self.total_len()]
+        }
+
+        /// The datagram payload. Ethernet padding is excluded.
+        pub fn payload(&self) -> &[u8] {
+            &self.ip()[self.header_len()..self.total_len()]
+        }
+
+        /// A header builder with the fields of this datagram.
+        pub fn to_header(&self) -> Ipv4Header {
+            Ipv4Header {
+                dscp: self.dscp(),
+                ecn: self.ecn(),
+                identification: self.identification(),
+                dont_fragment: self.dont_fragment(),
+                more_fragments: self.more_fragments(),
+                fragment_offset: self.fragment_offset(),
+                ttl: self.ttl(),
+                protocol: self.protocol(),
+                src: self.src(),
+                dst: self.dst(),
+                options: self.options().to_vec(),
+            }
+        }
+    }
+
+    impl fmt::Display for Packet<Ipv4> {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            writeln!(f, "{:?}", self.header)?;
+            writeln!(f, "{:?}", self.mac_address)?;
+            writeln!(f, "src: {}, dst: {}", self.src(), self.dst())?;
+            writeln!(f, "protocol: {}, ttl: {}, id: 0x{:0>4X}, len: {}",
+                self.protocol(), self.ttl(), self.identification(), self.total_len())?;
+
+            // Hex dump the payload
+            for (idx, byte) in self.payload().iter().enumerate() {
+                write!(f, "{:0>2X} ", byte)?;
+                match idx % 12 {
+                    5 => write!(f, " ")?,
+                    11 => writeln!(f)?,
+                    _ => {}
+                };
+            }
+            Ok(())
+        }
+    }
+
+    /// Fields of an IPv4 header, for constructing datagrams.
+    #[derive(Clone, Debug, PartialEq, Eq)]
+    pub struct Ipv4Header {
+        pub dscp: u8,
+        pub ecn: u8,
+        pub identification: u16,
+        pub dont_fragment: bool,
+        pub more_fragments: bool,
+        pub fragment_offset: u16,
+        pub ttl: u8,
+        pub protocol: IpProtocol,
+        pub src: Ipv4Addr,
+        pub dst: Ipv4Addr,
+        /// Options, padded to a multiple of 4 bytes when encoded.
+        pub options: Vec<u8>,
+    }
+
+    impl Ipv4Header {
+        pub fn new(src: Ipv4Addr, dst: Ipv4Addr, protocol: IpProtocol) -> Self {
+            Ipv4Header {
+                dscp: 0,
+                ecn: 0,
+                identification: 0,
+                dont_fragment: false,
+                more_fragments: false,
+                fragment_offset: 0,
+                ttl: DEFAULT_TTL,
+                protocol,
+                src,
+                dst,
+                options: Vec::new(),
+            }
+        }
+
+        /// Encodes the header followed by `payload`, filling in the header
+        /// length, total length and checksum.
+        pub fn encode(&self, payload: &[u8]) -> Vec<u8> {
+            let options_len = (self.options.len() + 3) & !3;
+            let header_len = MIN_HEADER_LEN + options_len;
+            let total_len = header_len + payload.len();
+            let flags_offset = (self.dont_fragment as u16) << 14
+                | (self.more_fragments as u16) << 13
+                | (self.fragment_offset & 0x1fff);
+
+            let mut datagram = Vec::with_capacity(total_len);
+            datagram.push(0x40 | (header_len / 4) as u8);
+            datagram.push(self.dscp << 2 | (self.ecn & 0x03));
+            datagram.extend_from_slice(&(total_len as u16).to_be_bytes());
+            datagram.extend_from_slice(&self.identification.to_be_bytes());
+            datagram.extend_from_slice(&flags_offset.to_be_bytes());
+            datagram.push(self.ttl);
+            datagram.push(self.protocol.into());
+            datagram.extend_from_slice(&[0, 0]);
+            datagram.extend_from_slice(&self.src.octets());
+            datagram.extend_from_slice(&self.dst.octets());
+            datagram.extend_from_slice(&self.options);
+            datagram.resize(header_len, 0);
+
+            let sum = checksum(&datagram);
+            datagram[10..12].copy_from_slice(&sum.to_be_bytes());
+            datagram.extend_from_slice(payload);
+            datagram
+        }
+    }
+
+    /// A route towards `prefix/prefix_len`, reached directly on `device`
+    /// when `gateway` is `None`.
+    #[derive(Clone, Debug, PartialEq, Eq)]
+    pub struct Route {
+        pub prefix: Ipv4Addr,
+        pub prefix_len: u8,
+        pub gateway: Option<Ipv4Addr>,
+        pub device: String,
+    }
+
+    impl Route {
+        pub fn contains(&self, addr: Ipv4Addr) -> bool {
+            let mask = prefix_mask(self.prefix_len);
+            u32::from(addr) & mask == u32::from(self.prefix) & mask
+        }
+
+        /// The neighbor to hand a datagram for `dst` to.
+        pub fn next_hop(&self, dst: Ipv4Addr) -> Ipv4Addr {
+            self.gateway.unwrap_or(dst)
+        }
+    }
+
+    impl fmt::Display for Route {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            if self.prefix_len == 0 {
+                write!(f, "default")?;
+            }
+            else {
+                write!(f, "{}/{}", self.prefix, self.prefix_len)?;
+            }
+            if let Some(gateway) = self.gateway {
+                write!(f, " via {}", gateway)?;
+            }
+            write!(f, " dev {}", self.device)
+        }
+    }
+
+    impl FromStr for Route {
+        type Err = RlinkError;
+
+        /// Parses routes in the notation of `ip route`, e.g.
+        /// `10.100.1.0/24 dev veth2-1` or `default via 10.100.2.2 dev veth2-3`.
+        fn from_str(s: &str) -> Result<Self, Self::Err> {
+            let invalid = || RlinkError::InvalidRoute(s.to_string());
+            let mut words = s.split_whitespace();
+            let (prefix, prefix_len) = match words.next().ok_or_else(invalid)? {
+                "default" => (Ipv4Addr::UNSPECIFIED, 0),
+                net => {
+                    let (addr, len) = net.split_once('/').unwrap_or((net, "32"));
+                    let len: u8 = len.parse().map_err(|_| invalid())?;
+                    if len > 32 {
+                        return Err(invalid());
+                    }
+                    (addr.parse().map_err(|_| invalid())?, len)
+                }
+            };
+            let (mut gateway, mut device) = (None, None);
+            while let Some(word) = words.next() {
+                let value = words.next().ok_or_else(invalid)?;
+                match word {
+                    "via" => gateway = Some(value.parse().map_err(|_| invalid())?),
+                    "dev" => device = Some(value.to_string()),
+                    _ => return Err(invalid()),
+                }
+            }
+            Ok(Route { prefix, prefix_len, gateway, device: device.ok_or_else(invalid)? })
+        }
+    }
+
+    /// Routing table with longest-prefix-match lookup.
+    #[derive(Clone, Debug, Default)]
+    pub struct RoutingTable {
+        routes: Vec<Route>,
+    }
+
+    impl RoutingTable {
+        pub fn new() -> Self {
+            RoutingTable::default()
+        }
+
+        /// Add a route, replacing any existing route to the same prefix.
+        pub fn add(&mut self, route: Route) {
+            self.remove(route.prefix, route.prefix_len);
+            self.routes.push(route);
+        }
+
+        /// Remove the route to `prefix/prefix_len`, if any.
+        pub fn remove(&mut self, prefix: Ipv4Addr, prefix_len: u8) -> Option<Route> {
+            let mask = prefix_mask(prefix_len);
+            let idx = self.routes.iter().position(|route| route.prefix_len == prefix_len
+                && u32::from(route.prefix) & mask == u32::from(prefix) & mask)?;
+            Some(self.routes.remove(idx))
+        }
+
+        /// The most specific route covering `dst`.
+        pub fn lookup(&self, dst: Ipv4Addr) -> Option<&Route> {
+            self.routes.iter()
+                .filter(|route| route.contains(dst))
+                .max_by_key(|route| route.prefix_len)
+        }
+
+        pub fn iter(&self) -> impl Iterator<Item = &Route> {
+            self.routes.iter()
+        }
+    }
+}
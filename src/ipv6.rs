@@ -0,0 +1,414 @@
+#![allow(dead_code)]
+#![allow(unused)]
+
+pub mod ipv6 {
+    //! IPv6 datagrams (RFC 8200) carried in Ethernet II frames: the fixed
+    //! header, the chain of extension headers, and link-local addresses
+    //! derived from MAC addresses (RFC 4291, appendix A).
+
+    use crate::ipv4::ipv4::{checksum_add, checksum_fold, IpProtocol, ETH_HEADER_LEN};
+    use crate::packet::packet::Type;
+    use crate::{EtherType, Eth, FrameError, Packet, Raw, RlinkError};
+    use mac_address::MacAddress;
+    use std::fmt;
+    use std::net::Ipv6Addr;
+
+    /// Length of the fixed IPv6 header.
+    pub const HEADER_LEN: usize = 40;
+    /// Default hop limit of outgoing datagrams.
+    pub const DEFAULT_HOP_LIMIT: u8 = 64;
+
+    /// Next header values of extension headers.
+    pub const HOP_BY_HOP: u8 = 0;
+    pub const ROUTING: u8 = 43;
+    pub const FRAGMENT: u8 = 44;
+    pub const NO_NEXT_HEADER: u8 = 59;
+    pub const DESTINATION_OPTIONS: u8 = 60;
+
+    /// Parsed as IPv6 datagram
+    #[derive(Debug)]
+    pub enum Ipv6 {}
+    impl Type for Ipv6 {}
+
+    /// An IPv6 extension header. Option areas are kept raw (TLV-encoded).
+    #[derive(Clone, Debug, PartialEq, Eq)]
+    pub enum ExtensionHeader {
+        HopByHop { options: Vec<u8> },
+        Routing { routing_type: u8, segments_left: u8, data: Vec<u8> },
+        Fragment { offset: u16, more_fragments: bool, identification: u32 },
+        DestinationOptions { options: Vec<u8> },
+    }
+
+    impl ExtensionHeader {
+        /// The next header value identifying this extension header.
+        pub fn header_type(&self) -> u8 {
+            match self {
+                ExtensionHeader::HopByHop { .. } => HOP_BY_HOP,
+                ExtensionHeader::Routing { .. } => ROUTING,
+                ExtensionHeader::Fragment { .. } => FRAGMENT,
+                ExtensionHeader::DestinationOptions { .. } => DESTINATION_OPTIONS,
+            }
+        }
+
+        /// Whether `next_header` is an extension header rlink can walk over.
+        pub fn is_extension(next_header: u8) -> bool {
+            matches!(next_header, HOP_BY_HOP | ROUTING | FRAGMENT | DESTINATION_OPTIONS)
+        }
+
+        /// Encodes the header, followed by `next_header`. Option areas are
+        /// padded with PadN to a multiple of 8 bytes.
+        pub fn encode(&self, next_header: u8) -> Vec<u8> {
+            let mut data = vec![next_header, 0];
+            match self {
+                ExtensionHeader::HopByHop { options } | ExtensionHeader::DestinationOptions { options } => {
+                    data.extend_from_slice(options);
+                    pad_options(&mut data);
+                }
+                ExtensionHeader::Routing { routing_type, segments_left, data: body } => {
+                    data.push(*routing_type);
+                    data.push(*segments_left);
+                    data.extend_from_slice(body);
+                    data.resize((data.len() + 7) & !7, 0);
+                }
+                ExtensionHeader::Fragment { offset, more_fragments, identification } => {
+                    data.extend_from_slice(&((offset << 3) | *more_fragments as u16).to_be_bytes());
+                    data.extend_from_slice(&identification.to_be_bytes());
+                    return data;
+                }
+            }
+            data[1] = (data.len() / 8 - 1) as u8;
+            data
+        }
+
+        /// Decodes an extension header of type `header_type` at the start
+        /// of `data`. Returns the header, the next header value and the
+        /// length consumed. `offset` locates `data` in the frame, for errors.
+        fn decode(header_type: u8, data: &[u8], offset: usize) -> Result<(Self, u8, usize), FrameError> {
+            if data.len() < 8 {
+                return Err(FrameError::Truncated { offset, needed: 8, available: data.len() });
+            }
+            let len = match header_type {
+                FRAGMENT => 8,
+                _ => (data[1] as usize + 1) * 8,
+            };
+            if data.len() < len {
+                return Err(FrameError::Truncated { offset, needed: len, available: data.len() });
+            }
+            let header = match header_type {
+                HOP_BY_HOP => ExtensionHeader::HopByHop { options: data[2..len].to_vec() },
+                DESTINATION_OPTIONS => ExtensionHeader::DestinationOptions { options: data[2..len].to_vec() },
+                ROUTING => ExtensionHeader::Routing {
+                    routing_type: data[2],
+                    segments_left: data[3],
+                    data: data[4..len].to_vec(),
+                },
+                FRAGMENT => {
+                    let field = u16::from_be_bytes([data[2], data[3]]);
+                    ExtensionHeader::Fragment {
+                        offset: field >> 3,
+                        more_fragments: field & 1 != 0,
+                        identification: u32::from_be_bytes(data[4..8].try_into().unwrap()),
+                    }
+                }
+                _ => return Err(FrameError::InvalidField { offset, field: "next header" }),
+            };
+            Ok((header, data[0], len))
+        }
+    }
+
+    /// Pads an options header to a multiple of 8 bytes, with Pad1 or PadN.
+    fn pad_options(data: &mut Vec<u8>) {
+        match (8 - data.len() % 8) % 8 {
+            0 => (),
+            1 => data.push(0),
+            n => {
+                data.push(1);
+                data.push(n as u8 - 2);
+                data.resize(data.len() + n - 2, 0);
+            }
+        }
+    }
+
+    /// Checksum over the IPv6 pseudo-header followed by `data`, as used by
+    /// UDP, TCP and ICMPv6.
+    pub fn pseudo_header_checksum(src: Ipv6Addr, dst: Ipv6Addr, protocol: IpProtocol, data: &[u8]) -> u16 {
+        let mut sum = checksum_add(0, &src.octets());
+        sum = checksum_add(sum, &dst.octets());
+        sum = checksum_add(sum, &(data.len() as u32).to_be_bytes());
+        sum += u8::from(protocol) as u32;
+        checksum_fold(checksum_add(sum, data))
+    }
+
+    /// Modified EUI-64 interface identifier of a MAC address: `ff:fe`
+    /// inserted in the middle and the universal/local bit flipped.
+    pub fn eui64(mac: &MacAddress) -> [u8; 8] {
+        let b = mac.bytes();
+        [b[0] ^ 0x02, b[1], b[2], 0xff, 0xfe, b[3], b[4], b[5]]
+    }
+
+    /// The `fe80::/64` link-local address derived from a MAC address.
+    pub fn link_local(mac: &MacAddress) -> Ipv6Addr {
+        let mut octets = [0u8; 16];
+        octets[0] = 0xfe;
+        octets[1] = 0x80;
+        octets[8..].copy_from_slice(&eui64(mac));
+        Ipv6Addr::from(octets)
+    }
+
+    /// The Ethernet multicast address of an IPv6 multicast group
+    /// (`33:33` followed by the low 32 bits, RFC 2464).
+    pub fn multicast_mac(addr: &Ipv6Addr) -> MacAddress {
+        let o = addr.octets();
+        MacAddress::new([0x33, 0x33, o[12], o[13], o[14], o[15]])
+    }
+
+    /// Netmask of a prefix length, as an integer.
+    pub fn prefix_mask(prefix_len: u8) -> u128 {
+        u128::MAX.checked_shl(128 - prefix_len.min(128) as u32).unwrap_or(0)
+    }
+
+    impl Packet<Eth> {
+        /// Parses the frame content as an IPv6 datagram. The payload length
+        /// and the extension header chain are checked.
+        pub fn parse_ipv6(self) -> Result<Packet<Ipv6>, RlinkError> {
+            match validate(&self) {
+                Ok(()) => Ok(self.cast()),
+                Err(why) => Err(RlinkError::InvalidPacket(self.cast::<Raw>(), why)),
+            }
+        }
+    }
+
+    fn validate(packet: &Packet<Eth>) -> Result<(), FrameError> {
+        if packet.ethtype() != EtherType::IPv6 {
+            return Err(FrameError::InvalidField { offset: 12, field: "ether type" });
+        }
+        let ip = packet.data();
+        let at = |offset| ETH_HEADER_LEN + offset;
+        if ip.len() < HEADER_LEN {
+            return Err(FrameError::Truncated { offset: at(0), needed: HEADER_LEN, available: ip.len() });
+        }
+        if ip[0] >> 4 != 6 {
+            return Err(FrameError::InvalidField { offset: at(0), field: "IP version" });
+        }
+        let total_len = HEADER_LEN + u16::from_be_bytes([ip[4], ip[5]]) as usize;
+        if ip.len() < total_len {
+            return Err(FrameError::Truncated { offset: at(0), needed: total_len, available: ip.len() });
+        }
+        walk(&ip[..total_len]).map_err(|why| match why {
+            FrameError::Truncated { offset, needed, available } =>
+                FrameError::Truncated { offset: at(offset), needed, available },
+            FrameError::InvalidField { offset, field } => FrameError::InvalidField { offset: at(offset), field },
+            why => why,
+        })?;
+        Ok(())
+    }
+
+    /// Walks the extension header chain of a datagram. Returns the headers,
+    /// the upper-layer protocol and the offset of its data.
+    fn walk(datagram: &[u8]) -> Result<(Vec<ExtensionHeader>, u8, usize), FrameError> {
+        let mut headers = Vec::new();
+        let mut next_header = datagram[6];
+        let mut offset = HEADER_LEN;
+        while ExtensionHeader::is_extension(next_header) {
+            if next_header == HOP_BY_HOP && offset != HEADER_LEN {
+                return Err(FrameError::InvalidField { offset, field: "hop-by-hop options position" });
+            }
+            let (header, next, len) = ExtensionHeader::decode(next_header, &datagram[offset..], offset)?;
+            headers.push(header);
+            next_header = next;
+            offset += len;
+        }
+        Ok((headers, next_header, offset))
+    }
+
+    impl Packet<Ipv6> {
+        fn ip(&self) -> &[u8] {
+            &self.data[ETH_HEADER_LEN..]
+        }
+
+        /// Destination MAC address of the carrying frame.
+        pub fn dst_mac(&self) -> MacAddress {
+            MacAddress::new(self.data[0..6].try_into().unwrap())
+        }
+
+        /// Source MAC address of the carrying frame.
+        pub fn src_mac(&self) -> MacAddress {
+            MacAddress::new(self.data[6..12].try_into().unwrap())
+        }
+
+        pub fn traffic_class(&self) -> u8 {
+            (self.ip()[0] << 4) | (self.ip()[1] >> 4)
+        }
+
+        pub fn dscp(&self) -> u8 {
+            self.traffic_class() >> 2
+        }
+
+        pub fn ecn(&self) -> u8 {
+            self.traffic_class() & 0x03
+        }
+
+        pub fn flow_label(&self) -> u32 {
+            u32::from_be_bytes([0, self.ip()[1] & 0x0f, self.ip()[2], self.ip()[3]])
+        }
+
+        /// Length of everything after the fixed header, extension headers
+        /// included.
+        pub fn payload_len(&self) -> usize {
+            u16::from_be_bytes([self.ip()[4], self.ip()[5]]) as usize
+        }
+
+        /// The next header field of the fixed header.
+        pub fn next_header(&self) -> u8 {
+            self.ip()[6]
+        }
+
+        pub fn hop_limit(&self) -> u8 {
+            self.ip()[7]
+        }
+
+        pub fn src(&self) -> Ipv6Addr {
+            Ipv6Addr::from(<[u8; 16]>::try_from(&self.ip()[8..24]).unwrap())
+        }
+
+        pub fn dst(&self) -> Ipv6Addr {
+            Ipv6Addr::from(<[u8; 16]>::try_from(&self.ip()[24..40]).unwrap())
+        }
+
+        /// The whole datagram, headers and payload.
+        pub fn datagram(&self) -> &[u8] {
+            &self.ip()[..HEADER_LEN + self.payload_len()]
+        }
+
+        fn chain(&self) -> (Vec<ExtensionHeader>, u8, usize) {
+            // Validated when parsing.
+            walk(self.datagram()).unwrap()
+        }
+
+        /// The extension headers, in order.
+        pub fn extension_headers(&self) -> Vec<ExtensionHeader> {
+            self.chain().0
+        }
+
+        /// The protocol following the extension headers.
+        pub fn protocol(&self) -> IpProtocol {
+            IpProtocol::from(self.chain().1)
+        }
+
+        /// Fragment offset (in units of 8 bytes), more fragments flag and
+        /// identification, if the datagram is a fragment.
+        pub fn fragment(&self) -> Option<(u16, bool, u32)> {
+            self.extension_headers().into_iter().find_map(|header| match header {
+                ExtensionHeader::Fragment { offset, more_fragments, identification } =>
+                    Some((offset, more_fragments, identification)),
+                _ => None,
+            })
+        }
+
+        /// The fixed header and the extension headers.
+        pub fn header(&self) -> &[u8] {
+            &self.ip()[..self.chain().2]
+        }
+
+        /// The upper-layer payload, after the extension headers. Ethernet
+        /// padding is excluded.
+        pub fn payload(&self) -> &[u8] {
+            &self.datagram()[self.chain().2..]
+        }
+
+        /// A header builder with the fields of this datagram.
+        pub fn to_header(&self) -> Ipv6Header {
+            Ipv6Header {
+                traffic_class: self.traffic_class(),
+                flow_label: self.flow_label(),
+                hop_limit: self.hop_limit(),
+                protocol: self.protocol(),
+                src: self.src(),
+                dst: self.dst(),
+                extension_headers: self.extension_headers(),
+            }
+        }
+    }
+
+    impl fmt::Display for Packet<Ipv6> {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            writeln!(f, "{:?}", self.header)?;
+            writeln!(f, "{:?}", self.mac_address)?;
+            writeln!(f, "src: {}, dst: {}", self.src(), self.dst())?;
+            writeln!(f, "protocol: {}, hop limit: {}, flow: 0x{:0>5X}, len: {}",
+                self.protocol(), self.hop_limit(), self.flow_label(), self.payload_len())?;
+            for header in self.extension_headers() {
+                writeln!(f, "{:?}", header)?;
+            }
+
+            // Hex dump the payload
+            for (idx, byte) in self.payload().iter().enumerate() {
+                write!(f, "{:0>2X} ", byte)?;
+                match idx % 12 {
+                    5 => write!(f, " ")?,
+                    11 => writeln!(f)?,
+                    _ => {}
+                };
+            }
+            Ok(())
+        }
+    }
+
+    /// Fields of an IPv6 header, for constructing datagrams.
+    #[derive(Clone, Debug, PartialEq, Eq)]
+    pub struct Ipv6Header {
+        pub traffic_class: u8,
+        /// Flow label, 20 bits
+        pub flow_label: u32,
+        pub hop_limit: u8,
+        /// Upper-layer protocol, following the extension headers
+        pub protocol: IpProtocol,
+        pub src: Ipv6Addr,
+        pub dst: Ipv6Addr,
+        pub extension_headers: Vec<ExtensionHeader>,
+    }
+
+    impl Ipv6Header {
+        pub fn new(src: Ipv6Addr, dst: Ipv6Addr, protocol: IpProtocol) -> Self {
+            Ipv6Header {
+                traffic_class: 0,
+                flow_label: 0,
+                hop_limit: DEFAULT_HOP_LIMIT,
+                protocol,
+                src,
+                dst,
+                extension_headers: Vec::new(),
+            }
+        }
+
+        /// Encodes the header, the extension headers and `payload`, chaining
+        /// the next header fields and filling in the payload length.
+        pub fn encode(&self, payload: &[u8]) -> Vec<u8> {
+            let mut extensions = Vec::new();
+            for (idx, header) in self.extension_headers.iter().enumerate() {
+                let next = match self.extension_headers.get(idx + 1) {
+                    Some(next) => next.header_type(),
+                    None => self.protocol.into(),
+                };
+                extensions.extend(header.encode(next));
+            }
+            let next_header = match self.extension_headers.first() {
+                Some(first) => first.header_type(),
+                None => self.protocol.into(),
+            };
+            let payload_len = extensions.len() + payload.len();
+
+            let mut datagram = Vec::with_capacity(HEADER_LEN + payload_len);
+            let first = 0x6000_0000u32 | (self.traffic_class as u32) << 20 | (self.flow_label & 0x000f_ffff);
+            datagram.extend_from_slice(&first.to_be_bytes());
+            datagram.extend_from_slice(&(payload_len as u16).to_be_bytes());
+            datagram.push(next_header);
+            datagram.push(self.hop_limit);
+            datagram.extend_from_slice(&self.src.octets());
+            datagram.extend_from_slice(&self.dst.octets());
+            datagram.extend_from_slice(&extensions);
+            datagram.extend_from_slice(payload);
+            datagram
+        }
+    }
+}
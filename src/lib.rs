@@ -12,23 +12,66 @@
 pub mod ethtype;
 pub mod device_pool;
 pub mod packet;
+pub mod mac;
+pub mod error;
+pub mod interface;
+pub mod netlink;
+pub mod netns;
+pub mod ipv4;
+pub mod ipv6;
+pub mod ndp;
+pub mod arp;
+pub mod icmp;
+pub mod stack;
+pub mod udp;
+pub mod dhcp;
+pub mod tcp;
+pub mod memlink;
+pub mod lldp;
+pub mod stats;
+pub mod qos;
+pub mod shaper;
+pub mod filter;
+pub mod config;
+pub mod cli;
+pub mod ptp;
+#[cfg(feature = "prometheus")]
+pub mod metrics;
 
-use pcap::{Capture, Active, Linktype, Packet as _Packet, PacketHeader, Stat};
-pub use pcap::{Device, Direction};
+use pcap::{Capture, Active, Packet as _Packet, PacketHeader, Stat};
+pub use pcap::{Device, Direction, Linktype, Precision, TimestampType};
 pub use pcap::Error as PError;
-pub use mac_address::MacAddress;
+pub use mac_address::{MacAddress, MacAddressError};
 use crc::{Crc, CRC_32_CKSUM};
 use std::fmt;
 use std::error::Error;
 use std::borrow::Borrow;
+use std::sync::Arc;
+use std::time::SystemTime;
 
 pub use ethtype::ethtype::EtherType;
 pub use packet::packet::Packet;
 pub use packet::packet::{Type, Raw, Eth};
-pub use device_pool::device_pool::DevicePool;
+pub use device_pool::device_pool::{DevicePool, DropPolicy, PoolHealth, PoolMonitor};
+pub use mac::mac::{MacAddressExt, parse_mac};
+pub use error::error::{RlinkError, FrameError};
+pub use interface::interface::{Interface, InterfaceEvent, InterfaceWatcher};
+pub use netns::netns::NetNs;
+pub use ipv4::ipv4::Ipv4;
+pub use ipv6::ipv6::Ipv6;
+pub use stack::stack::Stack;
+pub use udp::udp::UdpSocket;
+pub use dhcp::dhcp::{DhcpClient, DhcpServer};
+pub use tcp::tcp::{TcpListener, TcpStream};
+pub use lldp::lldp::LldpAgent;
+pub use stats::stats::{DeviceCounters, DeviceStats};
+pub use qos::qos::{EgressScheduler, QosConfig};
+pub use shaper::shaper::{RxShaper, Shaper, ShaperConfig};
+pub use filter::filter::Filter;
+pub use ptp::ptp::{PtpConfig, PtpPort};
 
 
-type DeviceCallback = Box<dyn Fn(Packet<Raw>, &MacAddress)->Option<Packet<Raw>>>;
+type DeviceCallback = Box<dyn Fn(Packet<Raw>, &MacAddress)->Option<Packet<Raw>> + Send>;
 
 /// An active network device to operate on.
 pub struct DeviceHandle {
@@ -40,43 +83,213 @@ pub struct DeviceHandle {
     cap: Capture<Active>,
     /// Callback function
     callback: Option<DeviceCallback>,
+    /// Filter evaluated in user space, before the callback
+    rx_filter: Option<Filter>,
+    /// rlink-level counters, shared with the other handles on the device
+    counters: Arc<DeviceCounters>,
+    /// Resolution of the capture timestamps
+    precision: Precision,
 }
 
-impl fmt::Display for DeviceHandle {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "device: {:?}, mac_address: {:?}, id: {:p},", self.device, self.mac_address, &self)
+/// Capture options of a DeviceHandle to open. Options left unset keep
+/// the defaults of libpcap. For high-rate tests, a larger `buffer_size()`
+/// avoids kernel drops, and `precision(Precision::Nano)` times packets
+/// finely enough to measure latencies.
+#[derive(Clone, Debug)]
+pub struct DeviceHandleBuilder {
+    name: String,
+    netns: Option<String>,
+    timeout: Option<i32>,
+    immediate: Option<bool>,
+    promisc: Option<bool>,
+    snaplen: Option<i32>,
+    buffer_size: Option<i32>,
+    tstamp_type: Option<TimestampType>,
+    precision: Option<Precision>,
+    rfmon: Option<bool>,
+    datalink: Option<Linktype>,
+}
+
+impl DeviceHandleBuilder {
+    pub fn new(name: &str) -> Self {
+        DeviceHandleBuilder {
+            name: name.to_string(),
+            netns: None,
+            timeout: None,
+            immediate: None,
+            promisc: None,
+            snaplen: None,
+            buffer_size: None,
+            tstamp_type: None,
+            precision: None,
+            rfmon: None,
+            datalink: None,
+        }
+    }
+
+    /// Open the device in another network namespace. The calling thread
+    /// enters `netns` to open the device, and returns to its original
+    /// namespace afterwards.
+    pub fn netns(mut self, netns: &str) -> Self {
+        self.netns = Some(netns.to_string());
+        self
+    }
+
+    /// Read timeout in milliseconds. Without one, reading blocks until a
+    /// packet arrives.
+    pub fn timeout(mut self, ms: i32) -> Self {
+        self.timeout = Some(ms);
+        self
+    }
+
+    /// Deliver packets as soon as they arrive, rather than in batches.
+    pub fn immediate(mut self, to: bool) -> Self {
+        self.immediate = Some(to);
+        self
+    }
+
+    pub fn promisc(mut self, to: bool) -> Self {
+        self.promisc = Some(to);
+        self
+    }
+
+    /// Bytes captured of each packet, 65535 by default.
+    pub fn snaplen(mut self, to: i32) -> Self {
+        self.snaplen = Some(to);
+        self
+    }
+
+    /// Bytes of the kernel buffer holding packets until they are read,
+    /// about 1MB by default. Raise it to avoid drops at high rates.
+    pub fn buffer_size(mut self, to: i32) -> Self {
+        self.buffer_size = Some(to);
+        self
+    }
+
+    /// Source of the timestamps, if the device offers several.
+    pub fn tstamp_type(mut self, tstamp_type: TimestampType) -> Self {
+        self.tstamp_type = Some(tstamp_type);
+        self
+    }
+
+    /// Resolution of the timestamps, microseconds by default. Opening
+    /// fails if the device does not offer it.
+    pub fn precision(mut self, precision: Precision) -> Self {
+        self.precision = Some(precision);
+        self
+    }
+
+    /// Monitor mode, on wireless devices.
+    pub fn rfmon(mut self, to: bool) -> Self {
+        self.rfmon = Some(to);
+        self
+    }
+
+    /// Datalink type to capture with, set before the handle is returned.
+    pub fn datalink(mut self, linktype: Linktype) -> Self {
+        self.datalink = Some(linktype);
+        self
+    }
+
+    /// Look up the device and activate a Capture on it.
+    pub fn open(self) -> Result<DeviceHandle, RlinkError> {
+        match self.netns.clone() {
+            Some(netns) => netns::netns::with_netns(&netns, || {
+                let iface = interface::interface::interface_by_name(&self.name)?;
+                self.open_interface(&iface)
+            }),
+            None => {
+                let (device, mac_address) = DeviceHandle::lookup(&self.name)?;
+                self.activate(device, mac_address)
+            }
+        }
+    }
+
+    /// Activate a Capture on an interface obtained from the interface
+    /// inventory, without listing the devices again. The name of the
+    /// builder is ignored, and its network namespace only keys the counters
+    /// of the device.
+    pub fn open_interface(self, iface: &Interface) -> Result<DeviceHandle, RlinkError> {
+        let mac_address = iface.mac_address.ok_or(RlinkError::MacLookup { 
+            device: iface.name.clone(), source: None })?;
+        self.activate(iface.device(), mac_address)
+    }
+
+    fn activate(self, device: Device, mac_address: MacAddress) -> Result<DeviceHandle, RlinkError> {
+        // Without it, nanoseconds would be read as microseconds
+        if self.precision == Some(Precision::Nano) && !precision_supported(&device.name, Precision::Nano)? {
+            return Err(RlinkError::Pcap(PError::PcapError(format!(
+                "{}: that device doesn't support that time stamp precision", device.name))));
+        }
+        let mut cap = Capture::from_device(device.name.as_str())?;
+        if let Some(ms) = self.timeout {
+            cap = cap.timeout(ms);
+        }
+        if let Some(to) = self.immediate {
+            cap = cap.immediate_mode(to);
+        }
+        if let Some(to) = self.promisc {
+            cap = cap.promisc(to);
+        }
+        if let Some(to) = self.snaplen {
+            cap = cap.snaplen(to);
+        }
+        if let Some(to) = self.buffer_size {
+            cap = cap.buffer_size(to);
+        }
+        if let Some(tstamp_type) = self.tstamp_type {
+            cap = cap.tstamp_type(tstamp_type);
+        }
+        if let Some(precision) = self.precision {
+            cap = cap.precision(precision);
+        }
+        if let Some(to) = self.rfmon {
+            cap = cap.rfmon(to);
+        }
+        let mut cap = cap.open()?;
+        if let Some(linktype) = self.datalink {
+            cap.set_datalink(linktype)?;
+        }
+        let counters = stats::stats::device_counters(self.netns.as_deref(), &device.name);
+        Ok(DeviceHandle{
+            device, 
+            mac_address, 
+            cap,
+            callback: None,
+            rx_filter: None,
+            counters,
+            precision: self.precision.unwrap_or(Precision::Micro),
+        })
     }
 }
 
-/// Errors beyond PcapError
-#[derive(Debug)]
-pub enum RlinkError {
-    /// An invalid device name is requested
-    InvalidDeviceName(&'static str),
-    /// Payload exceeds maximum frame size
-    PayloadTooLarge,
-    /// Payload size mismatch with specified length
-    PayloadLengthMismatch,
-    /// Invalid Packet Format
-    InvalidPacket(Packet::<Raw>, &'static str),
-    /// Broken Device Pool
-    BrokenDevicePool,
+extern "C" {
+    fn pcap_create(source: *const libc::c_char, errbuf: *mut libc::c_char) -> *mut libc::c_void;
+    fn pcap_set_tstamp_precision(p: *mut libc::c_void, precision: libc::c_int) -> libc::c_int;
+    fn pcap_close(p: *mut libc::c_void);
 }
 
-impl fmt::Display for RlinkError {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        use RlinkError::*;
-        match *self {
-            InvalidDeviceName(name) => write!(f, "invalid device name: {:?}", name),
-            PayloadTooLarge => write!(f, "payload too large"),
-            PayloadLengthMismatch => write!(f, "payload size mismatch with ether type"),
-            InvalidPacket(_, why) => write!(f, "invalid packet format: {}", why),
-            BrokenDevicePool => write!(f, "all devices in the pool are offline"),
-        }
+/// Whether libpcap grants timestamps of `precision` on device `name`.
+/// The pcap crate ignores the result of `pcap_set_tstamp_precision()`,
+/// so it is probed on a handle of our own, never activated.
+fn precision_supported(name: &str, precision: Precision) -> Result<bool, RlinkError> {
+    let source = std::ffi::CString::new(name).map_err(|_| RlinkError::Pcap(PError::InvalidString))?;
+    let mut errbuf = [0 as libc::c_char; 256];
+    let p = unsafe { pcap_create(source.as_ptr(), errbuf.as_mut_ptr()) };
+    if p.is_null() {
+        let why = unsafe { std::ffi::CStr::from_ptr(errbuf.as_ptr()) };
+        return Err(RlinkError::Pcap(PError::PcapError(why.to_string_lossy().into_owned())));
     }
+    let ret = unsafe { pcap_set_tstamp_precision(p, precision as libc::c_int) };
+    unsafe { pcap_close(p) };
+    Ok(ret == 0)
 }
 
-impl Error for RlinkError {}
+impl fmt::Display for DeviceHandle {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "device: {:?}, mac_address: {:?}, id: {:p},", self.device, self.mac_address, &self)
+    }
+}
 
 impl DeviceHandle {
     /// Create a new DeviceHandle by looking up given device name and 
@@ -88,27 +301,53 @@ impl DeviceHandle {
     /// 
     /// Returns the newly created DeviceHandle or an Error.
     pub fn new(name: &str, timeout: i32, immediate: bool) 
-        -> Result<Self, Box<dyn Error>> {
-        match Device::list()?
+        -> Result<Self, RlinkError> {
+        DeviceHandleBuilder::new(name).timeout(timeout).immediate(immediate).open()
+    }
+
+    /// Start building a DeviceHandle on given device name, to set capture
+    /// options `new()` leaves at their defaults.
+    pub fn builder(name: &str) -> DeviceHandleBuilder {
+        DeviceHandleBuilder::new(name)
+    }
+
+    fn lookup(name: &str) -> Result<(Device, MacAddress), RlinkError> {
+        let devices = Device::list()?;
+        match devices
             .iter()
             .find(|&x| x.name.eq(name)) {
             Some(device) => {
-                let cap = Capture::from_device(device.name.as_str())?
-                    .timeout(timeout)
-                    .immediate_mode(immediate)
-                    .open()?;
-                let mac_address = mac_address::mac_address_by_name(name)?.unwrap();
-                Ok(DeviceHandle{
-                    device: device.clone(), 
-                    mac_address, 
-                    cap,
-                    callback: None,
-                })
+                let mac_address = match mac_address::mac_address_by_name(name) {
+                    Ok(Some(mac_address)) => mac_address,
+                    Ok(None) => return Err(RlinkError::MacLookup { 
+                        device: name.to_string(), source: None }),
+                    Err(e) => return Err(RlinkError::MacLookup { 
+                        device: name.to_string(), source: Some(e) }),
+                };
+                Ok((device.clone(), mac_address))
             },
-            None => Err(Box::new(RlinkError::InvalidDeviceName("Invalid device name"))),
+            None => Err(RlinkError::InvalidDeviceName {
+                name: name.to_string(),
+                available: devices.into_iter().map(|d| d.name).collect(),
+            }),
         }                         
     }
 
+    /// Create a new DeviceHandle on an interface obtained from the 
+    /// interface inventory, without listing the devices again.
+    pub fn open(iface: &Interface, timeout: i32, immediate: bool)
+        -> Result<Self, RlinkError> {
+        DeviceHandleBuilder::new(&iface.name).timeout(timeout).immediate(immediate).open_interface(iface)
+    }
+
+    /// Create a new DeviceHandle on an interface of another network 
+    /// namespace. The calling thread enters `netns` to open the device,
+    /// and returns to its original namespace afterwards.
+    pub fn new_in_netns(netns: &str, name: &str, timeout: i32, immediate: bool)
+        -> Result<Self, RlinkError> {
+        DeviceHandleBuilder::new(name).timeout(timeout).immediate(immediate).netns(netns).open()
+    }
+
     /// Returns the associated device.
     pub fn device(&self) -> &Device {
         &self.device
@@ -118,6 +357,18 @@ impl DeviceHandle {
         &self.mac_address
     }
 
+    /// The IPv6 link-local address derived from the device MAC address
+    /// (EUI-64).
+    pub fn link_local_ipv6(&self) -> std::net::Ipv6Addr {
+        ipv6::ipv6::link_local(&self.mac_address)
+    }
+
+    /// Resolution of the timestamps of captured packets, see
+    /// `Packet::timestamp()`.
+    pub fn precision(&self) -> Precision {
+        self.precision
+    }
+
     /// List the datalink types that this captured device supports.
     pub fn list_datalinks(&self) -> Result<Vec<Linktype>, PError> {
         self.cap.list_datalinks()
@@ -151,6 +402,12 @@ impl DeviceHandle {
         self.cap.stats()
     }
 
+    /// rlink-level counters of the device, complementing `stats()`. They are
+    /// shared by all handles on the device.
+    pub fn counters(&self) -> &Arc<DeviceCounters> {
+        &self.counters
+    }
+
     /// Set the capture to be non-blocking. When this is set, `Self::next_packet()` 
     /// may return an error indicating that there is no packet available to be read.
     pub fn setnonblock(self) -> Result<Self, PError> {
@@ -173,21 +430,45 @@ impl DeviceHandle {
         ethtype: EtherType,
         dest_mac: &[u8; 6],
         checksum: bool,
-    ) -> Result<(), Box<dyn Error>> {
-        let payload = payload.borrow();
+    ) -> Result<(), RlinkError> {
+        self.transmit(payload.borrow(), ethtype, dest_mac, checksum).map(|_| ())
+    }
+
+    /// Sends a packet as `send_packet()` does, and returns the time it was
+    /// handed to the kernel, by the system clock, or `None` if no frame was
+    /// sent (IEEE 802.3 frames are not supported).
+    /// The time is taken in user space right before the frame is written,
+    /// hence precedes the capture timestamps of the frame, by the time the
+    /// kernel takes to take it through the network stack.
+    pub fn send_packet_timestamped<B: Borrow<[u8]>>(&mut self,
+        payload: B,
+        ethtype: EtherType,
+        dest_mac: &[u8; 6],
+        checksum: bool,
+    ) -> Result<Option<SystemTime>, RlinkError> {
+        self.transmit(payload.borrow(), ethtype, dest_mac, checksum)
+    }
+
+    fn transmit(&mut self,
+        payload: &[u8],
+        ethtype: EtherType,
+        dest_mac: &[u8; 6],
+        checksum: bool,
+    ) -> Result<Option<SystemTime>, RlinkError> {
         let len = payload.len();
         if let EtherType::IEEE802_3(_len) = ethtype {
             if _len as usize != len {
-                return Err(Box::new(RlinkError::PayloadLengthMismatch));
+                return Err(RlinkError::PayloadLengthMismatch { 
+                    expected: _len as usize, actual: len });
             }
         }
         if len >= 1500 {
-            return Err(Box::new(RlinkError::PayloadTooLarge));
+            return Err(RlinkError::PayloadTooLarge { size: len, max: 1499 });
         }
 
         match ethtype {
             // IEEE 802.3 Frame is currently not supported
-            EtherType::IEEE802_3(_) => Ok(()),
+            EtherType::IEEE802_3(_) => Ok(None),
             // For other types, assume Ethernet II Frame format
             _ => {
                 let mut frame = [
@@ -200,18 +481,24 @@ impl DeviceHandle {
                 // Pad frame to Minimum Frame Size
                 frame.resize(std::cmp::max(frame.len(), 60usize), 0u8);
 
-                if checksum {
-                    let checksum = crc::Crc::<u32>::new(&CRC_32_CKSUM).checksum(frame.as_ref());
-                    self.cap.sendpacket([
-                        frame.as_ref(), 
-                        checksum.to_be_bytes().as_ref()].concat())?;
+                let checksum = if checksum {
+                    crc::Crc::<u32>::new(&CRC_32_CKSUM).checksum(frame.as_ref())
                 }
                 else {
-                    self.cap.sendpacket([
-                        frame.as_ref(), 
-                        0u32.to_be_bytes().as_ref()].concat())?;
+                    0u32
+                };
+                frame.extend_from_slice(&checksum.to_be_bytes());
+                let sent_at = SystemTime::now();
+                match self.cap.sendpacket(frame.as_slice()) {
+                    Ok(()) => {
+                        self.counters.record_tx(&frame);
+                        Ok(Some(sent_at))
+                    }
+                    Err(e) => {
+                        self.counters.record_tx_error();
+                        Err(e.into())
+                    }
                 }
-                Ok(())
             }
         }
     }
@@ -222,15 +509,33 @@ impl DeviceHandle {
         self.callback = Some(callback);
     }
 
+    /// Set a filter evaluated in user space on the packets read by
+    /// `next_packet()`, or remove it. Unlike `filter()`, packets are still
+    /// captured (and counted) before being filtered out.
+    pub fn set_filter(&mut self, filter: Option<Filter>) {
+        self.rx_filter = filter;
+    }
+
     /// Read a packet from this capture handle’s interface. May or may not block,
     /// based on the handle setting. A callback function, if registered, will be
     /// invoked on the packet first. 
-    /// The callback function might take the packet, hence the return value is
+    /// The callback function might take the packet, and packets not matching
+    /// the filter set with `set_filter()` are skipped, hence the return value is
     /// `Option<Packet>` rather than `Packet`.
     pub fn next_packet(&mut self) -> Result<Option<Packet<Raw>>, PError> {
-        let packet = Packet::<Raw>::from(self.cap.next_packet()?, self.mac_address.clone());
+        let packet = Packet::<Raw>::from(self.cap.next_packet()?, self.mac_address.clone())
+            .with_counters(self.counters.clone())
+            .with_precision(self.precision);
+        self.counters.record_rx(&packet.data);
+        if self.rx_filter.as_ref().is_some_and(|filter| !filter.matches(&packet)) {
+            return Ok(None);
+        }
         if let Some(func) = &self.callback {
-            Ok(func(packet, &self.mac_address))
+            let packet = func(packet, &self.mac_address);
+            if packet.is_none() {
+                self.counters.record_consumed();
+            }
+            Ok(packet)
         }
         else {
             Ok(Some(packet))
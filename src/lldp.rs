@@ -0,0 +1,450 @@
+#![allow(dead_code)]
+#![allow(unused)]
+
+pub mod lldp {
+    //! Link Layer Discovery Protocol (IEEE 802.1AB): LLDPDU encoding, and
+    //! an agent advertising itself on a set of ports while recording the
+    //! neighbors heard on each of them.
+    //!
+    //! Like the stack, the agent owns one sending handle per port and is
+    //! fed by a `DevicePool`.
+
+    use crate::interface::interface::{self, Interface};
+    use crate::stack::stack::FrameSink;
+    use crate::{DeviceHandle, DevicePool, EtherType, Eth, FrameError, Packet, RlinkError};
+    use mac_address::MacAddress;
+    use std::fmt;
+    use std::sync::{Arc, Mutex, Weak};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    /// Nearest bridge group address, which LLDPDUs are sent to and which
+    /// bridges do not forward.
+    pub const NEAREST_BRIDGE: [u8; 6] = [0x01, 0x80, 0xc2, 0x00, 0x00, 0x0e];
+
+    /// TLV types.
+    pub const END: u8 = 0;
+    pub const CHASSIS_ID: u8 = 1;
+    pub const PORT_ID: u8 = 2;
+    pub const TTL: u8 = 3;
+    pub const PORT_DESCRIPTION: u8 = 4;
+    pub const SYSTEM_NAME: u8 = 5;
+    pub const SYSTEM_DESCRIPTION: u8 = 6;
+
+    /// Chassis ID subtype of MAC addresses.
+    const CHASSIS_MAC: u8 = 4;
+    /// Port ID subtypes.
+    const PORT_MAC: u8 = 3;
+    const PORT_INTERFACE_NAME: u8 = 5;
+
+    /// Default interval between advertisements (msgTxInterval).
+    pub const TX_INTERVAL: Duration = Duration::from_secs(30);
+    /// Default multiplier of the interval giving the TTL (msgTxHold).
+    pub const TX_HOLD: u16 = 4;
+    /// Resolution of the timer thread.
+    const TICK: Duration = Duration::from_millis(100);
+
+    #[derive(Clone, Debug, PartialEq, Eq, Hash)]
+    pub enum ChassisId {
+        MacAddress(MacAddress),
+        /// Any other subtype
+        Other { subtype: u8, id: Vec<u8> },
+    }
+
+    #[derive(Clone, Debug, PartialEq, Eq, Hash)]
+    pub enum PortId {
+        InterfaceName(String),
+        MacAddress(MacAddress),
+        /// Any other subtype
+        Other { subtype: u8, id: Vec<u8> },
+    }
+
+    /// Printable IDs as they are, others in hex.
+    fn fmt_id(f: &mut fmt::Formatter, id: &[u8]) -> fmt::Result {
+        match std::str::from_utf8(id) {
+            Ok(s) if !s.is_empty() && s.chars().all(|c| c.is_ascii_graphic()) => write!(f, "{}", s),
+            _ => id.iter().try_for_each(|b| write!(f, "{:02x}", b)),
+        }
+    }
+
+    impl fmt::Display for ChassisId {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            match self {
+                ChassisId::MacAddress(mac) => write!(f, "{}", mac),
+                ChassisId::Other { id, .. } => fmt_id(f, id),
+            }
+        }
+    }
+
+    impl fmt::Display for PortId {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            match self {
+                PortId::InterfaceName(name) => write!(f, "{}", name),
+                PortId::MacAddress(mac) => write!(f, "{}", mac),
+                PortId::Other { id, .. } => fmt_id(f, id),
+            }
+        }
+    }
+
+    /// An LLDP data unit. A TTL of zero announces that the sender shuts
+    /// down.
+    #[derive(Clone, Debug, PartialEq, Eq)]
+    pub struct Lldpdu {
+        pub chassis_id: ChassisId,
+        pub port_id: PortId,
+        /// Seconds
+        pub ttl: u16,
+        pub port_description: Option<String>,
+        pub system_name: Option<String>,
+        pub system_description: Option<String>,
+        /// Other TLVs, as `(type, value)`
+        pub other: Vec<(u8, Vec<u8>)>,
+    }
+
+    fn put_tlv(out: &mut Vec<u8>, tlv_type: u8, value: &[u8]) {
+        let len = value.len().min(511);
+        out.extend_from_slice(&((tlv_type as u16) << 9 | len as u16).to_be_bytes());
+        out.extend_from_slice(&value[..len]);
+    }
+
+    impl Lldpdu {
+        pub fn encode(&self) -> Vec<u8> {
+            let mut data = Vec::new();
+            let (subtype, id) = match &self.chassis_id {
+                ChassisId::MacAddress(mac) => (CHASSIS_MAC, mac.bytes().to_vec()),
+                ChassisId::Other { subtype, id } => (*subtype, id.clone()),
+            };
+            put_tlv(&mut data, CHASSIS_ID, &[&[subtype], id.as_slice()].concat());
+            let (subtype, id) = match &self.port_id {
+                PortId::InterfaceName(name) => (PORT_INTERFACE_NAME, name.as_bytes().to_vec()),
+                PortId::MacAddress(mac) => (PORT_MAC, mac.bytes().to_vec()),
+                PortId::Other { subtype, id } => (*subtype, id.clone()),
+            };
+            put_tlv(&mut data, PORT_ID, &[&[subtype], id.as_slice()].concat());
+            put_tlv(&mut data, TTL, &self.ttl.to_be_bytes());
+            for (tlv_type, value) in [
+                (PORT_DESCRIPTION, &self.port_description),
+                (SYSTEM_NAME, &self.system_name),
+                (SYSTEM_DESCRIPTION, &self.system_description),
+            ] {
+                if let Some(value) = value {
+                    put_tlv(&mut data, tlv_type, value.as_bytes());
+                }
+            }
+            for (tlv_type, value) in self.other.iter() {
+                put_tlv(&mut data, *tlv_type, value);
+            }
+            put_tlv(&mut data, END, &[]);
+            data
+        }
+
+        /// Decodes an LLDPDU, which must start with the chassis ID, port ID
+        /// and TTL TLVs, in this order.
+        pub fn decode(data: &[u8]) -> Result<Self, FrameError> {
+            let mut tlvs = Vec::new();
+            let mut offset = 0;
+            while offset < data.len() {
+                if offset + 2 > data.len() {
+                    return Err(FrameError::Truncated { offset, needed: 2, available: data.len() - offset });
+                }
+                let header = u16::from_be_bytes([data[offset], data[offset + 1]]);
+                let (tlv_type, len) = ((header >> 9) as u8, (header & 0x1ff) as usize);
+                if offset + 2 + len > data.len() {
+                    return Err(FrameError::Truncated { offset, needed: len + 2, available: data.len() - offset });
+                }
+                if tlv_type == END {
+                    break;
+                }
+                tlvs.push((offset, tlv_type, &data[offset + 2..offset + 2 + len]));
+                offset += 2 + len;
+            }
+
+            let mandatory = |index: usize, expected: u8, min_len: usize| match tlvs.get(index) {
+                Some((_, tlv_type, value)) if *tlv_type == expected && value.len() >= min_len => Ok(*value),
+                Some((offset, _, _)) => Err(FrameError::InvalidField { offset: *offset, field: "LLDP TLV" }),
+                None => Err(FrameError::Truncated { offset: data.len(), needed: 2, available: 0 }),
+            };
+            let value = mandatory(0, CHASSIS_ID, 2)?;
+            let chassis_id = match (value[0], value.len()) {
+                (CHASSIS_MAC, 7) => ChassisId::MacAddress(MacAddress::new(value[1..].try_into().unwrap())),
+                (subtype, _) => ChassisId::Other { subtype, id: value[1..].to_vec() },
+            };
+            let value = mandatory(1, PORT_ID, 2)?;
+            let port_id = match (value[0], value.len()) {
+                (PORT_INTERFACE_NAME, _) => PortId::InterfaceName(String::from_utf8_lossy(&value[1..]).into_owned()),
+                (PORT_MAC, 7) => PortId::MacAddress(MacAddress::new(value[1..].try_into().unwrap())),
+                (subtype, _) => PortId::Other { subtype, id: value[1..].to_vec() },
+            };
+            let value = mandatory(2, TTL, 2)?;
+            let mut lldpdu = Lldpdu {
+                chassis_id,
+                port_id,
+                ttl: u16::from_be_bytes([value[0], value[1]]),
+                port_description: None,
+                system_name: None,
+                system_description: None,
+                other: Vec::new(),
+            };
+            for (_, tlv_type, value) in tlvs.into_iter().skip(3) {
+                let text = || Some(String::from_utf8_lossy(value).into_owned());
+                match tlv_type {
+                    PORT_DESCRIPTION => lldpdu.port_description = text(),
+                    SYSTEM_NAME => lldpdu.system_name = text(),
+                    SYSTEM_DESCRIPTION => lldpdu.system_description = text(),
+                    _ => lldpdu.other.push((tlv_type, value.to_vec())),
+                }
+            }
+            Ok(lldpdu)
+        }
+    }
+
+    /// What the agent advertises about itself.
+    #[derive(Clone, Debug)]
+    pub struct LldpConfig {
+        pub system_name: Option<String>,
+        pub system_description: Option<String>,
+        pub tx_interval: Duration,
+        /// The advertised TTL is `tx_interval * tx_hold`
+        pub tx_hold: u16,
+    }
+
+    impl Default for LldpConfig {
+        /// Advertise the host name every 30 seconds.
+        fn default() -> Self {
+            LldpConfig {
+                system_name: hostname(),
+                system_description: None,
+                tx_interval: TX_INTERVAL,
+                tx_hold: TX_HOLD,
+            }
+        }
+    }
+
+    fn hostname() -> Option<String> {
+        let mut buf = [0u8; 256];
+        if unsafe { libc::gethostname(buf.as_mut_ptr() as *mut libc::c_char, buf.len()) } != 0 {
+            return None;
+        }
+        let len = buf.iter().position(|b| *b == 0).unwrap_or(buf.len());
+        Some(String::from_utf8_lossy(&buf[..len]).into_owned())
+    }
+
+    /// A neighbor heard on a port of the agent.
+    #[derive(Clone, Debug, PartialEq, Eq)]
+    pub struct LldpNeighbor {
+        /// Index of the local port
+        pub port: usize,
+        /// Name of the local port
+        pub local_port: String,
+        /// Source address of the last LLDPDU
+        pub mac_address: MacAddress,
+        pub chassis_id: ChassisId,
+        pub port_id: PortId,
+        pub port_description: Option<String>,
+        pub system_name: Option<String>,
+        pub system_description: Option<String>,
+        pub expires: Instant,
+    }
+
+    impl fmt::Display for LldpNeighbor {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "{} <-> {} port {}", self.local_port,
+                self.system_name.as_deref().unwrap_or("?"), self.port_id)?;
+            write!(f, " (chassis {}", self.chassis_id)?;
+            if let Some(description) = self.port_description.as_ref() {
+                write!(f, ", {}", description)?;
+            }
+            write!(f, ")")
+        }
+    }
+
+    struct Port {
+        name: String,
+        mac_address: MacAddress,
+        sink: Mutex<Box<dyn FrameSink>>,
+        next_tx: Mutex<Instant>,
+    }
+
+    struct Inner {
+        config: LldpConfig,
+        ports: Mutex<Vec<Arc<Port>>>,
+        neighbors: Mutex<Vec<LldpNeighbor>>,
+    }
+
+    /// An LLDP agent. When dropped, it announces its shutdown on every
+    /// port, so that neighbors forget it right away.
+    pub struct LldpAgent {
+        inner: Arc<Inner>,
+    }
+
+    impl LldpAgent {
+        /// An agent without ports.
+        pub fn new(config: LldpConfig) -> LldpAgent {
+            let inner = Arc::new(Inner {
+                config,
+                ports: Mutex::new(Vec::new()),
+                neighbors: Mutex::new(Vec::new()),
+            });
+            Inner::start_timer(Arc::downgrade(&inner));
+            LldpAgent { inner }
+        }
+
+        /// An agent on the given interfaces, with a receiving thread
+        /// capturing on all of them.
+        pub fn open(ifaces: Vec<Interface>, config: LldpConfig, timeout: i32) -> Result<LldpAgent, RlinkError> {
+            let agent = LldpAgent::new(config);
+            for iface in ifaces.iter() {
+                let handle = DeviceHandle::open(iface, timeout, true)?;
+                agent.add_port(&iface.name, *handle.mac_address(), Box::new(handle));
+            }
+            agent.attach(DevicePool::from_interfaces(ifaces, timeout)?);
+            Ok(agent)
+        }
+
+        /// Like `open()`, looking the interfaces up by name.
+        pub fn open_names(names: &[String], config: LldpConfig, timeout: i32) -> Result<LldpAgent, RlinkError> {
+            let ifaces = names.iter()
+                .map(|name| interface::interface_by_name(name))
+                .collect::<Result<Vec<_>, _>>()?;
+            LldpAgent::open(ifaces, config, timeout)
+        }
+
+        /// Add a port, advertised on right away. Returns the index of the
+        /// port.
+        pub fn add_port(&self, name: &str, mac_address: MacAddress, sink: Box<dyn FrameSink>) -> usize {
+            let mut ports = self.inner.ports.lock().unwrap();
+            ports.push(Arc::new(Port {
+                name: name.to_string(),
+                mac_address,
+                sink: Mutex::new(sink),
+                next_tx: Mutex::new(Instant::now()),
+            }));
+            ports.len() - 1
+        }
+
+        /// Feed the agent with the packets captured by `pool`, on a
+        /// background thread. Packets are assigned to ports by the MAC
+        /// address of the capturing device.
+        pub fn attach(&self, pool: DevicePool) {
+            let weak = Arc::downgrade(&self.inner);
+            thread::spawn(move || {
+                while let Ok(packet) = pool.select() {
+                    let inner = match weak.upgrade() {
+                        Some(inner) => inner,
+                        None => break,
+                    };
+                    let port = inner.ports.lock().unwrap().iter()
+                        .position(|port| port.mac_address == packet.mac_address);
+                    if let (Some(port), Ok(frame)) = (port, packet.parse_eth(false)) {
+                        inner.input(port, &frame);
+                    }
+                }
+            });
+        }
+
+        /// Process a frame received on port `port`. Frames other than
+        /// LLDPDUs are ignored.
+        pub fn input(&self, port: usize, frame: &Packet<Eth>) {
+            self.inner.input(port, frame);
+        }
+
+        /// Advertise on every port now, rather than at the next interval.
+        pub fn advertise(&self) {
+            let ports = self.inner.ports.lock().unwrap().clone();
+            for port in ports.iter() {
+                self.inner.transmit(port, self.inner.ttl());
+            }
+        }
+
+        /// Neighbors heard on any port and not expired, by port.
+        pub fn neighbors(&self) -> Vec<LldpNeighbor> {
+            let now = Instant::now();
+            let mut neighbors: Vec<_> = self.inner.neighbors.lock().unwrap().iter()
+                .filter(|neighbor| neighbor.expires > now)
+                .cloned()
+                .collect();
+            neighbors.sort_by(|a, b| (a.port, &a.system_name).cmp(&(b.port, &b.system_name)));
+            neighbors
+        }
+    }
+
+    impl Drop for LldpAgent {
+        fn drop(&mut self) {
+            let ports = self.inner.ports.lock().unwrap().clone();
+            for port in ports.iter() {
+                self.inner.transmit(port, 0);
+            }
+        }
+    }
+
+    impl Inner {
+        fn ttl(&self) -> u16 {
+            (self.config.tx_interval.as_secs().max(1) * self.config.tx_hold as u64).min(u16::MAX as u64) as u16
+        }
+
+        fn transmit(&self, port: &Port, ttl: u16) -> Result<(), RlinkError> {
+            let lldpdu = Lldpdu {
+                chassis_id: ChassisId::MacAddress(port.mac_address),
+                port_id: PortId::InterfaceName(port.name.clone()),
+                ttl,
+                port_description: None,
+                system_name: self.config.system_name.clone(),
+                system_description: self.config.system_description.clone(),
+                other: Vec::new(),
+            };
+            port.sink.lock().unwrap().send_frame(&lldpdu.encode(), EtherType::LLDP, &MacAddress::new(NEAREST_BRIDGE))
+        }
+
+        fn input(&self, port_idx: usize, frame: &Packet<Eth>) {
+            if frame.ethtype() != EtherType::LLDP {
+                return;
+            }
+            let port = match self.ports.lock().unwrap().get(port_idx) {
+                Some(port) if frame.src_mac() != port.mac_address => port.clone(),
+                _ => return,
+            };
+            let lldpdu = match Lldpdu::decode(frame.data()) {
+                Ok(lldpdu) => lldpdu,
+                Err(_) => return,
+            };
+            let mut neighbors = self.neighbors.lock().unwrap();
+            neighbors.retain(|neighbor| neighbor.port != port_idx
+                || neighbor.chassis_id != lldpdu.chassis_id || neighbor.port_id != lldpdu.port_id);
+            if lldpdu.ttl > 0 {
+                neighbors.push(LldpNeighbor {
+                    port: port_idx,
+                    local_port: port.name.clone(),
+                    mac_address: frame.src_mac(),
+                    chassis_id: lldpdu.chassis_id,
+                    port_id: lldpdu.port_id,
+                    port_description: lldpdu.port_description,
+                    system_name: lldpdu.system_name,
+                    system_description: lldpdu.system_description,
+                    expires: Instant::now() + Duration::from_secs(lldpdu.ttl as u64),
+                });
+            }
+        }
+
+        /// Advertise on the ports whose interval elapsed and forget expired
+        /// neighbors, until the agent goes away.
+        fn start_timer(inner: Weak<Inner>) {
+            thread::spawn(move || loop {
+                thread::sleep(TICK);
+                let inner = match inner.upgrade() {
+                    Some(inner) => inner,
+                    None => break,
+                };
+                let now = Instant::now();
+                let ports = inner.ports.lock().unwrap().clone();
+                for port in ports.iter() {
+                    let mut next_tx = port.next_tx.lock().unwrap();
+                    if now >= *next_tx {
+                        *next_tx = now + inner.config.tx_interval;
+                        inner.transmit(port, inner.ttl());
+                    }
+                }
+                inner.neighbors.lock().unwrap().retain(|neighbor| neighbor.expires > now);
+            });
+        }
+    }
+}
@@ -0,0 +1,205 @@
+#![allow(dead_code)]
+#![allow(unused)]
+
+pub mod mac {
+    //! Helpers on top of `MacAddress`: strict parsing, address class
+    //! predicates and OUI vendor lookup.
+
+    use crate::RlinkError;
+    use mac_address::MacAddress;
+
+    /// The Ethernet broadcast address, `FF:FF:FF:FF:FF:FF`.
+    pub fn broadcast() -> MacAddress {
+        MacAddress::new([0xff; 6])
+    }
+
+    /// The all-zero address, used e.g. as the unknown target in ARP requests.
+    pub fn zero() -> MacAddress {
+        MacAddress::new([0x00; 6])
+    }
+
+    /// Parses a MAC address in any of the common notations:
+    /// `aa:bb:cc:dd:ee:ff`, `aa-bb-cc-dd-ee-ff` or `aabb.ccdd.eeff`.
+    /// Hex digits may be in either case. Anything else, including mixed
+    /// separators and short or long input, is rejected.
+    pub fn parse_mac(s: &str) -> Result<MacAddress, RlinkError> {
+        let invalid = || RlinkError::InvalidMacAddress(s.to_string());
+        // Lengths below are in bytes, and the hex digits sliced by them
+        if !s.is_ascii() {
+            return Err(invalid());
+        }
+
+        let hex: String = if s.len() == 17 {
+            let sep = s.as_bytes()[2];
+            if sep != b':' && sep != b'-' {
+                return Err(invalid());
+            }
+            let groups: Vec<&str> = s.split(sep as char).collect();
+            if groups.len() != 6 || groups.iter().any(|g| g.len() != 2) {
+                return Err(invalid());
+            }
+            groups.concat()
+        }
+        else if s.len() == 14 {
+            let groups: Vec<&str> = s.split('.').collect();
+            if groups.len() != 3 || groups.iter().any(|g| g.len() != 4) {
+                return Err(invalid());
+            }
+            groups.concat()
+        }
+        else {
+            return Err(invalid());
+        };
+
+        let mut bytes = [0u8; 6];
+        for (idx, byte) in bytes.iter_mut().enumerate() {
+            let digits = &hex[idx*2..idx*2+2];
+            if !digits.bytes().all(|c| c.is_ascii_hexdigit()) {
+                return Err(invalid());
+            }
+            *byte = u8::from_str_radix(digits, 16).map_err(|_| invalid())?;
+        }
+        Ok(MacAddress::new(bytes))
+    }
+
+    /// Address class predicates and vendor lookup for `MacAddress`.
+    pub trait MacAddressExt {
+        /// Whether this is `FF:FF:FF:FF:FF:FF`.
+        fn is_broadcast(&self) -> bool;
+        /// Whether the I/G bit is set. Broadcast is also multicast.
+        fn is_multicast(&self) -> bool;
+        /// Whether the I/G bit is clear.
+        fn is_unicast(&self) -> bool;
+        /// Whether the U/L bit is set, i.e. the address was not assigned
+        /// by the manufacturer (as is the case for veths).
+        fn is_locally_administered(&self) -> bool;
+        /// Whether the U/L bit is clear.
+        fn is_universal(&self) -> bool;
+        /// Whether this is the all-zero address.
+        fn is_zero(&self) -> bool;
+        /// The Organizationally Unique Identifier, i.e. the first three bytes.
+        fn oui(&self) -> [u8; 3];
+        /// Vendor owning the OUI, if known. Always `None` for locally
+        /// administered addresses.
+        fn vendor(&self) -> Option<&'static str>;
+    }
+
+    impl MacAddressExt for MacAddress {
+        fn is_broadcast(&self) -> bool {
+            self.bytes() == [0xff; 6]
+        }
+
+        fn is_multicast(&self) -> bool {
+            self.bytes()[0] & 0x01 != 0
+        }
+
+        fn is_unicast(&self) -> bool {
+            !self.is_multicast()
+        }
+
+        fn is_locally_administered(&self) -> bool {
+            self.bytes()[0] & 0x02 != 0
+        }
+
+        fn is_universal(&self) -> bool {
+            !self.is_locally_administered()
+        }
+
+        fn is_zero(&self) -> bool {
+            self.bytes() == [0x00; 6]
+        }
+
+        fn oui(&self) -> [u8; 3] {
+            let bytes = self.bytes();
+            [bytes[0], bytes[1], bytes[2]]
+        }
+
+        fn vendor(&self) -> Option<&'static str> {
+            if self.is_locally_administered() {
+                return None;
+            }
+            oui_vendor(self.oui())
+        }
+    }
+
+    /// Well-known OUIs, mostly those seen on virtualized and lab hardware.
+    const OUI_TABLE: &[([u8; 3], &str)] = &[
+        ([0x00, 0x00, 0x0c], "Cisco Systems"),
+        ([0x00, 0x01, 0x42], "Cisco Systems"),
+        ([0x00, 0x03, 0xff], "Microsoft"),
+        ([0x00, 0x05, 0x69], "VMware"),
+        ([0x00, 0x0c, 0x29], "VMware"),
+        ([0x00, 0x1c, 0x14], "VMware"),
+        ([0x00, 0x50, 0x56], "VMware"),
+        ([0x00, 0x0d, 0x3a], "Microsoft"),
+        ([0x00, 0x15, 0x5d], "Microsoft (Hyper-V)"),
+        ([0x00, 0x16, 0x3e], "Xensource"),
+        ([0x00, 0x1b, 0x21], "Intel Corporation"),
+        ([0x00, 0x1e, 0x67], "Intel Corporation"),
+        ([0x3c, 0xfd, 0xfe], "Intel Corporation"),
+        ([0xa0, 0x36, 0x9f], "Intel Corporation"),
+        ([0x00, 0x10, 0x18], "Broadcom"),
+        ([0x00, 0x0a, 0xf7], "Broadcom"),
+        ([0x00, 0xe0, 0x4c], "Realtek Semiconductor"),
+        ([0x52, 0x54, 0x00], "QEMU/KVM virtual NIC"),
+        ([0x08, 0x00, 0x27], "Oracle VirtualBox virtual NIC"),
+        ([0x00, 0x1c, 0x42], "Parallels"),
+        ([0x00, 0x03, 0x93], "Apple"),
+        ([0x00, 0x1b, 0x63], "Apple"),
+        ([0xf0, 0x18, 0x98], "Apple"),
+        ([0x00, 0x02, 0xc9], "Mellanox Technologies"),
+        ([0x00, 0x1b, 0x17], "Palo Alto Networks"),
+        ([0x00, 0x05, 0x85], "Juniper Networks"),
+        ([0x00, 0x1a, 0xa0], "Dell"),
+        ([0x00, 0x25, 0x90], "Super Micro Computer"),
+        ([0xb8, 0x27, 0xeb], "Raspberry Pi Foundation"),
+        ([0xdc, 0xa6, 0x32], "Raspberry Pi Trading"),
+    ];
+
+    /// Looks up the vendor registered for the given OUI, if known.
+    pub fn oui_vendor(oui: [u8; 3]) -> Option<&'static str> {
+        OUI_TABLE.iter()
+            .find(|(prefix, _)| *prefix == oui)
+            .map(|(_, vendor)| *vendor)
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        const MAC: [u8; 6] = [0xaa, 0xbb, 0xcc, 0x0d, 0xee, 0xff];
+
+        #[test]
+        fn notations() {
+            for s in ["aa:bb:cc:0d:ee:ff", "AA-BB-CC-0D-EE-FF", "aabb.cc0d.eeff", "aA:Bb:cC:0d:Ee:fF"] {
+                assert_eq!(parse_mac(s).unwrap(), MacAddress::new(MAC), "{}", s);
+            }
+        }
+
+        #[test]
+        fn invalid() {
+            for s in [
+                "", "aa:bb:cc:dd:ee", "aa:bb:cc:dd:ee:f", "aa:bb:cc:dd:ee:ff:00", "aabb.ccdd.eef",
+                "aabbccddeeff", "aa:bb-cc:dd:ee:ff", "aa.bb.cc.dd.ee.ff", "aa:bb:cc:dd:eeff:", "aabb.ccdd:eeff",
+                "gg:bb:cc:dd:ee:ff", "aa:bb:cc:dd:ee:+f", "aabb.ccdd.ee f", " aa:bb:cc:dd:ee:f",
+                // Non-ASCII input, of the lengths of the notations in bytes
+                "aéb.ccdd.eeff", "aa:bb:cc:dd:ee:é", "é:bb:cc:dd:ee:ff", "ａａ:bb:cc:dd",
+            ] {
+                match parse_mac(s) {
+                    Err(RlinkError::InvalidMacAddress(found)) => assert_eq!(found, s),
+                    other => panic!("{:?} parsed as {:?}", s, other),
+                }
+            }
+        }
+
+        #[test]
+        fn classes() {
+            let mac = MacAddress::new(MAC);
+            assert!(mac.is_unicast() && mac.is_locally_administered() && !mac.is_broadcast());
+            assert!(broadcast().is_broadcast() && broadcast().is_multicast());
+            assert!(zero().is_zero() && zero().is_universal());
+            assert_eq!(MacAddress::new([0x00, 0x50, 0x56, 1, 2, 3]).vendor(), Some("VMware"));
+            assert_eq!(mac.vendor(), None);
+        }
+    }
+}
@@ -0,0 +1,126 @@
+#![allow(dead_code)]
+#![allow(unused)]
+
+pub mod memlink {
+    //! In-memory links between stacks, with optional impairments (loss,
+    //! duplication, delay and jitter). They let protocols be exercised in a
+    //! single process, without devices or privileges.
+
+    use crate::stack::stack::{FrameSink, WeakStack};
+    use crate::{EtherType, Packet, Raw, RlinkError, Stack};
+    use mac_address::MacAddress;
+    use std::net::Ipv4Addr;
+    use std::sync::mpsc::{self, Receiver, Sender};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    /// Impairments applied to every frame crossing a link, in each direction.
+    #[derive(Clone, Debug, Default)]
+    pub struct Impairment {
+        /// Probability of a frame being dropped, in `[0, 1]`
+        pub loss: f64,
+        /// Probability of a frame being delivered twice, in `[0, 1]`
+        pub duplicate: f64,
+        /// Fixed one-way delay
+        pub delay: Duration,
+        /// Maximum additional random delay. Frames are still delivered in order.
+        pub jitter: Duration,
+        /// Seed of the pseudo-random generator, for reproducible runs
+        pub seed: u64,
+    }
+
+    /// xorshift64*, good enough to decide the fate of frames.
+    struct Rng(u64);
+
+    impl Rng {
+        fn new(seed: u64) -> Self {
+            Rng(seed.max(1))
+        }
+
+        /// A uniform float in `[0, 1)`.
+        fn next_f64(&mut self) -> f64 {
+            self.0 ^= self.0 >> 12;
+            self.0 ^= self.0 << 25;
+            self.0 ^= self.0 >> 27;
+            (self.0.wrapping_mul(0x2545F4914F6CDD1D) >> 11) as f64 / (1u64 << 53) as f64
+        }
+    }
+
+    /// One end of an in-memory link. Frames sent through it are delivered
+    /// to the stack at the other end.
+    pub struct MemoryLink {
+        mac_address: MacAddress,
+        tx: Sender<Vec<u8>>,
+    }
+
+    impl FrameSink for MemoryLink {
+        fn send_frame(&mut self, payload: &[u8], ethtype: EtherType, dst: &MacAddress) -> Result<(), RlinkError> {
+            if payload.len() >= 1500 {
+                return Err(RlinkError::PayloadTooLarge { size: payload.len(), max: 1499 });
+            }
+            // Same framing as DeviceHandle::send_packet(), without checksum.
+            let mut frame = [
+                dst.bytes().as_ref(),
+                self.mac_address.bytes().as_ref(),
+                u16::from(ethtype).to_be_bytes().as_ref(),
+                payload,
+            ].concat();
+            frame.resize(std::cmp::max(frame.len(), 60usize), 0u8);
+            frame.extend_from_slice(&[0u8; 4]);
+            self.tx.send(frame).map_err(|_| RlinkError::BrokenDevicePool)
+        }
+    }
+
+    /// Configuration of one end of a link.
+    pub struct Endpoint<'a> {
+        pub stack: &'a Stack,
+        pub name: &'a str,
+        pub mac_address: MacAddress,
+        pub addresses: Vec<(Ipv4Addr, u8)>,
+    }
+
+    /// Connect two stacks with a link, adding a link to each of them.
+    /// Returns the link indices in `a` and `b`.
+    pub fn connect(a: Endpoint, b: Endpoint, impairment: Impairment) -> (usize, usize) {
+        let (a_tx, a_rx) = mpsc::channel();
+        let (b_tx, b_rx) = mpsc::channel();
+        let a_link = a.stack.add_link(a.name, a.mac_address, a.addresses,
+            Box::new(MemoryLink { mac_address: a.mac_address, tx: a_tx }));
+        let b_link = b.stack.add_link(b.name, b.mac_address, b.addresses,
+            Box::new(MemoryLink { mac_address: b.mac_address, tx: b_tx }));
+
+        let mut reverse = impairment.clone();
+        reverse.seed = impairment.seed.wrapping_add(1);
+        deliver(a_rx, b.stack.downgrade(), b_link, b.mac_address, impairment);
+        deliver(b_rx, a.stack.downgrade(), a_link, a.mac_address, reverse);
+        (a_link, b_link)
+    }
+
+    /// Deliver frames from `rx` to `link` of `stack` on a background thread,
+    /// until either side goes away.
+    fn deliver(rx: Receiver<Vec<u8>>, stack: WeakStack, link: usize, mac_address: MacAddress,
+        impairment: Impairment) {
+        thread::spawn(move || {
+            let mut rng = Rng::new(impairment.seed);
+            while let Ok(frame) = rx.recv() {
+                let sent = Instant::now();
+                if rng.next_f64() < impairment.loss {
+                    continue;
+                }
+                let copies = if rng.next_f64() < impairment.duplicate { 2 } else { 1 };
+                let delay = impairment.delay + impairment.jitter.mul_f64(rng.next_f64());
+                thread::sleep(delay.saturating_sub(sent.elapsed()));
+
+                let stack = match stack.upgrade() {
+                    Some(stack) => stack,
+                    None => break,
+                };
+                for _ in 0..copies {
+                    if let Ok(frame) = Packet::<Raw>::from_bytes(frame.clone(), mac_address).parse_eth(false) {
+                        stack.input(link, frame);
+                    }
+                }
+            }
+        });
+    }
+}
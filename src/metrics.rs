@@ -0,0 +1,264 @@
+#![allow(dead_code)]
+#![allow(unused)]
+
+pub mod metrics {
+    //! Prometheus exporter of the rlink counters (see `stats`) and of the
+    //! health of device pools, in the Prometheus text exposition format
+    //! over a minimal HTTP server. Available with the `prometheus` feature.
+    //!
+    //! Meant to be bound to a local address, e.g. `127.0.0.1:9100`, and
+    //! scraped at `/metrics`.
+
+    use crate::device_pool::device_pool::{PoolHealth, PoolMonitor};
+    use crate::stats::stats::{self, DeviceStats};
+    use crate::{EtherType, RlinkError};
+    use std::fmt::Write as _;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::{SocketAddr, TcpListener, TcpStream};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::Duration;
+
+    pub const CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+    /// Time allowed to a client to send its request.
+    const REQUEST_TIMEOUT: Duration = Duration::from_secs(2);
+    /// Longest request accepted, headers included.
+    const MAX_REQUEST: usize = 8192;
+
+    /// A sample: `(name, value)` label pairs and the value.
+    type Sample = (Vec<(&'static str, String)>, u64);
+    /// A counter of every device: name, help and accessor.
+    type DeviceCounter = (&'static str, &'static str, fn(&DeviceStats) -> u64);
+
+    /// Escape a label value.
+    fn escape(value: &str) -> String {
+        value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+    }
+
+    /// The labels of a device: its name and network namespace, empty for
+    /// the one of the process.
+    fn device_labels(stats: &DeviceStats) -> Vec<(&'static str, String)> {
+        vec![("device", stats.device.clone()), ("netns", stats.netns.clone().unwrap_or_default())]
+    }
+
+    /// The labels of a device with one more.
+    fn device_labels_with(stats: &DeviceStats, name: &'static str, value: String) -> Vec<(&'static str, String)> {
+        let mut labels = device_labels(stats);
+        labels.push((name, value));
+        labels
+    }
+
+    fn ethtype_label(ethtype: &EtherType) -> String {
+        match ethtype {
+            EtherType::IEEE802_3(_) => "802.3".to_string(),
+            ethtype => ethtype.abbreviation()
+                .map_or_else(|| format!("0x{:04X}", u16::from(*ethtype)), |s| s.to_string()),
+        }
+    }
+
+    /// Append a metric family: its help, type and samples.
+    fn family(out: &mut String, name: &str, kind: &str, help: &str, samples: &[Sample]) {
+        let _ = writeln!(out, "# HELP {} {}", name, help);
+        let _ = writeln!(out, "# TYPE {} {}", name, kind);
+        for (labels, value) in samples {
+            let labels: Vec<String> = labels.iter()
+                .map(|(name, value)| format!("{}=\"{}\"", name, escape(value)))
+                .collect();
+            let _ = writeln!(out, "{}{{{}}} {}", name, labels.join(","), value);
+        }
+    }
+
+    /// Render the counters of all devices and the health of the given
+    /// pools, labelled by pool name.
+    pub fn render(pools: &[(String, PoolMonitor)]) -> String {
+        let devices = stats::snapshot();
+        let mut out = String::new();
+        let per_device = |value: fn(&DeviceStats) -> u64| -> Vec<Sample> {
+            devices.iter().map(|stats| (device_labels(stats), value(stats))).collect()
+        };
+        let counters: [DeviceCounter; 11] = [
+            ("rlink_rx_frames_total", "Frames received.", |s| s.rx_frames),
+            ("rlink_rx_bytes_total", "Bytes received, Ethernet header and trailer included.", |s| s.rx_bytes),
+            ("rlink_rx_broadcast_frames_total", "Broadcast frames received.", |s| s.rx_broadcast),
+            ("rlink_rx_multicast_frames_total", "Multicast frames received.", |s| s.rx_multicast),
+            ("rlink_callback_consumed_frames_total", "Received frames taken by a handle callback.", |s| s.callback_consumed),
+            ("rlink_rx_queue_drops_total", "Received frames dropped by a full device pool queue.", |s| s.rx_queue_drops),
+            ("rlink_tx_frames_total", "Frames sent.", |s| s.tx_frames),
+            ("rlink_tx_bytes_total", "Bytes sent, Ethernet header and trailer included.", |s| s.tx_bytes),
+            ("rlink_tx_broadcast_frames_total", "Broadcast frames sent.", |s| s.tx_broadcast),
+            ("rlink_tx_multicast_frames_total", "Multicast frames sent.", |s| s.tx_multicast),
+            ("rlink_tx_errors_total", "Frames the device failed to send.", |s| s.tx_errors),
+        ];
+        for (name, help, value) in counters {
+            family(&mut out, name, "counter", help, &per_device(value));
+        }
+
+        let errors: Vec<Sample> = devices.iter()
+            .flat_map(|stats| [
+                (device_labels_with(stats, "reason", "too_small".to_string()), stats.rx_too_small),
+                (device_labels_with(stats, "reason", "checksum".to_string()), stats.rx_checksum_errors),
+            ])
+            .collect();
+        family(&mut out, "rlink_rx_parse_errors_total", "counter", "Received frames failing to parse, by reason.", &errors);
+
+        for (name, help, counts) in [
+            ("rlink_rx_ethertype_frames_total", "Frames received, by EtherType.",
+                devices.iter().map(|s| (s, &s.rx_ethertypes)).collect::<Vec<_>>()),
+            ("rlink_tx_ethertype_frames_total", "Frames sent, by EtherType.",
+                devices.iter().map(|s| (s, &s.tx_ethertypes)).collect::<Vec<_>>()),
+        ] {
+            let samples: Vec<Sample> = counts.iter()
+                .flat_map(|(stats, counts)| counts.iter().map(|(ethtype, count)| {
+                    (device_labels_with(stats, "ethertype", ethtype_label(ethtype)), *count)
+                }))
+                .collect();
+            family(&mut out, name, "counter", help, &samples);
+        }
+
+        let health: Vec<_> = pools.iter().map(|(name, monitor)| (name, monitor.health())).collect();
+        let per_pool = |value: fn(&PoolHealth) -> usize| -> Vec<Sample> {
+            health.iter().map(|(name, health)| (vec![("pool", (*name).clone())], value(health) as u64)).collect()
+        };
+        family(&mut out, "rlink_pool_workers", "gauge", "Capture workers of the pool.", &per_pool(|h| h.workers));
+        family(&mut out, "rlink_pool_live_workers", "gauge", "Capture workers still running.", &per_pool(|h| h.live_workers));
+        family(&mut out, "rlink_pool_queue_depth", "gauge", "Packets captured but not yet consumed.", &per_pool(|h| h.queue_depth));
+        family(&mut out, "rlink_pool_queue_capacity", "gauge", "Capacity of the queue of the pool.", &per_pool(|h| h.capacity));
+        out
+    }
+
+    /// An HTTP server exposing `render()` at `/metrics`, on a background
+    /// thread. The server stops when the exporter is dropped.
+    pub struct MetricsExporter {
+        addr: SocketAddr,
+        pools: Arc<Mutex<Vec<(String, PoolMonitor)>>>,
+        stop: Arc<AtomicBool>,
+    }
+
+    impl MetricsExporter {
+        /// Serve on `addr`; port 0 picks a free port.
+        pub fn start(addr: SocketAddr) -> Result<MetricsExporter, RlinkError> {
+            let listener = TcpListener::bind(addr)?;
+            let addr = listener.local_addr()?;
+            let pools = Arc::new(Mutex::new(Vec::new()));
+            let stop = Arc::new(AtomicBool::new(false));
+            let (served, stopped) = (pools.clone(), stop.clone());
+            thread::spawn(move || {
+                for stream in listener.incoming() {
+                    if stopped.load(Ordering::Relaxed) {
+                        break;
+                    }
+                    if let Ok(stream) = stream {
+                        let pools = served.lock().unwrap().clone();
+                        thread::spawn(move || serve(stream, &pools));
+                    }
+                }
+            });
+            Ok(MetricsExporter { addr, pools, stop })
+        }
+
+        pub fn local_addr(&self) -> SocketAddr {
+            self.addr
+        }
+
+        /// Report the health of a pool under the given name.
+        pub fn watch_pool(&self, name: &str, monitor: PoolMonitor) {
+            let mut pools = self.pools.lock().unwrap();
+            pools.retain(|(seen, _)| seen != name);
+            pools.push((name.to_string(), monitor));
+        }
+    }
+
+    impl Drop for MetricsExporter {
+        fn drop(&mut self) {
+            self.stop.store(true, Ordering::Relaxed);
+            // Wake the accepting thread up.
+            let _ = TcpStream::connect_timeout(&self.addr, Duration::from_millis(100));
+        }
+    }
+
+    /// Answer one request and close the connection.
+    fn serve(stream: TcpStream, pools: &[(String, PoolMonitor)]) -> std::io::Result<()> {
+        stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut request_line = String::new();
+        reader.read_line(&mut request_line)?;
+        // Skip the headers.
+        let mut total = request_line.len();
+        loop {
+            let mut line = String::new();
+            if reader.read_line(&mut line)? == 0 || line == "\r\n" || line == "\n" {
+                break;
+            }
+            total += line.len();
+            if total > MAX_REQUEST {
+                return respond(stream, "431 Request Header Fields Too Large", "text/plain", "");
+            }
+        }
+
+        let mut words = request_line.split_whitespace();
+        match (words.next(), words.next().map(|path| path.split('?').next().unwrap_or(path))) {
+            (Some("GET"), Some("/metrics")) => respond(stream, "200 OK", CONTENT_TYPE, &render(pools)),
+            (Some("GET"), Some("/")) => respond(stream, "200 OK", "text/html",
+                "<html><body><a href=\"/metrics\">Metrics</a></body></html>\n"),
+            (Some("GET"), _) => respond(stream, "404 Not Found", "text/plain", "not found\n"),
+            _ => respond(stream, "405 Method Not Allowed", "text/plain", "method not allowed\n"),
+        }
+    }
+
+    fn respond(mut stream: TcpStream, status: &str, content_type: &str, body: &str) -> std::io::Result<()> {
+        write!(stream, "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            status, content_type, body.len(), body)?;
+        stream.flush()
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use std::io::Read;
+
+        /// Send a GET request to the exporter, returning the status line,
+        /// the headers and the body.
+        fn get(addr: SocketAddr, path: &str) -> (String, Vec<String>, String) {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+            write!(stream, "GET {} HTTP/1.1\r\nHost: {}\r\n\r\n", path, addr).unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            let (head, body) = response.split_once("\r\n\r\n").unwrap();
+            let mut lines = head.split("\r\n").map(str::to_string);
+            let status = lines.next().unwrap();
+            (status, lines.collect(), body.to_string())
+        }
+
+        #[test]
+        fn scrape() {
+            let counters = stats::device_counters(None, "metrics-test0");
+            let mut frame = vec![0xff; 6];
+            frame.extend_from_slice(&[2, 0, 0, 0, 0, 1, 0x08, 0x06]);
+            frame.resize(64, 0);
+            counters.record_rx(&frame);
+            // A device of the same name in another namespace counts apart
+            let other = stats::device_counters(Some("metrics-ns"), "metrics-test0");
+            other.record_rx(&frame);
+            other.record_rx(&frame);
+
+            let exporter = MetricsExporter::start("127.0.0.1:0".parse().unwrap()).unwrap();
+            let (status, headers, body) = get(exporter.local_addr(), "/metrics");
+            assert_eq!(status, "HTTP/1.1 200 OK");
+            assert!(headers.contains(&format!("Content-Type: {}", CONTENT_TYPE)));
+            assert!(headers.contains(&format!("Content-Length: {}", body.len())));
+            assert!(body.contains("# TYPE rlink_rx_frames_total counter\n"));
+            assert!(body.contains("rlink_rx_frames_total{device=\"metrics-test0\",netns=\"\"} 1\n"));
+            assert!(body.contains("rlink_rx_frames_total{device=\"metrics-test0\",netns=\"metrics-ns\"} 2\n"));
+            assert!(body.contains("rlink_rx_ethertype_frames_total{device=\"metrics-test0\",netns=\"\",ethertype=\"ARP\"} 1\n"));
+        }
+
+        #[test]
+        fn not_found() {
+            let exporter = MetricsExporter::start("127.0.0.1:0".parse().unwrap()).unwrap();
+            let (status, _, _) = get(exporter.local_addr(), "/other");
+            assert_eq!(status, "HTTP/1.1 404 Not Found");
+        }
+    }
+}
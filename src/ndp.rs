@@ -0,0 +1,1388 @@
+#![allow(dead_code)]
+#![allow(unused)]
+
+pub mod ndp {
+    //! ICMPv6 Neighbor Discovery (RFC 4861) for the IPv6 side of the stack:
+    //! message encoding, a neighbor cache with reachability states,
+    //! duplicate address detection and stateless address autoconfiguration
+    //! from router advertisements (RFC 4862).
+    //!
+    //! It plays the part ARP plays for IPv4: outgoing datagrams wait in a
+    //! queue while their next hop is being solicited, so sending never
+    //! blocks on the network.
+
+    use crate::ipv4::ipv4::IpProtocol;
+    use crate::ipv6::ipv6::{self, pseudo_header_checksum, Ipv6, Ipv6Header};
+    use crate::stack::stack::{Link, WeakStack};
+    use crate::{EtherType, FrameError, Packet, RlinkError, Stack};
+    use mac_address::MacAddress;
+    use std::collections::HashMap;
+    use std::net::{IpAddr, Ipv6Addr};
+    use std::sync::atomic::{AtomicU8, Ordering};
+    use std::sync::{Arc, Condvar, Mutex, RwLock, Weak};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    pub const ECHO_REQUEST: u8 = 128;
+    pub const ECHO_REPLY: u8 = 129;
+    pub const ROUTER_SOLICITATION: u8 = 133;
+    pub const ROUTER_ADVERTISEMENT: u8 = 134;
+    pub const NEIGHBOR_SOLICITATION: u8 = 135;
+    pub const NEIGHBOR_ADVERTISEMENT: u8 = 136;
+    pub const REDIRECT: u8 = 137;
+
+    /// Option types.
+    pub const SOURCE_LINK_ADDRESS: u8 = 1;
+    pub const TARGET_LINK_ADDRESS: u8 = 2;
+    pub const PREFIX_INFORMATION: u8 = 3;
+    pub const REDIRECTED_HEADER: u8 = 4;
+    pub const MTU: u8 = 5;
+
+    /// Hop limit of every NDP message. Messages received with another
+    /// value crossed a router and are ignored.
+    pub const NDP_HOP_LIMIT: u8 = 255;
+
+    /// Protocol constants (RFC 4861, section 10).
+    pub const REACHABLE_TIME: Duration = Duration::from_secs(30);
+    pub const RETRANS_TIMER: Duration = Duration::from_secs(1);
+    pub const DELAY_FIRST_PROBE_TIME: Duration = Duration::from_secs(5);
+    pub const MAX_MULTICAST_SOLICIT: u32 = 3;
+    pub const MAX_UNICAST_SOLICIT: u32 = 3;
+
+    /// Datagrams queued per unresolved neighbor before dropping.
+    const MAX_PENDING: usize = 16;
+    /// Resolution of the timer thread.
+    const TICK: Duration = Duration::from_millis(100);
+
+    pub const ALL_NODES: Ipv6Addr = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 1);
+    pub const ALL_ROUTERS: Ipv6Addr = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 2);
+
+    /// The solicited-node multicast group of an address, which neighbor
+    /// solicitations for it are sent to.
+    pub fn solicited_node(addr: &Ipv6Addr) -> Ipv6Addr {
+        let o = addr.octets();
+        Ipv6Addr::new(0xff02, 0, 0, 0, 0, 1, 0xff00 | o[13] as u16, u16::from_be_bytes([o[14], o[15]]))
+    }
+
+    fn is_link_local(addr: &Ipv6Addr) -> bool {
+        addr.segments()[0] & 0xffc0 == 0xfe80
+    }
+
+    /// Prefix information option, announcing an on-link prefix and/or a
+    /// prefix for autoconfiguration.
+    #[derive(Clone, Debug, PartialEq, Eq)]
+    pub struct PrefixInformation {
+        pub prefix_len: u8,
+        pub on_link: bool,
+        pub autonomous: bool,
+        /// Seconds, `u32::MAX` meaning infinity
+        pub valid_lifetime: u32,
+        pub preferred_lifetime: u32,
+        pub prefix: Ipv6Addr,
+    }
+
+    #[derive(Clone, Debug, PartialEq, Eq)]
+    pub enum NdpOption {
+        SourceLinkAddress(MacAddress),
+        TargetLinkAddress(MacAddress),
+        PrefixInformation(PrefixInformation),
+        Mtu(u32),
+        /// Any other option, `data` following the type and length bytes
+        Other { option_type: u8, data: Vec<u8> },
+    }
+
+    impl NdpOption {
+        fn encode(&self, out: &mut Vec<u8>) {
+            let start = out.len();
+            match self {
+                NdpOption::SourceLinkAddress(mac) | NdpOption::TargetLinkAddress(mac) => {
+                    let option_type = match self {
+                        NdpOption::SourceLinkAddress(_) => SOURCE_LINK_ADDRESS,
+                        _ => TARGET_LINK_ADDRESS,
+                    };
+                    out.extend_from_slice(&[option_type, 0]);
+                    out.extend_from_slice(&mac.bytes());
+                }
+                NdpOption::PrefixInformation(info) => {
+                    out.extend_from_slice(&[PREFIX_INFORMATION, 0, info.prefix_len,
+                        (info.on_link as u8) << 7 | (info.autonomous as u8) << 6]);
+                    out.extend_from_slice(&info.valid_lifetime.to_be_bytes());
+                    out.extend_from_slice(&info.preferred_lifetime.to_be_bytes());
+                    out.extend_from_slice(&[0; 4]);
+                    out.extend_from_slice(&info.prefix.octets());
+                }
+                NdpOption::Mtu(mtu) => {
+                    out.extend_from_slice(&[MTU, 0, 0, 0]);
+                    out.extend_from_slice(&mtu.to_be_bytes());
+                }
+                NdpOption::Other { option_type, data } => {
+                    out.extend_from_slice(&[*option_type, 0]);
+                    out.extend_from_slice(data);
+                }
+            }
+            out.resize(start + ((out.len() - start + 7) & !7), 0);
+            out[start + 1] = ((out.len() - start) / 8) as u8;
+        }
+
+        /// Decodes the options following a message body. `offset` locates
+        /// `data` in the message, for errors.
+        fn decode_all(mut data: &[u8], mut offset: usize) -> Result<Vec<Self>, FrameError> {
+            let mut options = Vec::new();
+            while !data.is_empty() {
+                if data.len() < 2 {
+                    return Err(FrameError::Truncated { offset, needed: 2, available: data.len() });
+                }
+                let len = data[1] as usize * 8;
+                if len == 0 {
+                    return Err(FrameError::InvalidField { offset: offset + 1, field: "option length" });
+                }
+                if len > data.len() {
+                    return Err(FrameError::Truncated { offset, needed: len, available: data.len() });
+                }
+                let body = &data[..len];
+                let mac = || MacAddress::new(body[2..8].try_into().unwrap());
+                options.push(match (body[0], len) {
+                    (SOURCE_LINK_ADDRESS, 8) => NdpOption::SourceLinkAddress(mac()),
+                    (TARGET_LINK_ADDRESS, 8) => NdpOption::TargetLinkAddress(mac()),
+                    (PREFIX_INFORMATION, 32) => NdpOption::PrefixInformation(PrefixInformation {
+                        prefix_len: body[2],
+                        on_link: body[3] & 0x80 != 0,
+                        autonomous: body[3] & 0x40 != 0,
+                        valid_lifetime: u32::from_be_bytes(body[4..8].try_into().unwrap()),
+                        preferred_lifetime: u32::from_be_bytes(body[8..12].try_into().unwrap()),
+                        prefix: Ipv6Addr::from(<[u8; 16]>::try_from(&body[16..32]).unwrap()),
+                    }),
+                    (MTU, 8) => NdpOption::Mtu(u32::from_be_bytes(body[4..8].try_into().unwrap())),
+                    (option_type, _) => NdpOption::Other { option_type, data: body[2..].to_vec() },
+                });
+                data = &data[len..];
+                offset += len;
+            }
+            Ok(options)
+        }
+    }
+
+    /// A Neighbor Discovery message.
+    #[derive(Clone, Debug, PartialEq, Eq)]
+    pub enum NdpMessage {
+        RouterSolicitation {
+            options: Vec<NdpOption>,
+        },
+        RouterAdvertisement {
+            cur_hop_limit: u8,
+            managed: bool,
+            other: bool,
+            /// Seconds; 0 means the sender is not a default router
+            router_lifetime: u16,
+            /// Milliseconds; 0 means unspecified
+            reachable_time: u32,
+            /// Milliseconds; 0 means unspecified
+            retrans_timer: u32,
+            options: Vec<NdpOption>,
+        },
+        NeighborSolicitation {
+            target: Ipv6Addr,
+            options: Vec<NdpOption>,
+        },
+        NeighborAdvertisement {
+            router: bool,
+            solicited: bool,
+            override_: bool,
+            target: Ipv6Addr,
+            options: Vec<NdpOption>,
+        },
+        Redirect {
+            target: Ipv6Addr,
+            destination: Ipv6Addr,
+            options: Vec<NdpOption>,
+        },
+    }
+
+    impl NdpMessage {
+        pub fn icmp_type(&self) -> u8 {
+            match self {
+                NdpMessage::RouterSolicitation { .. } => ROUTER_SOLICITATION,
+                NdpMessage::RouterAdvertisement { .. } => ROUTER_ADVERTISEMENT,
+                NdpMessage::NeighborSolicitation { .. } => NEIGHBOR_SOLICITATION,
+                NdpMessage::NeighborAdvertisement { .. } => NEIGHBOR_ADVERTISEMENT,
+                NdpMessage::Redirect { .. } => REDIRECT,
+            }
+        }
+
+        pub fn options(&self) -> &[NdpOption] {
+            match self {
+                NdpMessage::RouterSolicitation { options }
+                | NdpMessage::RouterAdvertisement { options, .. }
+                | NdpMessage::NeighborSolicitation { options, .. }
+                | NdpMessage::NeighborAdvertisement { options, .. }
+                | NdpMessage::Redirect { options, .. } => options,
+            }
+        }
+
+        /// The source link-layer address option, if present.
+        pub fn source_link_address(&self) -> Option<MacAddress> {
+            self.options().iter().find_map(|option| match option {
+                NdpOption::SourceLinkAddress(mac) => Some(*mac),
+                _ => None,
+            })
+        }
+
+        /// The target link-layer address option, if present.
+        pub fn target_link_address(&self) -> Option<MacAddress> {
+            self.options().iter().find_map(|option| match option {
+                NdpOption::TargetLinkAddress(mac) => Some(*mac),
+                _ => None,
+            })
+        }
+
+        /// Encodes the message as an ICMPv6 payload, with the checksum
+        /// computed over the pseudo-header of `src` and `dst`.
+        pub fn encode(&self, src: Ipv6Addr, dst: Ipv6Addr) -> Vec<u8> {
+            let mut data = vec![self.icmp_type(), 0, 0, 0];
+            match self {
+                NdpMessage::RouterSolicitation { .. } => data.extend_from_slice(&[0; 4]),
+                NdpMessage::RouterAdvertisement {
+                    cur_hop_limit, managed, other, router_lifetime, reachable_time, retrans_timer, ..
+                } => {
+                    data.push(*cur_hop_limit);
+                    data.push((*managed as u8) << 7 | (*other as u8) << 6);
+                    data.extend_from_slice(&router_lifetime.to_be_bytes());
+                    data.extend_from_slice(&reachable_time.to_be_bytes());
+                    data.extend_from_slice(&retrans_timer.to_be_bytes());
+                }
+                NdpMessage::NeighborSolicitation { target, .. } => {
+                    data.extend_from_slice(&[0; 4]);
+                    data.extend_from_slice(&target.octets());
+                }
+                NdpMessage::NeighborAdvertisement { router, solicited, override_, target, .. } => {
+                    data.extend_from_slice(&[(*router as u8) << 7 | (*solicited as u8) << 6 | (*override_ as u8) << 5, 0, 0, 0]);
+                    data.extend_from_slice(&target.octets());
+                }
+                NdpMessage::Redirect { target, destination, .. } => {
+                    data.extend_from_slice(&[0; 4]);
+                    data.extend_from_slice(&target.octets());
+                    data.extend_from_slice(&destination.octets());
+                }
+            }
+            for option in self.options() {
+                option.encode(&mut data);
+            }
+            let sum = pseudo_header_checksum(src, dst, IpProtocol::ICMPv6, &data);
+            data[2..4].copy_from_slice(&sum.to_be_bytes());
+            data
+        }
+
+        /// Decodes a message from an ICMPv6 payload, verifying the checksum.
+        pub fn decode(data: &[u8], src: Ipv6Addr, dst: Ipv6Addr) -> Result<Self, FrameError> {
+            if data.len() < 8 {
+                return Err(FrameError::Truncated { offset: 0, needed: 8, available: data.len() });
+            }
+            if pseudo_header_checksum(src, dst, IpProtocol::ICMPv6, data) != 0 {
+                let mut zeroed = data.to_vec();
+                zeroed[2] = 0;
+                zeroed[3] = 0;
+                return Err(FrameError::ChecksumMismatch {
+                    expected: pseudo_header_checksum(src, dst, IpProtocol::ICMPv6, &zeroed) as u32,
+                    actual: u16::from_be_bytes([data[2], data[3]]) as u32,
+                });
+            }
+            if data[1] != 0 {
+                return Err(FrameError::InvalidField { offset: 1, field: "ICMPv6 code" });
+            }
+            let body_len = match data[0] {
+                ROUTER_SOLICITATION => 8,
+                ROUTER_ADVERTISEMENT => 16,
+                NEIGHBOR_SOLICITATION | NEIGHBOR_ADVERTISEMENT => 24,
+                REDIRECT => 40,
+                _ => return Err(FrameError::InvalidField { offset: 0, field: "ICMPv6 type" }),
+            };
+            if data.len() < body_len {
+                return Err(FrameError::Truncated { offset: 0, needed: body_len, available: data.len() });
+            }
+            let addr = |offset: usize| Ipv6Addr::from(<[u8; 16]>::try_from(&data[offset..offset+16]).unwrap());
+            let u32_at = |offset: usize| u32::from_be_bytes(data[offset..offset+4].try_into().unwrap());
+            let options = NdpOption::decode_all(&data[body_len..], body_len)?;
+            Ok(match data[0] {
+                ROUTER_SOLICITATION => NdpMessage::RouterSolicitation { options },
+                ROUTER_ADVERTISEMENT => NdpMessage::RouterAdvertisement {
+                    cur_hop_limit: data[4],
+                    managed: data[5] & 0x80 != 0,
+                    other: data[5] & 0x40 != 0,
+                    router_lifetime: u16::from_be_bytes([data[6], data[7]]),
+                    reachable_time: u32_at(8),
+                    retrans_timer: u32_at(12),
+                    options,
+                },
+                NEIGHBOR_SOLICITATION => NdpMessage::NeighborSolicitation { target: addr(8), options },
+                NEIGHBOR_ADVERTISEMENT => NdpMessage::NeighborAdvertisement {
+                    router: data[4] & 0x80 != 0,
+                    solicited: data[4] & 0x40 != 0,
+                    override_: data[4] & 0x20 != 0,
+                    target: addr(8),
+                    options,
+                },
+                _ => NdpMessage::Redirect { target: addr(8), destination: addr(24), options },
+            })
+        }
+    }
+
+    /// Reachability states of a neighbor (RFC 4861, section 7.3.2).
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub enum NeighborState {
+        /// Resolution in progress, no link-layer address yet
+        Incomplete,
+        /// Recently confirmed reachable
+        Reachable,
+        /// Not confirmed lately; confirmed again on next use
+        Stale,
+        /// Used while stale, waiting for upper layers to confirm
+        Delay,
+        /// Being probed with unicast solicitations
+        Probe,
+    }
+
+    #[derive(Clone, Debug)]
+    pub struct NeighborEntry {
+        pub link: usize,
+        pub mac_address: Option<MacAddress>,
+        pub state: NeighborState,
+        pub is_router: bool,
+        /// When the entry entered its state or was last probed
+        changed: Instant,
+        /// Solicitations sent in the current state
+        probes: u32,
+    }
+
+    /// Work the neighbor cache hands back to the stack.
+    #[derive(Clone, Debug, PartialEq, Eq)]
+    pub enum NdpAction {
+        /// Send a neighbor solicitation for `target`, unicast to `mac` if
+        /// given, to its solicited-node group otherwise
+        Solicit { link: usize, target: Ipv6Addr, mac: Option<MacAddress> },
+        /// Resolution of `target` failed; queued datagrams are dropped
+        Unreachable(Ipv6Addr),
+    }
+
+    /// IPv6 neighbors and their reachability.
+    #[derive(Clone, Debug)]
+    pub struct NeighborCache {
+        entries: HashMap<Ipv6Addr, NeighborEntry>,
+        reachable_time: Duration,
+        retrans_timer: Duration,
+    }
+
+    impl Default for NeighborCache {
+        fn default() -> Self {
+            NeighborCache::new(REACHABLE_TIME, RETRANS_TIMER)
+        }
+    }
+
+    impl NeighborCache {
+        pub fn new(reachable_time: Duration, retrans_timer: Duration) -> Self {
+            NeighborCache { entries: HashMap::new(), reachable_time, retrans_timer }
+        }
+
+        pub fn set_reachable_time(&mut self, reachable_time: Duration) {
+            self.reachable_time = reachable_time;
+        }
+
+        pub fn set_retrans_timer(&mut self, retrans_timer: Duration) {
+            self.retrans_timer = retrans_timer;
+        }
+
+        pub fn get(&self, ip: &Ipv6Addr) -> Option<&NeighborEntry> {
+            self.entries.get(ip)
+        }
+
+        /// The link-layer address to send a datagram for `ip` to, or `None`
+        /// while it is being resolved. Starts resolution of unknown
+        /// neighbors and reachability confirmation of stale ones.
+        pub fn lookup_for_send(&mut self, link: usize, ip: Ipv6Addr, now: Instant) -> (Option<MacAddress>, Option<NdpAction>) {
+            match self.entries.get_mut(&ip) {
+                None => {
+                    self.entries.insert(ip, NeighborEntry {
+                        link,
+                        mac_address: None,
+                        state: NeighborState::Incomplete,
+                        is_router: false,
+                        changed: now,
+                        probes: 1,
+                    });
+                    (None, Some(NdpAction::Solicit { link, target: ip, mac: None }))
+                }
+                Some(entry) => {
+                    if entry.state == NeighborState::Stale {
+                        entry.state = NeighborState::Delay;
+                        entry.changed = now;
+                    }
+                    (entry.mac_address, None)
+                }
+            }
+        }
+
+        /// Record the link-layer address of a neighbor learned from a
+        /// solicitation, router advertisement or redirect. Returns whether
+        /// the neighbor became resolved.
+        pub fn update(&mut self, link: usize, ip: Ipv6Addr, mac: MacAddress, now: Instant) -> bool {
+            let entry = self.entries.entry(ip).or_insert(NeighborEntry {
+                link,
+                mac_address: None,
+                state: NeighborState::Incomplete,
+                is_router: false,
+                changed: now,
+                probes: 0,
+            });
+            let resolved = entry.mac_address.is_none();
+            if entry.mac_address != Some(mac) {
+                entry.mac_address = Some(mac);
+                entry.link = link;
+                entry.state = NeighborState::Stale;
+                entry.changed = now;
+            }
+            resolved
+        }
+
+        /// Process a neighbor advertisement (RFC 4861, section 7.2.5).
+        /// Returns whether the neighbor became resolved.
+        pub fn advertisement(&mut self, ip: Ipv6Addr, mac: Option<MacAddress>, router: bool,
+            solicited: bool, override_: bool, now: Instant) -> bool {
+            let entry = match self.entries.get_mut(&ip) {
+                Some(entry) => entry,
+                None => return false,
+            };
+            if entry.state == NeighborState::Incomplete {
+                let mac = match mac {
+                    Some(mac) => mac,
+                    None => return false,
+                };
+                entry.mac_address = Some(mac);
+                entry.state = if solicited { NeighborState::Reachable } else { NeighborState::Stale };
+                entry.is_router = router;
+                entry.changed = now;
+                return true;
+            }
+            let different = mac.is_some() && mac != entry.mac_address;
+            if !override_ && different {
+                if entry.state == NeighborState::Reachable {
+                    entry.state = NeighborState::Stale;
+                    entry.changed = now;
+                }
+                return false;
+            }
+            if mac.is_some() {
+                entry.mac_address = mac;
+            }
+            if solicited {
+                entry.state = NeighborState::Reachable;
+                entry.changed = now;
+            }
+            else if different {
+                entry.state = NeighborState::Stale;
+                entry.changed = now;
+            }
+            entry.is_router = router;
+            false
+        }
+
+        /// Reachability confirmation from an upper layer, e.g. new
+        /// acknowledgments on a TCP connection.
+        pub fn confirm(&mut self, ip: &Ipv6Addr, now: Instant) {
+            if let Some(entry) = self.entries.get_mut(ip) {
+                if entry.mac_address.is_some() {
+                    entry.state = NeighborState::Reachable;
+                    entry.changed = now;
+                }
+            }
+        }
+
+        /// Record whether a neighbor is a router, e.g. after a router
+        /// advertisement.
+        pub fn set_router(&mut self, ip: &Ipv6Addr, is_router: bool) {
+            if let Some(entry) = self.entries.get_mut(ip) {
+                entry.is_router = is_router;
+            }
+        }
+
+        pub fn remove(&mut self, ip: &Ipv6Addr) -> Option<NeighborEntry> {
+            self.entries.remove(ip)
+        }
+
+        /// Advance the timers of all entries, returning the solicitations
+        /// to send and the neighbors given up on.
+        pub fn tick(&mut self, now: Instant) -> Vec<NdpAction> {
+            let mut actions = Vec::new();
+            let (reachable_time, retrans_timer) = (self.reachable_time, self.retrans_timer);
+            self.entries.retain(|ip, entry| {
+                let elapsed = now.saturating_duration_since(entry.changed);
+                match entry.state {
+                    NeighborState::Reachable if elapsed >= reachable_time => {
+                        entry.state = NeighborState::Stale;
+                        entry.changed = now;
+                    }
+                    NeighborState::Delay if elapsed >= DELAY_FIRST_PROBE_TIME => {
+                        entry.state = NeighborState::Probe;
+                        entry.changed = now;
+                        entry.probes = 1;
+                        actions.push(NdpAction::Solicit { link: entry.link, target: *ip, mac: entry.mac_address });
+                    }
+                    NeighborState::Incomplete | NeighborState::Probe if elapsed >= retrans_timer => {
+                        let (max, mac) = match entry.state {
+                            NeighborState::Incomplete => (MAX_MULTICAST_SOLICIT, None),
+                            _ => (MAX_UNICAST_SOLICIT, entry.mac_address),
+                        };
+                        if entry.probes >= max {
+                            actions.push(NdpAction::Unreachable(*ip));
+                            return false;
+                        }
+                        entry.probes += 1;
+                        entry.changed = now;
+                        actions.push(NdpAction::Solicit { link: entry.link, target: *ip, mac });
+                    }
+                    _ => (),
+                }
+                true
+            });
+            actions
+        }
+
+        /// All entries.
+        pub fn entries(&self) -> Vec<(Ipv6Addr, NeighborEntry)> {
+            self.entries.iter().map(|(ip, entry)| (*ip, entry.clone())).collect()
+        }
+    }
+
+    /// States of an address assigned to a link (RFC 4862).
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub enum AddressState {
+        /// Duplicate address detection in progress; not usable yet
+        Tentative,
+        Preferred,
+        /// Another node uses the address
+        Duplicate,
+    }
+
+    /// An IPv6 address of a link.
+    #[derive(Clone, Debug, PartialEq, Eq)]
+    pub struct Ipv6Address {
+        pub addr: Ipv6Addr,
+        pub prefix_len: u8,
+        pub state: AddressState,
+        /// End of validity of an autoconfigured address
+        pub valid_until: Option<Instant>,
+        dad_until: Option<Instant>,
+    }
+
+    /// A default router learned from router advertisements.
+    #[derive(Clone, Debug, PartialEq, Eq)]
+    pub struct DefaultRouter {
+        pub addr: Ipv6Addr,
+        pub link: usize,
+        pub expires: Instant,
+    }
+
+    /// A prefix reachable directly on a link.
+    #[derive(Clone, Debug, PartialEq, Eq)]
+    pub struct OnLinkPrefix {
+        pub prefix: Ipv6Addr,
+        pub prefix_len: u8,
+        pub link: usize,
+        /// `None` for prefixes of configured addresses
+        pub expires: Option<Instant>,
+    }
+
+    impl OnLinkPrefix {
+        pub fn contains(&self, addr: &Ipv6Addr) -> bool {
+            let mask = ipv6::prefix_mask(self.prefix_len);
+            u128::from(*addr) & mask == u128::from(self.prefix) & mask
+        }
+    }
+
+    struct PendingQueue {
+        link: usize,
+        datagrams: Vec<Vec<u8>>,
+    }
+
+    /// IPv6 state of a stack: addresses per link, neighbors, default
+    /// routers and on-link prefixes.
+    pub struct Ndp {
+        cache: Mutex<NeighborCache>,
+        resolved: Condvar,
+        pending: Mutex<HashMap<Ipv6Addr, PendingQueue>>,
+        addresses: RwLock<HashMap<usize, Vec<Ipv6Address>>>,
+        routers: Mutex<Vec<DefaultRouter>>,
+        prefixes: Mutex<Vec<OnLinkPrefix>>,
+        hop_limit: AtomicU8,
+    }
+
+    impl Default for Ndp {
+        fn default() -> Self {
+            Ndp {
+                cache: Mutex::new(NeighborCache::default()),
+                resolved: Condvar::new(),
+                pending: Mutex::new(HashMap::new()),
+                addresses: RwLock::new(HashMap::new()),
+                routers: Mutex::new(Vec::new()),
+                prefixes: Mutex::new(Vec::new()),
+                hop_limit: AtomicU8::new(ipv6::DEFAULT_HOP_LIMIT),
+            }
+        }
+    }
+
+    fn link_of(stack: &Stack, link: usize) -> Result<Arc<Link>, RlinkError> {
+        stack.link(link).ok_or_else(|| RlinkError::InvalidDeviceName {
+            name: format!("#{}", link),
+            available: stack.links().iter().map(|link| link.name.clone()).collect(),
+        })
+    }
+
+    impl Ndp {
+        /// Assign `addr` to `link`, after duplicate address detection. The
+        /// address is tentative until no other node claimed it for
+        /// `RETRANS_TIMER`.
+        pub fn add_address(&self, stack: &Stack, link: usize, addr: Ipv6Addr, prefix_len: u8) -> Result<(), RlinkError> {
+            self.insert_address(stack, link, addr, prefix_len, AddressState::Tentative, None)
+        }
+
+        /// Assign `addr` to `link` without duplicate address detection,
+        /// e.g. because the kernel already owns it.
+        pub fn add_preferred_address(&self, stack: &Stack, link: usize, addr: Ipv6Addr, prefix_len: u8) -> Result<(), RlinkError> {
+            self.insert_address(stack, link, addr, prefix_len, AddressState::Preferred, None)
+        }
+
+        fn insert_address(&self, stack: &Stack, link_idx: usize, addr: Ipv6Addr, prefix_len: u8,
+            state: AddressState, valid_until: Option<Instant>) -> Result<(), RlinkError> {
+            let link = link_of(stack, link_idx)?;
+            let now = Instant::now();
+            {
+                let mut addresses = self.addresses.write().unwrap();
+                let list = addresses.entry(link_idx).or_default();
+                if list.iter().any(|address| address.addr == addr) {
+                    return Ok(());
+                }
+                let dad_until = Some(now + RETRANS_TIMER).filter(|_| state == AddressState::Tentative);
+                list.push(Ipv6Address { addr, prefix_len, state, valid_until, dad_until });
+            }
+            if prefix_len < 128 && !is_link_local(&addr) {
+                let mask = ipv6::prefix_mask(prefix_len);
+                self.set_prefix(Ipv6Addr::from(u128::from(addr) & mask), prefix_len, link_idx, None);
+            }
+            if state == AddressState::Tentative {
+                let dst = solicited_node(&addr);
+                let message = NdpMessage::NeighborSolicitation { target: addr, options: Vec::new() };
+                self.send_ndp(&link, Ipv6Addr::UNSPECIFIED, dst, ipv6::multicast_mac(&dst), &message)?;
+            }
+            Ok(())
+        }
+
+        /// Assign the EUI-64 link-local address to `link`, with duplicate
+        /// address detection. A router solicitation follows once it is
+        /// usable.
+        pub fn enable(&self, stack: &Stack, link: usize) -> Result<(), RlinkError> {
+            let mac = link_of(stack, link)?.mac_address;
+            self.add_address(stack, link, ipv6::link_local(&mac), 64)
+        }
+
+        pub fn remove_address(&self, link: usize, addr: &Ipv6Addr) {
+            if let Some(list) = self.addresses.write().unwrap().get_mut(&link) {
+                list.retain(|address| address.addr != *addr);
+            }
+        }
+
+        /// The addresses of `link`, in all states.
+        pub fn addresses(&self, link: usize) -> Vec<Ipv6Address> {
+            self.addresses.read().unwrap().get(&link).cloned().unwrap_or_default()
+        }
+
+        fn address_state(&self, link: usize, addr: &Ipv6Addr) -> Option<AddressState> {
+            self.addresses.read().unwrap().get(&link)?
+                .iter().find(|address| address.addr == *addr)
+                .map(|address| address.state)
+        }
+
+        /// Whether `addr` is a usable address of one of the links.
+        pub fn is_local(&self, addr: &Ipv6Addr) -> bool {
+            self.addresses.read().unwrap().values().flatten()
+                .any(|address| address.addr == *addr && address.state == AddressState::Preferred)
+        }
+
+        pub fn neighbors(&self) -> Vec<(Ipv6Addr, NeighborEntry)> {
+            self.cache.lock().unwrap().entries()
+        }
+
+        pub fn default_routers(&self) -> Vec<DefaultRouter> {
+            self.routers.lock().unwrap().clone()
+        }
+
+        pub fn prefixes(&self) -> Vec<OnLinkPrefix> {
+            self.prefixes.lock().unwrap().clone()
+        }
+
+        /// Hop limit of outgoing datagrams, as last advertised by a router.
+        pub fn hop_limit(&self) -> u8 {
+            self.hop_limit.load(Ordering::Relaxed)
+        }
+
+        fn set_prefix(&self, prefix: Ipv6Addr, prefix_len: u8, link: usize, expires: Option<Instant>) {
+            let mut prefixes = self.prefixes.lock().unwrap();
+            prefixes.retain(|p| !(p.prefix == prefix && p.prefix_len == prefix_len && p.link == link));
+            prefixes.push(OnLinkPrefix { prefix, prefix_len, link, expires });
+        }
+
+        /// The link and next hop towards `dst`: the destination itself when
+        /// on-link, a default router otherwise. Link-local and multicast
+        /// destinations go out on the first link with IPv6 addresses.
+        pub fn route(&self, dst: &Ipv6Addr) -> Result<(usize, Ipv6Addr), RlinkError> {
+            let no_route = || RlinkError::NoRoute(IpAddr::V6(*dst));
+            if dst.is_multicast() || is_link_local(dst) {
+                let addresses = self.addresses.read().unwrap();
+                let link = addresses.iter()
+                    .filter(|(_, list)| !list.is_empty())
+                    .map(|(link, _)| *link)
+                    .min()
+                    .ok_or_else(no_route)?;
+                return Ok((link, *dst));
+            }
+            let on_link = self.prefixes.lock().unwrap().iter()
+                .filter(|prefix| prefix.contains(dst))
+                .max_by_key(|prefix| prefix.prefix_len)
+                .map(|prefix| prefix.link);
+            if let Some(link) = on_link {
+                return Ok((link, *dst));
+            }
+            // Prefer routers known to be reachable (RFC 4861, section 6.3.6).
+            let routers = self.routers.lock().unwrap();
+            let cache = self.cache.lock().unwrap();
+            routers.iter()
+                .find(|router| cache.get(&router.addr)
+                    .is_some_and(|entry| entry.state != NeighborState::Incomplete))
+                .or_else(|| routers.first())
+                .map(|router| (router.link, router.addr))
+                .ok_or_else(no_route)
+        }
+
+        /// The source address for datagrams to `dst` on `link`: link-local
+        /// for link-local and link-scoped multicast destinations, a global
+        /// address otherwise when there is one.
+        pub fn source_for(&self, stack: &Stack, link: usize, dst: &Ipv6Addr) -> Result<Ipv6Addr, RlinkError> {
+            let addresses = self.addresses(link);
+            let mut usable = addresses.iter().filter(|address| address.state == AddressState::Preferred);
+            let link_scope = is_link_local(dst) || (dst.is_multicast() && dst.segments()[0] & 0x000f <= 2);
+            let found = match link_scope {
+                true => usable.find(|address| is_link_local(&address.addr)),
+                false => usable.clone().find(|address| !is_link_local(&address.addr)).or_else(|| usable.next()),
+            };
+            found.map(|address| address.addr)
+                .ok_or_else(|| RlinkError::NoAddress(link_of(stack, link).map(|link| link.name.clone()).unwrap_or_default()))
+        }
+
+        /// Send `payload` with the given header. An unspecified source
+        /// address is filled in from the outgoing link.
+        pub fn send_with(&self, stack: &Stack, mut header: Ipv6Header, payload: &[u8]) -> Result<(), RlinkError> {
+            let (link, next_hop) = self.route(&header.dst)?;
+            if header.src.is_unspecified() {
+                header.src = self.source_for(stack, link, &header.dst)?;
+            }
+            self.transmit(stack, link, next_hop, header.encode(payload))
+        }
+
+        /// Hand an encoded datagram to `next_hop` on `link`, soliciting it
+        /// first if needed.
+        fn transmit(&self, stack: &Stack, link_idx: usize, next_hop: Ipv6Addr, datagram: Vec<u8>) -> Result<(), RlinkError> {
+            let link = link_of(stack, link_idx)?;
+            if next_hop.is_multicast() {
+                return link.send_frame(&datagram, EtherType::IPv6, &ipv6::multicast_mac(&next_hop));
+            }
+            let (mac, action) = self.cache.lock().unwrap().lookup_for_send(link_idx, next_hop, Instant::now());
+            if let Some(mac) = mac {
+                return link.send_frame(&datagram, EtherType::IPv6, &mac);
+            }
+            {
+                let mut pending = self.pending.lock().unwrap();
+                let queue = pending.entry(next_hop)
+                    .or_insert_with(|| PendingQueue { link: link_idx, datagrams: Vec::new() });
+                if queue.datagrams.len() < MAX_PENDING {
+                    queue.datagrams.push(datagram);
+                }
+            }
+            if let Some(action) = action {
+                self.perform(stack, action);
+            }
+            Ok(())
+        }
+
+        /// Resolve the MAC address of a neighbor, waiting up to `timeout`.
+        /// Must not be called from the receiving thread of the stack.
+        pub fn resolve(&self, stack: &Stack, ip: Ipv6Addr, timeout: Duration) -> Result<MacAddress, RlinkError> {
+            let (link, _) = self.route(&ip)?;
+            let deadline = Instant::now() + timeout;
+            let mut cache = self.cache.lock().unwrap();
+            loop {
+                let now = Instant::now();
+                let (mac, action) = cache.lookup_for_send(link, ip, now);
+                if let Some(mac) = mac {
+                    return Ok(mac);
+                }
+                if now >= deadline {
+                    return Err(RlinkError::Timeout);
+                }
+                if let Some(action) = action {
+                    drop(cache);
+                    self.perform(stack, action);
+                    cache = self.cache.lock().unwrap();
+                    continue;
+                }
+                cache = self.resolved.wait_timeout(cache, deadline - now).unwrap().0;
+            }
+        }
+
+        fn perform(&self, stack: &Stack, action: NdpAction) {
+            match action {
+                NdpAction::Solicit { link, target, mac } => {
+                    let _ = self.solicit(stack, link, target, mac);
+                }
+                NdpAction::Unreachable(ip) => {
+                    self.pending.lock().unwrap().remove(&ip);
+                    self.resolved.notify_all();
+                }
+            }
+        }
+
+        /// Send a neighbor solicitation for `target`, unicast to `mac` or
+        /// to the solicited-node group.
+        fn solicit(&self, stack: &Stack, link_idx: usize, target: Ipv6Addr, mac: Option<MacAddress>) -> Result<(), RlinkError> {
+            let link = link_of(stack, link_idx)?;
+            let src = self.source_for(stack, link_idx, &target)?;
+            let (dst, dst_mac) = match mac {
+                Some(mac) => (target, mac),
+                None => (solicited_node(&target), ipv6::multicast_mac(&solicited_node(&target))),
+            };
+            let message = NdpMessage::NeighborSolicitation {
+                target,
+                options: vec![NdpOption::SourceLinkAddress(link.mac_address)],
+            };
+            self.send_ndp(&link, src, dst, dst_mac, &message)
+        }
+
+        fn send_ndp(&self, link: &Link, src: Ipv6Addr, dst: Ipv6Addr, dst_mac: MacAddress, message: &NdpMessage) -> Result<(), RlinkError> {
+            let mut header = Ipv6Header::new(src, dst, IpProtocol::ICMPv6);
+            header.hop_limit = NDP_HOP_LIMIT;
+            link.send_frame(&header.encode(&message.encode(src, dst)), EtherType::IPv6, &dst_mac)
+        }
+
+        /// Send the datagrams queued for `ip`, now that it is resolved.
+        fn flush(&self, stack: &Stack, ip: &Ipv6Addr) {
+            let queue = match self.pending.lock().unwrap().remove(ip) {
+                Some(queue) => queue,
+                None => return,
+            };
+            let mac = self.cache.lock().unwrap().get(ip).and_then(|entry| entry.mac_address);
+            if let (Some(mac), Some(link)) = (mac, stack.link(queue.link)) {
+                for datagram in queue.datagrams {
+                    let _ = link.send_frame(&datagram, EtherType::IPv6, &mac);
+                }
+            }
+        }
+
+        fn learn(&self, stack: &Stack, link: usize, ip: Ipv6Addr, mac: MacAddress) {
+            if self.cache.lock().unwrap().update(link, ip, mac, Instant::now()) {
+                self.resolved.notify_all();
+                self.flush(stack, &ip);
+            }
+        }
+
+        /// Process a datagram received on `link`. Only ICMPv6 echo and
+        /// Neighbor Discovery are handled.
+        pub(crate) fn input(&self, stack: &Stack, link_idx: usize, packet: Packet<Ipv6>) {
+            let link = match stack.link(link_idx) {
+                Some(link) => link,
+                None => return,
+            };
+            // Our own frames, captured on the way out.
+            if packet.src_mac() == link.mac_address {
+                return;
+            }
+            let dst = packet.dst();
+            let for_us = self.address_state(link_idx, &dst).is_some()
+                || dst == ALL_NODES
+                || (dst == ALL_ROUTERS && stack.is_forwarding())
+                || self.addresses(link_idx).iter().any(|address| solicited_node(&address.addr) == dst);
+            if !for_us || packet.protocol() != IpProtocol::ICMPv6 {
+                return;
+            }
+            let payload = packet.payload();
+            match payload.first() {
+                Some(&ECHO_REQUEST) => self.echo_reply(stack, &packet),
+                Some(133..=137) if packet.hop_limit() == NDP_HOP_LIMIT => {
+                    if let Ok(message) = NdpMessage::decode(payload, packet.src(), dst) {
+                        self.input_ndp(stack, link_idx, &link, &packet, message);
+                    }
+                }
+                _ => (),
+            }
+        }
+
+        fn echo_reply(&self, stack: &Stack, request: &Packet<Ipv6>) {
+            let dst = request.dst();
+            if request.payload().len() < 8 || !self.is_local(&dst) || pseudo_header_checksum(request.src(), dst, IpProtocol::ICMPv6, request.payload()) != 0 {
+                return;
+            }
+            let mut reply = request.payload().to_vec();
+            reply[0] = ECHO_REPLY;
+            reply[2] = 0;
+            reply[3] = 0;
+            let sum = pseudo_header_checksum(dst, request.src(), IpProtocol::ICMPv6, &reply);
+            reply[2..4].copy_from_slice(&sum.to_be_bytes());
+            let mut header = Ipv6Header::new(dst, request.src(), IpProtocol::ICMPv6);
+            header.hop_limit = self.hop_limit();
+            let _ = self.send_with(stack, header, &reply);
+        }
+
+        fn input_ndp(&self, stack: &Stack, link_idx: usize, link: &Link, packet: &Packet<Ipv6>, message: NdpMessage) {
+            let src = packet.src();
+            let now = Instant::now();
+            match message {
+                NdpMessage::NeighborSolicitation { target, .. } => {
+                    match self.address_state(link_idx, &target) {
+                        // Another node probing the address: it is a duplicate,
+                        // and a tentative address is never answered for.
+                        Some(AddressState::Tentative) if src.is_unspecified() => {
+                            self.mark_duplicate(link_idx, &target);
+                            return;
+                        }
+                        Some(AddressState::Preferred) => (),
+                        _ => return,
+                    }
+                    if let (false, Some(mac)) = (src.is_unspecified(), message.source_link_address()) {
+                        self.learn(stack, link_idx, src, mac);
+                    }
+                    let (dst, dst_mac) = match src.is_unspecified() {
+                        true => (ALL_NODES, ipv6::multicast_mac(&ALL_NODES)),
+                        false => (src, message.source_link_address().unwrap_or_else(|| packet.src_mac())),
+                    };
+                    let reply = NdpMessage::NeighborAdvertisement {
+                        router: stack.is_forwarding(),
+                        solicited: !src.is_unspecified(),
+                        override_: true,
+                        target,
+                        options: vec![NdpOption::TargetLinkAddress(link.mac_address)],
+                    };
+                    let _ = self.send_ndp(link, target, dst, dst_mac, &reply);
+                }
+                NdpMessage::NeighborAdvertisement { router, solicited, override_, target, .. } => {
+                    if let Some(state) = self.address_state(link_idx, &target) {
+                        if state == AddressState::Tentative {
+                            self.mark_duplicate(link_idx, &target);
+                        }
+                        return;
+                    }
+                    let mac = message.target_link_address();
+                    let resolved = self.cache.lock().unwrap()
+                        .advertisement(target, mac, router, solicited, override_, now);
+                    self.resolved.notify_all();
+                    if resolved {
+                        self.flush(stack, &target);
+                    }
+                    if !router {
+                        self.routers.lock().unwrap().retain(|r| r.addr != target);
+                    }
+                }
+                NdpMessage::RouterAdvertisement {
+                    cur_hop_limit, router_lifetime, reachable_time, retrans_timer, ref options, ..
+                } => {
+                    if !is_link_local(&src) {
+                        return;
+                    }
+                    if let Some(mac) = message.source_link_address() {
+                        self.learn(stack, link_idx, src, mac);
+                    }
+                    self.cache.lock().unwrap().set_router(&src, true);
+                    {
+                        let mut routers = self.routers.lock().unwrap();
+                        routers.retain(|r| !(r.addr == src && r.link == link_idx));
+                        if router_lifetime != 0 {
+                            let expires = now + Duration::from_secs(router_lifetime as u64);
+                            routers.push(DefaultRouter { addr: src, link: link_idx, expires });
+                        }
+                    }
+                    if cur_hop_limit != 0 {
+                        self.hop_limit.store(cur_hop_limit, Ordering::Relaxed);
+                    }
+                    {
+                        let mut cache = self.cache.lock().unwrap();
+                        if reachable_time != 0 {
+                            cache.set_reachable_time(Duration::from_millis(reachable_time as u64));
+                        }
+                        if retrans_timer != 0 {
+                            cache.set_retrans_timer(Duration::from_millis(retrans_timer as u64));
+                        }
+                    }
+                    for option in options.iter() {
+                        if let NdpOption::PrefixInformation(info) = option {
+                            self.input_prefix(stack, link_idx, link, info, now);
+                        }
+                    }
+                }
+                NdpMessage::Redirect { target, .. } => {
+                    if let Some(mac) = message.target_link_address() {
+                        self.learn(stack, link_idx, target, mac);
+                    }
+                }
+                NdpMessage::RouterSolicitation { .. } => {
+                    // Only routers answer; they do learn the soliciting host.
+                    if let (true, false, Some(mac)) = (stack.is_forwarding(), src.is_unspecified(), message.source_link_address()) {
+                        self.learn(stack, link_idx, src, mac);
+                    }
+                }
+            }
+        }
+
+        /// Process a prefix information option: on-link determination and
+        /// stateless address autoconfiguration (RFC 4862, section 5.5.3).
+        fn input_prefix(&self, stack: &Stack, link_idx: usize, link: &Link, info: &PrefixInformation, now: Instant) {
+            if is_link_local(&info.prefix) || info.prefix_len > 128 {
+                return;
+            }
+            let lifetime = |seconds: u32| match seconds {
+                u32::MAX => None,
+                seconds => Some(now + Duration::from_secs(seconds as u64)),
+            };
+            let mask = ipv6::prefix_mask(info.prefix_len);
+            let prefix = Ipv6Addr::from(u128::from(info.prefix) & mask);
+            if info.on_link {
+                if info.valid_lifetime == 0 {
+                    self.prefixes.lock().unwrap()
+                        .retain(|p| !(p.prefix == prefix && p.prefix_len == info.prefix_len && p.link == link_idx));
+                }
+                else {
+                    self.set_prefix(prefix, info.prefix_len, link_idx, lifetime(info.valid_lifetime));
+                }
+            }
+            if !info.autonomous || info.prefix_len != 64 || info.preferred_lifetime > info.valid_lifetime
+                || info.valid_lifetime == 0 {
+                return;
+            }
+            let mut octets = prefix.octets();
+            octets[8..].copy_from_slice(&ipv6::eui64(&link.mac_address));
+            let addr = Ipv6Addr::from(octets);
+            let valid_until = lifetime(info.valid_lifetime);
+            {
+                let mut addresses = self.addresses.write().unwrap();
+                let existing = addresses.get_mut(&link_idx)
+                    .and_then(|list| list.iter_mut().find(|address| address.addr == addr));
+                if let Some(address) = existing {
+                    address.valid_until = valid_until;
+                    return;
+                }
+            }
+            let _ = self.insert_address(stack, link_idx, addr, 64, AddressState::Tentative, valid_until);
+        }
+
+        fn mark_duplicate(&self, link: usize, addr: &Ipv6Addr) {
+            if let Some(list) = self.addresses.write().unwrap().get_mut(&link) {
+                for address in list.iter_mut().filter(|address| address.addr == *addr) {
+                    address.state = AddressState::Duplicate;
+                    address.dad_until = None;
+                }
+            }
+        }
+
+        /// Advance neighbor, address, router and prefix timers.
+        fn tick(&self, stack: &Stack) {
+            let now = Instant::now();
+            let actions = self.cache.lock().unwrap().tick(now);
+            for action in actions {
+                self.perform(stack, action);
+            }
+
+            let mut usable_link_locals = Vec::new();
+            {
+                let mut addresses = self.addresses.write().unwrap();
+                for (link, list) in addresses.iter_mut() {
+                    list.retain(|address| address.valid_until.is_none_or(|until| until > now));
+                    for address in list.iter_mut() {
+                        if address.dad_until.is_some_and(|until| until <= now) {
+                            address.dad_until = None;
+                            address.state = AddressState::Preferred;
+                            if is_link_local(&address.addr) {
+                                usable_link_locals.push((*link, address.addr));
+                            }
+                        }
+                    }
+                }
+            }
+            // Look for routers as soon as the link can be used.
+            for (link_idx, src) in usable_link_locals {
+                if let Some(link) = stack.link(link_idx) {
+                    let message = NdpMessage::RouterSolicitation {
+                        options: vec![NdpOption::SourceLinkAddress(link.mac_address)],
+                    };
+                    let _ = self.send_ndp(&link, src, ALL_ROUTERS, ipv6::multicast_mac(&ALL_ROUTERS), &message);
+                }
+            }
+
+            self.routers.lock().unwrap().retain(|router| router.expires > now);
+            self.prefixes.lock().unwrap().retain(|prefix| prefix.expires.is_none_or(|until| until > now));
+        }
+
+        /// Start the thread driving the timers, which ends with the stack.
+        pub(crate) fn start_timer(self: &Arc<Self>, stack: WeakStack) {
+            let ndp: Weak<Ndp> = Arc::downgrade(self);
+            thread::spawn(move || loop {
+                thread::sleep(TICK);
+                match (stack.upgrade(), ndp.upgrade()) {
+                    (Some(stack), Some(ndp)) => ndp.tick(&stack),
+                    _ => break,
+                }
+            });
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::stack::stack::FrameSink;
+        use crate::Raw;
+        use std::sync::mpsc::{self, Receiver, Sender};
+
+        const MAC: [u8; 6] = [2, 0, 0, 0, 0, 1];
+        const PEER_MAC: [u8; 6] = [2, 0, 0, 0, 0, 2];
+        const ADDR: Ipv6Addr = Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0, 1);
+        const PEER: Ipv6Addr = Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0, 2);
+
+        /// Datagrams sent on a link, with their destination address.
+        type Sent = Receiver<(Vec<u8>, MacAddress)>;
+
+        /// Hands the datagrams sent on a link to the test.
+        struct Sink(Sender<(Vec<u8>, MacAddress)>);
+
+        impl FrameSink for Sink {
+            fn send_frame(&mut self, payload: &[u8], _: EtherType, dst: &MacAddress) -> Result<(), RlinkError> {
+                self.0.send((payload.to_vec(), *dst)).map_err(|_| RlinkError::BrokenDevicePool)
+            }
+        }
+
+        /// A stack with IPv6 on a single link, and the datagrams it sends.
+        fn host() -> (Stack, Arc<Ndp>, Sent) {
+            let (tx, rx) = mpsc::channel();
+            let stack = Stack::new();
+            stack.add_link("ndp0", MacAddress::new(MAC), Vec::new(), Box::new(Sink(tx)));
+            let ndp = stack.ndp();
+            (stack, ndp, rx)
+        }
+
+        /// Deliver an NDP message from the peer to the stack.
+        fn receive(stack: &Stack, src: Ipv6Addr, dst: Ipv6Addr, dst_mac: MacAddress, message: &NdpMessage) {
+            let mut header = Ipv6Header::new(src, dst, IpProtocol::ICMPv6);
+            header.hop_limit = NDP_HOP_LIMIT;
+            let mut frame = [
+                dst_mac.bytes().as_ref(),
+                PEER_MAC.as_ref(),
+                u16::from(EtherType::IPv6).to_be_bytes().as_ref(),
+                &header.encode(&message.encode(src, dst)),
+            ].concat();
+            frame.resize(frame.len().max(60), 0);
+            frame.extend_from_slice(&[0; 4]);
+            let frame = Packet::<Raw>::from_bytes(frame, MacAddress::new(MAC)).parse_eth(false).unwrap();
+            stack.input(0, frame);
+        }
+
+        /// The NDP message of a sent datagram, with its addresses.
+        fn sent(rx: &Sent) -> Option<(Ipv6Addr, Ipv6Addr, NdpMessage)> {
+            let (datagram, _) = rx.try_recv().ok()?;
+            let addr = |offset: usize| Ipv6Addr::from(<[u8; 16]>::try_from(&datagram[offset..offset+16]).unwrap());
+            let (src, dst) = (addr(8), addr(24));
+            Some((src, dst, NdpMessage::decode(&datagram[40..], src, dst).unwrap()))
+        }
+
+        fn state(ndp: &Ndp, addr: &Ipv6Addr) -> Option<AddressState> {
+            ndp.address_state(0, addr)
+        }
+
+        #[test]
+        fn message_round_trip() {
+            let prefix = PrefixInformation {
+                prefix_len: 64,
+                on_link: true,
+                autonomous: false,
+                valid_lifetime: u32::MAX,
+                preferred_lifetime: 3600,
+                prefix: Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 0),
+            };
+            let mac = MacAddress::new(MAC);
+            for message in [
+                NdpMessage::RouterSolicitation { options: vec![NdpOption::SourceLinkAddress(mac)] },
+                NdpMessage::RouterAdvertisement {
+                    cur_hop_limit: 64,
+                    managed: true,
+                    other: false,
+                    router_lifetime: 1800,
+                    reachable_time: 30000,
+                    retrans_timer: 1000,
+                    options: vec![
+                        NdpOption::SourceLinkAddress(mac),
+                        NdpOption::Mtu(1500),
+                        NdpOption::PrefixInformation(prefix),
+                    ],
+                },
+                NdpMessage::NeighborSolicitation { target: PEER, options: Vec::new() },
+                NdpMessage::NeighborAdvertisement {
+                    router: false,
+                    solicited: true,
+                    override_: true,
+                    target: ADDR,
+                    options: vec![NdpOption::TargetLinkAddress(mac)],
+                },
+                NdpMessage::Redirect {
+                    target: PEER,
+                    destination: Ipv6Addr::new(0x2001, 0xdb8, 0, 1, 0, 0, 0, 1),
+                    options: vec![NdpOption::Other { option_type: REDIRECTED_HEADER, data: vec![0; 14] }],
+                },
+            ] {
+                let data = message.encode(ADDR, PEER);
+                assert_eq!(data.len() % 8, 0);
+                assert_eq!(NdpMessage::decode(&data, ADDR, PEER), Ok(message.clone()));
+                // The pseudo header covers the addresses
+                assert!(matches!(NdpMessage::decode(&data, ADDR, ALL_NODES), Err(FrameError::ChecksumMismatch { .. })));
+            }
+        }
+
+        #[test]
+        fn invalid_options() {
+            let message = NdpMessage::NeighborSolicitation {
+                target: PEER,
+                options: vec![NdpOption::SourceLinkAddress(MacAddress::new(MAC))],
+            };
+            let reencode = |data: &mut Vec<u8>| {
+                data[2..4].copy_from_slice(&[0, 0]);
+                let sum = pseudo_header_checksum(ADDR, PEER, IpProtocol::ICMPv6, data);
+                data[2..4].copy_from_slice(&sum.to_be_bytes());
+            };
+            let mut zero_length = message.encode(ADDR, PEER);
+            zero_length[25] = 0;
+            reencode(&mut zero_length);
+            assert_eq!(NdpMessage::decode(&zero_length, ADDR, PEER),
+                Err(FrameError::InvalidField { offset: 25, field: "option length" }));
+            let mut overlong = message.encode(ADDR, PEER);
+            overlong[25] = 2;
+            reencode(&mut overlong);
+            assert_eq!(NdpMessage::decode(&overlong, ADDR, PEER),
+                Err(FrameError::Truncated { offset: 24, needed: 16, available: 8 }));
+        }
+
+        #[test]
+        fn neighbor_states() {
+            let mut cache = NeighborCache::default();
+            let mac = MacAddress::new(PEER_MAC);
+            let now = Instant::now();
+
+            // Unknown: solicited to its solicited-node group
+            assert_eq!(cache.lookup_for_send(0, PEER, now),
+                (None, Some(NdpAction::Solicit { link: 0, target: PEER, mac: None })));
+            assert_eq!(cache.get(&PEER).unwrap().state, NeighborState::Incomplete);
+            assert!(cache.advertisement(PEER, Some(mac), false, true, false, now));
+            assert_eq!(cache.get(&PEER).unwrap().state, NeighborState::Reachable);
+
+            // Not confirmed for REACHABLE_TIME
+            let now = now + REACHABLE_TIME;
+            assert!(cache.tick(now).is_empty());
+            assert_eq!(cache.get(&PEER).unwrap().state, NeighborState::Stale);
+
+            // Used while stale, then probed by unicast
+            assert_eq!(cache.lookup_for_send(0, PEER, now), (Some(mac), None));
+            assert_eq!(cache.get(&PEER).unwrap().state, NeighborState::Delay);
+            let now = now + DELAY_FIRST_PROBE_TIME;
+            assert_eq!(cache.tick(now), vec![NdpAction::Solicit { link: 0, target: PEER, mac: Some(mac) }]);
+            assert_eq!(cache.get(&PEER).unwrap().state, NeighborState::Probe);
+
+            // Confirmed by the answer
+            assert!(!cache.advertisement(PEER, Some(mac), false, true, false, now));
+            assert_eq!(cache.get(&PEER).unwrap().state, NeighborState::Reachable);
+        }
+
+        #[test]
+        fn neighbor_unreachable() {
+            let mut cache = NeighborCache::default();
+            let mut now = Instant::now();
+            cache.lookup_for_send(0, PEER, now);
+            for _ in 1..MAX_MULTICAST_SOLICIT {
+                now += RETRANS_TIMER;
+                assert_eq!(cache.tick(now), vec![NdpAction::Solicit { link: 0, target: PEER, mac: None }]);
+            }
+            now += RETRANS_TIMER;
+            assert_eq!(cache.tick(now), vec![NdpAction::Unreachable(PEER)]);
+            assert!(cache.get(&PEER).is_none());
+        }
+
+        #[test]
+        fn dad_probe_for_tentative_address() {
+            let (stack, ndp, rx) = host();
+            ndp.add_address(&stack, 0, ADDR, 64).unwrap();
+            let (src, dst, probe) = sent(&rx).unwrap();
+            assert_eq!((src, dst), (Ipv6Addr::UNSPECIFIED, solicited_node(&ADDR)));
+            assert_eq!(probe, NdpMessage::NeighborSolicitation { target: ADDR, options: Vec::new() });
+
+            // Another node probing the same address
+            receive(&stack, Ipv6Addr::UNSPECIFIED, solicited_node(&ADDR), ipv6::multicast_mac(&solicited_node(&ADDR)), &probe);
+            assert_eq!(state(&ndp, &ADDR), Some(AddressState::Duplicate));
+            assert!(sent(&rx).is_none());
+        }
+
+        #[test]
+        fn solicitation_for_tentative_address() {
+            let (stack, ndp, rx) = host();
+            ndp.add_address(&stack, 0, ADDR, 64).unwrap();
+            sent(&rx).unwrap();
+
+            // Address resolution of a tentative address is not answered
+            let solicitation = NdpMessage::NeighborSolicitation {
+                target: ADDR,
+                options: vec![NdpOption::SourceLinkAddress(MacAddress::new(PEER_MAC))],
+            };
+            receive(&stack, PEER, solicited_node(&ADDR), ipv6::multicast_mac(&solicited_node(&ADDR)), &solicitation);
+            assert_eq!(state(&ndp, &ADDR), Some(AddressState::Tentative));
+            assert!(sent(&rx).is_none());
+        }
+
+        #[test]
+        fn advertisement_for_tentative_address() {
+            let (stack, ndp, rx) = host();
+            ndp.add_address(&stack, 0, ADDR, 64).unwrap();
+            sent(&rx).unwrap();
+
+            let advertisement = NdpMessage::NeighborAdvertisement {
+                router: false,
+                solicited: false,
+                override_: true,
+                target: ADDR,
+                options: vec![NdpOption::TargetLinkAddress(MacAddress::new(PEER_MAC))],
+            };
+            receive(&stack, PEER, ALL_NODES, ipv6::multicast_mac(&ALL_NODES), &advertisement);
+            assert_eq!(state(&ndp, &ADDR), Some(AddressState::Duplicate));
+            assert!(sent(&rx).is_none());
+        }
+
+        #[test]
+        fn solicitation_for_preferred_address() {
+            let (stack, ndp, rx) = host();
+            ndp.add_preferred_address(&stack, 0, ADDR, 64).unwrap();
+
+            let solicitation = NdpMessage::NeighborSolicitation {
+                target: ADDR,
+                options: vec![NdpOption::SourceLinkAddress(MacAddress::new(PEER_MAC))],
+            };
+            receive(&stack, PEER, solicited_node(&ADDR), ipv6::multicast_mac(&solicited_node(&ADDR)), &solicitation);
+            let (src, dst, reply) = sent(&rx).unwrap();
+            assert_eq!((src, dst), (ADDR, PEER));
+            assert_eq!(reply, NdpMessage::NeighborAdvertisement {
+                router: false,
+                solicited: true,
+                override_: true,
+                target: ADDR,
+                options: vec![NdpOption::TargetLinkAddress(MacAddress::new(MAC))],
+            });
+            // The solicitor is learned on the way
+            let neighbor = ndp.neighbors().into_iter().find(|(ip, _)| *ip == PEER).unwrap().1;
+            assert_eq!((neighbor.mac_address, neighbor.state), (Some(MacAddress::new(PEER_MAC)), NeighborState::Stale));
+        }
+    }
+}
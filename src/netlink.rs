@@ -0,0 +1,378 @@
+#![allow(dead_code)]
+#![allow(unused)]
+
+pub mod netlink {
+    //! Minimal rtnetlink (NETLINK_ROUTE) plumbing: a socket, message
+    //! framing and attribute (TLV) encoding. Only what rlink needs to
+    //! inspect and watch interfaces is covered, plus, with the `netlink`
+    //! feature, the interface configuration done by the `vnetUtils` scripts.
+
+    use std::io;
+    use std::os::unix::io::RawFd;
+
+    // From <linux/netlink.h> and <linux/rtnetlink.h>. Netlink messages
+    // are in host byte order.
+    pub(crate) const NLMSG_HDRLEN: usize = 16;
+    pub(crate) const NLMSG_NOOP: u16 = 1;
+    pub(crate) const NLMSG_ERROR: u16 = 2;
+    pub(crate) const NLMSG_DONE: u16 = 3;
+
+    pub(crate) const NLM_F_REQUEST: u16 = 0x01;
+    pub(crate) const NLM_F_MULTI: u16 = 0x02;
+    pub(crate) const NLM_F_ACK: u16 = 0x04;
+    pub(crate) const NLM_F_DUMP: u16 = 0x300;
+    pub(crate) const NLM_F_REPLACE: u16 = 0x100;
+    pub(crate) const NLM_F_EXCL: u16 = 0x200;
+    pub(crate) const NLM_F_CREATE: u16 = 0x400;
+
+    pub(crate) const RTM_NEWLINK: u16 = 16;
+    pub(crate) const RTM_DELLINK: u16 = 17;
+    pub(crate) const RTM_GETLINK: u16 = 18;
+    pub(crate) const RTM_SETLINK: u16 = 19;
+    pub(crate) const RTM_NEWADDR: u16 = 20;
+    pub(crate) const RTM_DELADDR: u16 = 21;
+    pub(crate) const RTM_GETADDR: u16 = 22;
+
+    pub(crate) const RTMGRP_LINK: u32 = 0x1;
+    pub(crate) const RTMGRP_IPV4_IFADDR: u32 = 0x10;
+    pub(crate) const RTMGRP_IPV6_IFADDR: u32 = 0x100;
+
+    pub(crate) const IFLA_ADDRESS: u16 = 1;
+    pub(crate) const IFLA_BROADCAST: u16 = 2;
+    pub(crate) const IFLA_IFNAME: u16 = 3;
+    pub(crate) const IFLA_MTU: u16 = 4;
+    pub(crate) const IFLA_OPERSTATE: u16 = 16;
+
+    pub(crate) const IFA_ADDRESS: u16 = 1;
+    pub(crate) const IFA_LOCAL: u16 = 2;
+    pub(crate) const IFA_BROADCAST: u16 = 4;
+
+    pub(crate) const NLA_F_NESTED: u16 = 1 << 15;
+    const NLA_TYPE_MASK: u16 = !(1 << 15 | 1 << 14);
+
+    /// Size of `struct ifinfomsg`.
+    pub(crate) const IFINFOMSG_LEN: usize = 16;
+    /// Size of `struct ifaddrmsg`.
+    pub(crate) const IFADDRMSG_LEN: usize = 8;
+
+    fn align(len: usize) -> usize {
+        (len + 3) & !3
+    }
+
+    /// A received netlink message, with the header decoded.
+    #[derive(Debug, Clone)]
+    pub(crate) struct NetlinkMessage {
+        pub msg_type: u16,
+        pub flags: u16,
+        pub seq: u32,
+        pub payload: Vec<u8>,
+    }
+
+    /// A NETLINK_ROUTE socket.
+    pub(crate) struct NetlinkSocket {
+        fd: RawFd,
+        seq: u32,
+    }
+
+    impl NetlinkSocket {
+        /// Open a socket, subscribed to the given multicast `groups`
+        /// (0 for request/response use only).
+        pub fn open(groups: u32) -> io::Result<Self> {
+            let fd = unsafe {
+                libc::socket(libc::AF_NETLINK, libc::SOCK_RAW | libc::SOCK_CLOEXEC, libc::NETLINK_ROUTE)
+            };
+            if fd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let mut addr: libc::sockaddr_nl = unsafe { std::mem::zeroed() };
+            addr.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+            addr.nl_groups = groups;
+            let ret = unsafe {
+                libc::bind(fd,
+                    &addr as *const libc::sockaddr_nl as *const libc::sockaddr,
+                    std::mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t)
+            };
+            if ret < 0 {
+                let e = io::Error::last_os_error();
+                unsafe { libc::close(fd) };
+                return Err(e);
+            }
+            Ok(NetlinkSocket { fd, seq: 0 })
+        }
+
+        /// Send a request with the given type, flags and payload (the part
+        /// after the netlink header). Returns the sequence number used.
+        pub fn send(&mut self, msg_type: u16, flags: u16, payload: &[u8]) -> io::Result<u32> {
+            self.seq = self.seq.wrapping_add(1);
+            let len = NLMSG_HDRLEN + payload.len();
+            let mut buf = Vec::with_capacity(len);
+            buf.extend_from_slice(&(len as u32).to_ne_bytes());
+            buf.extend_from_slice(&msg_type.to_ne_bytes());
+            buf.extend_from_slice(&(flags | NLM_F_REQUEST).to_ne_bytes());
+            buf.extend_from_slice(&self.seq.to_ne_bytes());
+            buf.extend_from_slice(&0u32.to_ne_bytes());
+            buf.extend_from_slice(payload);
+
+            let ret = unsafe {
+                libc::send(self.fd, buf.as_ptr() as *const libc::c_void, buf.len(), 0)
+            };
+            if ret < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(self.seq)
+        }
+
+        /// Block until a datagram arrives and split it into messages.
+        pub fn recv(&mut self) -> io::Result<Vec<NetlinkMessage>> {
+            let mut buf = vec![0u8; 32768];
+            let len = loop {
+                let ret = unsafe {
+                    libc::recv(self.fd, buf.as_mut_ptr() as *mut libc::c_void, buf.len(), 0)
+                };
+                if ret >= 0 {
+                    break ret as usize;
+                }
+                let e = io::Error::last_os_error();
+                if e.kind() != io::ErrorKind::Interrupted {
+                    return Err(e);
+                }
+            };
+
+            let mut messages = Vec::new();
+            let mut offset = 0;
+            while offset + NLMSG_HDRLEN <= len {
+                let msg_len = u32::from_ne_bytes(buf[offset..offset+4].try_into().unwrap()) as usize;
+                if msg_len < NLMSG_HDRLEN || offset + msg_len > len {
+                    break;
+                }
+                messages.push(NetlinkMessage {
+                    msg_type: u16::from_ne_bytes(buf[offset+4..offset+6].try_into().unwrap()),
+                    flags: u16::from_ne_bytes(buf[offset+6..offset+8].try_into().unwrap()),
+                    seq: u32::from_ne_bytes(buf[offset+8..offset+12].try_into().unwrap()),
+                    payload: buf[offset+NLMSG_HDRLEN..offset+msg_len].to_vec(),
+                });
+                offset += align(msg_len);
+            }
+            Ok(messages)
+        }
+
+        /// Send a request and collect the responses, until `NLMSG_DONE` for
+        /// dumps or the acknowledgement otherwise. A negative error code in
+        /// an `NLMSG_ERROR` response is turned into an `io::Error`.
+        pub fn request(&mut self, msg_type: u16, flags: u16, payload: &[u8])
+            -> io::Result<Vec<NetlinkMessage>> {
+            let seq = self.send(msg_type, flags | NLM_F_ACK, payload)?;
+            let mut responses = Vec::new();
+            loop {
+                for message in self.recv()? {
+                    if message.seq != seq {
+                        continue;
+                    }
+                    match message.msg_type {
+                        NLMSG_DONE => return Ok(responses),
+                        NLMSG_ERROR => {
+                            let code = message.payload.get(0..4)
+                                .map(|b| i32::from_ne_bytes(b.try_into().unwrap()))
+                                .unwrap_or(0);
+                            if code < 0 {
+                                return Err(io::Error::from_raw_os_error(-code));
+                            }
+                            // An ack of a dump may still precede NLMSG_DONE.
+                            if flags & NLM_F_DUMP != NLM_F_DUMP {
+                                return Ok(responses);
+                            }
+                        }
+                        NLMSG_NOOP => {}
+                        _ => responses.push(message),
+                    }
+                }
+            }
+        }
+    }
+
+    impl Drop for NetlinkSocket {
+        fn drop(&mut self) {
+            unsafe { libc::close(self.fd) };
+        }
+    }
+
+    /// Iterate over the attributes (`struct rtattr`/`struct nlattr`) in
+    /// `buf`, yielding `(type, value)`. The nested flag is masked off.
+    pub(crate) fn parse_attrs(buf: &[u8]) -> Vec<(u16, &[u8])> {
+        let mut attrs = Vec::new();
+        let mut offset = 0;
+        while offset + 4 <= buf.len() {
+            let len = u16::from_ne_bytes(buf[offset..offset+2].try_into().unwrap()) as usize;
+            let kind = u16::from_ne_bytes(buf[offset+2..offset+4].try_into().unwrap());
+            if len < 4 || offset + len > buf.len() {
+                break;
+            }
+            attrs.push((kind & NLA_TYPE_MASK, &buf[offset+4..offset+len]));
+            offset += align(len);
+        }
+        attrs
+    }
+
+    /// Append an attribute to `buf`, padding it to 4 bytes.
+    pub(crate) fn put_attr(buf: &mut Vec<u8>, kind: u16, value: &[u8]) {
+        let len = 4 + value.len();
+        buf.extend_from_slice(&(len as u16).to_ne_bytes());
+        buf.extend_from_slice(&kind.to_ne_bytes());
+        buf.extend_from_slice(value);
+        buf.resize(align(buf.len()), 0);
+    }
+
+    /// Decode a NUL-terminated string attribute.
+    pub(crate) fn attr_str(value: &[u8]) -> String {
+        let end = value.iter().position(|&b| b == 0).unwrap_or(value.len());
+        String::from_utf8_lossy(&value[..end]).into_owned()
+    }
+
+    /// Decode a host-order u32 attribute.
+    pub(crate) fn attr_u32(value: &[u8]) -> Option<u32> {
+        value.get(0..4).map(|b| u32::from_ne_bytes(b.try_into().unwrap()))
+    }
+
+    /// Build a `struct ifinfomsg`.
+    pub(crate) fn ifinfomsg(family: u8, index: i32, flags: u32, change: u32) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(IFINFOMSG_LEN);
+        buf.push(family);
+        buf.push(0);
+        buf.extend_from_slice(&0u16.to_ne_bytes());
+        buf.extend_from_slice(&index.to_ne_bytes());
+        buf.extend_from_slice(&flags.to_ne_bytes());
+        buf.extend_from_slice(&change.to_ne_bytes());
+        buf
+    }
+
+    /// Build a `struct ifaddrmsg`.
+    pub(crate) fn ifaddrmsg(family: u8, prefix_len: u8, index: u32) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(IFADDRMSG_LEN);
+        buf.push(family);
+        buf.push(prefix_len);
+        buf.push(0);
+        buf.push(0);
+        buf.extend_from_slice(&index.to_ne_bytes());
+        buf
+    }
+
+    /// Look up the index of an interface in the current namespace.
+    pub(crate) fn link_index(name: &str) -> Result<u32, crate::RlinkError> {
+        let cname = std::ffi::CString::new(name)
+            .map_err(|_| crate::RlinkError::InvalidDeviceName { name: name.to_string(), available: Vec::new() })?;
+        match unsafe { libc::if_nametoindex(cname.as_ptr()) } {
+            0 => Err(crate::RlinkError::InvalidDeviceName {
+                name: name.to_string(),
+                available: crate::interface::interface::interfaces()
+                    .map(|ifaces| ifaces.into_iter().map(|iface| iface.name).collect())
+                    .unwrap_or_default(),
+            }),
+            index => Ok(index),
+        }
+    }
+
+    #[cfg(feature = "netlink")]
+    pub use self::config::*;
+
+    /// Interface configuration, replacing the `ip` invocations of the
+    /// `vnetUtils` helper scripts. All operations act on the network
+    /// namespace of the calling thread and require `CAP_NET_ADMIN`.
+    #[cfg(feature = "netlink")]
+    mod config {
+        use super::*;
+        use crate::RlinkError;
+        use std::net::IpAddr;
+
+        const IFLA_LINKINFO: u16 = 18;
+        const IFLA_NET_NS_FD: u16 = 28;
+        const IFLA_INFO_KIND: u16 = 1;
+        const IFLA_INFO_DATA: u16 = 2;
+        const VETH_INFO_PEER: u16 = 1;
+
+        fn request(msg_type: u16, flags: u16, payload: &[u8]) -> Result<(), RlinkError> {
+            NetlinkSocket::open(0)?.request(msg_type, flags, payload)?;
+            Ok(())
+        }
+
+        fn name_attr(name: &str) -> Vec<u8> {
+            let mut value = name.as_bytes().to_vec();
+            value.push(0);
+            value
+        }
+
+        /// Create a veth pair `name` <-> `peer` (see `addVethPair`).
+        pub fn create_veth_pair(name: &str, peer: &str) -> Result<(), RlinkError> {
+            let mut peer_info = ifinfomsg(libc::AF_UNSPEC as u8, 0, 0, 0);
+            put_attr(&mut peer_info, IFLA_IFNAME, &name_attr(peer));
+            let mut data = Vec::new();
+            put_attr(&mut data, VETH_INFO_PEER, &peer_info);
+            let mut linkinfo = Vec::new();
+            put_attr(&mut linkinfo, IFLA_INFO_KIND, b"veth");
+            put_attr(&mut linkinfo, IFLA_INFO_DATA, &data);
+
+            let mut payload = ifinfomsg(libc::AF_UNSPEC as u8, 0, 0, 0);
+            put_attr(&mut payload, IFLA_IFNAME, &name_attr(name));
+            put_attr(&mut payload, IFLA_LINKINFO, &linkinfo);
+            request(RTM_NEWLINK, NLM_F_CREATE | NLM_F_EXCL, &payload)
+        }
+
+        /// Delete a link. Deleting either end of a veth pair removes both
+        /// (see `delVeth`).
+        pub fn delete_link(name: &str) -> Result<(), RlinkError> {
+            let payload = ifinfomsg(libc::AF_UNSPEC as u8, link_index(name)? as i32, 0, 0);
+            request(RTM_DELLINK, 0, &payload)
+        }
+
+        /// Bring a link administratively up or down.
+        pub fn set_link_up(name: &str, up: bool) -> Result<(), RlinkError> {
+            let flags = if up { libc::IFF_UP as u32 } else { 0 };
+            let payload = ifinfomsg(libc::AF_UNSPEC as u8, link_index(name)? as i32, 
+                flags, libc::IFF_UP as u32);
+            request(RTM_NEWLINK, 0, &payload)
+        }
+
+        /// Change the MTU of a link.
+        pub fn set_mtu(name: &str, mtu: u32) -> Result<(), RlinkError> {
+            let mut payload = ifinfomsg(libc::AF_UNSPEC as u8, link_index(name)? as i32, 0, 0);
+            put_attr(&mut payload, IFLA_MTU, &mtu.to_ne_bytes());
+            request(RTM_NEWLINK, 0, &payload)
+        }
+
+        /// Move a link into the named network namespace, as created by
+        /// `ip netns add` (see `setNS`). The link is no longer visible in
+        /// the current namespace afterwards.
+        pub fn set_link_netns(name: &str, netns: &str) -> Result<(), RlinkError> {
+            let netns = crate::netns::netns::NetNs::open(netns)?;
+            let mut payload = ifinfomsg(libc::AF_UNSPEC as u8, link_index(name)? as i32, 0, 0);
+            put_attr(&mut payload, IFLA_NET_NS_FD, &(netns.as_raw_fd() as u32).to_ne_bytes());
+            request(RTM_NEWLINK, 0, &payload)
+        }
+
+        fn address_payload(name: &str, addr: IpAddr, prefix_len: u8) -> Result<Vec<u8>, RlinkError> {
+            let (family, bytes) = match addr {
+                IpAddr::V4(addr) => (libc::AF_INET, addr.octets().to_vec()),
+                IpAddr::V6(addr) => (libc::AF_INET6, addr.octets().to_vec()),
+            };
+            let mut payload = ifaddrmsg(family as u8, prefix_len, link_index(name)?);
+            put_attr(&mut payload, IFA_LOCAL, &bytes);
+            put_attr(&mut payload, IFA_ADDRESS, &bytes);
+            if let IpAddr::V4(addr) = addr {
+                let mask = u32::MAX.checked_shr(prefix_len as u32).unwrap_or(0);
+                let broadcast = u32::from(addr) | mask;
+                put_attr(&mut payload, IFA_BROADCAST, &broadcast.to_be_bytes());
+            }
+            Ok(payload)
+        }
+
+        /// Assign `addr/prefix_len` to a link (see `giveAddr`, which also
+        /// brings the link up).
+        pub fn add_address(name: &str, addr: IpAddr, prefix_len: u8) -> Result<(), RlinkError> {
+            request(RTM_NEWADDR, NLM_F_CREATE | NLM_F_EXCL, &address_payload(name, addr, prefix_len)?)
+        }
+
+        /// Remove `addr/prefix_len` from a link.
+        pub fn del_address(name: &str, addr: IpAddr, prefix_len: u8) -> Result<(), RlinkError> {
+            request(RTM_DELADDR, 0, &address_payload(name, addr, prefix_len)?)
+        }
+    }
+}
@@ -0,0 +1,84 @@
+#![allow(dead_code)]
+#![allow(unused)]
+
+pub mod netns {
+    //! Network namespace handling. Namespaces are referred to by the names
+    //! given to `ip netns add` (i.e. files under `/var/run/netns`). As in the
+    //! `vnetUtils` scripts, a name ending in `ns0` denotes the namespace the
+    //! process was started in.
+    //!
+    //! Switching namespace with `setns(2)` only affects the calling thread.
+    //! Sockets (and thus pcap captures) stay bound to the namespace they were
+    //! created in, so a DeviceHandle opened inside a namespace keeps working
+    //! after the thread switches back.
+
+    use crate::RlinkError;
+    use std::fs::File;
+    use std::os::unix::io::AsRawFd;
+
+    /// An open reference to a network namespace.
+    #[derive(Debug)]
+    pub struct NetNs {
+        file: File,
+        name: String,
+    }
+
+    impl NetNs {
+        /// Open the namespace with the given name.
+        pub fn open(name: &str) -> Result<NetNs, RlinkError> {
+            let path = if name.ends_with("ns0") {
+                String::from("/proc/self/ns/net")
+            }
+            else {
+                format!("/var/run/netns/{}", name)
+            };
+            let file = File::open(&path).map_err(|e| RlinkError::InvalidNetns {
+                name: name.to_string(),
+                source: e,
+            })?;
+            Ok(NetNs { file, name: name.to_string() })
+        }
+
+        /// The namespace of the calling thread.
+        pub fn current() -> Result<NetNs, RlinkError> {
+            Ok(NetNs {
+                file: File::open("/proc/thread-self/ns/net")?,
+                name: String::from("current"),
+            })
+        }
+
+        pub fn name(&self) -> &str {
+            &self.name
+        }
+
+        /// Raw descriptor of the namespace, e.g. for `IFLA_NET_NS_FD`.
+        pub fn as_raw_fd(&self) -> i32 {
+            self.file.as_raw_fd()
+        }
+
+        /// Move the calling thread into this namespace. Requires `CAP_SYS_ADMIN`.
+        pub fn enter(&self) -> Result<(), RlinkError> {
+            if unsafe { libc::setns(self.file.as_raw_fd(), libc::CLONE_NEWNET) } < 0 {
+                return Err(RlinkError::Io(std::io::Error::last_os_error()));
+            }
+            Ok(())
+        }
+    }
+
+    /// Run `f` with the calling thread inside the named namespace, then
+    /// switch back to the original one. If switching back fails, the
+    /// result of `f` is dropped and `NetnsRestore` returned, the thread
+    /// being left in the named namespace.
+    pub fn with_netns<T, F>(name: &str, f: F) -> Result<T, RlinkError>
+    where F: FnOnce() -> Result<T, RlinkError> {
+        let original = NetNs::current()?;
+        NetNs::open(name)?.enter()?;
+        let result = f();
+        // Failing to switch back must not go unnoticed.
+        original.enter().map_err(|e| match e {
+            RlinkError::Io(source) => RlinkError::NetnsRestore { name: name.to_string(), source },
+            e => e,
+        })?;
+        result
+    }
+}
@@ -2,12 +2,15 @@
 #![allow(unused)]
 
 pub mod packet {
-    use pcap::{Packet as _Packet, PacketHeader};
-    use crate::{EtherType, RlinkError};
+    use pcap::{Packet as _Packet, PacketHeader, Precision};
+    use crate::{EtherType, RlinkError, FrameError};
     use std::fmt::{self, write};
     use std::marker::PhantomData;
     use mac_address::MacAddress;
     use crc::{Crc, CRC_32_CKSUM};
+    use crate::stats::stats::DeviceCounters;
+    use std::sync::Arc;
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
     /// A rlink packet. The data is owned compared to pcap::Packet.
     #[derive(Clone, Debug)]
@@ -18,6 +21,11 @@ pub mod packet {
         pub data: Vec<u8>,
         /// MAC address of the device that received this packet
         pub mac_address: MacAddress,
+        /// Counters of the device that received this packet, charged with
+        /// parse failures
+        counters: Option<Arc<DeviceCounters>>,
+        /// Resolution of `header.ts`
+        precision: Precision,
         _marker: PhantomData<T>,
     }
 
@@ -26,25 +34,66 @@ pub mod packet {
         /// checksum is checked.
         pub fn parse_eth(self, checksum: bool) -> Result<Packet<Eth>, RlinkError> {
             if self.data.len() < 64 {
-                return Err(RlinkError::InvalidPacket(self, "packet size too small"));
+                let size = self.data.len();
+                return Err(self.invalid(FrameError::TooSmall { size, min: 64 }));
             }
             
             if checksum {
                 let len = self.data.len();
-                let checksum1 = &self.data[len-4..];
-                let checksum2 = crc::Crc::<u32>::new(&CRC_32_CKSUM)
-                    .checksum(self.data[..len-4].as_ref()).to_be_bytes();
-                if !checksum1.eq(checksum2.as_ref()) {
-                    return Err(RlinkError::InvalidPacket(self, "checksum mismatch"));
+                let actual = u32::from_be_bytes(self.data[len-4..].try_into().unwrap());
+                let expected = crc::Crc::<u32>::new(&CRC_32_CKSUM)
+                    .checksum(self.data[..len-4].as_ref());
+                if actual != expected {
+                    return Err(self.invalid(FrameError::ChecksumMismatch { expected, actual }));
                 }
             }
 
-            Ok(Packet::<Eth> {
-                header: self.header,
-                data: self.data,
-                mac_address: self.mac_address,
-                _marker: PhantomData::<Eth>,
-            })
+            Ok(self.cast())
+        }
+
+        fn invalid(self, error: FrameError) -> RlinkError {
+            if let Some(counters) = self.counters.as_ref() {
+                counters.record_parse_error(&error);
+            }
+            RlinkError::InvalidPacket(self, error)
+        }
+
+        /// Interpret the header timestamp with `precision`, that of the
+        /// capture.
+        pub(crate) fn with_precision(mut self, precision: Precision) -> Self {
+            self.precision = precision;
+            self
+        }
+
+        /// Charge parse failures of this packet to `counters`.
+        pub(crate) fn with_counters(mut self, counters: Arc<DeviceCounters>) -> Self {
+            self.counters = Some(counters);
+            self
+        }
+
+        /// Wraps raw frame bytes that did not come from pcap, e.g. frames
+        /// passed over an in-memory link. The header is stamped with the
+        /// current time.
+        pub fn from_bytes(data: Vec<u8>, addr: MacAddress) -> Packet<Raw> {
+            let now = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap_or_default();
+            let header = PacketHeader {
+                ts: libc::timeval { 
+                    tv_sec: now.as_secs() as libc::time_t, 
+                    tv_usec: now.subsec_micros() as libc::suseconds_t,
+                },
+                caplen: data.len() as u32,
+                len: data.len() as u32,
+            };
+            Packet::<Raw> {
+                header,
+                data,
+                mac_address: addr,
+                counters: None,
+                precision: Precision::Micro,
+                _marker: PhantomData::<Raw>,
+            }
         }
 
         pub fn from(packet: _Packet, addr: MacAddress) -> Packet<Raw> {
@@ -52,6 +101,8 @@ pub mod packet {
                 header: packet.header.to_owned(),
                 data: packet.data.to_owned(),
                 mac_address: addr,
+                counters: None,
+                precision: Precision::Micro,
                 _marker: PhantomData::<Raw>,
             }
         }
@@ -66,6 +117,16 @@ pub mod packet {
             self.data[6..12].try_into().unwrap()
         }
 
+        /// Destination MAC address of the frame.
+        pub fn dst_mac(&self) -> MacAddress {
+            MacAddress::new(*self.dst_addr())
+        }
+
+        /// Source MAC address of the frame.
+        pub fn src_mac(&self) -> MacAddress {
+            MacAddress::new(*self.src_addr())
+        }
+
         pub fn ethtype(&self) -> EtherType {
             let type_field = &self.data[12..14];
             // Big Endian
@@ -78,6 +139,46 @@ pub mod packet {
         }
     }
 
+    impl<T: Type> Packet<T> {
+        /// Reinterpret the packet as another layer. Parsers of upper layers
+        /// use this once the content has been validated.
+        pub(crate) fn cast<U: Type>(self) -> Packet<U> {
+            Packet::<U> {
+                header: self.header,
+                data: self.data,
+                mac_address: self.mac_address,
+                counters: self.counters,
+                precision: self.precision,
+                _marker: PhantomData::<U>,
+            }
+        }
+
+        /// Time the packet was captured, by the clock and at the
+        /// resolution the capture was opened with (see
+        /// `DeviceHandleBuilder`). Host timestamps, the default, follow the
+        /// system clock, as `SystemTime::now()` and the TX timestamps of
+        /// `DeviceHandle::send_packet_timestamped()` do.
+        pub fn timestamp(&self) -> SystemTime {
+            let fraction = match self.precision {
+                Precision::Micro => Duration::from_micros(self.header.ts.tv_usec as u64),
+                Precision::Nano => Duration::from_nanos(self.header.ts.tv_usec as u64),
+            };
+            let secs = Duration::from_secs(self.header.ts.tv_sec.unsigned_abs());
+            if self.header.ts.tv_sec < 0 {
+                UNIX_EPOCH - secs + fraction
+            }
+            else {
+                UNIX_EPOCH + secs + fraction
+            }
+        }
+
+        /// Resolution of `timestamp()`, and of `header.ts.tv_usec`, which
+        /// holds nanoseconds with `Precision::Nano`.
+        pub fn precision(&self) -> Precision {
+            self.precision
+        }
+    }
+
     impl fmt::Display for Packet<Raw> {
         fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
             
@@ -102,13 +203,8 @@ pub mod packet {
             write! (f, "{:?}\n", self.header);
             write! (f, "{:?}\n", self.mac_address);
 
-            let dst_addr = self.dst_addr();
-            write! (f, "dst_addr: {:0>2X}:{:0>2X}:{:0>2X}:{:0>2X}:{:0>2X}:{:0>2X}\n", 
-                dst_addr[0], dst_addr[1], dst_addr[2], dst_addr[3], dst_addr[4], dst_addr[5]);
-            
-            let src_addr = self.src_addr();
-            write! (f, "src_addr: {:0>2X}:{:0>2X}:{:0>2X}:{:0>2X}:{:0>2X}:{:0>2X}\n", 
-                src_addr[0], src_addr[1], src_addr[2], src_addr[3], src_addr[4], src_addr[5]);
+            writeln! (f, "dst_addr: {}", self.dst_mac());
+            writeln! (f, "src_addr: {}", self.src_mac());
 
             let ethtype = self.ethtype();
             write! (f, "ether type: 0x{:0>4X} ({})\n", <EtherType as Into<u16>>::into(ethtype), ethtype);
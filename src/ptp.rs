@@ -0,0 +1,659 @@
+#![allow(dead_code)]
+#![allow(unused)]
+
+pub mod ptp {
+    //! Precision Time Protocol (IEEE 1588-2008) over Ethernet: message
+    //! encoding, and a port measuring the offset and path delay between a
+    //! master and a slave clock with the two-step delay request-response
+    //! mechanism:
+    //!
+    //! ```text
+    //!   master            slave
+    //!     t1 |--- Sync ----->| t2
+    //!        |-- Follow_Up ->|      (t1)
+    //!     t4 |<- Delay_Req --| t3
+    //!        |- Delay_Resp ->|      (t4)
+    //! ```
+    //!
+    //! offset = ((t2 - t1) - (t4 - t3)) / 2, and
+    //! path delay = ((t2 - t1) + (t4 - t3)) / 2.
+    //!
+    //! Roles are fixed, without Announce messages nor best master clock
+    //! selection, and the clocks are not corrected: the slave reports its
+    //! measurements. Timestamps are those of the capture and of
+    //! `DeviceHandle::send_packet_timestamped()`, taken in software by the
+    //! system clock. Network namespaces share that clock, so a port may run
+    //! a clock offset from it (`PtpConfig::clock_offset`), which the slave
+    //! then measures.
+
+    use crate::{DeviceHandle, Direction, EtherType, Eth, FrameError, Packet, PError, RlinkError};
+    use mac_address::MacAddress;
+    use std::fmt;
+    use std::sync::{Arc, Condvar, Mutex, Weak};
+    use std::thread;
+    use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+    /// Destination of all the messages but peer delay ones, forwarded by
+    /// bridges.
+    pub const PRIMARY_MULTICAST: [u8; 6] = [0x01, 0x1b, 0x19, 0x00, 0x00, 0x00];
+    /// Version of the protocol, IEEE 1588-2008.
+    pub const VERSION: u8 = 2;
+    /// Length of the common header.
+    pub const HEADER_LEN: usize = 34;
+    /// Timestamps are 48-bit seconds and 32-bit nanoseconds.
+    const TIMESTAMP_LEN: usize = 10;
+    const PORT_IDENTITY_LEN: usize = 10;
+    /// Flag of Sync messages followed by a Follow_Up.
+    const TWO_STEP: u16 = 0x0200;
+    /// logMessageInterval of messages sent on demand.
+    const NO_INTERVAL: i8 = 0x7f;
+
+    /// Default interval between Sync messages.
+    pub const SYNC_INTERVAL: Duration = Duration::from_secs(1);
+    /// Default number of measurements kept by a slave.
+    pub const HISTORY: usize = 1024;
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub enum MessageType {
+        Sync = 0x0,
+        DelayReq = 0x1,
+        FollowUp = 0x8,
+        DelayResp = 0x9,
+    }
+
+    impl MessageType {
+        fn from_u8(value: u8) -> Option<MessageType> {
+            match value {
+                0x0 => Some(MessageType::Sync),
+                0x1 => Some(MessageType::DelayReq),
+                0x8 => Some(MessageType::FollowUp),
+                0x9 => Some(MessageType::DelayResp),
+                _ => None,
+            }
+        }
+
+        /// The controlField of IEEE 1588-2005, still set for compatibility.
+        fn control(self) -> u8 {
+            match self {
+                MessageType::Sync => 0,
+                MessageType::DelayReq => 1,
+                MessageType::FollowUp => 2,
+                MessageType::DelayResp => 3,
+            }
+        }
+    }
+
+    /// Identity of a clock, derived from a MAC address (EUI-64).
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+    pub struct ClockIdentity(pub [u8; 8]);
+
+    impl ClockIdentity {
+        pub fn from_mac(mac: &MacAddress) -> ClockIdentity {
+            let b = mac.bytes();
+            ClockIdentity([b[0], b[1], b[2], 0xff, 0xfe, b[3], b[4], b[5]])
+        }
+    }
+
+    impl fmt::Display for ClockIdentity {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            let b = self.0;
+            write!(f, "{:02x}{:02x}{:02x}.{:02x}{:02x}.{:02x}{:02x}{:02x}", b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7])
+        }
+    }
+
+    /// Identity of a port of a clock.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+    pub struct PortIdentity {
+        pub clock: ClockIdentity,
+        /// Starts at 1
+        pub port: u16,
+    }
+
+    impl PortIdentity {
+        fn encode(&self, out: &mut Vec<u8>) {
+            out.extend_from_slice(&self.clock.0);
+            out.extend_from_slice(&self.port.to_be_bytes());
+        }
+
+        fn decode(data: &[u8]) -> PortIdentity {
+            PortIdentity {
+                clock: ClockIdentity(data[..8].try_into().unwrap()),
+                port: u16::from_be_bytes([data[8], data[9]]),
+            }
+        }
+    }
+
+    impl fmt::Display for PortIdentity {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "{}-{}", self.clock, self.port)
+        }
+    }
+
+    /// A time since the epoch of the clock.
+    #[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+    pub struct Timestamp {
+        /// 48 bits on the wire
+        pub seconds: u64,
+        pub nanoseconds: u32,
+    }
+
+    impl Timestamp {
+        /// Nanoseconds before the epoch are clamped to it.
+        pub fn from_nanos(nanos: i128) -> Timestamp {
+            let nanos = nanos.max(0);
+            Timestamp {
+                seconds: (nanos / 1_000_000_000) as u64,
+                nanoseconds: (nanos % 1_000_000_000) as u32,
+            }
+        }
+
+        pub fn as_nanos(&self) -> i128 {
+            self.seconds as i128 * 1_000_000_000 + self.nanoseconds as i128
+        }
+
+        fn encode(&self, out: &mut Vec<u8>) {
+            out.extend_from_slice(&self.seconds.to_be_bytes()[2..]);
+            out.extend_from_slice(&self.nanoseconds.to_be_bytes());
+        }
+
+        fn decode(data: &[u8]) -> Timestamp {
+            let mut seconds = [0u8; 8];
+            seconds[2..].copy_from_slice(&data[..6]);
+            Timestamp {
+                seconds: u64::from_be_bytes(seconds),
+                nanoseconds: u32::from_be_bytes(data[6..10].try_into().unwrap()),
+            }
+        }
+    }
+
+    impl From<SystemTime> for Timestamp {
+        fn from(time: SystemTime) -> Self {
+            let since = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+            Timestamp { seconds: since.as_secs(), nanoseconds: since.subsec_nanos() }
+        }
+    }
+
+    impl fmt::Display for Timestamp {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "{}.{:09}", self.seconds, self.nanoseconds)
+        }
+    }
+
+    /// A PTP message of the delay request-response mechanism.
+    #[derive(Clone, Debug, PartialEq, Eq)]
+    pub struct Message {
+        pub message_type: MessageType,
+        pub domain: u8,
+        /// Set on Sync messages followed by a Follow_Up
+        pub two_step: bool,
+        /// Residence and path corrections, in units of 2^-16 ns
+        pub correction: i64,
+        pub source: PortIdentity,
+        pub sequence_id: u16,
+        /// Log2 of the interval between messages, in seconds
+        pub log_interval: i8,
+        /// originTimestamp, preciseOriginTimestamp of Follow_Up, or
+        /// receiveTimestamp of Delay_Resp
+        pub timestamp: Timestamp,
+        /// Port whose Delay_Req is answered, in Delay_Resp
+        pub requesting: Option<PortIdentity>,
+    }
+
+    impl Message {
+        pub fn new(message_type: MessageType, domain: u8, source: PortIdentity, sequence_id: u16) -> Message {
+            Message {
+                message_type,
+                domain,
+                two_step: false,
+                correction: 0,
+                source,
+                sequence_id,
+                log_interval: NO_INTERVAL,
+                timestamp: Timestamp::default(),
+                requesting: None,
+            }
+        }
+
+        /// `timestamp` corrected by the correction field, in nanoseconds:
+        /// plus the correction of Sync and Follow_Up, minus that of
+        /// Delay_Resp.
+        pub fn corrected_nanos(&self) -> i128 {
+            let correction = (self.correction >> 16) as i128;
+            match self.message_type {
+                MessageType::DelayResp => self.timestamp.as_nanos() - correction,
+                _ => self.timestamp.as_nanos() + correction,
+            }
+        }
+
+        pub fn encode(&self) -> Vec<u8> {
+            let len = HEADER_LEN + TIMESTAMP_LEN
+                + if self.message_type == MessageType::DelayResp { PORT_IDENTITY_LEN } else { 0 };
+            let mut data = Vec::with_capacity(len);
+            data.push(self.message_type as u8);
+            data.push(VERSION);
+            data.extend_from_slice(&(len as u16).to_be_bytes());
+            data.push(self.domain);
+            data.push(0);
+            data.extend_from_slice(&(if self.two_step { TWO_STEP } else { 0 }).to_be_bytes());
+            data.extend_from_slice(&self.correction.to_be_bytes());
+            data.extend_from_slice(&[0; 4]);
+            self.source.encode(&mut data);
+            data.extend_from_slice(&self.sequence_id.to_be_bytes());
+            data.push(self.message_type.control());
+            data.push(self.log_interval as u8);
+            self.timestamp.encode(&mut data);
+            if self.message_type == MessageType::DelayResp {
+                self.requesting.unwrap_or(self.source).encode(&mut data);
+            }
+            data
+        }
+
+        /// Decodes a message of the delay request-response mechanism. The
+        /// data may be padded beyond the message length.
+        pub fn decode(data: &[u8]) -> Result<Message, FrameError> {
+            if data.len() < HEADER_LEN {
+                return Err(FrameError::Truncated { offset: 0, needed: HEADER_LEN, available: data.len() });
+            }
+            let message_type = MessageType::from_u8(data[0] & 0x0f)
+                .ok_or(FrameError::InvalidField { offset: 0, field: "PTP message type" })?;
+            if data[1] & 0x0f != VERSION {
+                return Err(FrameError::InvalidField { offset: 1, field: "PTP version" });
+            }
+            let needed = HEADER_LEN + TIMESTAMP_LEN
+                + if message_type == MessageType::DelayResp { PORT_IDENTITY_LEN } else { 0 };
+            let len = u16::from_be_bytes([data[2], data[3]]) as usize;
+            if len < needed {
+                return Err(FrameError::InvalidField { offset: 2, field: "PTP message length" });
+            }
+            if data.len() < len {
+                return Err(FrameError::Truncated { offset: 0, needed: len, available: data.len() });
+            }
+            Ok(Message {
+                message_type,
+                domain: data[4],
+                two_step: u16::from_be_bytes([data[6], data[7]]) & TWO_STEP != 0,
+                correction: i64::from_be_bytes(data[8..16].try_into().unwrap()),
+                source: PortIdentity::decode(&data[20..30]),
+                sequence_id: u16::from_be_bytes([data[30], data[31]]),
+                log_interval: data[33] as i8,
+                timestamp: Timestamp::decode(&data[HEADER_LEN..]),
+                requesting: match message_type {
+                    MessageType::DelayResp => Some(PortIdentity::decode(&data[HEADER_LEN + TIMESTAMP_LEN..])),
+                    _ => None,
+                },
+            })
+        }
+    }
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub enum Role {
+        /// Sends Sync messages and answers Delay_Req
+        Master,
+        /// Follows the first master heard, and measures its offset
+        Slave,
+    }
+
+    #[derive(Clone, Debug)]
+    pub struct PtpConfig {
+        pub domain: u8,
+        /// Interval between Sync messages of a master
+        pub sync_interval: Duration,
+        /// Nanoseconds the clock of the port runs ahead of the system
+        /// clock, to emulate unsynchronized clocks
+        pub clock_offset: i64,
+        /// Measurements kept by a slave
+        pub history: usize,
+    }
+
+    impl Default for PtpConfig {
+        fn default() -> Self {
+            PtpConfig {
+                domain: 0,
+                sync_interval: SYNC_INTERVAL,
+                clock_offset: 0,
+                history: HISTORY,
+            }
+        }
+    }
+
+    /// One exchange of the delay request-response mechanism, by a slave.
+    /// Times are in nanoseconds since the epoch of each clock.
+    #[derive(Clone, Debug, PartialEq, Eq)]
+    pub struct Measurement {
+        /// Sequence ID of the Sync
+        pub sequence_id: u16,
+        pub master: PortIdentity,
+        /// Sync sent, by the master clock
+        pub t1: i128,
+        /// Sync received, by the slave clock
+        pub t2: i128,
+        /// Delay_Req sent, by the slave clock
+        pub t3: i128,
+        /// Delay_Req received, by the master clock
+        pub t4: i128,
+    }
+
+    impl Measurement {
+        /// Nanoseconds the slave clock is ahead of the master clock.
+        pub fn offset(&self) -> i64 {
+            (((self.t2 - self.t1) - (self.t4 - self.t3)) / 2) as i64
+        }
+
+        /// Mean of the delays of both directions, in nanoseconds.
+        pub fn path_delay(&self) -> i64 {
+            (((self.t2 - self.t1) + (self.t4 - self.t3)) / 2) as i64
+        }
+    }
+
+    impl fmt::Display for Measurement {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "seq {} from {}: offset {} ns, path delay {} ns",
+                self.sequence_id, self.master, self.offset(), self.path_delay())
+        }
+    }
+
+    /// Message counters of a port.
+    #[derive(Clone, Debug, Default, PartialEq, Eq)]
+    pub struct PtpStats {
+        pub sent: u64,
+        pub received: u64,
+        /// Messages of other domains or masters, or out of sequence
+        pub ignored: u64,
+        /// Frames failing to decode, or to send
+        pub errors: u64,
+    }
+
+    struct State {
+        stats: PtpStats,
+        master: Option<PortIdentity>,
+        measurements: Vec<Measurement>,
+        /// Measurements made, including those dropped from the history
+        total: u64,
+        /// Set when the capture fails
+        closed: bool,
+    }
+
+    struct Shared {
+        state: Mutex<State>,
+        measured: Condvar,
+    }
+
+    /// A PTP port running on a device, from a background thread. The
+    /// thread stops when the port is dropped.
+    pub struct PtpPort {
+        identity: PortIdentity,
+        role: Role,
+        shared: Arc<Shared>,
+        /// Measurements returned by `next_measurement()`
+        cursor: Mutex<u64>,
+    }
+
+    impl PtpPort {
+        /// Run a port on `handle`, which should have a read timeout well
+        /// below the Sync interval, and nanosecond precision for accurate
+        /// measurements (see `DeviceHandleBuilder`). Frames sent from the
+        /// device itself are not captured anymore.
+        pub fn start(handle: DeviceHandle, role: Role, config: PtpConfig) -> Result<PtpPort, RlinkError> {
+            if config.sync_interval.is_zero() || config.history == 0 {
+                return Err(RlinkError::InvalidConfig("PTP port without interval or history".to_string()));
+            }
+            handle.direction(Direction::In)?;
+            let identity = PortIdentity { clock: ClockIdentity::from_mac(handle.mac_address()), port: 1 };
+            let shared = Arc::new(Shared {
+                state: Mutex::new(State {
+                    stats: PtpStats::default(),
+                    master: None,
+                    measurements: Vec::new(),
+                    total: 0,
+                    closed: false,
+                }),
+                measured: Condvar::new(),
+            });
+            let mut worker = Worker {
+                handle,
+                identity,
+                role,
+                config,
+                shared: Arc::downgrade(&shared),
+                sequence_id: 0,
+                next_sync: Instant::now(),
+                sync: None,
+                request: None,
+            };
+            thread::spawn(move || worker.run());
+            Ok(PtpPort { identity, role, shared, cursor: Mutex::new(0) })
+        }
+
+        pub fn identity(&self) -> PortIdentity {
+            self.identity
+        }
+
+        pub fn role(&self) -> Role {
+            self.role
+        }
+
+        pub fn stats(&self) -> PtpStats {
+            self.shared.state.lock().unwrap().stats.clone()
+        }
+
+        /// The master followed by a slave, once heard.
+        pub fn master(&self) -> Option<PortIdentity> {
+            self.shared.state.lock().unwrap().master
+        }
+
+        /// Measurements of a slave, oldest first.
+        pub fn measurements(&self) -> Vec<Measurement> {
+            self.shared.state.lock().unwrap().measurements.clone()
+        }
+
+        /// Wait at most `timeout` for the measurement following the one
+        /// this method returned last, or for the first one. Measurements
+        /// dropped from the history meanwhile are skipped. Fails once the
+        /// capture has stopped.
+        pub fn next_measurement(&self, timeout: Duration) -> Result<Option<Measurement>, RlinkError> {
+            let deadline = Instant::now() + timeout;
+            let mut cursor = self.cursor.lock().unwrap();
+            let mut state = self.shared.state.lock().unwrap();
+            loop {
+                if state.total > *cursor {
+                    let oldest = state.total - state.measurements.len() as u64;
+                    let next = (*cursor).max(oldest);
+                    *cursor = next + 1;
+                    return Ok(Some(state.measurements[(next - oldest) as usize].clone()));
+                }
+                if state.closed {
+                    return Err(RlinkError::BrokenDevicePool);
+                }
+                let now = Instant::now();
+                if now >= deadline {
+                    return Ok(None);
+                }
+                state = self.shared.measured.wait_timeout(state, deadline - now).unwrap().0;
+            }
+        }
+    }
+
+    /// A two-step Sync received by a slave, awaiting its Follow_Up.
+    struct PendingSync {
+        sequence_id: u16,
+        master: PortIdentity,
+        t2: i128,
+    }
+
+    /// A Delay_Req of a slave awaiting its response.
+    struct PendingRequest {
+        sequence_id: u16,
+        sync_sequence_id: u16,
+        master: PortIdentity,
+        t1: i128,
+        t2: i128,
+        t3: i128,
+    }
+
+    /// The thread owning the device.
+    struct Worker {
+        handle: DeviceHandle,
+        identity: PortIdentity,
+        role: Role,
+        config: PtpConfig,
+        shared: Weak<Shared>,
+        sequence_id: u16,
+        next_sync: Instant,
+        sync: Option<PendingSync>,
+        request: Option<PendingRequest>,
+    }
+
+    impl Worker {
+        /// Nanoseconds of the port clock at `time` of the system clock.
+        fn clock(&self, time: SystemTime) -> i128 {
+            Timestamp::from(time).as_nanos() + self.config.clock_offset as i128
+        }
+
+        fn log_interval(&self) -> i8 {
+            self.config.sync_interval.as_secs_f64().log2().round() as i8
+        }
+
+        fn run(&mut self) {
+            while let Some(shared) = self.shared.upgrade() {
+                if self.role == Role::Master && Instant::now() >= self.next_sync {
+                    self.next_sync += self.config.sync_interval;
+                    self.send_sync(&shared);
+                }
+                let packet = match self.handle.next_packet() {
+                    Ok(Some(packet)) => packet,
+                    Ok(None) | Err(PError::TimeoutExpired) => continue,
+                    Err(_) => {
+                        shared.state.lock().unwrap().closed = true;
+                        shared.measured.notify_all();
+                        break;
+                    }
+                };
+                let frame = match packet.parse_eth(false) {
+                    Ok(frame) if frame.ethtype() == EtherType::PTP => frame,
+                    _ => continue,
+                };
+                let received = self.clock(frame.timestamp());
+                match Message::decode(frame.data()) {
+                    Ok(message) => {
+                        shared.state.lock().unwrap().stats.received += 1;
+                        if message.domain != self.config.domain || !self.input(&shared, message, received) {
+                            shared.state.lock().unwrap().stats.ignored += 1;
+                        }
+                    }
+                    Err(_) => shared.state.lock().unwrap().stats.errors += 1,
+                }
+            }
+        }
+
+        /// Send a message, returning the time it was sent by the port clock.
+        fn send(&mut self, shared: &Shared, message: &Message) -> Option<i128> {
+            let sent = self.handle.send_packet_timestamped(message.encode(), EtherType::PTP, &PRIMARY_MULTICAST, true);
+            let mut state = shared.state.lock().unwrap();
+            match sent {
+                Ok(Some(time)) => {
+                    state.stats.sent += 1;
+                    drop(state);
+                    Some(self.clock(time))
+                }
+                _ => {
+                    state.stats.errors += 1;
+                    None
+                }
+            }
+        }
+
+        fn send_sync(&mut self, shared: &Shared) {
+            self.sequence_id = self.sequence_id.wrapping_add(1);
+            let mut sync = Message::new(MessageType::Sync, self.config.domain, self.identity, self.sequence_id);
+            sync.two_step = true;
+            sync.log_interval = self.log_interval();
+            sync.timestamp = Timestamp::from_nanos(self.clock(SystemTime::now()));
+            if let Some(t1) = self.send(shared, &sync) {
+                let mut follow_up = Message::new(MessageType::FollowUp, self.config.domain, self.identity, self.sequence_id);
+                follow_up.log_interval = sync.log_interval;
+                follow_up.timestamp = Timestamp::from_nanos(t1);
+                self.send(shared, &follow_up);
+            }
+        }
+
+        /// Handle a message of the domain, received at `received` by the
+        /// port clock. Returns whether the message was expected.
+        fn input(&mut self, shared: &Shared, message: Message, received: i128) -> bool {
+            match (self.role, message.message_type) {
+                (Role::Master, MessageType::DelayReq) => {
+                    let mut response = Message::new(MessageType::DelayResp, self.config.domain, self.identity,
+                        message.sequence_id);
+                    response.log_interval = self.log_interval();
+                    response.timestamp = Timestamp::from_nanos(received);
+                    response.requesting = Some(message.source);
+                    self.send(shared, &response);
+                    true
+                }
+                (Role::Slave, MessageType::Sync) => {
+                    let master = *shared.state.lock().unwrap().master.get_or_insert(message.source);
+                    if message.source != master {
+                        return false;
+                    }
+                    if message.two_step {
+                        self.sync = Some(PendingSync { sequence_id: message.sequence_id, master, t2: received });
+                    }
+                    else {
+                        self.sync = None;
+                        self.request_delay(shared, master, message.sequence_id, message.corrected_nanos(), received);
+                    }
+                    true
+                }
+                (Role::Slave, MessageType::FollowUp) => {
+                    match self.sync.take() {
+                        Some(sync) if sync.master == message.source && sync.sequence_id == message.sequence_id => {
+                            self.request_delay(shared, sync.master, sync.sequence_id, message.corrected_nanos(), sync.t2);
+                            true
+                        }
+                        _ => false,
+                    }
+                }
+                (Role::Slave, MessageType::DelayResp) => {
+                    let identity = self.identity;
+                    let matches = |request: &PendingRequest| request.master == message.source
+                        && request.sequence_id == message.sequence_id
+                        && message.requesting == Some(identity);
+                    match self.request.take() {
+                        Some(request) if matches(&request) => {
+                            let measurement = Measurement {
+                                sequence_id: request.sync_sequence_id,
+                                master: request.master,
+                                t1: request.t1,
+                                t2: request.t2,
+                                t3: request.t3,
+                                t4: message.corrected_nanos(),
+                            };
+                            let mut state = shared.state.lock().unwrap();
+                            if state.measurements.len() == self.config.history {
+                                state.measurements.remove(0);
+                            }
+                            state.measurements.push(measurement);
+                            state.total += 1;
+                            shared.measured.notify_all();
+                            true
+                        }
+                        request => {
+                            // Responses to other slaves leave ours pending
+                            self.request = request;
+                            false
+                        }
+                    }
+                }
+                _ => false,
+            }
+        }
+
+        fn request_delay(&mut self, shared: &Shared, master: PortIdentity, sync_sequence_id: u16, t1: i128, t2: i128) {
+            self.sequence_id = self.sequence_id.wrapping_add(1);
+            let mut request = Message::new(MessageType::DelayReq, self.config.domain, self.identity, self.sequence_id);
+            request.timestamp = Timestamp::from_nanos(self.clock(SystemTime::now()));
+            if let Some(t3) = self.send(shared, &request) {
+                self.request = Some(PendingRequest { sequence_id: self.sequence_id, sync_sequence_id, master, t1, t2, t3 });
+            }
+        }
+    }
+}
@@ -0,0 +1,475 @@
+#![allow(dead_code)]
+#![allow(unused)]
+
+pub mod qos {
+    //! Egress scheduling. An `EgressScheduler` wraps the sink of a device
+    //! and queues outgoing frames by class instead of transmitting them
+    //! right away. Frames are classified by VLAN PCP, IP DSCP, IP protocol
+    //! or EtherType, and the queues are served by strict priority or
+    //! weighted round-robin, each optionally shaped by a token bucket, so
+    //! that control traffic (ARP, NDP, LLDP) is not starved by bulk data.
+    //!
+    //! A scheduler is installed on a link of a stack with
+    //! `Stack::set_egress()`, or used directly as a `FrameSink`.
+
+    use crate::stack::stack::FrameSink;
+    use crate::{EtherType, RlinkError};
+    use mac_address::MacAddress;
+    use std::collections::VecDeque;
+    use std::ops::RangeInclusive;
+    use std::sync::{Arc, Condvar, Mutex};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    /// Frames queued per queue before dropping, by default.
+    pub const DEFAULT_LIMIT: usize = 256;
+
+    /// Bytes a frame carrying `payload_len` bytes occupies on the wire,
+    /// Ethernet header, padding and FCS included.
+    pub fn wire_len(payload_len: usize) -> usize {
+        (payload_len + 14).max(60) + 4
+    }
+
+    /// A token bucket, filled at a constant rate up to its burst size.
+    /// A frame may be sent once the bucket holds as many bytes as the frame
+    /// (or is full, for frames larger than the burst size); sending it may
+    /// leave the bucket in debt.
+    #[derive(Clone, Debug)]
+    pub struct TokenBucket {
+        /// Bytes per second
+        rate: f64,
+        burst: f64,
+        tokens: f64,
+        last: Instant,
+    }
+
+    impl TokenBucket {
+        /// A full bucket of `burst` bytes filled at `bits_per_sec`.
+        pub fn new(bits_per_sec: u64, burst: usize) -> Self {
+            TokenBucket {
+                rate: bits_per_sec as f64 / 8.0,
+                burst: burst as f64,
+                tokens: burst as f64,
+                last: Instant::now(),
+            }
+        }
+
+        pub fn bits_per_sec(&self) -> u64 {
+            (self.rate * 8.0) as u64
+        }
+
+        pub fn burst(&self) -> usize {
+            self.burst as usize
+        }
+
+        fn refill(&mut self, now: Instant) {
+            if now > self.last {
+                let elapsed = now.duration_since(self.last).as_secs_f64();
+                self.tokens = (self.tokens + elapsed * self.rate).min(self.burst);
+                self.last = now;
+            }
+        }
+
+        /// Time to wait before `bytes` may be sent, zero if they may be sent
+        /// now.
+        pub fn delay(&mut self, bytes: usize, now: Instant) -> Duration {
+            self.refill(now);
+            let needed = (bytes as f64).min(self.burst);
+            if self.tokens >= needed {
+                Duration::ZERO
+            }
+            else if self.rate <= 0.0 {
+                Duration::MAX
+            }
+            else {
+                Duration::from_secs_f64((needed - self.tokens) / self.rate)
+            }
+        }
+
+        /// Take `bytes` out of the bucket.
+        pub fn consume(&mut self, bytes: usize, now: Instant) {
+            self.refill(now);
+            self.tokens -= bytes as f64;
+        }
+    }
+
+    /// A criterion on outgoing frames.
+    #[derive(Clone, Debug, PartialEq, Eq)]
+    pub enum Match {
+        Any,
+        EtherType(EtherType),
+        /// Priority Code Point of 802.1Q tagged frames
+        Pcp(RangeInclusive<u8>),
+        /// DSCP of IPv4 and IPv6 datagrams, looked up past a VLAN tag
+        Dscp(RangeInclusive<u8>),
+        /// IPv4 protocol or IPv6 next header, looked up past a VLAN tag
+        IpProtocol(u8),
+    }
+
+    impl Match {
+        pub fn matches(&self, ethtype: EtherType, payload: &[u8]) -> bool {
+            // Look past a VLAN tag
+            let (inner, inner_payload) = match (ethtype, payload.len() >= 4) {
+                (EtherType::VLAN, true) =>
+                    (EtherType::from(u16::from_be_bytes([payload[2], payload[3]])), &payload[4..]),
+                _ => (ethtype, payload),
+            };
+            match self {
+                Match::Any => true,
+                Match::EtherType(expected) => ethtype == *expected || inner == *expected,
+                Match::Pcp(range) => ethtype == EtherType::VLAN && !payload.is_empty()
+                    && range.contains(&(payload[0] >> 5)),
+                Match::Dscp(range) => dscp(inner, inner_payload).is_some_and(|dscp| range.contains(&dscp)),
+                Match::IpProtocol(protocol) => ip_protocol(inner, inner_payload) == Some(*protocol),
+            }
+        }
+    }
+
+    fn dscp(ethtype: EtherType, payload: &[u8]) -> Option<u8> {
+        match ethtype {
+            EtherType::IPv4 if payload.len() >= 20 => Some(payload[1] >> 2),
+            EtherType::IPv6 if payload.len() >= 40 => Some(((payload[0] & 0x0F) << 2) | (payload[1] >> 6)),
+            _ => None,
+        }
+    }
+
+    fn ip_protocol(ethtype: EtherType, payload: &[u8]) -> Option<u8> {
+        match ethtype {
+            EtherType::IPv4 if payload.len() >= 20 => Some(payload[9]),
+            EtherType::IPv6 if payload.len() >= 40 => Some(payload[6]),
+            _ => None,
+        }
+    }
+
+    /// How queues take turns.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub enum Scheduling {
+        /// Always serve the first queue holding a frame it may send.
+        StrictPriority,
+        /// Serve the queues in turn, up to `weight` frames each.
+        WeightedRoundRobin,
+    }
+
+    /// Configuration of one queue.
+    #[derive(Clone, Debug)]
+    pub struct QueueConfig {
+        /// Frames served per round under `WeightedRoundRobin`
+        pub weight: u32,
+        /// Frames queued before dropping
+        pub limit: usize,
+        /// Shaping of the queue: bit rate and burst size in bytes
+        pub rate: Option<(u64, usize)>,
+    }
+
+    impl Default for QueueConfig {
+        fn default() -> Self {
+            QueueConfig { weight: 1, limit: DEFAULT_LIMIT, rate: None }
+        }
+    }
+
+    /// Configuration of a scheduler.
+    #[derive(Clone, Debug)]
+    pub struct QosConfig {
+        pub scheduling: Scheduling,
+        pub queues: Vec<QueueConfig>,
+        /// Rules mapping frames to queues; the first match wins.
+        pub classes: Vec<(Match, usize)>,
+        /// Queue of frames matching no rule
+        pub default_queue: usize,
+        /// Shaping of the whole port: bit rate and burst size in bytes
+        pub rate: Option<(u64, usize)>,
+    }
+
+    impl QosConfig {
+        /// `queues` queues with default settings, all traffic going to
+        /// the last.
+        pub fn new(scheduling: Scheduling, queues: usize) -> Self {
+            QosConfig {
+                scheduling,
+                queues: vec![QueueConfig::default(); queues],
+                classes: Vec::new(),
+                default_queue: queues.saturating_sub(1),
+                rate: None,
+            }
+        }
+
+        /// Two queues in strict priority: ARP, LLDP, ICMPv6 (NDP), network
+        /// control DSCPs (CS6, CS7) and PCPs 6 and 7 first, then the rest.
+        pub fn control_first() -> Self {
+            let mut config = QosConfig::new(Scheduling::StrictPriority, 2);
+            config.classes = vec![
+                (Match::EtherType(EtherType::ARP), 0),
+                (Match::EtherType(EtherType::LLDP), 0),
+                (Match::IpProtocol(58), 0),
+                (Match::Dscp(48..=63), 0),
+                (Match::Pcp(6..=7), 0),
+            ];
+            config
+        }
+
+        pub fn classify(&self, ethtype: EtherType, payload: &[u8]) -> usize {
+            self.classes.iter()
+                .find(|(rule, _)| rule.matches(ethtype, payload))
+                .map_or(self.default_queue, |(_, queue)| *queue)
+        }
+
+        /// Check that the rules and the default queue refer to existing
+        /// queues, and that queues have a weight and some room.
+        pub fn validate(&self) -> Result<(), RlinkError> {
+            let invalid = |why: String| Err(RlinkError::InvalidConfig(why));
+            if self.queues.is_empty() {
+                return invalid("no queue".to_string());
+            }
+            for (index, queue) in self.queues.iter().enumerate() {
+                if queue.weight == 0 || queue.limit == 0 {
+                    return invalid(format!("queue {} has no weight or no room", index));
+                }
+            }
+            let n = self.queues.len();
+            match self.classes.iter().find(|(_, queue)| *queue >= n) {
+                Some((_, queue)) => invalid(format!("no queue {}", queue)),
+                None if self.default_queue >= n => invalid(format!("no queue {}", self.default_queue)),
+                None => Ok(()),
+            }
+        }
+    }
+
+    /// Counters of a queue.
+    #[derive(Clone, Debug, Default, PartialEq, Eq)]
+    pub struct QueueStats {
+        pub enqueued: u64,
+        pub sent: u64,
+        pub sent_bytes: u64,
+        /// Frames dropped because the queue was full
+        pub dropped: u64,
+        /// Frames the sink failed to send
+        pub errors: u64,
+        /// Frames currently queued
+        pub queued: usize,
+    }
+
+    struct Frame {
+        payload: Vec<u8>,
+        ethtype: EtherType,
+        dst: MacAddress,
+    }
+
+    struct Queue {
+        config: QueueConfig,
+        frames: VecDeque<Frame>,
+        bucket: Option<TokenBucket>,
+        stats: QueueStats,
+    }
+
+    /// What the scheduler does next.
+    enum Next {
+        Send(usize, Frame),
+        /// Nothing may be sent before the given time, if any.
+        Wait(Option<Instant>),
+    }
+
+    struct State {
+        scheduling: Scheduling,
+        queues: Vec<Queue>,
+        bucket: Option<TokenBucket>,
+        /// Queue served by round-robin, with the frames it has left.
+        cursor: usize,
+        credit: u32,
+        closed: bool,
+    }
+
+    impl State {
+        /// Whether the head frame of queue `index` may be sent at `now`,
+        /// else when it may.
+        fn ready(&mut self, index: usize, now: Instant) -> Result<(), Instant> {
+            let bytes = wire_len(self.queues[index].frames[0].payload.len());
+            let delay = self.queues[index].bucket.as_mut().map_or(Duration::ZERO, |bucket| bucket.delay(bytes, now));
+            if delay.is_zero() {
+                Ok(())
+            }
+            else {
+                Err(now.checked_add(delay).unwrap_or(now + Duration::from_secs(3600)))
+            }
+        }
+
+        fn next(&mut self, now: Instant) -> Next {
+            let n = self.queues.len();
+            let mut earliest: Option<Instant> = None;
+            let mut picked = None;
+            match self.scheduling {
+                Scheduling::StrictPriority => {
+                    for index in 0..n {
+                        if self.queues[index].frames.is_empty() {
+                            continue;
+                        }
+                        match self.ready(index, now) {
+                            Ok(()) => {
+                                picked = Some(index);
+                                break;
+                            }
+                            Err(at) => earliest = Some(earliest.map_or(at, |t| t.min(at))),
+                        }
+                    }
+                }
+                Scheduling::WeightedRoundRobin => {
+                    for _ in 0..=n {
+                        let index = self.cursor;
+                        if self.credit > 0 && !self.queues[index].frames.is_empty() {
+                            match self.ready(index, now) {
+                                Ok(()) => {
+                                    picked = Some(index);
+                                    break;
+                                }
+                                Err(at) => earliest = Some(earliest.map_or(at, |t| t.min(at))),
+                            }
+                        }
+                        self.cursor = (self.cursor + 1) % n;
+                        self.credit = self.queues[self.cursor].config.weight;
+                    }
+                }
+            }
+            let index = match picked {
+                Some(index) => index,
+                None => return Next::Wait(earliest),
+            };
+
+            // Shaping of the port applies to whatever is picked
+            let bytes = wire_len(self.queues[index].frames[0].payload.len());
+            if let Some(bucket) = self.bucket.as_mut() {
+                let delay = bucket.delay(bytes, now);
+                if !delay.is_zero() {
+                    return Next::Wait(now.checked_add(delay));
+                }
+                bucket.consume(bytes, now);
+            }
+            if self.scheduling == Scheduling::WeightedRoundRobin {
+                self.credit -= 1;
+            }
+            let queue = &mut self.queues[index];
+            if let Some(bucket) = queue.bucket.as_mut() {
+                bucket.consume(bytes, now);
+            }
+            let frame = queue.frames.pop_front().unwrap();
+            Next::Send(index, frame)
+        }
+    }
+
+    struct Shared {
+        config: QosConfig,
+        state: Mutex<State>,
+        updated: Condvar,
+    }
+
+    /// A handle reporting on a scheduler from other threads.
+    #[derive(Clone)]
+    pub struct QosMonitor {
+        shared: Arc<Shared>,
+    }
+
+    impl QosMonitor {
+        /// Counters of each queue.
+        pub fn stats(&self) -> Vec<QueueStats> {
+            let state = self.shared.state.lock().unwrap();
+            state.queues.iter()
+                .map(|queue| QueueStats { queued: queue.frames.len(), ..queue.stats.clone() })
+                .collect()
+        }
+
+        pub fn config(&self) -> &QosConfig {
+            &self.shared.config
+        }
+    }
+
+    /// A sink queuing frames and passing them on to another sink from a
+    /// background thread, according to a `QosConfig`. Sending through it
+    /// never blocks; frames finding their queue full are dropped and
+    /// counted. Queued frames are discarded when the scheduler is dropped.
+    pub struct EgressScheduler {
+        monitor: QosMonitor,
+    }
+
+    impl EgressScheduler {
+        pub fn new(mut sink: Box<dyn FrameSink>, config: QosConfig) -> Result<EgressScheduler, RlinkError> {
+            config.validate()?;
+            let state = State {
+                scheduling: config.scheduling,
+                queues: config.queues.iter()
+                    .map(|queue| Queue {
+                        config: queue.clone(),
+                        frames: VecDeque::new(),
+                        bucket: queue.rate.map(|(rate, burst)| TokenBucket::new(rate, burst)),
+                        stats: QueueStats::default(),
+                    })
+                    .collect(),
+                bucket: config.rate.map(|(rate, burst)| TokenBucket::new(rate, burst)),
+                cursor: 0,
+                credit: config.queues[0].weight,
+                closed: false,
+            };
+            let shared = Arc::new(Shared { config, state: Mutex::new(state), updated: Condvar::new() });
+
+            let scheduled = shared.clone();
+            thread::spawn(move || {
+                let mut state = scheduled.state.lock().unwrap();
+                while !state.closed {
+                    match state.next(Instant::now()) {
+                        Next::Send(index, frame) => {
+                            drop(state);
+                            let result = sink.send_frame(&frame.payload, frame.ethtype, &frame.dst);
+                            state = scheduled.state.lock().unwrap();
+                            let stats = &mut state.queues[index].stats;
+                            match result {
+                                Ok(()) => {
+                                    stats.sent += 1;
+                                    stats.sent_bytes += wire_len(frame.payload.len()) as u64;
+                                }
+                                Err(_) => stats.errors += 1,
+                            }
+                        }
+                        Next::Wait(Some(at)) => {
+                            let timeout = at.saturating_duration_since(Instant::now());
+                            state = scheduled.updated.wait_timeout(state, timeout).unwrap().0;
+                        }
+                        Next::Wait(None) => state = scheduled.updated.wait(state).unwrap(),
+                    }
+                }
+            });
+            Ok(EgressScheduler { monitor: QosMonitor { shared } })
+        }
+
+        pub fn monitor(&self) -> QosMonitor {
+            self.monitor.clone()
+        }
+
+        pub fn stats(&self) -> Vec<QueueStats> {
+            self.monitor.stats()
+        }
+    }
+
+    impl FrameSink for EgressScheduler {
+        fn send_frame(&mut self, payload: &[u8], ethtype: EtherType, dst: &MacAddress) -> Result<(), RlinkError> {
+            if payload.len() >= 1500 {
+                return Err(RlinkError::PayloadTooLarge { size: payload.len(), max: 1499 });
+            }
+            let shared = &self.monitor.shared;
+            let index = shared.config.classify(ethtype, payload);
+            let mut state = shared.state.lock().unwrap();
+            let queue = &mut state.queues[index];
+            if queue.frames.len() >= queue.config.limit {
+                queue.stats.dropped += 1;
+                return Ok(());
+            }
+            queue.frames.push_back(Frame { payload: payload.to_vec(), ethtype, dst: *dst });
+            queue.stats.enqueued += 1;
+            shared.updated.notify_one();
+            Ok(())
+        }
+    }
+
+    impl Drop for EgressScheduler {
+        fn drop(&mut self) {
+            self.monitor.shared.state.lock().unwrap().closed = true;
+            self.monitor.shared.updated.notify_one();
+        }
+    }
+}
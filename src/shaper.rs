@@ -0,0 +1,258 @@
+#![allow(dead_code)]
+#![allow(unused)]
+
+pub mod shaper {
+    //! Token-bucket shaping of devices, e.g. to emulate slow links on fast
+    //! veths. A `Shaper` paces the frames sent through a sink; an
+    //! `RxShaper` paces the frames received on a device. Both queue frames
+    //! exceeding the rate, up to a limit, then drop them, like the queue
+    //! in front of a slow link would.
+    //!
+    //! Transmission is shaped by the egress scheduler of the `qos` module
+    //! with a single queue, so a `ShaperConfig` may also be installed on a
+    //! link of a stack with `Stack::set_egress(link, config.into())`.
+
+    use crate::qos::qos::{self, EgressScheduler, QosConfig, QueueStats, Scheduling, TokenBucket};
+    use crate::stack::stack::FrameSink;
+    use crate::{DeviceHandle, EtherType, Packet, PError, Raw, RlinkError};
+    use mac_address::MacAddress;
+    use std::collections::VecDeque;
+    use std::sync::{Arc, Condvar, Mutex};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    /// Frames queued before dropping, by default.
+    pub const DEFAULT_LIMIT: usize = 1000;
+
+    /// Parse a rate in bits per second, with an optional decimal suffix
+    /// as in `tc`: `"512kbit"`, `"10mbit"`, `"1gbit"` or `"9600"` in bits
+    /// per second, `"64kbps"` or `"1mbps"` in bytes per second.
+    pub fn parse_rate(s: &str) -> Result<u64, RlinkError> {
+        let lower = s.trim().to_ascii_lowercase();
+        let digits = lower.trim_end_matches(|c: char| c.is_ascii_alphabetic());
+        let multiplier = match &lower[digits.len()..] {
+            "" | "bit" => 1u64,
+            "k" | "kbit" => 1_000,
+            "m" | "mbit" => 1_000_000,
+            "g" | "gbit" => 1_000_000_000,
+            "bps" => 8,
+            "kbps" => 8_000,
+            "mbps" => 8_000_000,
+            "gbps" => 8_000_000_000,
+            _ => return Err(RlinkError::InvalidConfig(format!("invalid rate: {:?}", s))),
+        };
+        match digits.parse::<f64>() {
+            Ok(value) if value.is_finite() && value > 0.0 => Ok((value * multiplier as f64) as u64),
+            _ => Err(RlinkError::InvalidConfig(format!("invalid rate: {:?}", s))),
+        }
+    }
+
+    /// Rate, burst size and queue length of a shaper.
+    #[derive(Clone, Debug, PartialEq, Eq)]
+    pub struct ShaperConfig {
+        pub bits_per_sec: u64,
+        /// Bytes that may be sent back to back at full speed
+        pub burst: usize,
+        /// Frames queued before dropping
+        pub limit: usize,
+    }
+
+    impl ShaperConfig {
+        /// A shaper at `bits_per_sec`, with a burst of one full frame, or
+        /// of 1ms worth of traffic at high rates.
+        pub fn new(bits_per_sec: u64) -> Self {
+            ShaperConfig {
+                bits_per_sec,
+                burst: ((bits_per_sec / 8 / 1000) as usize).max(qos::wire_len(1500)),
+                limit: DEFAULT_LIMIT,
+            }
+        }
+    }
+
+    impl From<ShaperConfig> for QosConfig {
+        fn from(config: ShaperConfig) -> Self {
+            let mut qos = QosConfig::new(Scheduling::StrictPriority, 1);
+            qos.queues[0].limit = config.limit;
+            qos.rate = Some((config.bits_per_sec, config.burst));
+            qos
+        }
+    }
+
+    /// Counters of a shaper.
+    #[derive(Clone, Debug, Default, PartialEq, Eq)]
+    pub struct ShaperStats {
+        /// Frames let through
+        pub frames: u64,
+        /// Bytes let through, Ethernet header and trailer included
+        pub bytes: u64,
+        /// Frames dropped because the queue was full
+        pub dropped: u64,
+        /// Frames the device failed to send or receive
+        pub errors: u64,
+        /// Frames currently queued
+        pub queued: usize,
+    }
+
+    impl From<QueueStats> for ShaperStats {
+        fn from(stats: QueueStats) -> Self {
+            ShaperStats {
+                frames: stats.sent,
+                bytes: stats.sent_bytes,
+                dropped: stats.dropped,
+                errors: stats.errors,
+                queued: stats.queued,
+            }
+        }
+    }
+
+    /// A sink sending frames no faster than the configured rate. Sending
+    /// never blocks; frames are queued and sent from a background thread.
+    pub struct Shaper {
+        scheduler: EgressScheduler,
+    }
+
+    impl Shaper {
+        pub fn new(sink: Box<dyn FrameSink>, config: ShaperConfig) -> Result<Shaper, RlinkError> {
+            Ok(Shaper { scheduler: EgressScheduler::new(sink, config.into())? })
+        }
+
+        /// Like `DeviceHandle::send_packet()`, without checksum.
+        pub fn send_packet(&mut self, payload: &[u8], ethtype: EtherType, dest_mac: &[u8; 6])
+            -> Result<(), RlinkError> {
+            self.send_frame(payload, ethtype, &MacAddress::new(*dest_mac))
+        }
+
+        pub fn stats(&self) -> ShaperStats {
+            self.scheduler.stats().remove(0).into()
+        }
+    }
+
+    impl FrameSink for Shaper {
+        fn send_frame(&mut self, payload: &[u8], ethtype: EtherType, dst: &MacAddress) -> Result<(), RlinkError> {
+            self.scheduler.send_frame(payload, ethtype, dst)
+        }
+    }
+
+    struct RxState {
+        packets: VecDeque<Packet<Raw>>,
+        bucket: TokenBucket,
+        stats: ShaperStats,
+        /// Set when the capture stops, or the shaper is dropped
+        closed: bool,
+    }
+
+    struct RxShared {
+        limit: usize,
+        state: Mutex<RxState>,
+        arrived: Condvar,
+    }
+
+    /// Bytes a captured frame occupied on the wire, as counted by `Shaper`.
+    fn captured_len(packet: &Packet<Raw>) -> usize {
+        packet.data.len().max(60) + 4
+    }
+
+    /// A receiving handle delivering frames no faster than the configured
+    /// rate. Frames are captured on a background thread as they arrive,
+    /// and queued until their turn.
+    pub struct RxShaper {
+        shared: Arc<RxShared>,
+    }
+
+    impl RxShaper {
+        pub fn new(mut handle: DeviceHandle, config: ShaperConfig) -> Result<RxShaper, RlinkError> {
+            if config.limit == 0 {
+                return Err(RlinkError::InvalidConfig("shaper without room".to_string()));
+            }
+            let shared = Arc::new(RxShared {
+                limit: config.limit,
+                state: Mutex::new(RxState {
+                    packets: VecDeque::new(),
+                    bucket: TokenBucket::new(config.bits_per_sec, config.burst),
+                    stats: ShaperStats::default(),
+                    closed: false,
+                }),
+                arrived: Condvar::new(),
+            });
+            let capturing = shared.clone();
+            thread::spawn(move || {
+                loop {
+                    let packet = handle.next_packet();
+                    let mut state = capturing.state.lock().unwrap();
+                    if state.closed {
+                        break;
+                    }
+                    match packet {
+                        Ok(Some(packet)) if state.packets.len() >= capturing.limit => state.stats.dropped += 1,
+                        Ok(Some(packet)) => {
+                            state.packets.push_back(packet);
+                            capturing.arrived.notify_one();
+                        }
+                        Ok(None) | Err(PError::TimeoutExpired) => {}
+                        Err(_) => {
+                            state.stats.errors += 1;
+                            state.closed = true;
+                            capturing.arrived.notify_all();
+                            break;
+                        }
+                    }
+                }
+            });
+            Ok(RxShaper { shared })
+        }
+
+        /// Block until the next frame is due. Fails once the capture has
+        /// stopped and all queued frames were delivered.
+        pub fn next_packet(&mut self) -> Result<Packet<Raw>, RlinkError> {
+            let mut state = self.shared.state.lock().unwrap();
+            let packet = loop {
+                if let Some(packet) = state.packets.pop_front() {
+                    break packet;
+                }
+                if state.closed {
+                    return Err(RlinkError::BrokenDevicePool);
+                }
+                state = self.shared.arrived.wait(state).unwrap();
+            };
+            let bytes = captured_len(&packet);
+            let now = Instant::now();
+            let delay = state.bucket.delay(bytes, now);
+            state.bucket.consume(bytes, now + delay);
+            state.stats.frames += 1;
+            state.stats.bytes += bytes as u64;
+            drop(state);
+            thread::sleep(delay);
+            Ok(packet)
+        }
+
+        pub fn stats(&self) -> ShaperStats {
+            let state = self.shared.state.lock().unwrap();
+            ShaperStats { queued: state.packets.len(), ..state.stats.clone() }
+        }
+    }
+
+    impl Drop for RxShaper {
+        fn drop(&mut self) {
+            // The capturing thread stops with the next packet or timeout.
+            self.shared.state.lock().unwrap().closed = true;
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn rates() {
+            for (s, bits_per_sec) in [
+                ("9600", 9600), ("512kbit", 512_000), ("10Mbit", 10_000_000), ("1.5gbit", 1_500_000_000),
+                ("100bps", 800), ("64kbps", 512_000), ("1mbps", 8_000_000), ("2gbps", 16_000_000_000),
+            ] {
+                assert_eq!(parse_rate(s).unwrap(), bits_per_sec, "{}", s);
+            }
+            for s in ["", "0", "-1mbit", "10mb", "mbit", "1 kbit/s", "inf", "1e999bit"] {
+                assert!(matches!(parse_rate(s), Err(RlinkError::InvalidConfig(_))), "{}", s);
+            }
+        }
+    }
+}
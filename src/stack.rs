@@ -0,0 +1,704 @@
+#![allow(dead_code)]
+#![allow(unused)]
+
+pub mod stack {
+    //! A small user-space IPv4 stack on top of rlink devices. The stack owns
+    //! one sending handle per link and a receiving thread fed by a
+    //! `DevicePool`. It answers ARP and ICMP echo requests, optionally
+    //! forwards datagrams between its links, and hands datagrams of other
+    //! protocols to registered handlers. IPv6 with Neighbor Discovery is
+    //! available on request, see `ndp()`.
+    //!
+    //! Outgoing datagrams whose next hop is not yet resolved are queued
+    //! while an ARP request (or neighbor solicitation) is in flight, so
+    //! sending never blocks on the network (in particular, not on the
+    //! receiving thread).
+
+    use crate::arp::arp::{ArpCache, ArpOperation, ArpPacket};
+    use crate::icmp::icmp::{self, IcmpMessage};
+    use crate::interface::interface::{self, Interface};
+    use crate::ipv4::ipv4::{prefix_mask, IpProtocol, Ipv4, Ipv4Header, Route, RoutingTable};
+    use crate::ipv6::ipv6::Ipv6Header;
+    use crate::ndp::ndp::Ndp;
+    use crate::qos::qos::{EgressScheduler, QosConfig, QosMonitor};
+    use crate::tcp::tcp::TcpLayer;
+    use crate::udp::udp::PortTable;
+    use crate::{DeviceHandle, DevicePool, EtherType, Eth, MacAddressExt, Packet, RlinkError};
+    use mac_address::MacAddress;
+    use std::collections::HashMap;
+    use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+    use std::sync::atomic::{AtomicBool, AtomicU16, Ordering};
+    use std::sync::{Arc, Condvar, Mutex, OnceLock, RwLock, Weak};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    /// Datagrams queued per unresolved neighbor before dropping.
+    const MAX_PENDING: usize = 16;
+    /// Minimum interval between ARP requests for the same neighbor.
+    const ARP_RETRY: Duration = Duration::from_secs(1);
+
+    /// Something frames can be transmitted through.
+    pub trait FrameSink: Send {
+        /// Transmit a frame carrying `payload` to `dst`. The source address
+        /// is that of the sink.
+        fn send_frame(&mut self, payload: &[u8], ethtype: EtherType, dst: &MacAddress) -> Result<(), RlinkError>;
+    }
+
+    impl FrameSink for DeviceHandle {
+        fn send_frame(&mut self, payload: &[u8], ethtype: EtherType, dst: &MacAddress) -> Result<(), RlinkError> {
+            self.send_packet(payload, ethtype, &dst.bytes(), false)
+        }
+    }
+
+    /// Handler of datagrams addressed to the stack, invoked on the
+    /// receiving thread with the index of the link the datagram came in on.
+    pub type ProtocolHandler = Box<dyn Fn(&Stack, usize, Packet<Ipv4>) + Send + Sync>;
+
+    /// A link (network interface) of the stack.
+    pub struct Link {
+        pub name: String,
+        pub mac_address: MacAddress,
+        /// IPv4 addresses with prefix lengths
+        pub addresses: Vec<(Ipv4Addr, u8)>,
+        /// Shared with the copies made when addresses change
+        sink: Arc<Mutex<Box<dyn FrameSink>>>,
+    }
+
+    impl Link {
+        /// Transmit a frame on this link.
+        pub fn send_frame(&self, payload: &[u8], ethtype: EtherType, dst: &MacAddress) -> Result<(), RlinkError> {
+            self.sink.lock().unwrap().send_frame(payload, ethtype, dst)
+        }
+
+        /// The primary IPv4 address of the link.
+        pub fn ipv4(&self) -> Option<Ipv4Addr> {
+            self.addresses.first().map(|(addr, _)| *addr)
+        }
+
+        /// Whether `addr` is the directed broadcast address of one of the
+        /// link's subnets.
+        pub fn is_subnet_broadcast(&self, addr: Ipv4Addr) -> bool {
+            self.addresses.iter().any(|(own, len)| *len < 31
+                && u32::from(*own) | !prefix_mask(*len) == u32::from(addr))
+        }
+    }
+
+    struct PendingQueue {
+        link: usize,
+        last_request: Instant,
+        datagrams: Vec<Vec<u8>>,
+    }
+
+    struct Inner {
+        links: RwLock<Vec<Arc<Link>>>,
+        arp: Mutex<ArpCache>,
+        arp_updated: Condvar,
+        pending: Mutex<HashMap<Ipv4Addr, PendingQueue>>,
+        routes: RwLock<RoutingTable>,
+        /// Shared, to be invoked without holding the lock
+        handlers: RwLock<HashMap<IpProtocol, Arc<ProtocolHandler>>>,
+        forwarding: AtomicBool,
+        next_id: AtomicU16,
+        udp: OnceLock<Arc<PortTable>>,
+        tcp: OnceLock<Arc<TcpLayer>>,
+        ndp: OnceLock<Arc<Ndp>>,
+    }
+
+    /// Handle to a stack. Clones refer to the same stack.
+    #[derive(Clone)]
+    pub struct Stack {
+        inner: Arc<Inner>,
+    }
+
+    /// Weak handle to a stack, for background threads that must not keep
+    /// it alive.
+    #[derive(Clone)]
+    pub struct WeakStack {
+        inner: Weak<Inner>,
+    }
+
+    impl WeakStack {
+        pub fn upgrade(&self) -> Option<Stack> {
+            self.inner.upgrade().map(|inner| Stack { inner })
+        }
+    }
+
+    impl Default for Stack {
+        fn default() -> Self {
+            Stack::new()
+        }
+    }
+
+    impl Stack {
+        /// An empty stack without links.
+        pub fn new() -> Stack {
+            Stack {
+                inner: Arc::new(Inner {
+                    links: RwLock::new(Vec::new()),
+                    arp: Mutex::new(ArpCache::default()),
+                    arp_updated: Condvar::new(),
+                    pending: Mutex::new(HashMap::new()),
+                    routes: RwLock::new(RoutingTable::new()),
+                    handlers: RwLock::new(HashMap::new()),
+                    forwarding: AtomicBool::new(false),
+                    next_id: AtomicU16::new(std::process::id() as u16),
+                    udp: OnceLock::new(),
+                    tcp: OnceLock::new(),
+                    ndp: OnceLock::new(),
+                }),
+            }
+        }
+
+        /// A stack on the given interfaces, using their addresses, with a
+        /// receiving thread capturing on all of them. IPv6 is enabled on
+        /// interfaces with IPv6 addresses, which are taken over without
+        /// duplicate address detection.
+        pub fn open(ifaces: Vec<Interface>, timeout: i32) -> Result<Stack, RlinkError> {
+            let stack = Stack::new();
+            for iface in ifaces.iter() {
+                stack.add_interface(iface, DeviceHandle::open(iface, timeout, true)?)?;
+            }
+            stack.attach(DevicePool::from_interfaces(ifaces, timeout)?);
+            Ok(stack)
+        }
+
+        /// Add a link on an interface, sending through `handle`, with the
+        /// addresses of the interface as `open()` takes them. Packets are
+        /// received once a pool capturing on the interface is attached.
+        /// Returns the index of the link.
+        pub fn add_interface(&self, iface: &Interface, handle: DeviceHandle) -> Result<usize, RlinkError> {
+            let addresses = iface.addresses.iter()
+                .filter_map(|addr| match addr.addr {
+                    IpAddr::V4(v4) => Some((v4, addr.prefix_len)),
+                    IpAddr::V6(_) => None,
+                })
+                .collect();
+            let link = self.add_link(&iface.name, *handle.mac_address(), addresses, Box::new(handle));
+            for addr in iface.addresses.iter() {
+                if let IpAddr::V6(v6) = addr.addr {
+                    self.ndp().add_preferred_address(self, link, v6, addr.prefix_len)?;
+                }
+            }
+            Ok(link)
+        }
+
+        /// Like `open()`, looking the interfaces up by name.
+        pub fn open_names(names: &[String], timeout: i32) -> Result<Stack, RlinkError> {
+            let ifaces = names.iter()
+                .map(|name| interface::interface_by_name(name))
+                .collect::<Result<Vec<_>, _>>()?;
+            Stack::open(ifaces, timeout)
+        }
+
+        /// Add a link, together with routes to its connected subnets.
+        /// Returns the index of the link.
+        pub fn add_link(&self, name: &str, mac_address: MacAddress, addresses: Vec<(Ipv4Addr, u8)>,
+            sink: Box<dyn FrameSink>) -> usize {
+            let mut routes = self.inner.routes.write().unwrap();
+            for (addr, prefix_len) in addresses.iter() {
+                let prefix = Ipv4Addr::from(u32::from(*addr) & prefix_mask(*prefix_len));
+                routes.add(Route { prefix, prefix_len: *prefix_len, gateway: None, device: name.to_string() });
+            }
+            let mut links = self.inner.links.write().unwrap();
+            links.push(Arc::new(Link {
+                name: name.to_string(),
+                mac_address,
+                addresses,
+                sink: Arc::new(Mutex::new(sink)),
+            }));
+            links.len() - 1
+        }
+
+        /// Assign an address to a link, together with a route to its
+        /// subnet. Assigning an address the link already has only updates
+        /// its prefix length.
+        pub fn add_address(&self, link: usize, addr: Ipv4Addr, prefix_len: u8) -> Result<(), RlinkError> {
+            let old = self.remove_address(link, addr)?;
+            let mut links = self.inner.links.write().unwrap();
+            let current = links.get(link).ok_or(RlinkError::NoAddress(format!("link {}", link)))?;
+            let mut addresses = current.addresses.clone();
+            addresses.push((addr, prefix_len));
+            let prefix = Ipv4Addr::from(u32::from(addr) & prefix_mask(prefix_len));
+            self.inner.routes.write().unwrap().add(Route {
+                prefix, prefix_len, gateway: None, device: current.name.clone(),
+            });
+            links[link] = Arc::new(Link {
+                name: current.name.clone(),
+                mac_address: current.mac_address,
+                addresses,
+                sink: current.sink.clone(),
+            });
+            Ok(())
+        }
+
+        /// Remove an address from a link, together with the route to its
+        /// subnet unless another address of the link is in it. Returns the
+        /// prefix length of the removed address.
+        pub fn remove_address(&self, link: usize, addr: Ipv4Addr) -> Result<Option<u8>, RlinkError> {
+            let mut links = self.inner.links.write().unwrap();
+            let current = links.get(link).ok_or(RlinkError::NoAddress(format!("link {}", link)))?;
+            let prefix_len = match current.addresses.iter().find(|(own, _)| *own == addr) {
+                Some((_, prefix_len)) => *prefix_len,
+                None => return Ok(None),
+            };
+            let addresses: Vec<_> = current.addresses.iter().copied()
+                .filter(|(own, _)| *own != addr)
+                .collect();
+            let mask = prefix_mask(prefix_len);
+            let prefix = Ipv4Addr::from(u32::from(addr) & mask);
+            if !addresses.iter().any(|(own, len)| *len == prefix_len && u32::from(*own) & mask == u32::from(prefix)) {
+                let mut routes = self.inner.routes.write().unwrap();
+                if routes.iter().any(|route| route.prefix == prefix && route.prefix_len == prefix_len
+                    && route.gateway.is_none() && route.device == current.name) {
+                    routes.remove(prefix, prefix_len);
+                }
+            }
+            links[link] = Arc::new(Link {
+                name: current.name.clone(),
+                mac_address: current.mac_address,
+                addresses,
+                sink: current.sink.clone(),
+            });
+            Ok(Some(prefix_len))
+        }
+
+        /// Queue the frames sent on a link by class, according to `config`,
+        /// instead of transmitting them right away. Returns a handle on the
+        /// counters of the queues.
+        pub fn set_egress(&self, link: usize, config: QosConfig) -> Result<QosMonitor, RlinkError> {
+            let current = self.link(link).ok_or(RlinkError::NoAddress(format!("link {}", link)))?;
+            let mut sink = current.sink.lock().unwrap();
+            // Takes the place of the sink while it is being wrapped
+            struct Detached;
+            impl FrameSink for Detached {
+                fn send_frame(&mut self, _: &[u8], _: EtherType, _: &MacAddress) -> Result<(), RlinkError> {
+                    Err(RlinkError::BrokenDevicePool)
+                }
+            }
+            // Checked before the sink is taken, so that an invalid
+            // configuration leaves the link untouched
+            config.validate()?;
+            let inner = std::mem::replace(&mut *sink, Box::new(Detached));
+            let scheduler = EgressScheduler::new(inner, config)?;
+            let monitor = scheduler.monitor();
+            *sink = Box::new(scheduler);
+            Ok(monitor)
+        }
+
+        /// Feed the stack with the packets captured by `pool`, on a
+        /// background thread. Packets are assigned to links by the MAC
+        /// address of the capturing device. The thread ends once the stack
+        /// is dropped and another packet arrives, or the pool breaks.
+        pub fn attach(&self, pool: DevicePool) {
+            let weak = self.downgrade();
+            thread::spawn(move || {
+                while let Ok(packet) = pool.select() {
+                    let stack = match weak.upgrade() {
+                        Some(stack) => stack,
+                        None => break,
+                    };
+                    if let Some(link) = stack.link_by_mac(&packet.mac_address) {
+                        if let Ok(frame) = packet.parse_eth(false) {
+                            stack.input(link, frame);
+                        }
+                    }
+                }
+            });
+        }
+
+        pub fn downgrade(&self) -> WeakStack {
+            WeakStack { inner: Arc::downgrade(&self.inner) }
+        }
+
+        pub fn link(&self, index: usize) -> Option<Arc<Link>> {
+            self.inner.links.read().unwrap().get(index).cloned()
+        }
+
+        pub fn links(&self) -> Vec<Arc<Link>> {
+            self.inner.links.read().unwrap().clone()
+        }
+
+        pub fn link_by_name(&self, name: &str) -> Option<usize> {
+            self.inner.links.read().unwrap().iter().position(|link| link.name == name)
+        }
+
+        pub fn link_by_mac(&self, mac: &MacAddress) -> Option<usize> {
+            self.inner.links.read().unwrap().iter().position(|link| link.mac_address == *mac)
+        }
+
+        /// The UDP ports of the stack. The UDP handler is registered on
+        /// first use.
+        pub fn udp(&self) -> Arc<PortTable> {
+            self.inner.udp.get_or_init(|| {
+                let ports = Arc::new(PortTable::default());
+                let input = ports.clone();
+                self.register_handler(IpProtocol::UDP, Box::new(move |stack, link, packet| {
+                    input.input(stack, link, packet);
+                }));
+                ports
+            }).clone()
+        }
+
+        /// The TCP connections of the stack. The TCP handler and the timer
+        /// thread are started on first use.
+        pub fn tcp(&self) -> Arc<TcpLayer> {
+            self.inner.tcp.get_or_init(|| {
+                let layer = Arc::new(TcpLayer::default());
+                let input = layer.clone();
+                self.register_handler(IpProtocol::TCP, Box::new(move |stack, _, packet| {
+                    input.input(stack, packet);
+                }));
+                layer.start_timer(self.downgrade());
+                layer
+            }).clone()
+        }
+
+        /// The IPv6 side of the stack: addresses, neighbors and routers.
+        /// Receiving IPv6 and the Neighbor Discovery timers start on first
+        /// use.
+        pub fn ndp(&self) -> Arc<Ndp> {
+            self.inner.ndp.get_or_init(|| {
+                let ndp = Arc::new(Ndp::default());
+                ndp.start_timer(self.downgrade());
+                ndp
+            }).clone()
+        }
+
+        /// Enable IPv6 on a link: assign its EUI-64 link-local address,
+        /// then solicit routers to autoconfigure global addresses.
+        pub fn enable_ipv6(&self, link: usize) -> Result<(), RlinkError> {
+            self.ndp().enable(self, link)
+        }
+
+        /// Whether `addr` is the directed broadcast address of a subnet of
+        /// one of the links.
+        pub fn is_subnet_broadcast(&self, addr: Ipv4Addr) -> bool {
+            self.inner.links.read().unwrap().iter().any(|link| link.is_subnet_broadcast(addr))
+        }
+
+        /// Whether `addr` is assigned to one of the links.
+        pub fn is_local(&self, addr: Ipv4Addr) -> bool {
+            self.inner.links.read().unwrap().iter()
+                .any(|link| link.addresses.iter().any(|(own, _)| *own == addr))
+        }
+
+        /// Enable or disable forwarding of datagrams not addressed to the stack.
+        pub fn set_forwarding(&self, forwarding: bool) {
+            self.inner.forwarding.store(forwarding, Ordering::Relaxed);
+        }
+
+        pub fn is_forwarding(&self) -> bool {
+            self.inner.forwarding.load(Ordering::Relaxed)
+        }
+
+        pub fn add_route(&self, route: Route) {
+            self.inner.routes.write().unwrap().add(route);
+        }
+
+        pub fn remove_route(&self, prefix: Ipv4Addr, prefix_len: u8) -> Option<Route> {
+            self.inner.routes.write().unwrap().remove(prefix, prefix_len)
+        }
+
+        /// A copy of the routing table.
+        pub fn routes(&self) -> RoutingTable {
+            self.inner.routes.read().unwrap().clone()
+        }
+
+        /// Resolved neighbors.
+        pub fn arp_entries(&self) -> Vec<(Ipv4Addr, MacAddress)> {
+            self.inner.arp.lock().unwrap().entries()
+        }
+
+        /// Register the handler of datagrams with the given protocol,
+        /// replacing the previous one. ICMP echo requests are answered by
+        /// the stack before reaching the ICMP handler.
+        pub fn register_handler(&self, protocol: IpProtocol, handler: ProtocolHandler) {
+            self.inner.handlers.write().unwrap().insert(protocol, Arc::new(handler));
+        }
+
+        pub fn unregister_handler(&self, protocol: IpProtocol) {
+            self.inner.handlers.write().unwrap().remove(&protocol);
+        }
+
+        /// The route and link that datagrams to `dst` leave through.
+        pub fn route_to(&self, dst: Ipv4Addr) -> Result<(Route, usize), RlinkError> {
+            let route = self.inner.routes.read().unwrap().lookup(dst).cloned()
+                .ok_or(RlinkError::NoRoute(IpAddr::V4(dst)))?;
+            let link = self.link_by_name(&route.device)
+                .ok_or(RlinkError::NoRoute(IpAddr::V4(dst)))?;
+            Ok((route, link))
+        }
+
+        /// The source address to use towards `dst`.
+        pub fn source_for(&self, dst: Ipv4Addr) -> Result<Ipv4Addr, RlinkError> {
+            let (_, link) = self.route_to(dst)?;
+            let link = self.link(link).unwrap();
+            link.ipv4().ok_or_else(|| RlinkError::NoAddress(link.name.clone()))
+        }
+
+        /// Send `payload` to `dst` with default header fields.
+        pub fn send_ipv4(&self, dst: Ipv4Addr, protocol: IpProtocol, payload: &[u8]) -> Result<(), RlinkError> {
+            let src = if dst.is_broadcast() { Ipv4Addr::UNSPECIFIED } else { self.source_for(dst)? };
+            self.send_ipv4_with(Ipv4Header::new(src, dst, protocol), payload)
+        }
+
+        /// Send `payload` with the given header. An unspecified source
+        /// address is filled in from the outgoing link, except for limited
+        /// broadcasts. The identification field is assigned by the stack.
+        pub fn send_ipv4_with(&self, mut header: Ipv4Header, payload: &[u8]) -> Result<(), RlinkError> {
+            header.identification = self.inner.next_id.fetch_add(1, Ordering::Relaxed);
+            if header.dst.is_broadcast() {
+                let links = self.links();
+                for link in links.iter() {
+                    link.send_frame(&header.encode(payload), EtherType::IPv4, &crate::mac::mac::broadcast())?;
+                }
+                return Ok(());
+            }
+            if header.src.is_unspecified() {
+                header.src = self.source_for(header.dst)?;
+            }
+            self.transmit(header.dst, header.encode(payload))
+        }
+
+        /// Send `payload` with the given header on link `link` to `dst_mac`,
+        /// bypassing routing and ARP, e.g. for hosts not configured yet.
+        pub fn send_ipv4_on(&self, link: usize, mut header: Ipv4Header, payload: &[u8], dst_mac: &MacAddress)
+            -> Result<(), RlinkError> {
+            let link = self.link(link).ok_or(RlinkError::NoAddress(format!("link {}", link)))?;
+            header.identification = self.inner.next_id.fetch_add(1, Ordering::Relaxed);
+            link.send_frame(&header.encode(payload), EtherType::IPv4, dst_mac)
+        }
+
+        /// Send `payload` to `dst` over IPv6.
+        pub fn send_ipv6(&self, dst: Ipv6Addr, protocol: IpProtocol, payload: &[u8]) -> Result<(), RlinkError> {
+            let ndp = self.ndp();
+            let mut header = Ipv6Header::new(Ipv6Addr::UNSPECIFIED, dst, protocol);
+            header.hop_limit = ndp.hop_limit();
+            ndp.send_with(self, header, payload)
+        }
+
+        /// Send `payload` with the given IPv6 header. An unspecified source
+        /// address is filled in from the outgoing link.
+        pub fn send_ipv6_with(&self, header: Ipv6Header, payload: &[u8]) -> Result<(), RlinkError> {
+            self.ndp().send_with(self, header, payload)
+        }
+
+        /// Send `payload` to `dst` over IPv4 or IPv6, resolving the next hop
+        /// with ARP or Neighbor Discovery respectively.
+        pub fn send_ip(&self, dst: IpAddr, protocol: IpProtocol, payload: &[u8]) -> Result<(), RlinkError> {
+            match dst {
+                IpAddr::V4(dst) => self.send_ipv4(dst, protocol, payload),
+                IpAddr::V6(dst) => self.send_ipv6(dst, protocol, payload),
+            }
+        }
+
+        /// Resolve the MAC address of an IPv4 or IPv6 neighbor, waiting up
+        /// to `timeout`. Must not be called from a protocol handler.
+        pub fn resolve_ip(&self, ip: IpAddr, timeout: Duration) -> Result<MacAddress, RlinkError> {
+            match ip {
+                IpAddr::V4(ip) => self.resolve(ip, timeout),
+                IpAddr::V6(ip) => self.ndp().resolve(self, ip, timeout),
+            }
+        }
+
+        /// Send an ICMP message to `dst`.
+        pub fn send_icmp(&self, dst: Ipv4Addr, message: &IcmpMessage) -> Result<(), RlinkError> {
+            self.send_ipv4(dst, IpProtocol::ICMP, &message.encode())
+        }
+
+        /// Route an encoded datagram towards `dst`.
+        fn transmit(&self, dst: Ipv4Addr, datagram: Vec<u8>) -> Result<(), RlinkError> {
+            let (route, link_idx) = self.route_to(dst)?;
+            let link = self.link(link_idx).unwrap();
+            if link.is_subnet_broadcast(dst) {
+                return link.send_frame(&datagram, EtherType::IPv4, &crate::mac::mac::broadcast());
+            }
+            let next_hop = route.next_hop(dst);
+            let cached = self.inner.arp.lock().unwrap().lookup(next_hop);
+            match cached {
+                Some(mac) => link.send_frame(&datagram, EtherType::IPv4, &mac),
+                None => {
+                    let mut pending = self.inner.pending.lock().unwrap();
+                    let queue = pending.entry(next_hop).or_insert_with(|| PendingQueue {
+                        link: link_idx,
+                        last_request: Instant::now() - ARP_RETRY,
+                        datagrams: Vec::new(),
+                    });
+                    if queue.datagrams.len() < MAX_PENDING {
+                        queue.datagrams.push(datagram);
+                    }
+                    if queue.last_request.elapsed() >= ARP_RETRY {
+                        queue.last_request = Instant::now();
+                        drop(pending);
+                        self.send_arp_request(&link, next_hop)?;
+                    }
+                    Ok(())
+                }
+            }
+        }
+
+        fn send_arp_request(&self, link: &Link, target: Ipv4Addr) -> Result<(), RlinkError> {
+            let sender_ip = link.ipv4().ok_or_else(|| RlinkError::NoAddress(link.name.clone()))?;
+            let request = ArpPacket::request(link.mac_address, sender_ip, target);
+            link.send_frame(&request.encode(), EtherType::ARP, &crate::mac::mac::broadcast())
+        }
+
+        /// Resolve the MAC address of a neighbor, waiting up to `timeout`
+        /// for the reply. Must not be called from a protocol handler, as
+        /// the reply is processed on the receiving thread.
+        pub fn resolve(&self, ip: Ipv4Addr, timeout: Duration) -> Result<MacAddress, RlinkError> {
+            let (_, link) = self.route_to(ip)?;
+            let link = self.link(link).unwrap();
+            let deadline = Instant::now() + timeout;
+            let mut arp = self.inner.arp.lock().unwrap();
+            let mut last_request: Option<Instant> = None;
+            loop {
+                if let Some(mac) = arp.lookup(ip) {
+                    return Ok(mac);
+                }
+                let now = Instant::now();
+                if now >= deadline {
+                    return Err(RlinkError::Timeout);
+                }
+                if last_request.is_none_or(|at| at.elapsed() >= ARP_RETRY) {
+                    last_request = Some(now);
+                    drop(arp);
+                    self.send_arp_request(&link, ip)?;
+                    arp = self.inner.arp.lock().unwrap();
+                    continue;
+                }
+                let wait = (deadline - now).min(ARP_RETRY);
+                arp = self.inner.arp_updated.wait_timeout(arp, wait).unwrap().0;
+            }
+        }
+
+        /// Process a frame received on link `link`.
+        pub fn input(&self, link: usize, frame: Packet<Eth>) {
+            let link_ref = match self.link(link) {
+                Some(link) => link,
+                None => return,
+            };
+            let dst = frame.dst_mac();
+            if dst != link_ref.mac_address && !dst.is_multicast() {
+                return;
+            }
+            match frame.ethtype() {
+                EtherType::ARP => self.input_arp(link, &link_ref, &frame),
+                EtherType::IPv4 => {
+                    if let Ok(packet) = frame.parse_ipv4() {
+                        self.input_ipv4(link, &link_ref, packet);
+                    }
+                }
+                EtherType::IPv6 => {
+                    if let (Some(ndp), Ok(packet)) = (self.inner.ndp.get(), frame.parse_ipv6()) {
+                        ndp.input(self, link, packet);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        fn input_arp(&self, link_idx: usize, link: &Link, frame: &Packet<Eth>) {
+            let arp = match ArpPacket::decode(frame.data()) {
+                Ok(arp) => arp,
+                Err(_) => return,
+            };
+            let for_us = link.addresses.iter().any(|(addr, _)| *addr == arp.target_ip);
+            if !arp.sender_ip.is_unspecified() {
+                let mut cache = self.inner.arp.lock().unwrap();
+                // Learn from requests targeting us, and refresh known entries.
+                if for_us || cache.lookup(arp.sender_ip).is_some()
+                    || arp.operation == ArpOperation::Reply {
+                    cache.insert(arp.sender_ip, arp.sender_mac);
+                    self.inner.arp_updated.notify_all();
+                }
+            }
+            if let Some(queue) = self.inner.pending.lock().unwrap().remove(&arp.sender_ip) {
+                for datagram in queue.datagrams {
+                    link.send_frame(&datagram, EtherType::IPv4, &arp.sender_mac);
+                }
+            }
+            if arp.operation == ArpOperation::Request && for_us {
+                link.send_frame(&arp.reply(link.mac_address).encode(), EtherType::ARP, &arp.sender_mac);
+            }
+        }
+
+        fn input_ipv4(&self, link_idx: usize, link: &Link, packet: Packet<Ipv4>) {
+            let dst = packet.dst();
+            if self.is_local(dst) || dst.is_broadcast() || link.is_subnet_broadcast(dst) {
+                self.deliver(link_idx, packet);
+            }
+            else if self.is_forwarding() {
+                self.forward(packet);
+            }
+        }
+
+        fn deliver(&self, link: usize, packet: Packet<Ipv4>) {
+            let protocol = packet.protocol();
+            if protocol == IpProtocol::ICMP {
+                if let Ok(IcmpMessage::EchoRequest { id, seq, data }) = IcmpMessage::decode(packet.payload()) {
+                    if !packet.dst().is_broadcast() {
+                        let mut header = Ipv4Header::new(packet.dst(), packet.src(), IpProtocol::ICMP);
+                        let reply = IcmpMessage::EchoReply { id, seq, data };
+                        self.send_ipv4_with(header, &reply.encode());
+                    }
+                }
+            }
+            // Handlers may register others, e.g. on a first use of `tcp()`
+            let handler = self.inner.handlers.read().unwrap().get(&protocol).cloned();
+            match handler {
+                Some(handler) => handler(self, link, packet),
+                None if protocol != IpProtocol::ICMP => {
+                    self.send_icmp_error(&packet, IcmpMessage::destination_unreachable(
+                        icmp::PROTOCOL_UNREACHABLE, &packet));
+                }
+                None => {}
+            }
+        }
+
+        /// Forward a datagram not addressed to the stack, decrementing its
+        /// TTL. A time exceeded message is returned to the source when the
+        /// TTL runs out, and a net unreachable one when there is no route.
+        fn forward(&self, packet: Packet<Ipv4>) {
+            if packet.ttl() <= 1 {
+                self.send_icmp_error(&packet, IcmpMessage::time_exceeded(&packet));
+                return;
+            }
+            if self.route_to(packet.dst()).is_err() {
+                self.send_icmp_error(&packet, IcmpMessage::destination_unreachable(
+                    icmp::NET_UNREACHABLE, &packet));
+                return;
+            }
+            let mut datagram = packet.datagram().to_vec();
+            datagram[8] -= 1;
+            datagram[10] = 0;
+            datagram[11] = 0;
+            let header_len = packet.header_len();
+            let sum = crate::ipv4::ipv4::checksum(&datagram[..header_len]);
+            datagram[10..12].copy_from_slice(&sum.to_be_bytes());
+            self.transmit(packet.dst(), datagram);
+        }
+
+        /// Send an ICMP error about `original` back to its source, unless
+        /// RFC 1122 forbids it (errors about errors, broadcasts, non-initial
+        /// fragments or unspecified sources).
+        pub fn send_icmp_error(&self, original: &Packet<Ipv4>, message: IcmpMessage) -> Result<(), RlinkError> {
+            let src = original.src();
+            if src.is_unspecified() || src.is_broadcast() || src.is_multicast()
+                || original.dst().is_broadcast() || original.dst().is_multicast()
+                || original.dst_mac().is_multicast()
+                || original.fragment_offset() != 0 {
+                return Ok(());
+            }
+            if original.protocol() == IpProtocol::ICMP {
+                let is_error = IcmpMessage::decode(original.payload())
+                    .map(|message| message.is_error())
+                    .unwrap_or(true);
+                if is_error {
+                    return Ok(());
+                }
+            }
+            self.send_icmp(src, &message)
+        }
+    }
+}
@@ -0,0 +1,309 @@
+#![allow(dead_code)]
+#![allow(unused)]
+
+pub mod stats {
+    //! rlink-level counters of the frames going through each device,
+    //! complementing the capture statistics of pcap. Counters are kept per
+    //! network namespace and device name in a process-wide registry, so that
+    //! all handles on a device (e.g. the sending handle of a stack link and the capturing
+    //! handle of its pool) add up, and snapshots can be taken anywhere.
+
+    use crate::{EtherType, FrameError};
+    use std::collections::HashMap;
+    use std::fmt;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::{Arc, Mutex, OnceLock};
+
+    /// Live counters of a device.
+    #[derive(Debug, Default)]
+    pub struct DeviceCounters {
+        netns: Option<String>,
+        device: String,
+        rx_frames: AtomicU64,
+        rx_bytes: AtomicU64,
+        rx_broadcast: AtomicU64,
+        rx_multicast: AtomicU64,
+        rx_too_small: AtomicU64,
+        rx_checksum_errors: AtomicU64,
+        callback_consumed: AtomicU64,
+        rx_queue_drops: AtomicU64,
+        tx_frames: AtomicU64,
+        tx_bytes: AtomicU64,
+        tx_broadcast: AtomicU64,
+        tx_multicast: AtomicU64,
+        tx_errors: AtomicU64,
+        rx_ethertypes: Mutex<HashMap<EtherType, u64>>,
+        tx_ethertypes: Mutex<HashMap<EtherType, u64>>,
+    }
+
+    /// A frame's EtherType, with IEEE 802.3 length fields counted together,
+    /// and whether it is broadcast or multicast.
+    fn classify(frame: &[u8]) -> Option<(EtherType, bool, bool)> {
+        if frame.len() < 14 {
+            return None;
+        }
+        let ethtype = match EtherType::from(u16::from_be_bytes([frame[12], frame[13]])) {
+            EtherType::IEEE802_3(_) => EtherType::IEEE802_3(0),
+            ethtype => ethtype,
+        };
+        let broadcast = frame[..6] == [0xff; 6];
+        Some((ethtype, broadcast, !broadcast && frame[0] & 1 != 0))
+    }
+
+    impl DeviceCounters {
+        pub fn new(netns: Option<&str>, device: &str) -> Self {
+            DeviceCounters {
+                netns: netns.map(str::to_string),
+                device: device.to_string(),
+                ..Default::default()
+            }
+        }
+
+        /// The network namespace of the device, `None` for the one of the
+        /// process.
+        pub fn netns(&self) -> Option<&str> {
+            self.netns.as_deref()
+        }
+
+        pub fn device(&self) -> &str {
+            &self.device
+        }
+
+        /// Count a frame received from the device.
+        pub fn record_rx(&self, frame: &[u8]) {
+            self.rx_frames.fetch_add(1, Ordering::Relaxed);
+            self.rx_bytes.fetch_add(frame.len() as u64, Ordering::Relaxed);
+            if let Some((ethtype, broadcast, multicast)) = classify(frame) {
+                if broadcast {
+                    self.rx_broadcast.fetch_add(1, Ordering::Relaxed);
+                }
+                if multicast {
+                    self.rx_multicast.fetch_add(1, Ordering::Relaxed);
+                }
+                *self.rx_ethertypes.lock().unwrap().entry(ethtype).or_insert(0) += 1;
+            }
+        }
+
+        /// Count a frame sent through the device.
+        pub fn record_tx(&self, frame: &[u8]) {
+            self.tx_frames.fetch_add(1, Ordering::Relaxed);
+            self.tx_bytes.fetch_add(frame.len() as u64, Ordering::Relaxed);
+            if let Some((ethtype, broadcast, multicast)) = classify(frame) {
+                if broadcast {
+                    self.tx_broadcast.fetch_add(1, Ordering::Relaxed);
+                }
+                if multicast {
+                    self.tx_multicast.fetch_add(1, Ordering::Relaxed);
+                }
+                *self.tx_ethertypes.lock().unwrap().entry(ethtype).or_insert(0) += 1;
+            }
+        }
+
+        pub fn record_tx_error(&self) {
+            self.tx_errors.fetch_add(1, Ordering::Relaxed);
+        }
+
+        /// Count a received frame that failed to parse as Ethernet.
+        pub fn record_parse_error(&self, error: &FrameError) {
+            match error {
+                FrameError::TooSmall { .. } => self.rx_too_small.fetch_add(1, Ordering::Relaxed),
+                FrameError::ChecksumMismatch { .. } => self.rx_checksum_errors.fetch_add(1, Ordering::Relaxed),
+                _ => 0,
+            };
+        }
+
+        /// Count a received frame taken by the callback of a handle.
+        pub fn record_consumed(&self) {
+            self.callback_consumed.fetch_add(1, Ordering::Relaxed);
+        }
+
+        /// Count a received frame dropped by the queue of a device pool.
+        pub fn record_queue_drop(&self) {
+            self.rx_queue_drops.fetch_add(1, Ordering::Relaxed);
+        }
+
+        pub fn snapshot(&self) -> DeviceStats {
+            let load = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+            let sorted = |counts: &Mutex<HashMap<EtherType, u64>>| {
+                let mut counts: Vec<_> = counts.lock().unwrap().iter().map(|(k, v)| (*k, *v)).collect();
+                counts.sort_by_key(|(ethtype, _)| u16::from(*ethtype));
+                counts
+            };
+            DeviceStats {
+                netns: self.netns.clone(),
+                device: self.device.clone(),
+                rx_frames: load(&self.rx_frames),
+                rx_bytes: load(&self.rx_bytes),
+                rx_broadcast: load(&self.rx_broadcast),
+                rx_multicast: load(&self.rx_multicast),
+                rx_too_small: load(&self.rx_too_small),
+                rx_checksum_errors: load(&self.rx_checksum_errors),
+                callback_consumed: load(&self.callback_consumed),
+                rx_queue_drops: load(&self.rx_queue_drops),
+                tx_frames: load(&self.tx_frames),
+                tx_bytes: load(&self.tx_bytes),
+                tx_broadcast: load(&self.tx_broadcast),
+                tx_multicast: load(&self.tx_multicast),
+                tx_errors: load(&self.tx_errors),
+                rx_ethertypes: sorted(&self.rx_ethertypes),
+                tx_ethertypes: sorted(&self.tx_ethertypes),
+            }
+        }
+    }
+
+    /// A snapshot of the counters of a device, or a sum over devices.
+    /// Byte counts include the Ethernet header and trailer.
+    #[derive(Clone, Debug, Default, PartialEq, Eq)]
+    pub struct DeviceStats {
+        /// Network namespace of the device, `None` for the one of the
+        /// process or a sum over devices
+        pub netns: Option<String>,
+        pub device: String,
+        pub rx_frames: u64,
+        pub rx_bytes: u64,
+        pub rx_broadcast: u64,
+        pub rx_multicast: u64,
+        /// Frames shorter than the minimum frame size
+        pub rx_too_small: u64,
+        pub rx_checksum_errors: u64,
+        /// Frames taken by the callback of a handle
+        pub callback_consumed: u64,
+        /// Frames dropped by a full device pool queue
+        pub rx_queue_drops: u64,
+        pub tx_frames: u64,
+        pub tx_bytes: u64,
+        pub tx_broadcast: u64,
+        pub tx_multicast: u64,
+        /// Frames the device failed to send
+        pub tx_errors: u64,
+        /// Frames received per EtherType, IEEE 802.3 frames counted as
+        /// `IEEE802_3(0)`
+        pub rx_ethertypes: Vec<(EtherType, u64)>,
+        pub tx_ethertypes: Vec<(EtherType, u64)>,
+    }
+
+    fn merge_counts(into: &mut Vec<(EtherType, u64)>, from: &[(EtherType, u64)]) {
+        for (ethtype, count) in from.iter() {
+            match into.iter_mut().find(|(seen, _)| seen == ethtype) {
+                Some((_, total)) => *total += count,
+                None => into.push((*ethtype, *count)),
+            }
+        }
+        into.sort_by_key(|(ethtype, _)| u16::from(*ethtype));
+    }
+
+    impl DeviceStats {
+        /// Add the counts of `other` to these.
+        pub fn merge(&mut self, other: &DeviceStats) {
+            self.rx_frames += other.rx_frames;
+            self.rx_bytes += other.rx_bytes;
+            self.rx_broadcast += other.rx_broadcast;
+            self.rx_multicast += other.rx_multicast;
+            self.rx_too_small += other.rx_too_small;
+            self.rx_checksum_errors += other.rx_checksum_errors;
+            self.callback_consumed += other.callback_consumed;
+            self.rx_queue_drops += other.rx_queue_drops;
+            self.tx_frames += other.tx_frames;
+            self.tx_bytes += other.tx_bytes;
+            self.tx_broadcast += other.tx_broadcast;
+            self.tx_multicast += other.tx_multicast;
+            self.tx_errors += other.tx_errors;
+            merge_counts(&mut self.rx_ethertypes, &other.rx_ethertypes);
+            merge_counts(&mut self.tx_ethertypes, &other.tx_ethertypes);
+        }
+
+        /// The sum of several snapshots, under the given device name.
+        pub fn total<'a>(device: &str, stats: impl IntoIterator<Item = &'a DeviceStats>) -> DeviceStats {
+            let mut total = DeviceStats { device: device.to_string(), ..Default::default() };
+            for stats in stats {
+                total.merge(stats);
+            }
+            total
+        }
+
+        /// The counts since an `earlier` snapshot of the same device, e.g.
+        /// to print rates periodically.
+        pub fn since(&self, earlier: &DeviceStats) -> DeviceStats {
+            let counts = |now: &[(EtherType, u64)], before: &[(EtherType, u64)]| now.iter()
+                .map(|(ethtype, count)| {
+                    let before = before.iter().find(|(seen, _)| seen == ethtype).map_or(0, |(_, count)| *count);
+                    (*ethtype, count.saturating_sub(before))
+                })
+                .filter(|(_, count)| *count > 0)
+                .collect();
+            DeviceStats {
+                netns: self.netns.clone(),
+                device: self.device.clone(),
+                rx_frames: self.rx_frames.saturating_sub(earlier.rx_frames),
+                rx_bytes: self.rx_bytes.saturating_sub(earlier.rx_bytes),
+                rx_broadcast: self.rx_broadcast.saturating_sub(earlier.rx_broadcast),
+                rx_multicast: self.rx_multicast.saturating_sub(earlier.rx_multicast),
+                rx_too_small: self.rx_too_small.saturating_sub(earlier.rx_too_small),
+                rx_checksum_errors: self.rx_checksum_errors.saturating_sub(earlier.rx_checksum_errors),
+                callback_consumed: self.callback_consumed.saturating_sub(earlier.callback_consumed),
+                rx_queue_drops: self.rx_queue_drops.saturating_sub(earlier.rx_queue_drops),
+                tx_frames: self.tx_frames.saturating_sub(earlier.tx_frames),
+                tx_bytes: self.tx_bytes.saturating_sub(earlier.tx_bytes),
+                tx_broadcast: self.tx_broadcast.saturating_sub(earlier.tx_broadcast),
+                tx_multicast: self.tx_multicast.saturating_sub(earlier.tx_multicast),
+                tx_errors: self.tx_errors.saturating_sub(earlier.tx_errors),
+                rx_ethertypes: counts(&self.rx_ethertypes, &earlier.rx_ethertypes),
+                tx_ethertypes: counts(&self.tx_ethertypes, &earlier.tx_ethertypes),
+            }
+        }
+    }
+
+    impl fmt::Display for DeviceStats {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            match &self.netns {
+                Some(netns) => writeln!(f, "{} (netns {}):", self.device, netns)?,
+                None => writeln!(f, "{}:", self.device)?,
+            }
+            writeln!(f, "  RX {} frames, {} bytes ({} broadcast, {} multicast), {} too small, {} bad checksum, {} consumed, {} queue drops",
+                self.rx_frames, self.rx_bytes, self.rx_broadcast, self.rx_multicast,
+                self.rx_too_small, self.rx_checksum_errors, self.callback_consumed, self.rx_queue_drops)?;
+            write!(f, "  TX {} frames, {} bytes ({} broadcast, {} multicast), {} errors",
+                self.tx_frames, self.tx_bytes, self.tx_broadcast, self.tx_multicast, self.tx_errors)?;
+            for (direction, counts) in [("RX", &self.rx_ethertypes), ("TX", &self.tx_ethertypes)] {
+                if counts.is_empty() {
+                    continue;
+                }
+                write!(f, "\n  {} by type:", direction)?;
+                for (ethtype, count) in counts.iter() {
+                    match ethtype {
+                        EtherType::IEEE802_3(_) => write!(f, " 802.3={}", count)?,
+                        ethtype => write!(f, " {}={}", ethtype.abbreviation()
+                            .map_or_else(|| format!("0x{:04X}", u16::from(*ethtype)), |s| s.to_string()), count)?,
+                    }
+                }
+            }
+            Ok(())
+        }
+    }
+
+    type Registry = HashMap<(Option<String>, String), Arc<DeviceCounters>>;
+
+    fn registry() -> &'static Mutex<Registry> {
+        static REGISTRY: OnceLock<Mutex<Registry>> = OnceLock::new();
+        REGISTRY.get_or_init(|| Mutex::new(HashMap::new()))
+    }
+
+    /// The counters of the named device in network namespace `netns`
+    /// (`None` for the one of the process), shared by all its handles.
+    pub fn device_counters(netns: Option<&str>, device: &str) -> Arc<DeviceCounters> {
+        registry().lock().unwrap()
+            .entry((netns.map(str::to_string), device.to_string()))
+            .or_insert_with(|| Arc::new(DeviceCounters::new(netns, device)))
+            .clone()
+    }
+
+    /// Snapshots of all devices opened so far, by network namespace and
+    /// name.
+    pub fn snapshot() -> Vec<DeviceStats> {
+        let mut stats: Vec<_> = registry().lock().unwrap().values()
+            .map(|counters| counters.snapshot())
+            .collect();
+        stats.sort_by(|a, b| (&a.netns, &a.device).cmp(&(&b.netns, &b.device)));
+        stats
+    }
+}
@@ -0,0 +1,1365 @@
+#![allow(dead_code)]
+#![allow(unused)]
+
+pub mod tcp {
+    //! A minimal Transmission Control Protocol (RFC 793) on the rlink IPv4
+    //! stack: the connection state machine with three-way handshake and
+    //! orderly release, a sliding send window bounded by the peer's
+    //! advertised window, retransmission with RTT estimation (RFC 6298)
+    //! and exponential backoff, and blocking socket-like APIs.
+    //!
+    //! Segments are processed on the receiving thread of the stack, and
+    //! retransmissions on a timer thread, so the blocking calls never drive
+    //! the protocol themselves. There is no congestion control, urgent data
+    //! or window scaling.
+
+    use crate::ipv4::ipv4::{pseudo_header_checksum, IpProtocol, Ipv4, Ipv4Header};
+    use crate::stack::stack::WeakStack;
+    use crate::udp::udp::EPHEMERAL_PORTS;
+    use crate::{FrameError, Packet, RlinkError, Stack};
+    use std::collections::hash_map::RandomState;
+    use std::collections::{HashMap, VecDeque};
+    use std::hash::BuildHasher;
+    use std::io;
+    use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+    use std::sync::{Arc, Condvar, Mutex, MutexGuard, Weak};
+    use std::thread;
+    use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+    pub const HEADER_LEN: usize = 20;
+    /// Maximum segment size announced and used, keeping segments within
+    /// the 1499 bytes of payload a frame can carry.
+    pub const DEFAULT_MSS: u16 = 1400;
+    /// Size of the send and receive buffers of a connection.
+    pub const BUFFER_SIZE: usize = 65535;
+    /// Connections waiting to be accepted per listener, by default.
+    pub const DEFAULT_BACKLOG: usize = 16;
+
+    /// Retransmission timeout before any RTT measurement.
+    pub const INITIAL_RTO: Duration = Duration::from_secs(1);
+    pub const MIN_RTO: Duration = Duration::from_millis(200);
+    pub const MAX_RTO: Duration = Duration::from_secs(60);
+    /// Consecutive retransmissions of a segment before giving up.
+    pub const MAX_RETRIES: u32 = 8;
+    /// Time spent in TIME-WAIT (2 MSL, shortened for a lab stack).
+    pub const TIME_WAIT: Duration = Duration::from_secs(2);
+    /// Resolution of the timer thread.
+    const TICK: Duration = Duration::from_millis(10);
+    /// Segments held for reassembly per connection.
+    const MAX_OUT_OF_ORDER: usize = 64;
+
+    pub const FIN: u8 = 0x01;
+    pub const SYN: u8 = 0x02;
+    pub const RST: u8 = 0x04;
+    pub const PSH: u8 = 0x08;
+    pub const ACK: u8 = 0x10;
+    pub const URG: u8 = 0x20;
+
+    /// `a < b` in sequence space.
+    fn seq_lt(a: u32, b: u32) -> bool {
+        (a.wrapping_sub(b) as i32) < 0
+    }
+
+    fn seq_le(a: u32, b: u32) -> bool {
+        !seq_lt(b, a)
+    }
+
+    fn seq_gt(a: u32, b: u32) -> bool {
+        seq_lt(b, a)
+    }
+
+    fn seq_ge(a: u32, b: u32) -> bool {
+        !seq_lt(a, b)
+    }
+
+    /// A TCP segment. Of the options, only the maximum segment size is
+    /// understood.
+    #[derive(Clone, Debug, PartialEq, Eq)]
+    pub struct TcpSegment {
+        pub src_port: u16,
+        pub dst_port: u16,
+        pub seq: u32,
+        pub ack: u32,
+        pub flags: u8,
+        pub window: u16,
+        pub urgent: u16,
+        pub mss: Option<u16>,
+        pub payload: Vec<u8>,
+    }
+
+    impl TcpSegment {
+        pub fn has(&self, flag: u8) -> bool {
+            self.flags & flag != 0
+        }
+
+        /// Sequence space occupied by the segment: its payload, plus one
+        /// for each of SYN and FIN.
+        pub fn seq_len(&self) -> u32 {
+            self.payload.len() as u32 + self.has(SYN) as u32 + self.has(FIN) as u32
+        }
+
+        /// Encodes the segment, computing the checksum over the
+        /// pseudo-header of `src` and `dst`.
+        pub fn encode(&self, src: Ipv4Addr, dst: Ipv4Addr) -> Vec<u8> {
+            let header_len = HEADER_LEN + if self.mss.is_some() { 4 } else { 0 };
+            let mut data = Vec::with_capacity(header_len + self.payload.len());
+            data.extend_from_slice(&self.src_port.to_be_bytes());
+            data.extend_from_slice(&self.dst_port.to_be_bytes());
+            data.extend_from_slice(&self.seq.to_be_bytes());
+            data.extend_from_slice(&self.ack.to_be_bytes());
+            data.push(((header_len / 4) as u8) << 4);
+            data.push(self.flags);
+            data.extend_from_slice(&self.window.to_be_bytes());
+            data.extend_from_slice(&[0, 0]);
+            data.extend_from_slice(&self.urgent.to_be_bytes());
+            if let Some(mss) = self.mss {
+                data.extend_from_slice(&[2, 4]);
+                data.extend_from_slice(&mss.to_be_bytes());
+            }
+            data.extend_from_slice(&self.payload);
+            let sum = pseudo_header_checksum(src, dst, IpProtocol::TCP, &data);
+            data[16..18].copy_from_slice(&sum.to_be_bytes());
+            data
+        }
+
+        /// Decodes a segment from an IPv4 payload, verifying the checksum.
+        pub fn decode(data: &[u8], src: Ipv4Addr, dst: Ipv4Addr) -> Result<Self, FrameError> {
+            if data.len() < HEADER_LEN {
+                return Err(FrameError::Truncated { offset: 0, needed: HEADER_LEN, available: data.len() });
+            }
+            let header_len = (data[12] >> 4) as usize * 4;
+            if header_len < HEADER_LEN {
+                return Err(FrameError::InvalidField { offset: 12, field: "TCP data offset" });
+            }
+            if header_len > data.len() {
+                return Err(FrameError::Truncated { offset: 0, needed: header_len, available: data.len() });
+            }
+            if pseudo_header_checksum(src, dst, IpProtocol::TCP, data) != 0 {
+                let mut zeroed = data.to_vec();
+                zeroed[16] = 0;
+                zeroed[17] = 0;
+                return Err(FrameError::ChecksumMismatch {
+                    expected: pseudo_header_checksum(src, dst, IpProtocol::TCP, &zeroed) as u32,
+                    actual: u16::from_be_bytes([data[16], data[17]]) as u32,
+                });
+            }
+
+            let mut mss = None;
+            let mut options = &data[HEADER_LEN..header_len];
+            while let Some(&kind) = options.first() {
+                match kind {
+                    0 => break,
+                    1 => options = &options[1..],
+                    _ => {
+                        let len = *options.get(1).unwrap_or(&0) as usize;
+                        if len < 2 || len > options.len() {
+                            return Err(FrameError::InvalidField { offset: header_len - options.len(), field: "TCP option" });
+                        }
+                        if kind == 2 && len == 4 {
+                            mss = Some(u16::from_be_bytes([options[2], options[3]]));
+                        }
+                        options = &options[len..];
+                    }
+                }
+            }
+
+            let u32_at = |offset: usize| u32::from_be_bytes(data[offset..offset+4].try_into().unwrap());
+            Ok(TcpSegment {
+                src_port: u16::from_be_bytes([data[0], data[1]]),
+                dst_port: u16::from_be_bytes([data[2], data[3]]),
+                seq: u32_at(4),
+                ack: u32_at(8),
+                flags: data[13],
+                window: u16::from_be_bytes([data[14], data[15]]),
+                urgent: u16::from_be_bytes([data[18], data[19]]),
+                mss,
+                payload: data[header_len..].to_vec(),
+            })
+        }
+    }
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+    pub enum TcpState {
+        Closed,
+        Listen,
+        SynSent,
+        SynReceived,
+        Established,
+        FinWait1,
+        FinWait2,
+        CloseWait,
+        Closing,
+        LastAck,
+        TimeWait,
+    }
+
+    impl TcpState {
+        /// Whether the handshake completed and the connection is not
+        /// closed yet.
+        pub fn is_synchronized(&self) -> bool {
+            !matches!(self, TcpState::Closed | TcpState::Listen | TcpState::SynSent | TcpState::SynReceived)
+        }
+    }
+
+    /// Why a connection was torn down.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    enum Failure {
+        Refused,
+        Reset,
+        Timeout,
+    }
+
+    /// Transmission control block: the state of one connection.
+    struct Tcb {
+        state: TcpState,
+        local: SocketAddrV4,
+        remote: SocketAddrV4,
+
+        iss: u32,
+        snd_una: u32,
+        snd_nxt: u32,
+        snd_wnd: u32,
+        snd_wl1: u32,
+        snd_wl2: u32,
+        snd_mss: usize,
+        /// Sequence number of the first byte of `send_buf`
+        snd_data: u32,
+        /// Written bytes not acknowledged yet
+        send_buf: VecDeque<u8>,
+        /// Whether the application closed its sending side
+        fin_queued: bool,
+        /// Sequence number of our FIN, once sent
+        fin_seq: Option<u32>,
+
+        irs: u32,
+        rcv_nxt: u32,
+        recv_buf: VecDeque<u8>,
+        out_of_order: Vec<(u32, Vec<u8>)>,
+        fin_received: bool,
+        /// Window announced in the last segment sent
+        last_window: u32,
+
+        srtt: Option<Duration>,
+        rttvar: Duration,
+        rto: Duration,
+        /// Doublings of `rto` since the last new acknowledgment
+        backoff: u32,
+        /// Sequence number whose acknowledgment completes the RTT
+        /// measurement in progress, and when it started
+        rtt_sample: Option<(u32, Instant)>,
+        retransmit_at: Option<Instant>,
+        retries: u32,
+        time_wait_until: Option<Instant>,
+
+        failure: Option<Failure>,
+        /// Listener to hand the connection to once established
+        listener: Option<Weak<Listener>>,
+    }
+
+    /// Initial sequence number: a clock ticking every 4 µs plus a keyed
+    /// hash of the connection, in the spirit of RFC 6528.
+    fn initial_sequence(local: SocketAddrV4, remote: SocketAddrV4) -> u32 {
+        let clock = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_micros() / 4;
+        (clock as u32).wrapping_add(RandomState::new().hash_one((local, remote)) as u32)
+    }
+
+    impl Tcb {
+        fn new(local: SocketAddrV4, remote: SocketAddrV4, state: TcpState) -> Self {
+            let iss = initial_sequence(local, remote);
+            Tcb {
+                state,
+                local,
+                remote,
+                iss,
+                snd_una: iss,
+                snd_nxt: iss,
+                snd_wnd: 0,
+                snd_wl1: 0,
+                snd_wl2: 0,
+                snd_mss: DEFAULT_MSS as usize,
+                snd_data: iss.wrapping_add(1),
+                send_buf: VecDeque::new(),
+                fin_queued: false,
+                fin_seq: None,
+                irs: 0,
+                rcv_nxt: 0,
+                recv_buf: VecDeque::new(),
+                out_of_order: Vec::new(),
+                fin_received: false,
+                last_window: 0,
+                srtt: None,
+                rttvar: Duration::ZERO,
+                rto: INITIAL_RTO,
+                backoff: 0,
+                rtt_sample: None,
+                retransmit_at: None,
+                retries: 0,
+                time_wait_until: None,
+                failure: None,
+                listener: None,
+            }
+        }
+
+        fn rcv_window(&self) -> u32 {
+            (BUFFER_SIZE - self.recv_buf.len()).min(u16::MAX as usize) as u32
+        }
+
+        /// A segment from this connection, acknowledging what was received
+        /// when `flags` has ACK.
+        fn segment(&mut self, seq: u32, flags: u8, payload: Vec<u8>) -> TcpSegment {
+            self.last_window = self.rcv_window();
+            TcpSegment {
+                src_port: self.local.port(),
+                dst_port: self.remote.port(),
+                seq,
+                ack: if flags & ACK != 0 { self.rcv_nxt } else { 0 },
+                flags,
+                window: self.last_window as u16,
+                urgent: 0,
+                mss: None,
+                payload,
+            }
+        }
+
+        fn ack_segment(&mut self) -> TcpSegment {
+            self.segment(self.snd_nxt, ACK, Vec::new())
+        }
+
+        fn syn_segment(&mut self) -> TcpSegment {
+            let flags = if self.state == TcpState::SynReceived { SYN | ACK } else { SYN };
+            let mut segment = self.segment(self.iss, flags, Vec::new());
+            segment.mss = Some(DEFAULT_MSS);
+            segment
+        }
+
+        /// Send the SYN (or SYN-ACK) opening the connection.
+        fn open(&mut self, now: Instant) -> TcpSegment {
+            self.snd_nxt = self.iss.wrapping_add(1);
+            self.retransmit_at = Some(now + self.timeout());
+            self.rtt_sample = Some((self.snd_nxt, now));
+            self.syn_segment()
+        }
+
+        fn data_segment(&mut self, seq: u32, len: usize) -> TcpSegment {
+            let offset = seq.wrapping_sub(self.snd_data) as usize;
+            let payload: Vec<u8> = self.send_buf.range(offset..offset + len).copied().collect();
+            let flags = if offset + len == self.send_buf.len() { ACK | PSH } else { ACK };
+            self.segment(seq, flags, payload)
+        }
+
+        fn fail(&mut self, failure: Failure) {
+            self.failure = Some(failure);
+            self.state = TcpState::Closed;
+            self.retransmit_at = None;
+        }
+
+        fn enter_time_wait(&mut self, now: Instant) {
+            self.state = TcpState::TimeWait;
+            self.retransmit_at = None;
+            self.time_wait_until = Some(now + TIME_WAIT);
+        }
+
+        /// Update the RTT estimators with a measurement (RFC 6298).
+        fn update_rtt(&mut self, rtt: Duration) {
+            match self.srtt {
+                None => {
+                    self.srtt = Some(rtt);
+                    self.rttvar = rtt / 2;
+                }
+                Some(srtt) => {
+                    let delta = srtt.abs_diff(rtt);
+                    self.rttvar = self.rttvar * 3 / 4 + delta / 4;
+                    self.srtt = Some(srtt * 7 / 8 + rtt / 8);
+                }
+            }
+            self.rto = (self.srtt.unwrap() + (self.rttvar * 4).max(TICK)).clamp(MIN_RTO, MAX_RTO);
+        }
+
+        /// The retransmission timeout, backed off.
+        fn timeout(&self) -> Duration {
+            self.rto.saturating_mul(1 << self.backoff.min(16)).min(MAX_RTO)
+        }
+
+        /// Send whatever the peer's window allows, then our FIN once all
+        /// data is out.
+        fn output(&mut self, now: Instant) -> Vec<TcpSegment> {
+            let mut segments = Vec::new();
+            if !self.state.is_synchronized() || self.state == TcpState::TimeWait {
+                return segments;
+            }
+            loop {
+                let sent = self.snd_nxt.wrapping_sub(self.snd_data) as usize;
+                if sent >= self.send_buf.len() {
+                    break;
+                }
+                let in_flight = self.snd_nxt.wrapping_sub(self.snd_una) as usize;
+                let len = (self.snd_wnd as usize).saturating_sub(in_flight)
+                    .min(self.snd_mss)
+                    .min(self.send_buf.len() - sent);
+                if len == 0 {
+                    // Zero window: the retransmission timer doubles as the
+                    // persist timer, probing the window when it fires.
+                    if self.retransmit_at.is_none() {
+                        self.retransmit_at = Some(now + self.timeout());
+                    }
+                    break;
+                }
+                let segment = self.data_segment(self.snd_nxt, len);
+                self.snd_nxt = self.snd_nxt.wrapping_add(len as u32);
+                if self.rtt_sample.is_none() {
+                    self.rtt_sample = Some((self.snd_nxt, now));
+                }
+                segments.push(segment);
+            }
+            let all_sent = self.snd_nxt.wrapping_sub(self.snd_data) as usize == self.send_buf.len();
+            if self.fin_queued && self.fin_seq.is_none() && all_sent {
+                self.fin_seq = Some(self.snd_nxt);
+                let segment = self.segment(self.snd_nxt, FIN | ACK, Vec::new());
+                self.snd_nxt = self.snd_nxt.wrapping_add(1);
+                segments.push(segment);
+            }
+            if !segments.is_empty() {
+                if self.retransmit_at.is_none() {
+                    self.retransmit_at = Some(now + self.timeout());
+                }
+                if self.rtt_sample.is_none() {
+                    self.rtt_sample = Some((self.snd_nxt, now));
+                }
+            }
+            segments
+        }
+
+        /// Handle expiry of the retransmission or TIME-WAIT timer.
+        fn on_tick(&mut self, now: Instant) -> Vec<TcpSegment> {
+            if self.time_wait_until.is_some_and(|at| now >= at) {
+                self.state = TcpState::Closed;
+                return Vec::new();
+            }
+            match self.retransmit_at {
+                Some(at) if now >= at => (),
+                _ => return Vec::new(),
+            }
+
+            let unsent = self.send_buf.len() as i64 - self.snd_nxt.wrapping_sub(self.snd_data) as i64;
+            if self.snd_una == self.snd_nxt && unsent > 0 {
+                // Window probe: one byte beyond the closed window.
+                self.retransmit_at = Some(now + self.timeout());
+                self.backoff += 1;
+                let segment = self.data_segment(self.snd_nxt, 1);
+                self.snd_nxt = self.snd_nxt.wrapping_add(1);
+                return vec![segment];
+            }
+            if self.snd_una == self.snd_nxt {
+                self.retransmit_at = None;
+                return Vec::new();
+            }
+
+            self.retries += 1;
+            if self.retries > MAX_RETRIES {
+                self.fail(Failure::Timeout);
+                return Vec::new();
+            }
+            // Back off, and do not time retransmitted segments (Karn).
+            self.backoff += 1;
+            self.rtt_sample = None;
+            self.retransmit_at = Some(now + self.timeout());
+
+            let segment = match self.state {
+                TcpState::SynSent | TcpState::SynReceived => self.syn_segment(),
+                _ if self.fin_seq == Some(self.snd_una) => self.segment(self.snd_una, FIN | ACK, Vec::new()),
+                _ => {
+                    let offset = self.snd_una.wrapping_sub(self.snd_data) as usize;
+                    let in_flight = self.snd_nxt.wrapping_sub(self.snd_una) as usize;
+                    let len = self.snd_mss.min(self.send_buf.len() - offset).min(in_flight);
+                    self.data_segment(self.snd_una, len)
+                }
+            };
+            vec![segment]
+        }
+
+        /// Process a segment in the SYN-SENT state.
+        fn syn_sent_arrives(&mut self, segment: &TcpSegment, now: Instant) -> Vec<TcpSegment> {
+            let acceptable_ack = segment.has(ACK)
+                && seq_gt(segment.ack, self.iss) && seq_le(segment.ack, self.snd_nxt);
+            if segment.has(ACK) && !acceptable_ack {
+                return match segment.has(RST) {
+                    true => Vec::new(),
+                    false => vec![self.segment(segment.ack, RST, Vec::new())],
+                };
+            }
+            if segment.has(RST) {
+                if acceptable_ack {
+                    self.fail(Failure::Refused);
+                }
+                return Vec::new();
+            }
+            if !segment.has(SYN) {
+                return Vec::new();
+            }
+
+            self.irs = segment.seq;
+            self.rcv_nxt = segment.seq.wrapping_add(1);
+            self.snd_mss = segment.mss.unwrap_or(536).min(DEFAULT_MSS) as usize;
+            self.snd_wnd = segment.window as u32;
+            self.snd_wl1 = segment.seq;
+            self.snd_wl2 = segment.ack;
+            if !acceptable_ack {
+                // Simultaneous open
+                self.state = TcpState::SynReceived;
+                return vec![self.syn_segment()];
+            }
+            self.snd_una = segment.ack;
+            self.take_rtt_sample(now);
+            self.retransmit_at = None;
+            self.retries = 0;
+            self.state = TcpState::Established;
+            let mut segments = vec![self.ack_segment()];
+            segments.extend(self.output(now));
+            segments
+        }
+
+        fn take_rtt_sample(&mut self, now: Instant) {
+            if let Some((seq, at)) = self.rtt_sample {
+                if seq_ge(self.snd_una, seq) {
+                    self.update_rtt(now - at);
+                    self.rtt_sample = None;
+                }
+            }
+        }
+
+        /// Whether a segment falls within the receive window (RFC 793,
+        /// section 3.3).
+        fn is_acceptable(&self, segment: &TcpSegment) -> bool {
+            let window = self.rcv_window();
+            let in_window = |seq: u32| seq_ge(seq, self.rcv_nxt)
+                && seq_lt(seq, self.rcv_nxt.wrapping_add(window));
+            match (segment.seq_len(), window) {
+                (0, 0) => segment.seq == self.rcv_nxt,
+                (0, _) => in_window(segment.seq),
+                (_, 0) => false,
+                (len, _) => in_window(segment.seq) || in_window(segment.seq.wrapping_add(len - 1)),
+            }
+        }
+
+        /// Queue received data, in order or for reassembly.
+        fn receive_data(&mut self, seq: u32, payload: &[u8]) {
+            let skip = self.rcv_nxt.wrapping_sub(seq);
+            let (seq, payload) = if seq_lt(seq, self.rcv_nxt) {
+                match payload.get(skip as usize..) {
+                    Some(rest) if !rest.is_empty() => (self.rcv_nxt, rest),
+                    _ => return,
+                }
+            }
+            else {
+                (seq, payload)
+            };
+            let room = (self.rcv_window() as usize).saturating_sub(seq.wrapping_sub(self.rcv_nxt) as usize);
+            let payload = &payload[..payload.len().min(room)];
+            if payload.is_empty() {
+                return;
+            }
+            if seq != self.rcv_nxt {
+                if self.out_of_order.len() < MAX_OUT_OF_ORDER
+                    && !self.out_of_order.iter().any(|(other, _)| *other == seq) {
+                    self.out_of_order.push((seq, payload.to_vec()));
+                }
+                return;
+            }
+            self.recv_buf.extend(payload);
+            self.rcv_nxt = self.rcv_nxt.wrapping_add(payload.len() as u32);
+
+            while let Some(index) = self.out_of_order.iter().position(|(seq, _)| seq_le(*seq, self.rcv_nxt)) {
+                let (seq, data) = self.out_of_order.swap_remove(index);
+                let skip = self.rcv_nxt.wrapping_sub(seq) as usize;
+                if skip < data.len() {
+                    self.recv_buf.extend(&data[skip..]);
+                    self.rcv_nxt = self.rcv_nxt.wrapping_add((data.len() - skip) as u32);
+                }
+            }
+        }
+
+        /// Process a segment of an existing connection. Returns the
+        /// segments to send in response.
+        fn segment_arrives(&mut self, segment: &TcpSegment, now: Instant) -> Vec<TcpSegment> {
+            match self.state {
+                TcpState::Closed | TcpState::Listen => return Vec::new(),
+                TcpState::SynSent => return self.syn_sent_arrives(segment, now),
+                _ => (),
+            }
+
+            if !self.is_acceptable(segment) {
+                return match segment.has(RST) {
+                    true => Vec::new(),
+                    false => vec![self.ack_segment()],
+                };
+            }
+            if segment.has(RST) {
+                if self.state == TcpState::SynReceived && self.listener.is_some() {
+                    self.state = TcpState::Closed;
+                }
+                else {
+                    self.fail(Failure::Reset);
+                }
+                return Vec::new();
+            }
+            if segment.has(SYN) {
+                self.fail(Failure::Reset);
+                return vec![self.segment(self.snd_nxt, RST, Vec::new())];
+            }
+            if !segment.has(ACK) {
+                return Vec::new();
+            }
+
+            if self.state == TcpState::SynReceived {
+                if !(seq_lt(self.snd_una, segment.ack) && seq_le(segment.ack, self.snd_nxt)) {
+                    return vec![self.segment(segment.ack, RST, Vec::new())];
+                }
+                self.state = TcpState::Established;
+                self.snd_wnd = segment.window as u32;
+                self.snd_wl1 = segment.seq;
+                self.snd_wl2 = segment.ack;
+            }
+            if seq_gt(segment.ack, self.snd_nxt) {
+                return vec![self.ack_segment()];
+            }
+            if seq_lt(self.snd_una, segment.ack) {
+                if seq_gt(segment.ack, self.snd_data) {
+                    let acked = (segment.ack.wrapping_sub(self.snd_data) as usize).min(self.send_buf.len());
+                    self.send_buf.drain(..acked);
+                    self.snd_data = self.snd_data.wrapping_add(acked as u32);
+                }
+                self.snd_una = segment.ack;
+                self.take_rtt_sample(now);
+                self.retries = 0;
+                self.backoff = 0;
+                self.retransmit_at = if self.snd_una == self.snd_nxt { None } else { Some(now + self.timeout()) };
+            }
+            else if segment.ack == self.snd_una && segment.window == 0 {
+                // The peer answers window probes: it is alive.
+                self.retries = 0;
+            }
+            if seq_lt(self.snd_wl1, segment.seq)
+                || (self.snd_wl1 == segment.seq && seq_le(self.snd_wl2, segment.ack)) {
+                self.snd_wnd = segment.window as u32;
+                self.snd_wl1 = segment.seq;
+                self.snd_wl2 = segment.ack;
+            }
+
+            if self.state == TcpState::Established {
+                if let Some(listener) = self.listener.take() {
+                    match listener.upgrade() {
+                        Some(listener) => listener.push(self.local, self.remote),
+                        None => {
+                            self.fail(Failure::Reset);
+                            return vec![self.segment(self.snd_nxt, RST, Vec::new())];
+                        }
+                    }
+                }
+            }
+
+            let fin_acked = self.fin_seq.is_some_and(|fin| seq_gt(self.snd_una, fin));
+            match self.state {
+                TcpState::FinWait1 if fin_acked => self.state = TcpState::FinWait2,
+                TcpState::Closing if fin_acked => self.enter_time_wait(now),
+                TcpState::LastAck if fin_acked => {
+                    self.state = TcpState::Closed;
+                    return Vec::new();
+                }
+                _ => (),
+            }
+
+            let mut need_ack = false;
+            if !segment.payload.is_empty()
+                && matches!(self.state, TcpState::Established | TcpState::FinWait1 | TcpState::FinWait2) {
+                self.receive_data(segment.seq, &segment.payload);
+                need_ack = true;
+            }
+
+            let fin_seq = segment.seq.wrapping_add(segment.payload.len() as u32);
+            if segment.has(FIN) && (fin_seq == self.rcv_nxt || self.state == TcpState::TimeWait) {
+                if !self.fin_received {
+                    self.fin_received = true;
+                    self.rcv_nxt = self.rcv_nxt.wrapping_add(1);
+                }
+                match self.state {
+                    TcpState::Established => self.state = TcpState::CloseWait,
+                    TcpState::FinWait1 if fin_acked => self.enter_time_wait(now),
+                    TcpState::FinWait1 => self.state = TcpState::Closing,
+                    TcpState::FinWait2 | TcpState::TimeWait => self.enter_time_wait(now),
+                    _ => (),
+                }
+                need_ack = true;
+            }
+
+            let mut segments = self.output(now);
+            if need_ack && segments.is_empty() {
+                segments.push(self.ack_segment());
+            }
+            segments
+        }
+    }
+
+    /// Reply to a segment no connection exists for (RFC 793, "If the
+    /// connection does not exist").
+    fn reset_for(segment: &TcpSegment) -> Option<TcpSegment> {
+        if segment.has(RST) {
+            return None;
+        }
+        let (seq, ack, flags) = match segment.has(ACK) {
+            true => (segment.ack, 0, RST),
+            false => (0, segment.seq.wrapping_add(segment.seq_len()), RST | ACK),
+        };
+        Some(TcpSegment {
+            src_port: segment.dst_port,
+            dst_port: segment.src_port,
+            seq,
+            ack,
+            flags,
+            window: 0,
+            urgent: 0,
+            mss: None,
+            payload: Vec::new(),
+        })
+    }
+
+    fn send_segment(stack: &Stack, local: SocketAddrV4, remote: SocketAddrV4, segment: &TcpSegment) {
+        let header = Ipv4Header::new(*local.ip(), *remote.ip(), IpProtocol::TCP);
+        // Lost segments are recovered by retransmission.
+        let _ = stack.send_ipv4_with(header, &segment.encode(*local.ip(), *remote.ip()));
+    }
+
+    struct Connection {
+        tcb: Mutex<Tcb>,
+        /// Signalled whenever the state of the connection changes
+        changed: Condvar,
+    }
+
+    struct Listener {
+        /// Local address connections are accepted for, if restricted
+        ip: Option<Ipv4Addr>,
+        /// Established connections not accepted yet
+        queue: Mutex<VecDeque<(SocketAddrV4, SocketAddrV4)>>,
+        ready: Condvar,
+        backlog: usize,
+    }
+
+    impl Listener {
+        fn push(&self, local: SocketAddrV4, remote: SocketAddrV4) {
+            self.queue.lock().unwrap().push_back((local, remote));
+            self.ready.notify_all();
+        }
+    }
+
+    type ConnectionKey = (SocketAddrV4, SocketAddrV4);
+
+    /// The TCP connections and listeners of a stack.
+    #[derive(Default)]
+    pub struct TcpLayer {
+        connections: Mutex<HashMap<ConnectionKey, Arc<Connection>>>,
+        listeners: Mutex<HashMap<u16, Arc<Listener>>>,
+    }
+
+    impl TcpLayer {
+        /// Local address, remote address and state of every connection.
+        pub fn connections(&self) -> Vec<(SocketAddrV4, SocketAddrV4, TcpState)> {
+            self.connections.lock().unwrap().iter()
+                .map(|((local, remote), conn)| (*local, *remote, conn.tcb.lock().unwrap().state))
+                .collect()
+        }
+
+        pub fn is_listening(&self, port: u16) -> bool {
+            self.listeners.lock().unwrap().contains_key(&port)
+        }
+
+        fn connection(&self, local: SocketAddrV4, remote: SocketAddrV4) -> Option<Arc<Connection>> {
+            self.connections.lock().unwrap().get(&(local, remote)).cloned()
+        }
+
+        fn insert(&self, tcb: Tcb) -> Arc<Connection> {
+            let key = (tcb.local, tcb.remote);
+            let conn = Arc::new(Connection { tcb: Mutex::new(tcb), changed: Condvar::new() });
+            self.connections.lock().unwrap().insert(key, conn.clone());
+            conn
+        }
+
+        fn remove(&self, local: SocketAddrV4, remote: SocketAddrV4) {
+            self.connections.lock().unwrap().remove(&(local, remote));
+        }
+
+        /// A free local port towards `remote`.
+        fn ephemeral_port(&self, local: Ipv4Addr, remote: SocketAddrV4) -> Result<u16, RlinkError> {
+            let connections = self.connections.lock().unwrap();
+            let listeners = self.listeners.lock().unwrap();
+            let span = (EPHEMERAL_PORTS.end() - EPHEMERAL_PORTS.start()) as u32 + 1;
+            let offset = RandomState::new().hash_one(remote) as u32 % span;
+            (0..span)
+                .map(|i| EPHEMERAL_PORTS.start() + ((offset + i) % span) as u16)
+                .find(|port| !listeners.contains_key(port)
+                    && !connections.contains_key(&(SocketAddrV4::new(local, *port), remote)))
+                .ok_or(RlinkError::AddrInUse(0))
+        }
+
+        /// Run `f` on the control block of a connection, then wake its
+        /// waiters, send the resulting segments and forget the connection
+        /// if it closed.
+        fn update<F>(&self, stack: &Stack, conn: &Connection, f: F)
+        where
+            F: FnOnce(&mut Tcb) -> Vec<TcpSegment>,
+        {
+            let mut tcb = conn.tcb.lock().unwrap();
+            let segments = f(&mut tcb);
+            let (local, remote, closed) = (tcb.local, tcb.remote, tcb.state == TcpState::Closed);
+            drop(tcb);
+            conn.changed.notify_all();
+            for segment in segments.iter() {
+                send_segment(stack, local, remote, segment);
+            }
+            if closed {
+                self.remove(local, remote);
+            }
+        }
+
+        /// Process a received TCP segment.
+        pub(crate) fn input(&self, stack: &Stack, packet: Packet<Ipv4>) {
+            if packet.dst().is_broadcast() || packet.dst().is_multicast() || stack.is_subnet_broadcast(packet.dst()) {
+                return;
+            }
+            let segment = match TcpSegment::decode(packet.payload(), packet.src(), packet.dst()) {
+                Ok(segment) => segment,
+                Err(_) => return,
+            };
+            let local = SocketAddrV4::new(packet.dst(), segment.dst_port);
+            let remote = SocketAddrV4::new(packet.src(), segment.src_port);
+            let now = Instant::now();
+
+            if let Some(conn) = self.connection(local, remote) {
+                self.update(stack, &conn, |tcb| tcb.segment_arrives(&segment, now));
+                return;
+            }
+
+            let listener = self.listeners.lock().unwrap().get(&local.port()).cloned();
+            match listener {
+                Some(listener) if segment.flags & (SYN | ACK | RST) == SYN
+                    && listener.ip.is_none_or(|ip| ip == *local.ip()) => {
+                    if listener.queue.lock().unwrap().len() >= listener.backlog {
+                        return;
+                    }
+                    let mut tcb = Tcb::new(local, remote, TcpState::SynReceived);
+                    tcb.irs = segment.seq;
+                    tcb.rcv_nxt = segment.seq.wrapping_add(1);
+                    tcb.snd_mss = segment.mss.unwrap_or(536).min(DEFAULT_MSS) as usize;
+                    tcb.snd_wnd = segment.window as u32;
+                    tcb.snd_wl1 = segment.seq;
+                    tcb.listener = Some(Arc::downgrade(&listener));
+                    let syn_ack = tcb.open(now);
+                    self.insert(tcb);
+                    send_segment(stack, local, remote, &syn_ack);
+                }
+                _ => {
+                    if let Some(reset) = reset_for(&segment) {
+                        send_segment(stack, local, remote, &reset);
+                    }
+                }
+            }
+        }
+
+        /// Start the thread driving retransmissions and TIME-WAIT, which
+        /// ends with the stack.
+        pub(crate) fn start_timer(self: &Arc<Self>, stack: WeakStack) {
+            let layer: Weak<TcpLayer> = Arc::downgrade(self);
+            thread::spawn(move || loop {
+                thread::sleep(TICK);
+                let (stack, layer) = match (stack.upgrade(), layer.upgrade()) {
+                    (Some(stack), Some(layer)) => (stack, layer),
+                    _ => break,
+                };
+                let now = Instant::now();
+                let connections: Vec<_> = layer.connections.lock().unwrap().values().cloned().collect();
+                for conn in connections {
+                    layer.update(&stack, &conn, |tcb| tcb.on_tick(now));
+                }
+            });
+        }
+    }
+
+    fn io_error(e: RlinkError) -> io::Error {
+        let kind = match e {
+            RlinkError::Timeout => io::ErrorKind::TimedOut,
+            RlinkError::ConnectionRefused(_) => io::ErrorKind::ConnectionRefused,
+            RlinkError::ConnectionReset => io::ErrorKind::ConnectionReset,
+            RlinkError::NotConnected => io::ErrorKind::NotConnected,
+            _ => io::ErrorKind::Other,
+        };
+        io::Error::new(kind, e)
+    }
+
+    /// A TCP connection on a stack, modelled after `std::net::TcpStream`.
+    /// Dropping the stream closes it; the stack completes the closing
+    /// handshake in the background.
+    pub struct TcpStream {
+        stack: Stack,
+        conn: Arc<Connection>,
+        local: SocketAddrV4,
+        remote: SocketAddrV4,
+        read_timeout: Option<Duration>,
+        write_timeout: Option<Duration>,
+    }
+
+    impl TcpStream {
+        /// Open a connection to `addr`, giving up after the SYN has been
+        /// retransmitted `MAX_RETRIES` times.
+        pub fn connect(stack: &Stack, addr: SocketAddrV4) -> Result<TcpStream, RlinkError> {
+            Self::connect_with(stack, addr, None)
+        }
+
+        /// Open a connection to `addr`, waiting up to `timeout`.
+        pub fn connect_timeout(stack: &Stack, addr: SocketAddrV4, timeout: Duration) -> Result<TcpStream, RlinkError> {
+            Self::connect_with(stack, addr, Some(timeout))
+        }
+
+        fn connect_with(stack: &Stack, addr: SocketAddrV4, timeout: Option<Duration>) -> Result<TcpStream, RlinkError> {
+            let layer = stack.tcp();
+            let local_ip = stack.source_for(*addr.ip())?;
+            let local = SocketAddrV4::new(local_ip, layer.ephemeral_port(local_ip, addr)?);
+            let mut tcb = Tcb::new(local, addr, TcpState::SynSent);
+            let syn = tcb.open(Instant::now());
+            let conn = layer.insert(tcb);
+            send_segment(stack, local, addr, &syn);
+
+            let stream = TcpStream {
+                stack: stack.clone(),
+                conn,
+                local,
+                remote: addr,
+                read_timeout: None,
+                write_timeout: None,
+            };
+            let deadline = timeout.map(|timeout| Instant::now() + timeout);
+            let failure = stream.wait(deadline, |tcb| !matches!(tcb.state, TcpState::SynSent | TcpState::SynReceived))
+                .map(|tcb| tcb.failure);
+            match failure {
+                Ok(None) => Ok(stream),
+                Ok(failure) => Err(stream.error(failure)),
+                Err(e) => {
+                    stream.abort();
+                    Err(e)
+                }
+            }
+        }
+
+        /// Wait until `ready` holds or the connection failed, up to `deadline`.
+        fn wait<F>(&self, deadline: Option<Instant>, ready: F) -> Result<MutexGuard<'_, Tcb>, RlinkError>
+        where
+            F: Fn(&Tcb) -> bool,
+        {
+            let mut tcb = self.conn.tcb.lock().unwrap();
+            while !ready(&tcb) && tcb.failure.is_none() {
+                match deadline {
+                    Some(deadline) => {
+                        let now = Instant::now();
+                        if now >= deadline {
+                            return Err(RlinkError::Timeout);
+                        }
+                        tcb = self.conn.changed.wait_timeout(tcb, deadline - now).unwrap().0;
+                    }
+                    None => tcb = self.conn.changed.wait(tcb).unwrap(),
+                }
+            }
+            Ok(tcb)
+        }
+
+        fn error(&self, failure: Option<Failure>) -> RlinkError {
+            match failure {
+                Some(Failure::Refused) => RlinkError::ConnectionRefused(SocketAddr::V4(self.remote)),
+                Some(Failure::Reset) => RlinkError::ConnectionReset,
+                Some(Failure::Timeout) => RlinkError::Timeout,
+                None => RlinkError::NotConnected,
+            }
+        }
+
+        pub fn local_addr(&self) -> SocketAddrV4 {
+            self.local
+        }
+
+        pub fn peer_addr(&self) -> SocketAddrV4 {
+            self.remote
+        }
+
+        pub fn state(&self) -> TcpState {
+            self.conn.tcb.lock().unwrap().state
+        }
+
+        /// Smoothed round-trip time, once measured.
+        pub fn srtt(&self) -> Option<Duration> {
+            self.conn.tcb.lock().unwrap().srtt
+        }
+
+        /// Set the timeout of `read()`; `None` blocks indefinitely.
+        pub fn set_read_timeout(&mut self, timeout: Option<Duration>) {
+            self.read_timeout = timeout;
+        }
+
+        /// Set the timeout of `write()`; `None` blocks indefinitely.
+        pub fn set_write_timeout(&mut self, timeout: Option<Duration>) {
+            self.write_timeout = timeout;
+        }
+
+        /// Read received data, blocking until some is available. Returns 0
+        /// once the peer closed its side and everything was read.
+        pub fn read(&self, buf: &mut [u8]) -> Result<usize, RlinkError> {
+            let deadline = self.read_timeout.map(|timeout| Instant::now() + timeout);
+            let mut tcb = self.wait(deadline, |tcb| !tcb.recv_buf.is_empty() || tcb.fin_received)?;
+            if tcb.recv_buf.is_empty() {
+                return match tcb.failure {
+                    Some(_) if !tcb.fin_received => Err(self.error(tcb.failure)),
+                    _ => Ok(0),
+                };
+            }
+            let len = buf.len().min(tcb.recv_buf.len());
+            for (dst, src) in buf.iter_mut().zip(tcb.recv_buf.drain(..len)) {
+                *dst = src;
+            }
+            // Announce the reopened window once it grew enough to matter
+            // (receiver-side silly window syndrome avoidance).
+            let threshold = (BUFFER_SIZE as u32 / 2).min(tcb.snd_mss as u32);
+            if tcb.state.is_synchronized() && tcb.rcv_window() >= tcb.last_window + threshold {
+                let update = tcb.ack_segment();
+                drop(tcb);
+                send_segment(&self.stack, self.local, self.remote, &update);
+            }
+            Ok(len)
+        }
+
+        /// Queue data for sending, blocking until there is room for some of
+        /// it. Returns the number of bytes queued.
+        pub fn write(&self, buf: &[u8]) -> Result<usize, RlinkError> {
+            if buf.is_empty() {
+                return Ok(0);
+            }
+            let deadline = self.write_timeout.map(|timeout| Instant::now() + timeout);
+            let mut tcb = self.wait(deadline, |tcb| tcb.send_buf.len() < BUFFER_SIZE || tcb.fin_queued)?;
+            if tcb.failure.is_some() {
+                return Err(self.error(tcb.failure));
+            }
+            if tcb.fin_queued || !matches!(tcb.state, TcpState::Established | TcpState::CloseWait) {
+                return Err(RlinkError::NotConnected);
+            }
+            let len = buf.len().min(BUFFER_SIZE - tcb.send_buf.len());
+            tcb.send_buf.extend(&buf[..len]);
+            let segments = tcb.output(Instant::now());
+            drop(tcb);
+            for segment in segments.iter() {
+                send_segment(&self.stack, self.local, self.remote, segment);
+            }
+            Ok(len)
+        }
+
+        /// Block until everything written was acknowledged by the peer.
+        pub fn flush(&self) -> Result<(), RlinkError> {
+            let deadline = self.write_timeout.map(|timeout| Instant::now() + timeout);
+            let tcb = self.wait(deadline, |tcb| tcb.send_buf.is_empty())?;
+            match tcb.failure {
+                Some(_) if !tcb.send_buf.is_empty() => Err(self.error(tcb.failure)),
+                _ => Ok(()),
+            }
+        }
+
+        /// Close the sending side: a FIN follows the queued data. Reading
+        /// remains possible until the peer closes as well.
+        pub fn close(&self) -> Result<(), RlinkError> {
+            let conn = self.conn.clone();
+            let mut result = Ok(());
+            self.stack.tcp().update(&self.stack, &conn, |tcb| {
+                if tcb.fin_queued {
+                    return Vec::new();
+                }
+                match tcb.state {
+                    TcpState::Established => tcb.state = TcpState::FinWait1,
+                    TcpState::CloseWait => tcb.state = TcpState::LastAck,
+                    _ => {
+                        result = Err(self.error(tcb.failure));
+                        return Vec::new();
+                    }
+                }
+                tcb.fin_queued = true;
+                tcb.output(Instant::now())
+            });
+            result
+        }
+
+        /// Reset the connection, discarding queued data.
+        pub fn abort(&self) {
+            let conn = self.conn.clone();
+            self.stack.tcp().update(&self.stack, &conn, |tcb| {
+                let segments = match tcb.state {
+                    TcpState::Closed | TcpState::SynSent | TcpState::TimeWait => Vec::new(),
+                    _ => vec![tcb.segment(tcb.snd_nxt, RST, Vec::new())],
+                };
+                tcb.fail(Failure::Reset);
+                segments
+            });
+        }
+    }
+
+    impl Drop for TcpStream {
+        fn drop(&mut self) {
+            let _ = self.close();
+        }
+    }
+
+    impl io::Read for TcpStream {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            TcpStream::read(self, buf).map_err(io_error)
+        }
+    }
+
+    impl io::Write for TcpStream {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            TcpStream::write(self, buf).map_err(io_error)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            TcpStream::flush(self).map_err(io_error)
+        }
+    }
+
+    /// A listening TCP socket, modelled after `std::net::TcpListener`.
+    /// The port is released when the listener is dropped, closing the
+    /// connections not accepted yet.
+    pub struct TcpListener {
+        stack: Stack,
+        local: SocketAddrV4,
+        listener: Arc<Listener>,
+    }
+
+    impl TcpListener {
+        /// Listen on `addr`. Port 0 picks an ephemeral port. A specified IP
+        /// address restricts the listener to connections towards it.
+        pub fn bind(stack: &Stack, addr: SocketAddrV4) -> Result<TcpListener, RlinkError> {
+            Self::bind_with_backlog(stack, addr, DEFAULT_BACKLOG)
+        }
+
+        pub fn bind_with_backlog(stack: &Stack, addr: SocketAddrV4, backlog: usize) -> Result<TcpListener, RlinkError> {
+            let layer = stack.tcp();
+            let port = match addr.port() {
+                0 => layer.ephemeral_port(*addr.ip(), SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, 0))?,
+                port => port,
+            };
+            let mut listeners = layer.listeners.lock().unwrap();
+            if listeners.contains_key(&port) {
+                return Err(RlinkError::AddrInUse(port));
+            }
+            let listener = Arc::new(Listener {
+                ip: Some(*addr.ip()).filter(|ip| !ip.is_unspecified()),
+                queue: Mutex::new(VecDeque::new()),
+                ready: Condvar::new(),
+                backlog,
+            });
+            listeners.insert(port, listener.clone());
+            Ok(TcpListener { stack: stack.clone(), local: SocketAddrV4::new(*addr.ip(), port), listener })
+        }
+
+        pub fn local_addr(&self) -> SocketAddrV4 {
+            self.local
+        }
+
+        /// Accept an established connection, blocking until one arrives.
+        pub fn accept(&self) -> Result<(TcpStream, SocketAddrV4), RlinkError> {
+            self.accept_with(None)
+        }
+
+        /// Like `accept()`, waiting up to `timeout`.
+        pub fn accept_timeout(&self, timeout: Duration) -> Result<(TcpStream, SocketAddrV4), RlinkError> {
+            self.accept_with(Some(Instant::now() + timeout))
+        }
+
+        fn accept_with(&self, deadline: Option<Instant>) -> Result<(TcpStream, SocketAddrV4), RlinkError> {
+            let layer = self.stack.tcp();
+            let mut queue = self.listener.queue.lock().unwrap();
+            loop {
+                while let Some((local, remote)) = queue.pop_front() {
+                    // Connections reset before being accepted are skipped.
+                    if let Some(conn) = layer.connection(local, remote) {
+                        let stream = TcpStream {
+                            stack: self.stack.clone(),
+                            conn,
+                            local,
+                            remote,
+                            read_timeout: None,
+                            write_timeout: None,
+                        };
+                        return Ok((stream, remote));
+                    }
+                }
+                queue = match deadline {
+                    Some(deadline) => {
+                        let now = Instant::now();
+                        if now >= deadline {
+                            return Err(RlinkError::Timeout);
+                        }
+                        self.listener.ready.wait_timeout(queue, deadline - now).unwrap().0
+                    }
+                    None => self.listener.ready.wait(queue).unwrap(),
+                };
+            }
+        }
+    }
+
+    impl Drop for TcpListener {
+        fn drop(&mut self) {
+            let layer = self.stack.tcp();
+            layer.listeners.lock().unwrap().remove(&self.local.port());
+            let pending: Vec<_> = self.listener.queue.lock().unwrap().drain(..).collect();
+            for (local, remote) in pending {
+                if let Some(conn) = layer.connection(local, remote) {
+                    drop(TcpStream { stack: self.stack.clone(), conn, local, remote, read_timeout: None, write_timeout: None });
+                }
+            }
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::memlink::memlink::{self, Endpoint, Impairment};
+        use mac_address::MacAddress;
+
+        const CLIENT: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 1);
+        const SERVER: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 2);
+        const PORT: u16 = 7000;
+
+        /// A client and a server stack on both ends of a link.
+        fn pair(impairment: Impairment) -> (Stack, Stack) {
+            let (client, server) = (Stack::new(), Stack::new());
+            memlink::connect(
+                Endpoint { stack: &client, name: "client0", mac_address: MacAddress::new([2, 0, 0, 0, 0, 1]),
+                    addresses: vec![(CLIENT, 24)] },
+                Endpoint { stack: &server, name: "server0", mac_address: MacAddress::new([2, 0, 0, 0, 0, 2]),
+                    addresses: vec![(SERVER, 24)] },
+                impairment,
+            );
+            (client, server)
+        }
+
+        fn establish(client: &Stack, server: &Stack) -> (TcpStream, TcpStream) {
+            let listener = TcpListener::bind(server, SocketAddrV4::new(SERVER, PORT)).unwrap();
+            let stream = TcpStream::connect_timeout(client, SocketAddrV4::new(SERVER, PORT), Duration::from_secs(10))
+                .unwrap();
+            let (accepted, _) = listener.accept_timeout(Duration::from_secs(10)).unwrap();
+            (stream, accepted)
+        }
+
+        /// Wait up to 10 seconds for `done` to hold.
+        fn eventually<F: Fn() -> bool>(done: F) -> bool {
+            let deadline = Instant::now() + Duration::from_secs(10);
+            while !done() {
+                if Instant::now() >= deadline {
+                    return false;
+                }
+                thread::sleep(TICK);
+            }
+            true
+        }
+
+        #[test]
+        fn segment_round_trip() {
+            let segment = TcpSegment {
+                src_port: 40000,
+                dst_port: PORT,
+                seq: 0xfffffff0,
+                ack: 12345,
+                flags: SYN | ACK,
+                window: 4096,
+                urgent: 0,
+                mss: Some(DEFAULT_MSS),
+                payload: Vec::new(),
+            };
+            let data = segment.encode(CLIENT, SERVER);
+            assert_eq!(TcpSegment::decode(&data, CLIENT, SERVER), Ok(segment));
+            // The pseudo header covers the addresses
+            assert!(TcpSegment::decode(&data, CLIENT, Ipv4Addr::new(10, 0, 0, 3)).is_err());
+        }
+
+        #[test]
+        fn handshake() {
+            let (client, server) = pair(Impairment::default());
+            let (stream, accepted) = establish(&client, &server);
+            assert_eq!(stream.state(), TcpState::Established);
+            assert_eq!(accepted.state(), TcpState::Established);
+            assert_eq!(stream.peer_addr(), SocketAddrV4::new(SERVER, PORT));
+            assert_eq!(accepted.peer_addr(), stream.local_addr());
+            assert_eq!(accepted.local_addr(), stream.peer_addr());
+        }
+
+        #[test]
+        fn connection_refused() {
+            let (client, server) = pair(Impairment::default());
+            server.tcp();
+            let result = TcpStream::connect_timeout(&client, SocketAddrV4::new(SERVER, PORT), Duration::from_secs(10));
+            assert!(matches!(result, Err(RlinkError::ConnectionRefused(_))));
+        }
+
+        #[test]
+        fn bulk_transfer_over_impaired_link() {
+            let impairment = Impairment {
+                loss: 0.02,
+                duplicate: 0.02,
+                delay: Duration::from_millis(1),
+                jitter: Duration::from_millis(2),
+                seed: 42,
+            };
+            let (client, server) = pair(impairment);
+            let (stream, accepted) = establish(&client, &server);
+            let data: Vec<u8> = (0..200_000u32).map(|i| (i * 7 + i / 251) as u8).collect();
+
+            let sent = data.clone();
+            let writer = thread::spawn(move || {
+                let mut rest = &sent[..];
+                while !rest.is_empty() {
+                    let len = stream.write(rest).unwrap();
+                    rest = &rest[len..];
+                }
+                stream.flush().unwrap();
+                stream.close().unwrap();
+                stream
+            });
+            let mut received = Vec::new();
+            let mut buf = [0u8; 4096];
+            loop {
+                match accepted.read(&mut buf).unwrap() {
+                    0 => break,
+                    len => received.extend_from_slice(&buf[..len]),
+                }
+            }
+            let stream = writer.join().unwrap();
+            assert_eq!(received.len(), data.len());
+            assert!(received == data);
+            assert!(stream.srtt().is_some());
+        }
+
+        #[test]
+        fn orderly_close() {
+            let (client, server) = pair(Impairment::default());
+            let (stream, accepted) = establish(&client, &server);
+            stream.write(b"last words").unwrap();
+            stream.close().unwrap();
+
+            let mut buf = [0u8; 64];
+            let len = accepted.read(&mut buf).unwrap();
+            assert_eq!(&buf[..len], b"last words");
+            assert_eq!(accepted.read(&mut buf).unwrap(), 0);
+            assert_eq!(accepted.state(), TcpState::CloseWait);
+            // Half-closed: the server may still send
+            accepted.write(b"goodbye").unwrap();
+            accepted.close().unwrap();
+
+            let len = stream.read(&mut buf).unwrap();
+            assert_eq!(&buf[..len], b"goodbye");
+            assert_eq!(stream.read(&mut buf).unwrap(), 0);
+            assert!(stream.write(b"more").is_err());
+            assert!(eventually(|| stream.state() == TcpState::TimeWait));
+            assert!(eventually(|| server.tcp().connections().is_empty()));
+            assert!(eventually(|| client.tcp().connections().is_empty()));
+        }
+    }
+}
@@ -0,0 +1,252 @@
+#![allow(dead_code)]
+#![allow(unused)]
+
+pub mod udp {
+    //! User Datagram Protocol (RFC 768) on the rlink IPv4 stack: datagram
+    //! encoding with the pseudo-header checksum, a table of bound ports, and
+    //! a socket-like API.
+
+    use crate::icmp::icmp::{self, IcmpMessage};
+    use crate::ipv4::ipv4::{pseudo_header_checksum, IpProtocol, Ipv4, Ipv4Header};
+    use crate::{FrameError, Packet, RlinkError, Stack};
+    use std::collections::HashMap;
+    use std::net::{Ipv4Addr, SocketAddrV4};
+    use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    pub const HEADER_LEN: usize = 8;
+    /// Range ports are allocated from when binding to port 0.
+    pub const EPHEMERAL_PORTS: std::ops::RangeInclusive<u16> = 49152..=65535;
+
+    /// A UDP datagram.
+    #[derive(Clone, Debug, PartialEq, Eq)]
+    pub struct UdpDatagram {
+        pub src_port: u16,
+        pub dst_port: u16,
+        pub payload: Vec<u8>,
+    }
+
+    impl UdpDatagram {
+        /// Encodes the datagram, computing the checksum over the pseudo-header
+        /// of `src` and `dst`.
+        pub fn encode(&self, src: Ipv4Addr, dst: Ipv4Addr) -> Vec<u8> {
+            let len = HEADER_LEN + self.payload.len();
+            let mut data = Vec::with_capacity(len);
+            data.extend_from_slice(&self.src_port.to_be_bytes());
+            data.extend_from_slice(&self.dst_port.to_be_bytes());
+            data.extend_from_slice(&(len as u16).to_be_bytes());
+            data.extend_from_slice(&[0, 0]);
+            data.extend_from_slice(&self.payload);
+            let sum = match pseudo_header_checksum(src, dst, IpProtocol::UDP, &data) {
+                // Zero means no checksum; it is sent as all ones instead.
+                0 => 0xffff,
+                sum => sum,
+            };
+            data[6..8].copy_from_slice(&sum.to_be_bytes());
+            data
+        }
+
+        /// Decodes a datagram from an IPv4 payload. The checksum is verified
+        /// unless the sender left it out.
+        pub fn decode(data: &[u8], src: Ipv4Addr, dst: Ipv4Addr) -> Result<Self, FrameError> {
+            if data.len() < HEADER_LEN {
+                return Err(FrameError::Truncated { offset: 0, needed: HEADER_LEN, available: data.len() });
+            }
+            let len = u16::from_be_bytes([data[4], data[5]]) as usize;
+            if len < HEADER_LEN {
+                return Err(FrameError::InvalidField { offset: 4, field: "UDP length" });
+            }
+            if len > data.len() {
+                return Err(FrameError::Truncated { offset: 0, needed: len, available: data.len() });
+            }
+            let data = &data[..len];
+            let actual = u16::from_be_bytes([data[6], data[7]]);
+            if actual != 0 && pseudo_header_checksum(src, dst, IpProtocol::UDP, data) != 0 {
+                let mut zeroed = data.to_vec();
+                zeroed[6] = 0;
+                zeroed[7] = 0;
+                return Err(FrameError::ChecksumMismatch {
+                    expected: pseudo_header_checksum(src, dst, IpProtocol::UDP, &zeroed) as u32,
+                    actual: actual as u32,
+                });
+            }
+            Ok(UdpDatagram {
+                src_port: u16::from_be_bytes([data[0], data[1]]),
+                dst_port: u16::from_be_bytes([data[2], data[3]]),
+                payload: data[HEADER_LEN..].to_vec(),
+            })
+        }
+    }
+
+    /// Handler of datagrams to a bound port, invoked on the receiving
+    /// thread of the stack with the index of the link the datagram came in
+    /// on, and the source and destination addresses.
+    pub type UdpHandler = Box<dyn Fn(&Stack, usize, SocketAddrV4, SocketAddrV4, &[u8]) + Send + Sync>;
+
+    /// Receiving end of a bound port: `(source, payload)` pairs.
+    pub type UdpQueue = Receiver<(SocketAddrV4, Vec<u8>)>;
+
+    enum Binding {
+        /// Queue of a socket, possibly restricted to one local address
+        Queue(Option<Ipv4Addr>, Sender<(SocketAddrV4, Vec<u8>)>),
+        /// Shared, to be invoked without holding the table
+        Handler(Arc<UdpHandler>),
+    }
+
+    /// Ports bound on a stack. Datagrams to unbound ports are answered with
+    /// ICMP port unreachable.
+    #[derive(Default)]
+    pub struct PortTable {
+        bindings: Mutex<HashMap<u16, Binding>>,
+    }
+
+    impl PortTable {
+        fn bind(&self, port: u16, binding: Binding) -> Result<u16, RlinkError> {
+            let mut bindings = self.bindings.lock().unwrap();
+            let port = if port == 0 {
+                EPHEMERAL_PORTS.clone()
+                    .find(|port| !bindings.contains_key(port))
+                    .ok_or(RlinkError::AddrInUse(0))?
+            }
+            else {
+                port
+            };
+            if bindings.contains_key(&port) {
+                return Err(RlinkError::AddrInUse(port));
+            }
+            bindings.insert(port, binding);
+            Ok(port)
+        }
+
+        /// Bind `port` to a handler (0 for an ephemeral port). Returns the port.
+        pub fn bind_handler(&self, port: u16, handler: UdpHandler) -> Result<u16, RlinkError> {
+            self.bind(port, Binding::Handler(Arc::new(handler)))
+        }
+
+        /// Bind `addr` to a queue (port 0 for an ephemeral port). Returns the
+        /// port and the receiving end of the queue. A specified IP address
+        /// restricts the queue to datagrams sent to it or broadcast.
+        pub fn bind_queue(&self, addr: SocketAddrV4) -> Result<(u16, UdpQueue), RlinkError> {
+            let (tx, rx) = mpsc::channel();
+            let ip = Some(*addr.ip()).filter(|ip| !ip.is_unspecified());
+            Ok((self.bind(addr.port(), Binding::Queue(ip, tx))?, rx))
+        }
+
+        pub fn unbind(&self, port: u16) {
+            self.bindings.lock().unwrap().remove(&port);
+        }
+
+        pub fn is_bound(&self, port: u16) -> bool {
+            self.bindings.lock().unwrap().contains_key(&port)
+        }
+
+        /// Dispatch a received datagram. Returns whether the port was bound.
+        fn dispatch(&self, stack: &Stack, link: usize, src: SocketAddrV4, dst: SocketAddrV4, payload: Vec<u8>) -> bool {
+            let bindings = self.bindings.lock().unwrap();
+            let handler = match bindings.get(&dst.port()) {
+                Some(Binding::Queue(Some(ip), _)) 
+                    if ip != dst.ip() && !dst.ip().is_broadcast() && !stack.is_subnet_broadcast(*dst.ip()) => return false,
+                Some(Binding::Queue(_, tx)) => {
+                    tx.send((src, payload));
+                    return true;
+                }
+                Some(Binding::Handler(handler)) => handler.clone(),
+                None => return false,
+            };
+            // Handlers may bind and unbind ports themselves
+            drop(bindings);
+            handler(stack, link, src, dst, &payload);
+            true
+        }
+
+        /// Process a received UDP datagram.
+        pub(crate) fn input(&self, stack: &Stack, link: usize, packet: Packet<Ipv4>) {
+            let datagram = match UdpDatagram::decode(packet.payload(), packet.src(), packet.dst()) {
+                Ok(datagram) => datagram,
+                Err(_) => return,
+            };
+            let src = SocketAddrV4::new(packet.src(), datagram.src_port);
+            let dst = SocketAddrV4::new(packet.dst(), datagram.dst_port);
+            if !self.dispatch(stack, link, src, dst, datagram.payload) {
+                stack.send_icmp_error(&packet, IcmpMessage::destination_unreachable(
+                    icmp::PORT_UNREACHABLE, &packet));
+            }
+        }
+    }
+
+    /// Send a datagram from `src` to `dst`. An unspecified source address is
+    /// filled in from the outgoing link, except for limited broadcasts.
+    pub fn send_to(stack: &Stack, src: SocketAddrV4, dst: SocketAddrV4, payload: &[u8]) -> Result<(), RlinkError> {
+        let src_ip = if src.ip().is_unspecified() && !dst.ip().is_broadcast() {
+            stack.source_for(*dst.ip())?
+        }
+        else {
+            *src.ip()
+        };
+        let datagram = UdpDatagram { src_port: src.port(), dst_port: dst.port(), payload: payload.to_vec() };
+        let header = Ipv4Header::new(src_ip, *dst.ip(), IpProtocol::UDP);
+        stack.send_ipv4_with(header, &datagram.encode(src_ip, *dst.ip()))
+    }
+
+    /// A UDP socket on a stack, modelled after `std::net::UdpSocket`.
+    /// The port is released when the socket is dropped.
+    pub struct UdpSocket {
+        stack: Stack,
+        local: SocketAddrV4,
+        rx: Mutex<UdpQueue>,
+        read_timeout: Option<Duration>,
+    }
+
+    impl UdpSocket {
+        /// Bind a socket to `addr`. Port 0 picks an ephemeral port. A specified
+        /// IP address restricts the socket to datagrams sent to it.
+        pub fn bind(stack: &Stack, addr: SocketAddrV4) -> Result<UdpSocket, RlinkError> {
+            let (port, rx) = stack.udp().bind_queue(addr)?;
+            Ok(UdpSocket {
+                stack: stack.clone(),
+                local: SocketAddrV4::new(*addr.ip(), port),
+                rx: Mutex::new(rx),
+                read_timeout: None,
+            })
+        }
+
+        pub fn local_addr(&self) -> SocketAddrV4 {
+            self.local
+        }
+
+        /// Send `buf` to `dst`. Returns the number of bytes sent.
+        pub fn send_to(&self, buf: &[u8], dst: SocketAddrV4) -> Result<usize, RlinkError> {
+            send_to(&self.stack, self.local, dst, buf)?;
+            Ok(buf.len())
+        }
+
+        /// Receive a datagram, blocking up to the read timeout. Bytes not
+        /// fitting in `buf` are discarded. Returns the number of bytes read
+        /// and the source address.
+        pub fn recv_from(&self, buf: &mut [u8]) -> Result<(usize, SocketAddrV4), RlinkError> {
+            let rx = self.rx.lock().unwrap();
+            let (src, payload) = match self.read_timeout {
+                Some(timeout) => rx.recv_timeout(timeout).map_err(|e| match e {
+                    RecvTimeoutError::Timeout => RlinkError::Timeout,
+                    RecvTimeoutError::Disconnected => RlinkError::BrokenDevicePool,
+                })?,
+                None => rx.recv().map_err(|_| RlinkError::BrokenDevicePool)?,
+            };
+            let len = payload.len().min(buf.len());
+            buf[..len].copy_from_slice(&payload[..len]);
+            Ok((len, src))
+        }
+
+        /// Set the timeout of `recv_from()`; `None` blocks indefinitely.
+        pub fn set_read_timeout(&mut self, timeout: Option<Duration>) {
+            self.read_timeout = timeout;
+        }
+    }
+
+    impl Drop for UdpSocket {
+        fn drop(&mut self) {
+            self.stack.udp().unbind(self.local.port());
+        }
+    }
+}